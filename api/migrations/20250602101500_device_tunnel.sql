@@ -0,0 +1,10 @@
+CREATE TABLE device_tunnel (
+    device_id INTEGER NOT NULL REFERENCES device (id) ON DELETE CASCADE,
+    local_port INTEGER NOT NULL,
+    remote_port INTEGER NOT NULL,
+    opened_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    requested_by TEXT,
+    reported_at TIMESTAMPTZ NOT NULL DEFAULT now (),
+    PRIMARY KEY (device_id, local_port)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE shell_session (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid (),
+    device_id INTEGER NOT NULL REFERENCES device (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES auth.users (id),
+    cols INTEGER NOT NULL,
+    rows INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now (),
+    started_at TIMESTAMPTZ,
+    ended_at TIMESTAMPTZ,
+    exit_code INTEGER,
+    recording TEXT
+);
+
+CREATE INDEX idx_shell_session_device_id ON shell_session (device_id);
@@ -0,0 +1,17 @@
+CREATE TABLE config_file (
+    id SERIAL PRIMARY KEY,
+    -- NULL applies the file to every device
+    distribution_id INTEGER REFERENCES distribution (id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    template TEXT NOT NULL,
+    mode INTEGER NOT NULL DEFAULT 420,
+    owner TEXT NOT NULL DEFAULT 'root',
+    "group" TEXT NOT NULL DEFAULT 'root',
+    reload_units TEXT[] NOT NULL DEFAULT '{}',
+    restart_units TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now (),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now (),
+    CONSTRAINT config_file_absolute_path CHECK (path LIKE '/%')
+);
+
+CREATE UNIQUE INDEX idx_config_file_distribution_path ON config_file (COALESCE(distribution_id, 0), path);
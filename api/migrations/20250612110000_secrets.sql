@@ -0,0 +1,23 @@
+-- Secret values are sealed with envelope encryption by the API, the plaintext
+-- column stays empty for them.
+ALTER TABLE variable
+ADD COLUMN secret BOOLEAN NOT NULL DEFAULT false,
+ADD COLUMN sealed_value BYTEA,
+ADD CONSTRAINT variable_secret_sealed CHECK (NOT secret OR sealed_value IS NOT NULL);
+
+ALTER TABLE network
+ADD COLUMN secret BOOLEAN NOT NULL DEFAULT false,
+ADD COLUMN sealed_password BYTEA,
+ADD CONSTRAINT network_secret_sealed CHECK (NOT secret OR sealed_password IS NOT NULL);
+
+CREATE TABLE secret_audit (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    secret_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    device_id INTEGER REFERENCES device (id) ON DELETE SET NULL,
+    purpose TEXT NOT NULL,
+    accessed_at TIMESTAMPTZ NOT NULL DEFAULT now ()
+);
+
+CREATE INDEX idx_secret_audit_accessed_at ON secret_audit (accessed_at);
@@ -0,0 +1,8 @@
+ALTER TABLE network
+ADD COLUMN priority INTEGER NOT NULL DEFAULT 500,
+ADD COLUMN eap_method TEXT,
+ADD COLUMN eap_identity TEXT,
+ADD COLUMN ipv4_address TEXT,
+ADD COLUMN ipv4_gateway TEXT,
+ADD COLUMN dns TEXT[] NOT NULL DEFAULT '{}',
+ADD COLUMN apn TEXT;
@@ -0,0 +1,21 @@
+ALTER TABLE modem
+ADD COLUMN iccid TEXT,
+ADD COLUMN access_technology TEXT,
+ADD COLUMN signal_quality INTEGER,
+ADD COLUMN rx_bytes BIGINT,
+ADD COLUMN tx_bytes BIGINT;
+
+CREATE TABLE modem_history (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    device_id INTEGER NOT NULL REFERENCES device (id) ON DELETE CASCADE,
+    modem_id INTEGER NOT NULL REFERENCES modem (id) ON DELETE CASCADE,
+    network_provider TEXT NOT NULL,
+    iccid TEXT,
+    access_technology TEXT,
+    signal_quality INTEGER,
+    rx_bytes BIGINT,
+    tx_bytes BIGINT,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_modem_history_device_recorded_at ON modem_history (device_id, recorded_at);
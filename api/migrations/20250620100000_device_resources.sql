@@ -0,0 +1,21 @@
+CREATE TABLE device_resources (
+    device_id INTEGER PRIMARY KEY REFERENCES device (id) ON DELETE CASCADE,
+    cpu_usage SMALLINT NOT NULL,
+    -- one minute
+    load_average REAL NOT NULL,
+    memory_usage REAL NOT NULL,
+    swap_usage REAL,
+    -- percent used of the fullest mount
+    disk_usage REAL,
+    fullest_mount TEXT,
+    -- hottest thermal zone, in degrees Celsius
+    temperature REAL,
+    throttling BOOLEAN NOT NULL DEFAULT false,
+    gpu_usage SMALLINT,
+    emc_usage SMALLINT,
+    uptime BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_device_resources_disk_usage ON device_resources (disk_usage);
+CREATE INDEX idx_device_resources_throttling ON device_resources (device_id) WHERE throttling;
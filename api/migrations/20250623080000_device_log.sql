@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS public.device_log (
+  "timestamp" timestamptz NOT NULL,
+  id bigint GENERATED ALWAYS AS IDENTITY,
+  device_id integer NOT NULL,
+  unit text,
+  priority smallint NOT NULL,
+  message text NOT NULL,
+  CONSTRAINT device_log_pkey PRIMARY KEY ("timestamp", id)
+) PARTITION BY RANGE ("timestamp");
+
+CREATE INDEX IF NOT EXISTS idx_device_log_device_ts
+    ON public.device_log (device_id, "timestamp" DESC);
+
+SELECT partman.create_parent(
+    p_parent_table := 'public.device_log',
+    p_control := 'timestamp',
+    p_interval := '1 day'
+);
+
+-- the API overrides this at startup when LOG_RETENTION is set
+UPDATE partman.part_config
+SET retention = '14 days',
+    retention_keep_table = false
+WHERE parent_table = 'public.device_log';
+
+SELECT partman.run_maintenance();
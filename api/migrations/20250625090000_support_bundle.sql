@@ -0,0 +1,13 @@
+CREATE TABLE support_bundle (
+    id SERIAL PRIMARY KEY,
+    device_id INTEGER NOT NULL REFERENCES device (id) ON DELETE CASCADE,
+    -- command that collected it, NULL when the device did it on its own
+    command_id INTEGER,
+    profile TEXT NOT NULL,
+    -- object key in the assets bucket
+    file TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_support_bundle_device_id ON support_bundle (device_id, created_at DESC);
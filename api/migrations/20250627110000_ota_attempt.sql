@@ -0,0 +1,16 @@
+CREATE TABLE ota_attempt (
+    id SERIAL PRIMARY KEY,
+    device_id INTEGER NOT NULL REFERENCES device (id) ON DELETE CASCADE,
+    -- set by the device, identifies the attempt together with device_id
+    started_at TIMESTAMPTZ NOT NULL,
+    profile TEXT NOT NULL,
+    phase TEXT NOT NULL,
+    expected_version TEXT,
+    previous_version TEXT,
+    version TEXT,
+    error TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (device_id, started_at)
+);
+
+CREATE INDEX idx_ota_attempt_device_id ON ota_attempt (device_id, started_at DESC);
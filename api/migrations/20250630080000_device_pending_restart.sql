@@ -0,0 +1,2 @@
+-- restart the device reported as scheduled on its last ping
+ALTER TABLE device ADD COLUMN pending_restart JSONB;
@@ -0,0 +1,7 @@
+-- what the apt Packages index lists for a package, read from the .deb at upload
+CREATE TABLE package_index (
+    package_id INTEGER PRIMARY KEY REFERENCES package (id) ON DELETE CASCADE,
+    control TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    sha256 TEXT NOT NULL
+);
@@ -0,0 +1,12 @@
+-- binary patches rebuilding a package from an older version of it
+CREATE TABLE package_delta (
+    id SERIAL PRIMARY KEY,
+    package_id INTEGER NOT NULL REFERENCES package (id) ON DELETE CASCADE,
+    base_package_id INTEGER NOT NULL REFERENCES package (id) ON DELETE CASCADE,
+    -- what devices know the base by, the SHA256 of the .deb they have
+    base_sha256 TEXT NOT NULL,
+    file TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (package_id, base_package_id)
+);
@@ -0,0 +1,5 @@
+-- download caps in MB/s, a device override wins over its tags
+ALTER TABLE device ADD COLUMN download_rate DOUBLE PRECISION;
+ALTER TABLE device ADD COLUMN download_metered_rate DOUBLE PRECISION;
+ALTER TABLE tag ADD COLUMN download_rate DOUBLE PRECISION;
+ALTER TABLE tag ADD COLUMN download_metered_rate DOUBLE PRECISION;
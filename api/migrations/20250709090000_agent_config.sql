@@ -0,0 +1,21 @@
+-- magic.toml fragments pushed to devices, merged global, distribution, tag then device
+CREATE TABLE agent_config (
+    id SERIAL PRIMARY KEY,
+    distribution_id INTEGER REFERENCES distribution (id) ON DELETE CASCADE,
+    tag_id INTEGER REFERENCES tag (id) ON DELETE CASCADE,
+    device_id INTEGER REFERENCES device (id) ON DELETE CASCADE,
+    config TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now (),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now (),
+    -- no scope at all applies the fragment to every device
+    CONSTRAINT agent_config_one_scope CHECK (num_nonnulls (distribution_id, tag_id, device_id) <= 1)
+);
+
+CREATE UNIQUE INDEX idx_agent_config_scope ON agent_config (
+    COALESCE(distribution_id, 0),
+    COALESCE(tag_id, 0),
+    COALESCE(device_id, 0)
+);
+
+-- version of the agent config the device last reported running
+ALTER TABLE device ADD COLUMN agent_config_version TEXT;
@@ -0,0 +1,2 @@
+-- plugins smithd reported it can run, NULL until a smithd that has plugins reports
+ALTER TABLE device ADD COLUMN plugins TEXT[];
@@ -0,0 +1,2 @@
+-- Signature of commands signed by an admin, for devices whose policy requires it
+ALTER TABLE command_queue ADD COLUMN signature JSONB;
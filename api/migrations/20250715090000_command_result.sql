@@ -0,0 +1,19 @@
+-- How a command ended, NULL for agents that only report a status
+CREATE TYPE command_outcome AS ENUM (
+    'success',
+    'non_zero_exit',
+    'timeout',
+    'cancelled',
+    'unsupported',
+    'policy_denied',
+    'internal_error'
+);
+
+ALTER TABLE command_response
+    ADD COLUMN started_at TIMESTAMPTZ,
+    ADD COLUMN finished_at TIMESTAMPTZ,
+    ADD COLUMN duration_ms BIGINT,
+    ADD COLUMN outcome command_outcome,
+    ADD COLUMN error TEXT;
+
+CREATE INDEX idx_command_response_outcome ON command_response (outcome);
@@ -0,0 +1,4 @@
+-- what smithd reported speaking on its last ping, NULL until a smithd that
+-- negotiates capabilities pings
+ALTER TABLE device ADD COLUMN protocol_version INT;
+ALTER TABLE device ADD COLUMN capabilities TEXT[];
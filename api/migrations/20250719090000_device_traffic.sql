@@ -0,0 +1,13 @@
+-- bytes each device exchanged with the API, per UTC day
+CREATE TABLE device_traffic (
+    device_id INTEGER NOT NULL REFERENCES device (id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    -- request bodies of the device as they went over the wire
+    sent_bytes BIGINT NOT NULL DEFAULT 0,
+    -- response bodies of the API
+    received_bytes BIGINT NOT NULL DEFAULT 0,
+    requests INTEGER NOT NULL DEFAULT 0,
+    -- pings with nothing to report
+    heartbeats INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (device_id, day)
+);
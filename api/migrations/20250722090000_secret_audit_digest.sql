@@ -0,0 +1,6 @@
+-- A secret is only audited the first time each sealed value is revealed for a
+-- device, refreshing an unchanged secret leaves no new row.
+ALTER TABLE secret_audit
+ADD COLUMN sealed_digest BYTEA;
+
+CREATE INDEX idx_secret_audit_digest ON secret_audit (kind, secret_id, device_id, sealed_digest);
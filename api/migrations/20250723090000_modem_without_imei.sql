@@ -0,0 +1,8 @@
+-- Modems reported before their IMEI was known all shared the empty one, so
+-- that row mixes up unrelated devices. They report again once it's known.
+UPDATE device
+SET modem_id = NULL
+WHERE modem_id IN (SELECT id FROM modem WHERE imei = '');
+
+DELETE FROM modem
+WHERE imei = '';
@@ -0,0 +1,16 @@
+-- tag fragments are merged by priority, a device can have several tags
+ALTER TABLE agent_config ADD COLUMN priority INTEGER;
+
+UPDATE agent_config SET priority = ordered.priority
+FROM (
+    SELECT id, row_number() OVER (ORDER BY tag_id) AS priority
+    FROM agent_config
+    WHERE tag_id IS NOT NULL
+) ordered
+WHERE agent_config.id = ordered.id;
+
+ALTER TABLE agent_config ADD CONSTRAINT agent_config_tag_priority
+    CHECK ((tag_id IS NULL) = (priority IS NULL));
+
+CREATE UNIQUE INDEX idx_agent_config_tag_priority ON agent_config (priority)
+WHERE tag_id IS NOT NULL;
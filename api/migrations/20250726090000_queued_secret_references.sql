@@ -0,0 +1,31 @@
+-- Secrets are queued as references and only revealed when the device fetches
+-- the command. Replace the values queued before, config files were rendered
+-- with them and can't be told apart.
+UPDATE command_queue cq
+SET cmd = jsonb_set(
+    cq.cmd::jsonb,
+    '{UpdateVariables,variables}',
+    (
+        SELECT COALESCE(jsonb_object_agg(
+            entry.key,
+            CASE
+                WHEN cq.cmd::jsonb -> 'UpdateVariables' -> 'secrets' ? entry.key
+                THEN to_jsonb(COALESCE('{{secret:variable:' || v.id || '}}', '<secret>'))
+                ELSE entry.value
+            END
+        ), '{}'::jsonb)
+        FROM jsonb_each(cq.cmd::jsonb -> 'UpdateVariables' -> 'variables') entry
+        LEFT JOIN variable v ON v.device = cq.device_id AND v.name = entry.key AND v.secret
+    )
+)::json
+WHERE cq.cmd::jsonb ? 'UpdateVariables'
+  AND jsonb_typeof(cq.cmd::jsonb -> 'UpdateVariables' -> 'secrets') = 'array';
+
+UPDATE command_queue
+SET cmd = jsonb_set(
+    cmd::jsonb,
+    '{UpdateNetwork,network,password}',
+    to_jsonb('{{secret:network:' || (cmd::jsonb -> 'UpdateNetwork' -> 'network' ->> 'id') || '}}')
+)::json
+WHERE cmd::jsonb ? 'UpdateNetwork'
+  AND (cmd::jsonb -> 'UpdateNetwork' -> 'network' ->> 'secret')::boolean;
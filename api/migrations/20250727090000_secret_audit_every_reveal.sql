@@ -0,0 +1,3 @@
+-- Every reveal is audited again, the digest only tells which sealed value was
+-- revealed and is no longer looked up.
+DROP INDEX idx_secret_audit_digest;
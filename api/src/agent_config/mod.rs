@@ -0,0 +1,181 @@
+use crate::agent_config::schema::NewAgentConfig;
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use smith::magic::merge::merge;
+use smith::magic::structure::MagicFile;
+use smith::utils::schema::SafeCommandTx;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tracing::{error, info};
+
+pub mod routes;
+pub mod schema;
+
+impl NewAgentConfig {
+    pub fn validate(&self) -> Result<()> {
+        let scopes = [self.distribution_id, self.tag_id, self.device_id];
+        if scopes.iter().flatten().count() > 1 {
+            return Err(anyhow!(
+                "only one of distribution_id, tag_id and device_id can be set"
+            ));
+        }
+        // a device can have several tags, their fragments are merged in order
+        if self.tag_id.is_some() != self.priority.is_some() {
+            return Err(anyhow!(
+                "priority is required for tag fragments and only for them"
+            ));
+        }
+
+        // checked on its own, the device checks the merged result again
+        let fragment = MagicFile::parse_managed(&self.config)?;
+        let mut table = toml::Table::try_from(MagicFile::default())?;
+        merge(&mut table, fragment)?;
+        let magic: MagicFile = table.try_into()?;
+        magic.validate()
+    }
+}
+
+/// Merges every fragment that applies to the device, returns the result
+/// and its version.
+pub async fn render_for_device(
+    device_id: i32,
+    executor: impl PgExecutor<'_>,
+) -> Result<(String, String)> {
+    let fragments = sqlx::query_scalar!(
+        r#"
+        SELECT ac.config
+        FROM agent_config ac
+        WHERE num_nonnulls(ac.distribution_id, ac.tag_id, ac.device_id) = 0
+           OR ac.distribution_id = (
+               SELECT r.distribution_id
+               FROM device d
+               JOIN release r ON r.id = d.release_id
+               WHERE d.id = $1
+           )
+           OR ac.tag_id IN (SELECT td.tag_id FROM tag_device td WHERE td.device_id = $1)
+           OR ac.device_id = $1
+        ORDER BY
+            CASE
+                WHEN ac.device_id IS NOT NULL THEN 3
+                WHEN ac.tag_id IS NOT NULL THEN 2
+                WHEN ac.distribution_id IS NOT NULL THEN 1
+                ELSE 0
+            END,
+            ac.priority
+        "#,
+        device_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let mut merged = toml::Table::new();
+    for fragment in fragments {
+        merge(&mut merged, toml::from_str(&fragment)?)?;
+    }
+    let config = toml::to_string(&merged)?;
+    let version = format!("{:x}", Sha256::digest(&config));
+
+    Ok((config, version))
+}
+
+/// Devices that receive fragments of the given scope, every device when
+/// none is given.
+pub async fn affected_devices(
+    distribution_id: Option<i32>,
+    tag_id: Option<i32>,
+    device_id: Option<i32>,
+    pool: &PgPool,
+) -> Result<Vec<i32>> {
+    let devices = sqlx::query_scalar!(
+        "
+        SELECT d.id
+        FROM device d
+        LEFT JOIN release r ON r.id = d.release_id
+        WHERE d.archived = false
+          AND ($1::int4 IS NULL OR r.distribution_id = $1)
+          AND ($2::int4 IS NULL OR EXISTS (
+              SELECT 1 FROM tag_device td WHERE td.device_id = d.id AND td.tag_id = $2
+          ))
+          AND ($3::int4 IS NULL OR d.id = $3)
+        ",
+        distribution_id,
+        tag_id,
+        device_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(devices)
+}
+
+/// Queues the merged configuration of the device for it to apply.
+pub async fn send_to_device(device_id: i32, pool: &PgPool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let (config, version) = render_for_device(device_id, &mut *tx).await?;
+    queue(device_id, config, version, &mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Sends the configuration to a device that came up with another one
+/// than it should run. Devices that never had one don't get an empty one.
+pub async fn sync_device(
+    device_id: i32,
+    running: Option<&str>,
+    conn: &mut PgConnection,
+) -> Result<()> {
+    let (config, version) = render_for_device(device_id, &mut *conn).await?;
+    if running == Some(version.as_str()) || (running.is_none() && config.is_empty()) {
+        return Ok(());
+    }
+
+    info!("Device {device_id} runs agent config {running:?} instead of {version}");
+    queue(device_id, config, version, conn).await
+}
+
+/// Queues the configuration in place of any the device hasn't fetched yet,
+/// it only needs the latest one.
+async fn queue(
+    device_id: i32,
+    config: String,
+    version: String,
+    conn: &mut PgConnection,
+) -> Result<()> {
+    let command = SafeCommandTx::UpdateAgentConfig { version, config };
+
+    sqlx::query!(
+        "
+        UPDATE command_queue SET canceled = true
+        WHERE device_id = $1 AND fetched = false AND canceled = false
+          AND cmd::jsonb ? 'UpdateAgentConfig'
+        ",
+        device_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let bundle_id = sqlx::query!(r#"INSERT INTO command_bundles DEFAULT VALUES RETURNING uuid"#)
+        .fetch_one(&mut *conn)
+        .await?;
+    sqlx::query!(
+        r#"INSERT INTO command_queue (device_id, cmd, continue_on_error, canceled, bundle)
+        VALUES ($1, $2::jsonb, false, false, $3)"#,
+        device_id,
+        serde_json::to_value(command)?,
+        bundle_id.uuid
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Sends the merged configuration to every device the change touches.
+pub fn refresh_devices(device_ids: Vec<i32>, pool: PgPool) {
+    tokio::spawn(async move {
+        for device_id in device_ids {
+            if let Err(err) = send_to_device(device_id, &pool).await {
+                error!("Failed to send agent config to device {device_id}: {err}");
+            }
+        }
+    });
+}
@@ -0,0 +1,287 @@
+use crate::State;
+use crate::agent_config::schema::{AgentConfig, DeviceAgentConfig, NewAgentConfig};
+use crate::agent_config::{affected_devices, render_for_device, send_to_device};
+use crate::middlewares::authorization;
+use crate::users::db::CurrentUser;
+use axum::{Extension, Json, extract::Path};
+use axum::{http::StatusCode, response::Result};
+use sqlx::PgPool;
+use tracing::{error, warn};
+
+const TAG: &str = "agent config";
+
+/// Distribution, tag and device a fragment is scoped to.
+type Scope = (Option<i32>, Option<i32>, Option<i32>);
+
+#[utoipa::path(
+    get,
+    path = "/agent-configs",
+    responses(
+        (status = StatusCode::OK, description = "List of agent config fragments", body = Vec<AgentConfig>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve agent config"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_agent_configs(
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<AgentConfig>>, StatusCode> {
+    let configs = sqlx::query_as!(
+        AgentConfig,
+        "
+        SELECT * FROM agent_config
+        ORDER BY distribution_id NULLS FIRST, tag_id NULLS FIRST, device_id NULLS FIRST
+        "
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get agent config: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(configs))
+}
+
+#[utoipa::path(
+    post,
+    path = "/agent-configs",
+    request_body = NewAgentConfig,
+    responses(
+        (status = StatusCode::CREATED, description = "Agent config fragment created", body = AgentConfig),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid agent config"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage agent config"),
+        (status = StatusCode::CONFLICT, description = "The scope already has a fragment, or another tag fragment has the priority"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to create agent config"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn create_agent_config(
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(config): Json<NewAgentConfig>,
+) -> Result<(StatusCode, Json<AgentConfig>), StatusCode> {
+    if !authorization::check(current_user, "agent_config", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    config.validate().map_err(|err| {
+        warn!("Invalid agent config: {err}");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let created = sqlx::query_as!(
+        AgentConfig,
+        "
+        INSERT INTO agent_config (distribution_id, tag_id, device_id, priority, config)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        ",
+        config.distribution_id,
+        config.tag_id,
+        config.device_id,
+        config.priority,
+        config.config
+    )
+    .fetch_one(&state.pg_pool)
+    .await
+    .map_err(|err| database_error("create", err))?;
+
+    refresh(vec![scope(&created)], state.pg_pool.clone());
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/agent-configs/:agent_config_id",
+    request_body = NewAgentConfig,
+    responses(
+        (status = StatusCode::OK, description = "Agent config fragment updated", body = AgentConfig),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid agent config"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage agent config"),
+        (status = StatusCode::NOT_FOUND, description = "Agent config fragment not found"),
+        (status = StatusCode::CONFLICT, description = "The scope already has a fragment, or another tag fragment has the priority"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to update agent config"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn update_agent_config(
+    Path(agent_config_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(config): Json<NewAgentConfig>,
+) -> Result<Json<AgentConfig>, StatusCode> {
+    if !authorization::check(current_user, "agent_config", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    config.validate().map_err(|err| {
+        warn!("Invalid agent config {agent_config_id}: {err}");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let previous = sqlx::query_as!(
+        AgentConfig,
+        "SELECT * FROM agent_config WHERE id = $1",
+        agent_config_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| database_error("fetch", err))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let updated = sqlx::query_as!(
+        AgentConfig,
+        "
+        UPDATE agent_config SET
+            distribution_id = $2,
+            tag_id = $3,
+            device_id = $4,
+            priority = $5,
+            config = $6,
+            updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        ",
+        agent_config_id,
+        config.distribution_id,
+        config.tag_id,
+        config.device_id,
+        config.priority,
+        config.config
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| database_error("update", err))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    refresh(
+        vec![scope(&previous), scope(&updated)],
+        state.pg_pool.clone(),
+    );
+
+    Ok(Json(updated))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/agent-configs/:agent_config_id",
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Agent config fragment deleted"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage agent config"),
+        (status = StatusCode::NOT_FOUND, description = "Agent config fragment not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to delete agent config"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn delete_agent_config(
+    Path(agent_config_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "agent_config", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let deleted = sqlx::query_as!(
+        AgentConfig,
+        "DELETE FROM agent_config WHERE id = $1 RETURNING *",
+        agent_config_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| database_error("delete", err))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    refresh(vec![scope(&deleted)], state.pg_pool.clone());
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/agent-config",
+    responses(
+        (status = StatusCode::OK, description = "Agent config the device should run and the version it runs", body = DeviceAgentConfig),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve agent config"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_device_agent_config(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<DeviceAgentConfig>, StatusCode> {
+    let running_version = sqlx::query_scalar!(
+        "SELECT agent_config_version FROM device WHERE id = $1",
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| database_error("fetch", err))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let (config, version) = render_for_device(device_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to render agent config of device {device_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(DeviceAgentConfig {
+        config,
+        version,
+        running_version,
+    }))
+}
+
+fn scope(config: &AgentConfig) -> Scope {
+    (config.distribution_id, config.tag_id, config.device_id)
+}
+
+fn database_error(action: &str, err: sqlx::Error) -> StatusCode {
+    if err
+        .as_database_error()
+        .is_some_and(|err| err.is_unique_violation())
+    {
+        return StatusCode::CONFLICT;
+    }
+    error!("Failed to {action} agent config: {err}");
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Sends the merged configuration to every device of the scopes.
+fn refresh(scopes: Vec<Scope>, pool: PgPool) {
+    tokio::spawn(async move {
+        let mut devices = vec![];
+        for (distribution_id, tag_id, device_id) in scopes {
+            match affected_devices(distribution_id, tag_id, device_id, &pool).await {
+                Ok(ids) => devices.extend(ids),
+                Err(err) => error!("Failed to get devices for agent config refresh: {err}"),
+            }
+        }
+        devices.sort_unstable();
+        devices.dedup();
+
+        for device_id in devices {
+            if let Err(err) = send_to_device(device_id, &pool).await {
+                error!("Failed to send agent config to device {device_id}: {err}");
+            }
+        }
+    });
+}
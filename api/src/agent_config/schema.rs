@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono;
+
+/// A fragment of magic.toml managed from the API.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct AgentConfig {
+    pub id: i32,
+    /// Fragments without a distribution, tag or device go to every device.
+    pub distribution_id: Option<i32>,
+    pub tag_id: Option<i32>,
+    pub device_id: Option<i32>,
+    /// Order of tag fragments, the highest priority is merged last and wins.
+    pub priority: Option<i32>,
+    pub config: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct NewAgentConfig {
+    /// At most one of `distribution_id`, `tag_id` and `device_id`.
+    pub distribution_id: Option<i32>,
+    pub tag_id: Option<i32>,
+    pub device_id: Option<i32>,
+    /// Required for tag fragments, unique among them. A device with several
+    /// tags gets the fragment with the highest priority merged last.
+    pub priority: Option<i32>,
+    /// TOML with any of `[[check]]`, `[[metric]]`, `[police]`, `[logs]`
+    /// and `[downloads]`.
+    pub config: String,
+}
+
+/// The agent configuration a device should run and the one it does.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct DeviceAgentConfig {
+    /// Every fragment of the device merged, global first, tags by priority
+    /// and the device fragment last.
+    pub config: String,
+    pub version: String,
+    /// Version the device last reported running.
+    pub running_version: Option<String>,
+}
@@ -0,0 +1,331 @@
+use crate::config::Config;
+use crate::storage::Storage;
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::{Compression, write::GzEncoder};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use sqlx::types::chrono::Utc;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// Every release is published as its own repository with a single suite,
+/// devices use `deb <api>/smith/releases/<id>/apt smith main`.
+pub const SUITE: &str = "smith";
+const COMPONENT: &str = "main";
+
+/// Publishing the same release twice at once could leave the older index in
+/// place, so releases are published one after the other.
+static PUBLISHING: Mutex<()> = Mutex::const_new(());
+
+/// Where the repository of a release is kept in the packages bucket.
+pub fn release_prefix(release_id: i32) -> String {
+    format!("apt/{release_id}")
+}
+
+/// The control file of a .deb as a Packages index paragraph.
+pub fn control_paragraph(control: &debpkg::Control) -> String {
+    let mut paragraph = String::new();
+    for tag in control.tags() {
+        let Some(value) = control.get(tag) else {
+            continue;
+        };
+        paragraph.push_str(&format!("{tag}: {value}\n"));
+        if tag.eq_ignore_ascii_case("Description") {
+            for line in control.long_description().unwrap_or_default().lines() {
+                paragraph.push_str(&format!(" {line}\n"));
+            }
+        }
+    }
+    paragraph
+}
+
+/// Keeps what the Packages index needs from an uploaded package, so
+/// publishing a release doesn't have to download its packages again.
+pub async fn index_package(
+    package_id: i32,
+    control: &str,
+    data: &[u8],
+    pool: &PgPool,
+) -> Result<()> {
+    sqlx::query!(
+        "
+        INSERT INTO package_index (package_id, control, size, sha256)
+        VALUES ($1, $2, $3, $4)
+        ",
+        package_id,
+        control,
+        data.len() as i64,
+        format!("{:x}", Sha256::digest(data))
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Indexes a package uploaded before packages were indexed at upload, from
+/// the .deb in the packages bucket.
+pub async fn backfill(
+    package_id: i32,
+    file: &str,
+    config: &'static Config,
+    pool: &PgPool,
+) -> Result<(String, i64, String)> {
+    let data = Storage::load_from_s3(&config.packages_bucket_name, file).await?;
+    let mut pkg = debpkg::DebPkg::parse(std::io::Cursor::new(&data))
+        .map_err(|err| anyhow!("failed to parse {file}: {err}"))?;
+    let control_tar = pkg
+        .control()
+        .map_err(|err| anyhow!("failed to read the control of {file}: {err}"))?;
+    let control = debpkg::Control::extract(control_tar)
+        .map_err(|err| anyhow!("failed to read the control of {file}: {err}"))?;
+    let control = control_paragraph(&control);
+
+    index_package(package_id, &control, &data, pool).await?;
+    info!("Indexed {} for the apt repositories", file);
+
+    Ok((
+        control,
+        data.len() as i64,
+        format!("{:x}", Sha256::digest(&data)),
+    ))
+}
+
+/// Publishes the release again in the background, once its packages changed.
+pub fn republish(release_id: i32, config: &'static Config, pool: PgPool) {
+    if config.apt_signing_key_path.is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(err) = publish(release_id, config, &pool).await {
+            error!(
+                "error: failed to publish apt repository for release {}: {:?}",
+                release_id, err
+            );
+        }
+    });
+}
+
+/// Generates and signs the Packages, Release and InRelease files of a release.
+pub async fn publish(release_id: i32, config: &'static Config, pool: &PgPool) -> Result<()> {
+    let key_path = config
+        .apt_signing_key_path
+        .as_deref()
+        .ok_or_else(|| anyhow!("APT_SIGNING_KEY_PATH is not configured"))?;
+
+    let _publishing = PUBLISHING.lock().await;
+
+    let release = sqlx::query!(
+        "
+        SELECT release.version, distribution.name, distribution.architecture
+        FROM release
+        JOIN distribution ON release.distribution_id = distribution.id
+        WHERE release.id = $1
+        ",
+        release_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let packages = sqlx::query!(
+        r#"
+        SELECT package.id, package.name, package.version, package.architecture, package.file,
+        package_index.control AS "control?",
+        package_index.size AS "size?",
+        package_index.sha256 AS "sha256?"
+        FROM release_packages
+        JOIN package ON package.id = release_packages.package_id
+        LEFT JOIN package_index ON package_index.package_id = package.id
+        WHERE release_packages.release_id = $1
+        ORDER BY package.name
+        "#,
+        release_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut indexed = HashMap::new();
+    for package in &packages {
+        let index = match (&package.control, package.size, &package.sha256) {
+            (Some(control), Some(size), Some(sha256)) => (control.clone(), size, sha256.clone()),
+            _ => match backfill(package.id, &package.file, config, pool).await {
+                Ok(index) => index,
+                Err(err) => {
+                    warn!(
+                        "{} {} is left out of release {}, it could not be indexed: {:?}",
+                        package.name, package.version, release_id, err
+                    );
+                    continue;
+                }
+            },
+        };
+        indexed.insert(package.id, index);
+    }
+
+    let mut architectures = BTreeSet::from([release.architecture.clone()]);
+    architectures.extend(
+        packages
+            .iter()
+            .filter(|package| package.architecture != "all")
+            .map(|package| package.architecture.clone()),
+    );
+
+    let mut files = Vec::new();
+    for architecture in &architectures {
+        let mut index = String::new();
+        for package in &packages {
+            if package.architecture != *architecture && package.architecture != "all" {
+                continue;
+            }
+            let Some((control, size, sha256)) = indexed.get(&package.id) else {
+                continue;
+            };
+
+            if !index.is_empty() {
+                index.push('\n');
+            }
+            index.push_str(control);
+            index.push_str(&format!(
+                "Filename: pool/{}\nSize: {}\nSHA256: {}\n",
+                package.file, size, sha256
+            ));
+        }
+
+        let path = format!("{COMPONENT}/binary-{architecture}/Packages");
+        let mut compressed = GzEncoder::new(Vec::new(), Compression::default());
+        compressed.write_all(index.as_bytes())?;
+        files.push((format!("{path}.gz"), compressed.finish()?));
+        files.push((path, index.into_bytes()));
+    }
+
+    let mut release_file = format!(
+        "Origin: Smith\n\
+         Label: {name}\n\
+         Suite: {SUITE}\n\
+         Codename: {SUITE}\n\
+         Version: {version}\n\
+         Date: {date}\n\
+         Architectures: {architectures}\n\
+         Components: {COMPONENT}\n\
+         Description: {name} {version}\n\
+         SHA256:\n",
+        name = release.name,
+        version = release.version,
+        date = Utc::now().format("%a, %d %b %Y %H:%M:%S UTC"),
+        architectures = architectures.into_iter().collect::<Vec<_>>().join(" "),
+    );
+    for (path, data) in &files {
+        release_file.push_str(&format!(
+            " {:x} {:>16} {}\n",
+            Sha256::digest(data),
+            data.len(),
+            path
+        ));
+    }
+
+    let signer = Signer::new(key_path).await?;
+    let in_release = signer.clearsign(release_file.as_bytes()).await?;
+    let release_signature = signer.detach_sign(release_file.as_bytes()).await?;
+
+    // indices first, so the signed Release never lists files that aren't there yet
+    let dists = format!("{}/dists/{SUITE}", release_prefix(release_id));
+    for (path, data) in &files {
+        Storage::save_to_s3(&config.packages_bucket_name, Some(&dists), path, data).await?;
+    }
+    for (path, data) in [
+        ("Release", release_file.into_bytes()),
+        ("Release.gpg", release_signature),
+        ("InRelease", in_release),
+    ] {
+        Storage::save_to_s3(&config.packages_bucket_name, Some(&dists), path, &data).await?;
+    }
+
+    info!(
+        "Published apt repository for release {} with {} packages",
+        release_id,
+        packages.len()
+    );
+
+    Ok(())
+}
+
+/// The armored public key devices verify the repositories with.
+pub async fn public_key(config: &'static Config) -> Result<Vec<u8>> {
+    let key_path = config
+        .apt_signing_key_path
+        .as_deref()
+        .ok_or_else(|| anyhow!("APT_SIGNING_KEY_PATH is not configured"))?;
+
+    Signer::new(key_path).await?.public_key().await
+}
+
+/// Signs with gpg, from a keyring of its own that is removed once dropped.
+struct Signer {
+    home: tempfile::TempDir,
+}
+
+impl Signer {
+    async fn new(key_path: &str) -> Result<Self> {
+        let home = tempfile::tempdir()?;
+        gpg(home.path(), &["--import", key_path], None).await?;
+        Ok(Self { home })
+    }
+
+    async fn clearsign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        gpg(
+            self.home.path(),
+            &["--digest-algo", "SHA256", "--clearsign"],
+            Some(data),
+        )
+        .await
+    }
+
+    async fn detach_sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        gpg(
+            self.home.path(),
+            &["--digest-algo", "SHA256", "--armor", "--detach-sign"],
+            Some(data),
+        )
+        .await
+    }
+
+    async fn public_key(&self) -> Result<Vec<u8>> {
+        gpg(self.home.path(), &["--armor", "--export"], None).await
+    }
+}
+
+async fn gpg(home: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = Command::new("gpg")
+        .arg("--homedir")
+        .arg(home)
+        .args(["--batch", "--yes", "--pinentry-mode", "loopback"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run gpg")?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("gpg has no stdin"))?;
+    if let Some(input) = input {
+        stdin.write_all(input).await?;
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!("gpg failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    Ok(output.stdout)
+}
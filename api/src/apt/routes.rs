@@ -0,0 +1,143 @@
+use crate::State;
+use crate::apt::repository;
+use crate::db::DeviceWithToken;
+use crate::handlers::device_from_token;
+use axum::body::Body;
+use axum::extract::{FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, async_trait, http::StatusCode};
+use axum_extra::{
+    TypedHeader,
+    headers::{
+        Authorization,
+        authorization::{Basic, Bearer},
+    },
+};
+use futures::TryStreamExt;
+use s3::error::S3Error;
+use s3::{Bucket, creds::Credentials};
+use std::error::Error;
+use tracing::error;
+
+/// A device fetching from its repository. apt can only send the token as
+/// the password of basic auth, from auth.conf, so unlike everywhere else
+/// basic auth is accepted here.
+#[derive(Debug)]
+pub struct AptDevice;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AptDevice
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+            .await
+            .is_ok()
+        {
+            return DeviceWithToken::from_request_parts(parts, state)
+                .await
+                .map(|_| Self);
+        }
+
+        let TypedHeader(Authorization(basic)) =
+            TypedHeader::<Authorization<Basic>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| (StatusCode::UNAUTHORIZED,).into_response())?;
+
+        device_from_token(parts, basic.password()).await?;
+        Ok(Self)
+    }
+}
+
+/// Streams an object of the packages bucket, apt authenticates to the API
+/// but not to the bucket so it can't follow a redirect there.
+async fn stream(state: &State, key: &str) -> Result<Response, StatusCode> {
+    let region = state.config.aws_region.parse().map_err(|err| {
+        error!("error: failed to parse AWS region: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let credentials = Credentials::default().map_err(|err| {
+        error!("error: failed to get AWS credentials: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let bucket =
+        Bucket::new(&state.config.packages_bucket_name, region, credentials).map_err(|err| {
+            error!("error: failed to open packages bucket: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let stream = bucket
+        .get_object_stream(key)
+        .await
+        .map_err(|err| match err {
+            S3Error::HttpFailWithBody(404, _) => StatusCode::NOT_FOUND,
+            err => {
+                error!("error: failed to get {}: {:?}", key, err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+    let stream = stream
+        .bytes
+        .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync + 'static>);
+
+    Ok(Response::new(Body::from_stream(stream)).into_response())
+}
+
+#[tracing::instrument]
+pub async fn release_dists(
+    _device: AptDevice,
+    Path((release_id, path)): Path<(i32, String)>,
+    Extension(state): Extension<State>,
+) -> Result<Response, StatusCode> {
+    let key = format!("{}/dists/{}", repository::release_prefix(release_id), path);
+    stream(&state, &key).await
+}
+
+#[tracing::instrument]
+pub async fn release_pool(
+    _device: AptDevice,
+    Path((release_id, file)): Path<(i32, String)>,
+    Extension(state): Extension<State>,
+) -> Result<Response, StatusCode> {
+    // only the packages of the release are in its pool
+    let file = sqlx::query_scalar!(
+        "
+        SELECT package.file FROM release_packages
+        JOIN package ON package.id = release_packages.package_id
+        WHERE release_packages.release_id = $1 AND package.file = $2
+        ",
+        release_id,
+        file
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("error: failed to get package {}: {:?}", file, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    stream(&state, &file).await
+}
+
+#[tracing::instrument]
+pub async fn signing_key(
+    _device: AptDevice,
+    Extension(state): Extension<State>,
+) -> Result<Response, StatusCode> {
+    if state.config.apt_signing_key_path.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let key = repository::public_key(state.config).await.map_err(|err| {
+        error!("error: failed to export the apt signing key: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(([("content-type", "application/pgp-keys")], key).into_response())
+}
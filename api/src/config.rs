@@ -1,3 +1,4 @@
+use crate::secret::Secrets;
 use anyhow::Context;
 use axum::http::HeaderMap;
 use std::env;
@@ -43,6 +44,11 @@ pub struct Config {
     pub sentry_url: Option<String>,
     pub slack_hook_url: Option<String>,
     pub victoria_metrics_client: Option<VictoriaMetricsClient>,
+    pub secrets: Secrets,
+    /// How long device logs are kept, as a Postgres interval.
+    pub log_retention: Option<String>,
+    /// File with the armored private key release apt repositories are signed with.
+    pub apt_signing_key_path: Option<String>,
 }
 
 impl Config {
@@ -59,6 +65,9 @@ impl Config {
             sentry_url: env::var("SENTRY_URL").ok(),
             slack_hook_url: env::var("SLACK_HOOK_URL").ok(),
             victoria_metrics_client: VictoriaMetricsClient::new(),
+            secrets: Secrets::from_env()?,
+            log_retention: env::var("LOG_RETENTION").ok(),
+            apt_signing_key_path: env::var("APT_SIGNING_KEY_PATH").ok(),
         })
     }
 }
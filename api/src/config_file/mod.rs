@@ -0,0 +1,152 @@
+use crate::config_file::schema::{ConfigFile, NewConfigFile};
+use crate::handlers::devices::helpers;
+use anyhow::{Result, anyhow};
+use smith::utils::schema::ConfigFile as DeviceConfigFile;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use tracing::error;
+
+pub mod routes;
+pub mod schema;
+
+impl ConfigFile {
+    /// Renders every file that applies to the device, either globally or
+    /// through the distribution of its release. Files that fail to render are
+    /// left out, so one bad template doesn't hold back the others.
+    pub async fn render_for_device(device_id: i32, pool: &PgPool) -> Result<Vec<DeviceConfigFile>> {
+        let files = sqlx::query_as!(
+            ConfigFile,
+            r#"
+            SELECT cf.*
+            FROM config_file cf
+            WHERE cf.distribution_id IS NULL
+               OR cf.distribution_id = (
+                   SELECT r.distribution_id
+                   FROM device d
+                   JOIN release r ON r.id = d.release_id
+                   WHERE d.id = $1
+               )
+            ORDER BY cf.path, cf.distribution_id NULLS FIRST
+            "#,
+            device_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let variables = helpers::variables_for_device(pool, device_id).await?;
+
+        // a distribution file replaces a global one at the same path
+        let mut rendered: Vec<DeviceConfigFile> = vec![];
+        for file in files {
+            let content = match render(&file.template, &variables) {
+                Ok(content) => content,
+                Err(err) => {
+                    error!(
+                        "Failed to render config file {} for device {device_id}: {err}",
+                        file.path
+                    );
+                    continue;
+                }
+            };
+
+            rendered.retain(|other| other.path != file.path);
+            rendered.push(DeviceConfigFile {
+                path: file.path,
+                content,
+                mode: file.mode as u32,
+                owner: file.owner,
+                group: file.group,
+                reload: file.reload_units,
+                restart: file.restart_units,
+            });
+        }
+
+        Ok(rendered)
+    }
+
+    /// Devices that receive files of the given distribution, or all of them
+    /// for global files.
+    pub async fn affected_devices(distribution_id: Option<i32>, pool: &PgPool) -> Result<Vec<i32>> {
+        let devices = sqlx::query_scalar!(
+            "
+            SELECT d.id
+            FROM device d
+            LEFT JOIN release r ON r.id = d.release_id
+            WHERE d.archived = false
+              AND ($1::int4 IS NULL OR r.distribution_id = $1)
+            ",
+            distribution_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(devices)
+    }
+}
+
+impl NewConfigFile {
+    pub fn validate(&self) -> Result<()> {
+        if !self.path.starts_with('/') {
+            return Err(anyhow!("path must be absolute"));
+        }
+        if !(0..=0o7777).contains(&self.mode) {
+            return Err(anyhow!("mode must be between 0 and 0o7777"));
+        }
+        if self.owner.is_empty() || self.group.is_empty() {
+            return Err(anyhow!("owner and group can't be empty"));
+        }
+        if self
+            .reload_units
+            .iter()
+            .chain(&self.restart_units)
+            .any(|unit| unit.is_empty() || unit.starts_with('-'))
+        {
+            return Err(anyhow!("invalid unit name"));
+        }
+        // only the syntax is checked here, variables differ between devices
+        placeholders(&self.template)?;
+        Ok(())
+    }
+}
+
+/// Replaces every `{{ NAME }}` in the template with the variable `NAME`.
+/// Missing variables are an error rather than an empty string, so a device
+/// never ends up with a half configured file.
+fn render(template: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut content = String::with_capacity(template.len());
+    let mut last = 0;
+
+    for (start, name, end) in placeholders(template)? {
+        let value = variables
+            .get(name)
+            .ok_or_else(|| anyhow!("variable {name} is not set"))?;
+        content.push_str(&template[last..start]);
+        content.push_str(value);
+        last = end;
+    }
+    content.push_str(&template[last..]);
+
+    Ok(content)
+}
+
+/// Start offset, name and end offset of every placeholder in the template.
+fn placeholders(template: &str) -> Result<Vec<(usize, &str, usize)>> {
+    let mut found = vec![];
+    let mut position = 0;
+
+    while let Some(start) = template[position..].find("{{") {
+        let start = position + start;
+        let end = template[start..]
+            .find("}}")
+            .map(|end| start + end + 2)
+            .ok_or_else(|| anyhow!("unclosed placeholder at {start}"))?;
+        let name = template[start + 2..end - 2].trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!("invalid placeholder at {start}"));
+        }
+        found.push((start, name, end));
+        position = end;
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,278 @@
+use crate::State;
+use crate::config_file::schema::{ConfigFile, NewConfigFile};
+use crate::handlers::devices::helpers;
+use crate::middlewares::authorization;
+use crate::users::db::CurrentUser;
+use axum::{Extension, Json, extract::Path};
+use axum::{http::StatusCode, response::Result};
+use tracing::{error, warn};
+
+const TAG: &str = "config files";
+
+#[utoipa::path(
+    get,
+    path = "/config-files",
+    responses(
+        (status = StatusCode::OK, description = "List of config files", body = Vec<ConfigFile>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve config files"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_config_files(
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<ConfigFile>>, StatusCode> {
+    let files = sqlx::query_as!(
+        ConfigFile,
+        "SELECT * FROM config_file ORDER BY path, distribution_id NULLS FIRST"
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("error: failed to get config files: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(files))
+}
+
+#[utoipa::path(
+    post,
+    path = "/config-files",
+    request_body = NewConfigFile,
+    responses(
+        (status = StatusCode::CREATED, description = "Config file created", body = ConfigFile),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid config file"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage config files"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to create config file"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn create_config_file(
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(file): Json<NewConfigFile>,
+) -> Result<(StatusCode, Json<ConfigFile>), StatusCode> {
+    if !authorization::check(current_user, "config_files", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    file.validate().map_err(|err| {
+        warn!("Invalid config file {}: {err}", file.path);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let created = sqlx::query_as!(
+        ConfigFile,
+        r#"
+        INSERT INTO config_file
+            (distribution_id, path, template, mode, owner, "group", reload_units, restart_units)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING *
+        "#,
+        file.distribution_id,
+        file.path,
+        file.template,
+        file.mode,
+        file.owner,
+        file.group,
+        &file.reload_units,
+        &file.restart_units
+    )
+    .fetch_one(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to create config file: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    refresh_devices(vec![created.distribution_id], state.clone());
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/config-files/:config_file_id",
+    responses(
+        (status = StatusCode::OK, description = "Config file", body = ConfigFile),
+        (status = StatusCode::NOT_FOUND, description = "Config file not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve config file"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_config_file(
+    Path(config_file_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<ConfigFile>, StatusCode> {
+    let file = sqlx::query_as!(
+        ConfigFile,
+        "SELECT * FROM config_file WHERE id = $1",
+        config_file_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("error: failed to get config file: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(file))
+}
+
+#[utoipa::path(
+    put,
+    path = "/config-files/:config_file_id",
+    request_body = NewConfigFile,
+    responses(
+        (status = StatusCode::OK, description = "Config file updated", body = ConfigFile),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid config file"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage config files"),
+        (status = StatusCode::NOT_FOUND, description = "Config file not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to update config file"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn update_config_file(
+    Path(config_file_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(file): Json<NewConfigFile>,
+) -> Result<Json<ConfigFile>, StatusCode> {
+    if !authorization::check(current_user, "config_files", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    file.validate().map_err(|err| {
+        warn!("Invalid config file {}: {err}", file.path);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let previous = sqlx::query_scalar!(
+        "SELECT distribution_id FROM config_file WHERE id = $1",
+        config_file_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to fetch config file: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let updated = sqlx::query_as!(
+        ConfigFile,
+        r#"
+        UPDATE config_file SET
+            distribution_id = $2,
+            path = $3,
+            template = $4,
+            mode = $5,
+            owner = $6,
+            "group" = $7,
+            reload_units = $8,
+            restart_units = $9,
+            updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#,
+        config_file_id,
+        file.distribution_id,
+        file.path,
+        file.template,
+        file.mode,
+        file.owner,
+        file.group,
+        &file.reload_units,
+        &file.restart_units
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to update config file: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    refresh_devices(vec![previous, updated.distribution_id], state.clone());
+
+    Ok(Json(updated))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/config-files/:config_file_id",
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Config file deleted"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage config files"),
+        (status = StatusCode::NOT_FOUND, description = "Config file not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to delete config file"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn delete_config_file(
+    Path(config_file_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "config_files", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // the file stays on the devices, it just isn't managed anymore
+    let distribution_id = sqlx::query_scalar!(
+        "DELETE FROM config_file WHERE id = $1 RETURNING distribution_id",
+        config_file_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to delete config file: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    // a global file may have been shadowed by this one
+    if distribution_id.is_some() {
+        refresh_devices(vec![distribution_id], state.clone());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sends the freshly rendered files to every device the change touches.
+fn refresh_devices(distribution_ids: Vec<Option<i32>>, state: State) {
+    tokio::spawn(async move {
+        let pool = &state.pg_pool;
+        let mut devices = vec![];
+        for distribution_id in distribution_ids {
+            match ConfigFile::affected_devices(distribution_id, pool).await {
+                Ok(ids) => devices.extend(ids),
+                Err(err) => error!("Failed to get devices for config file refresh: {err}"),
+            }
+        }
+        devices.sort_unstable();
+        devices.dedup();
+
+        for device_id in devices {
+            if let Err(status) = helpers::refresh_device(pool, device_id).await {
+                error!("Failed to refresh config files of device {device_id}: {status}");
+            }
+        }
+    });
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ConfigFile {
+    pub id: i32,
+    /// Files without a distribution go to every device.
+    pub distribution_id: Option<i32>,
+    pub path: String,
+    pub template: String,
+    pub mode: i32,
+    pub owner: String,
+    pub group: String,
+    pub reload_units: Vec<String>,
+    pub restart_units: Vec<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct NewConfigFile {
+    pub distribution_id: Option<i32>,
+    pub path: String,
+    /// File content, `{{ NAME }}` is replaced with the device variable `NAME`.
+    pub template: String,
+    #[serde(default = "default_mode")]
+    pub mode: i32,
+    #[serde(default = "default_user")]
+    pub owner: String,
+    #[serde(default = "default_user")]
+    pub group: String,
+    #[serde(default)]
+    pub reload_units: Vec<String>,
+    #[serde(default)]
+    pub restart_units: Vec<String>,
+}
+
+fn default_mode() -> i32 {
+    0o644
+}
+
+fn default_user() -> String {
+    "root".to_string()
+}
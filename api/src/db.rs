@@ -1,15 +1,24 @@
-use crate::handlers::devices::types::Variable;
+use crate::agent_config;
+use crate::config_file::schema::ConfigFile;
+use crate::device::Device;
+use crate::handlers::devices::helpers;
+use crate::secret::{self, Secrets};
 use anyhow::Result;
 use serde_json::Value;
 use serde_json::json;
 use smith::utils::schema;
-use smith::utils::schema::SafeCommandTx::{UpdateNetwork, UpdateVariables};
-use smith::utils::schema::{HomePost, NetworkType, SafeCommandRequest, SafeCommandRx};
+use smith::utils::schema::SafeCommandTx::{UpdateConfigFiles, UpdateNetwork, UpdateVariables};
+use smith::utils::schema::{
+    CommandOutcome, HomePost, SafeCommandRequest, SafeCommandRx, SafeCommandTx,
+};
+use smith::utils::system;
 use sqlx::PgPool;
+use sqlx::types::Uuid;
+use std::collections::HashSet;
 use thiserror::Error;
 use tracing::{debug, error};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DeviceWithToken {
     pub id: i32,
     pub serial_number: String,
@@ -17,8 +26,10 @@ pub struct DeviceWithToken {
 
 pub struct CommandsDB {
     id: i32,
+    bundle: Uuid,
     cmd: Value,
     continue_on_error: bool,
+    signature: Option<Value>,
 }
 
 pub struct DBHandler;
@@ -56,38 +67,38 @@ impl DBHandler {
 
         for response in payload.responses {
             match response.command {
+                // for services that still source /root/.teton_environment
                 SafeCommandRx::GetVariables => {
-                    let variables = sqlx::query_as!(
-                        Variable,
-                        "
-                        SELECT id, device, name, value
-                        FROM variable
-                        WHERE device = $1
-                        ORDER BY device, name
-                        ",
-                        device.id
+                    let variables = helpers::variables_for_device(pool, device.id).await?;
+                    let secrets = helpers::secret_variable_names(pool, device.id).await?;
+                    DBHandler::add_commands(
+                        &device.serial_number,
+                        vec![SafeCommandRequest {
+                            id: -1,
+                            command: UpdateVariables { variables, secrets },
+                            continue_on_error: false,
+                            signature: None,
+                        }],
+                        pool,
                     )
-                    .fetch_all(&mut *tx)
                     .await?;
-                    let update_variables = UpdateVariables {
-                        variables: variables
-                            .into_iter()
-                            .map(|variable| (variable.name, variable.value))
-                            .collect(),
-                    };
+                }
+                SafeCommandRx::GetConfigFiles => {
+                    let files = ConfigFile::render_for_device(device.id, pool).await?;
                     DBHandler::add_commands(
                         &device.serial_number,
                         vec![SafeCommandRequest {
                             id: -1,
-                            command: update_variables,
+                            command: UpdateConfigFiles { files },
                             continue_on_error: false,
+                            signature: None,
                         }],
                         pool,
                     )
                     .await?;
                 }
                 SafeCommandRx::GetNetwork => {
-                    let network = sqlx::query_as!(
+                    let mut network = sqlx::query_as!(
                         schema::Network,
                         r#"
                         SELECT
@@ -97,7 +108,15 @@ impl DBHandler {
                             n.ssid,
                             n.name,
                             n.description,
-                            n.password
+                            n.password,
+                            n.secret,
+                            n.priority,
+                            n.eap_method,
+                            n.eap_identity,
+                            n.ipv4_address,
+                            n.ipv4_gateway,
+                            n.dns,
+                            n.apn
                         FROM network n
                         JOIN device d ON n.id = d.network_id
                         WHERE d.id = $1"#,
@@ -106,45 +125,153 @@ impl DBHandler {
                     .fetch_optional(&mut *tx)
                     .await?;
 
+                    if let Some(network) = network.as_mut().filter(|network| network.secret) {
+                        network.password = Some(secret::reference("network", network.id));
+                    }
+
                     if let Some(network) = network {
-                        if network.network_type == NetworkType::Wifi {
-                            DBHandler::add_commands(
-                                &device.serial_number,
-                                vec![SafeCommandRequest {
-                                    id: -4,
-                                    command: UpdateNetwork { network },
-                                    continue_on_error: false,
-                                }],
-                                pool,
-                            )
-                            .await?;
-                        }
+                        DBHandler::add_commands(
+                            &device.serial_number,
+                            vec![SafeCommandRequest {
+                                id: -4,
+                                command: UpdateNetwork { network },
+                                continue_on_error: false,
+                                signature: None,
+                            }],
+                            pool,
+                        )
+                        .await?;
                     }
                 }
                 SafeCommandRx::UpdateSystemInfo { ref system_info } => {
+                    Device::save_system_info(device, system_info, &mut tx).await?;
+                }
+                SafeCommandRx::SupportBundleCollected {
+                    profile,
+                    ref file,
+                    size,
+                } => {
+                    sqlx::query!(
+                        "
+                        INSERT INTO support_bundle (device_id, command_id, profile, file, size)
+                        VALUES ($1, CASE WHEN $2 < 0 THEN NULL ELSE $2 END, $3, $4, $5)
+                        ",
+                        device.id,
+                        response.id,
+                        format!("{profile:?}"),
+                        file,
+                        size as i64
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                SafeCommandRx::OtaStatus { ref attempt } => {
+                    sqlx::query!(
+                        "
+                        INSERT INTO ota_attempt (
+                            device_id, started_at, profile, phase, expected_version,
+                            previous_version, version, error
+                        )
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                        ON CONFLICT (device_id, started_at) DO UPDATE SET
+                            phase = EXCLUDED.phase,
+                            version = EXCLUDED.version,
+                            error = EXCLUDED.error,
+                            updated_at = NOW()
+                        ",
+                        device.id,
+                        attempt.started_at,
+                        attempt.profile,
+                        format!("{:?}", attempt.phase),
+                        attempt.expected_version,
+                        attempt.previous_version,
+                        attempt.version,
+                        attempt.error
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                SafeCommandRx::SelfUpdateStatus { ref attempt } => {
+                    let text = match &attempt.error {
+                        Some(error) => format!(
+                            "Self-update to smith {} {:?}: {}",
+                            attempt.version, attempt.phase, error
+                        ),
+                        None => format!(
+                            "Self-update to smith {} {:?}",
+                            attempt.version, attempt.phase
+                        ),
+                    };
+                    sqlx::query!(
+                        r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+                        device.id,
+                        "update",
+                        text
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                SafeCommandRx::AgentConfig { ref version, .. } => {
+                    sqlx::query!(
+                        "UPDATE device SET agent_config_version = $2 WHERE id = $1",
+                        device.id,
+                        version.as_deref()
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                    // reported on startup, catch up on what changed while it was away
+                    if response.id == -7 {
+                        agent_config::sync_device(device.id, version.as_deref(), &mut tx).await?;
+                    }
+                }
+                SafeCommandRx::Plugins { ref plugins } => {
                     sqlx::query!(
-                        "UPDATE device SET system_info = $2 WHERE id = $1",
+                        "UPDATE device SET plugins = $2 WHERE id = $1",
                         device.id,
-                        system_info
+                        plugins
                     )
-                    .execute(pool)
+                    .execute(&mut *tx)
                     .await?;
                 }
+                SafeCommandRx::SystemInfoChanged { ref changes } => {
+                    let mut system_info = sqlx::query_scalar!(
+                        "SELECT system_info FROM device WHERE id = $1 FOR UPDATE",
+                        device.id
+                    )
+                    .fetch_one(&mut *tx)
+                    .await?
+                    .unwrap_or_else(|| json!({}));
+                    system::merge(&mut system_info, changes.clone());
+
+                    Device::save_system_info(device, &system_info, &mut tx).await?;
+                }
                 _ => {}
             }
+            let result = response.result.as_ref();
             let _response_id = sqlx::query_scalar!(
-                "INSERT INTO command_response (device_id, command_id, response, status)
+                "INSERT INTO command_response
+                    (device_id, command_id, response, status, started_at, finished_at, duration_ms, outcome, error)
                 VALUES (
                     $1,
                     CASE WHEN $2 < 0 THEN NULL ELSE $2 END,
                     $3::jsonb,
-                    $4
+                    $4,
+                    $5,
+                    $6,
+                    $7,
+                    $8,
+                    $9
                 )
                 RETURNING id",
                 device.id,
                 response.id,
                 json!(response.command),
-                response.status
+                response.status,
+                result.map(|result| result.started_at),
+                result.map(|result| result.finished_at),
+                result.map(|result| result.duration_ms as i64),
+                result.map(|result| result.outcome) as Option<CommandOutcome>,
+                result.and_then(|result| result.error.clone())
             )
             .fetch_one(&mut *tx)
             .await?;
@@ -154,13 +281,22 @@ impl DBHandler {
         Ok(())
     }
 
-    pub async fn get_commands(device: &DeviceWithToken, pool: &PgPool) -> Vec<SafeCommandRequest> {
+    /// Commands queued for the device. Those it can't run, see
+    /// `SafeCommandTx::runs_on`, stay queued until it runs a smithd that
+    /// knows them, together with the rest of their bundle.
+    pub async fn get_commands(
+        device: &DeviceWithToken,
+        capabilities: Option<&[String]>,
+        pool: &PgPool,
+        secrets: &Secrets,
+    ) -> Vec<SafeCommandRequest> {
         if let Ok(mut tx) = pool.begin().await {
-            let fetched_commands: Vec<CommandsDB> = sqlx::query_as!(
+            let queued_commands: Vec<CommandsDB> = sqlx::query_as!(
                 CommandsDB,
-                "SELECT id, cmd, continue_on_error
+                "SELECT id, bundle, cmd, continue_on_error, signature
                  FROM command_queue
-                 WHERE device_id = $1 AND fetched = false AND canceled = false",
+                 WHERE device_id = $1 AND fetched = false AND canceled = false
+                 ORDER BY id",
                 device.id
             )
             .fetch_all(&mut *tx)
@@ -170,12 +306,70 @@ impl DBHandler {
                 Vec::new()
             });
 
+            let mut fetched = Vec::new();
+            let mut commands = Vec::new();
+            // a bundle runs in order, what comes after a held command waits
+            // for it
+            let mut held = HashSet::new();
+            for cmd in queued_commands {
+                if held.contains(&cmd.bundle) {
+                    continue;
+                }
+
+                let mut value = cmd.cmd;
+                let command: SafeCommandTx = match serde_json::from_value(value.clone()) {
+                    Ok(command) => command,
+                    Err(err) => {
+                        error!(
+                            serial_number = device.serial_number,
+                            "Failed to deserialize command from database: {err}"
+                        );
+                        fetched.push(cmd.id);
+                        continue;
+                    }
+                };
+                if !command.runs_on(capabilities) {
+                    debug!(
+                        serial_number = device.serial_number,
+                        "Holding command {} the device can't handle", cmd.id
+                    );
+                    held.insert(cmd.bundle);
+                    continue;
+                }
+
+                // secrets are only queued as references, see `secret::reference`
+                let command = match secrets
+                    .reveal_references(&mut value, device.id, command.name(), pool)
+                    .await
+                    .and_then(|()| Ok(serde_json::from_value(value)?))
+                {
+                    Ok(command) => command,
+                    Err(err) => {
+                        error!(
+                            serial_number = device.serial_number,
+                            "Failed to reveal the secrets of command {}: {err:?}", cmd.id
+                        );
+                        fetched.push(cmd.id);
+                        continue;
+                    }
+                };
+
+                fetched.push(cmd.id);
+                commands.push(SafeCommandRequest {
+                    id: cmd.id,
+                    command,
+                    continue_on_error: cmd.continue_on_error,
+                    signature: cmd
+                        .signature
+                        .and_then(|signature| serde_json::from_value(signature).ok()),
+                });
+            }
+
             // If commands are fetched successfully, update fetched_at timestamp
-            if !fetched_commands.is_empty() {
-                let ids: Vec<i32> = fetched_commands.iter().map(|cmd| cmd.id).collect();
+            if !fetched.is_empty() {
                 let _update_query = sqlx::query!(
                     "UPDATE command_queue SET fetched_at = CURRENT_TIMESTAMP, fetched = true WHERE id = ANY($1)",
-                    &ids
+                    &fetched
                 )
                 .execute(&mut *tx)
                 .await;
@@ -185,23 +379,7 @@ impl DBHandler {
                 error!("Failed to commit transaction: {err}");
             });
 
-            fetched_commands
-                .into_iter()
-                .filter_map(|cmd| match serde_json::from_value(cmd.cmd) {
-                    Ok(command) => Some(SafeCommandRequest {
-                        id: cmd.id,
-                        command,
-                        continue_on_error: cmd.continue_on_error,
-                    }),
-                    Err(err) => {
-                        error!(
-                            serial_number = device.serial_number,
-                            "Failed to deserialize command from database: {err}"
-                        );
-                        None
-                    }
-                })
-                .collect()
+            commands
         } else {
             Vec::new()
         }
@@ -0,0 +1,165 @@
+use crate::apt::repository;
+use crate::config::Config;
+use crate::storage::Storage;
+use anyhow::Result;
+use sqlx::PgPool;
+use std::io::Write;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// Deltas are made once per pair and downloaded by every device, so they
+/// are compressed as far as zstd goes.
+const LEVEL: i32 = 19;
+/// zstd can't reach back further than 2 GiB.
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// Generating deltas holds both packages in memory, one release at a time.
+static GENERATING: Mutex<()> = Mutex::const_new(());
+
+/// Generates the missing deltas of a release in the background, once its
+/// packages changed.
+pub fn prepare(release_id: i32, config: &'static Config, pool: PgPool) {
+    tokio::spawn(async move {
+        if let Err(err) = generate(release_id, config, &pool).await {
+            error!(
+                "error: failed to generate deltas for release {}: {:?}",
+                release_id, err
+            );
+        }
+    });
+}
+
+/// Makes a delta for every package of the release from the version of it
+/// in the release before, which is what most devices are running.
+pub async fn generate(release_id: i32, config: &'static Config, pool: &PgPool) -> Result<()> {
+    let _generating = GENERATING.lock().await;
+
+    // packages uploaded before they were indexed have no hash to pair them by
+    let unindexed = sqlx::query!(
+        "
+        SELECT package.id, package.file
+        FROM release_packages
+        JOIN release ON release.id = $1
+        JOIN package ON package.id = release_packages.package_id
+        LEFT JOIN package_index ON package_index.package_id = package.id
+        WHERE package_index.package_id IS NULL
+        AND release_packages.release_id IN (
+            release.id,
+            (
+                SELECT MAX(previous.id) FROM release previous
+                WHERE previous.distribution_id = release.distribution_id
+                AND previous.id < release.id
+                AND NOT previous.yanked
+            )
+        )
+        ",
+        release_id
+    )
+    .fetch_all(pool)
+    .await?;
+    for package in unindexed {
+        if let Err(err) = repository::backfill(package.id, &package.file, config, pool).await {
+            warn!("Failed to index {} for deltas: {:?}", package.file, err);
+        }
+    }
+
+    let pairs = sqlx::query!(
+        r#"
+        SELECT package.id, package.name, package.file,
+        base.id AS base_id, base.file AS base_file, base_index.sha256 AS base_sha256
+        FROM release_packages
+        JOIN release ON release.id = release_packages.release_id
+        JOIN package ON package.id = release_packages.package_id
+        JOIN package_index ON package_index.package_id = package.id
+        JOIN release_packages previous_packages ON previous_packages.release_id = (
+            SELECT MAX(previous.id) FROM release previous
+            WHERE previous.distribution_id = release.distribution_id
+            AND previous.id < release.id
+            AND NOT previous.yanked
+        )
+        JOIN package base ON base.id = previous_packages.package_id AND base.name = package.name
+        JOIN package_index base_index ON base_index.package_id = base.id
+        WHERE release_packages.release_id = $1
+        AND base_index.sha256 <> package_index.sha256
+        AND NOT EXISTS (
+            SELECT 1 FROM package_delta
+            WHERE package_delta.package_id = package.id
+            AND package_delta.base_package_id = base.id
+        )
+        "#,
+        release_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // one pair that fails doesn't hold back the deltas of the others
+    for pair in pairs {
+        let result = async {
+            let base = Storage::load_from_s3(&config.packages_bucket_name, &pair.base_file).await?;
+            let target = Storage::load_from_s3(&config.packages_bucket_name, &pair.file).await?;
+            let target_size = target.len();
+
+            let delta = tokio::task::spawn_blocking(move || diff(&base, &target)).await??;
+
+            // a delta saving less than a fifth isn't worth rebuilding the package for
+            if delta.len() * 5 > target_size * 4 {
+                info!(
+                    "Skipping delta for {}, {} of {} bytes",
+                    pair.name,
+                    delta.len(),
+                    target_size
+                );
+                return Ok(());
+            }
+
+            let file = format!("deltas/{}/{}.zst", pair.id, pair.base_sha256);
+            Storage::save_to_s3(&config.packages_bucket_name, None, &file, &delta).await?;
+            sqlx::query!(
+                "
+                INSERT INTO package_delta (package_id, base_package_id, base_sha256, file, size)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (package_id, base_package_id) DO NOTHING
+                ",
+                pair.id,
+                pair.base_id,
+                pair.base_sha256,
+                file,
+                delta.len() as i64
+            )
+            .execute(pool)
+            .await?;
+
+            info!(
+                "Generated delta for {}, {} of {} bytes",
+                pair.name,
+                delta.len(),
+                target_size
+            );
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            error!(
+                "error: failed to generate delta for {} from package {}: {:?}",
+                pair.name, pair.base_id, err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// zstd compression of `target` referencing `base`, like `zstd --patch-from`.
+fn diff(base: &[u8], target: &[u8]) -> Result<Vec<u8>> {
+    let size = base.len().max(target.len()).max(1) as u64;
+    let window_log = (u64::BITS - (size - 1).leading_zeros()).clamp(10, MAX_WINDOW_LOG);
+
+    let mut encoder = zstd::stream::Encoder::with_ref_prefix(Vec::new(), LEVEL, base)?;
+    encoder.window_log(window_log)?;
+    encoder.long_distance_matching(true)?;
+    encoder.include_checksum(true)?;
+    encoder.write_all(target)?;
+
+    Ok(encoder.finish()?)
+}
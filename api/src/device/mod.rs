@@ -2,8 +2,9 @@ use crate::config::Config;
 use crate::db::DeviceWithToken;
 pub(crate) use crate::device::schema::Device;
 use serde_json::{Value, json};
-use smith::utils::schema::{DeviceRegistration, DeviceRegistrationResponse};
-use sqlx::PgPool;
+use smith::utils::resources::Resources;
+use smith::utils::schema::{DeviceRegistration, DeviceRegistrationResponse, PendingRestart};
+use sqlx::{PgConnection, PgPool};
 use thiserror::Error;
 use tracing::error;
 
@@ -191,6 +192,59 @@ impl Device {
         Ok(())
     }
 
+    pub async fn save_pending_restart(
+        device: &DeviceWithToken,
+        restart: Option<PendingRestart>,
+        pool: &PgPool,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE device SET pending_restart = $1
+            WHERE id = $2 AND pending_restart IS DISTINCT FROM $1",
+            restart.map(|restart| json!(restart)),
+            device.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Both go back to NULL when the device is downgraded to a smithd that
+    /// doesn't negotiate capabilities.
+    pub async fn save_protocol(
+        device: &DeviceWithToken,
+        protocol_version: u32,
+        capabilities: Option<Vec<String>>,
+        pool: &PgPool,
+    ) -> anyhow::Result<()> {
+        let protocol_version = capabilities.as_ref().map(|_| protocol_version as i32);
+        sqlx::query!(
+            "UPDATE device SET protocol_version = $1, capabilities = $2
+            WHERE id = $3
+              AND (protocol_version IS DISTINCT FROM $1 OR capabilities IS DISTINCT FROM $2)",
+            protocol_version,
+            capabilities.as_deref(),
+            device.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Capabilities the device reported last, for the pings that leave
+    /// them out.
+    pub async fn get_capabilities(device: &DeviceWithToken, pool: &PgPool) -> Option<Vec<String>> {
+        sqlx::query_scalar!("SELECT capabilities FROM device WHERE id = $1", device.id)
+            .fetch_one(pool)
+            .await
+            .unwrap_or_else(|err| {
+                error!(
+                    "Failed to get capabilities of {}: {err}",
+                    device.serial_number
+                );
+                None
+            })
+    }
+
     pub async fn get_target_release(device: &DeviceWithToken, pool: &PgPool) -> Option<i32> {
         if let Ok(device) = sqlx::query!(
             "SELECT target_release_id FROM device WHERE id = $1",
@@ -245,6 +299,87 @@ impl Device {
         }
         Ok(())
     }
+
+    /// Stores the system info and keeps the filterable resource columns in sync.
+    pub async fn save_system_info(
+        device: &DeviceWithToken,
+        system_info: &Value,
+        conn: &mut PgConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE device SET system_info = $2 WHERE id = $1",
+            device.id,
+            system_info
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        // agents older than the resource metrics don't send them
+        let Some(resources) = system_info
+            .get("resources")
+            .and_then(|resources| serde_json::from_value::<Resources>(resources.clone()).ok())
+        else {
+            return Ok(());
+        };
+
+        let percent =
+            |used: u64, total: u64| (total > 0).then(|| used as f32 * 100.0 / total as f32);
+        let fullest = resources
+            .disks
+            .iter()
+            .filter_map(|disk| Some((disk, percent(disk.used, disk.total)?)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        let temperature = resources
+            .thermal_zones
+            .iter()
+            .map(|zone| zone.temperature)
+            .max_by(f32::total_cmp);
+
+        sqlx::query!(
+            "
+            INSERT INTO device_resources (
+                device_id, cpu_usage, load_average, memory_usage, swap_usage, disk_usage,
+                fullest_mount, temperature, throttling, gpu_usage, emc_usage, uptime, updated_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, NOW())
+            ON CONFLICT (device_id) DO UPDATE SET
+                cpu_usage = $2,
+                load_average = $3,
+                memory_usage = $4,
+                swap_usage = $5,
+                disk_usage = $6,
+                fullest_mount = $7,
+                temperature = $8,
+                throttling = $9,
+                gpu_usage = $10,
+                emc_usage = $11,
+                uptime = $12,
+                updated_at = NOW()
+            ",
+            device.id,
+            i16::from(resources.cpu_usage),
+            resources.load_average[0],
+            percent(resources.memory.used, resources.memory.total).unwrap_or_default(),
+            percent(resources.swap.used, resources.swap.total),
+            fullest.map(|(_, usage)| usage),
+            fullest.map(|(disk, _)| disk.mount.clone()),
+            temperature,
+            resources.thermal_zones.iter().any(|zone| zone.throttling),
+            resources
+                .tegrastats
+                .as_ref()
+                .map(|stats| i16::from(stats.gpu)),
+            resources
+                .tegrastats
+                .as_ref()
+                .map(|stats| i16::from(stats.emc)),
+            resources.uptime as i64
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug)]
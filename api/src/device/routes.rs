@@ -1 +1,80 @@
+use crate::State;
+use crate::device::schema::DeviceResources;
+use axum::extract::Query;
+use axum::{Extension, Json};
+use axum::{http::StatusCode, response::Result};
+use serde::Deserialize;
+use tracing::error;
 
+const TAG: &str = "devices";
+
+#[derive(Debug, Deserialize)]
+pub struct ResourcesFilter {
+    min_disk_usage: Option<f32>,
+    min_memory_usage: Option<f32>,
+    min_temperature: Option<f32>,
+    throttling: Option<bool>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/resources",
+    params(
+        ("min_disk_usage" = Option<f32>, Query, description = "Only devices whose fullest mount is at least this full, in percent"),
+        ("min_memory_usage" = Option<f32>, Query, description = "Only devices using at least this much memory, in percent"),
+        ("min_temperature" = Option<f32>, Query, description = "Only devices with a thermal zone at least this hot, in degrees Celsius"),
+        ("throttling" = Option<bool>, Query, description = "Only devices that are, or are not, thermally throttled"),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Latest resource usage of the matching devices", body = Vec<DeviceResources>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve resource usage"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_device_resources(
+    Extension(state): Extension<State>,
+    Query(filter): Query<ResourcesFilter>,
+) -> Result<Json<Vec<DeviceResources>>, StatusCode> {
+    let resources = sqlx::query_as!(
+        DeviceResources,
+        "
+        SELECT
+            r.device_id,
+            d.serial_number,
+            r.cpu_usage,
+            r.load_average,
+            r.memory_usage,
+            r.swap_usage,
+            r.disk_usage,
+            r.fullest_mount,
+            r.temperature,
+            r.throttling,
+            r.gpu_usage,
+            r.emc_usage,
+            r.uptime,
+            r.updated_at
+        FROM device_resources r
+        JOIN device d ON d.id = r.device_id
+        WHERE ($1::real IS NULL OR r.disk_usage >= $1)
+          AND ($2::real IS NULL OR r.memory_usage >= $2)
+          AND ($3::real IS NULL OR r.temperature >= $3)
+          AND ($4::boolean IS NULL OR r.throttling = $4)
+        ORDER BY d.serial_number
+        ",
+        filter.min_disk_usage,
+        filter.min_memory_usage,
+        filter.min_temperature,
+        filter.throttling
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("error: failed to get device resources: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(resources))
+}
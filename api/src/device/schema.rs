@@ -14,4 +14,37 @@ pub struct Device {
     pub target_release_id: Option<i32>,
     pub system_info: Option<serde_json::Value>,
     pub modem_id: Option<i32>,
+    /// Restart the device has scheduled, as of its last ping.
+    pub pending_restart: Option<serde_json::Value>,
+    /// Agent configuration the device runs, as of its last report.
+    pub agent_config_version: Option<String>,
+    /// Plugins the device can run, `None` before it reported any.
+    pub plugins: Option<Vec<String>>,
+    /// Protocol smithd speaks, `None` before it negotiated capabilities.
+    pub protocol_version: Option<i32>,
+    /// Commands smithd can handle, others are held in its queue.
+    pub capabilities: Option<Vec<String>>,
+}
+
+/// Latest resource usage reported by a device. Percentages go from 0 to 100.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct DeviceResources {
+    pub device_id: i32,
+    pub serial_number: String,
+    pub cpu_usage: i16,
+    /// One minute load average.
+    pub load_average: f32,
+    pub memory_usage: f32,
+    pub swap_usage: Option<f32>,
+    /// Usage of the fullest mount.
+    pub disk_usage: Option<f32>,
+    pub fullest_mount: Option<String>,
+    /// Hottest thermal zone, in degrees Celsius.
+    pub temperature: Option<f32>,
+    pub throttling: bool,
+    pub gpu_usage: Option<i16>,
+    pub emc_usage: Option<i16>,
+    /// Seconds since boot.
+    pub uptime: i64,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
 }
@@ -0,0 +1,109 @@
+use crate::download_limits::schema::DownloadLimits;
+use anyhow::{Result, anyhow};
+use smith::utils::schema::{DownloadLimits as DeviceDownloadLimits, SafeCommandTx};
+use sqlx::PgPool;
+use tracing::error;
+
+pub mod routes;
+pub mod schema;
+
+impl DownloadLimits {
+    pub fn validate(&self) -> Result<()> {
+        if [self.rate, self.metered_rate]
+            .into_iter()
+            .flatten()
+            .any(|rate| !rate.is_finite() || rate <= 0.0)
+        {
+            return Err(anyhow!("rates must be positive"));
+        }
+        Ok(())
+    }
+
+    /// Limits the device runs with, each rate is its own override or the
+    /// lowest one of its tags.
+    pub async fn for_device(device_id: i32, pool: &PgPool) -> Result<Option<Self>> {
+        let limits = sqlx::query_as!(
+            DownloadLimits,
+            r#"
+            SELECT
+                COALESCE(d.download_rate, MIN(t.download_rate)) AS rate,
+                COALESCE(d.download_metered_rate, MIN(t.download_metered_rate)) AS metered_rate
+            FROM device d
+            LEFT JOIN tag_device td ON td.device_id = d.id
+            LEFT JOIN tag t ON t.id = td.tag_id
+            WHERE d.id = $1
+            GROUP BY d.id
+            "#,
+            device_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(limits)
+    }
+
+    /// Devices that pick up the limits of the tag.
+    pub async fn tagged_devices(tag_id: i32, pool: &PgPool) -> Result<Vec<i32>> {
+        let devices = sqlx::query_scalar!(
+            "
+            SELECT d.id
+            FROM device d
+            JOIN tag_device td ON td.device_id = d.id
+            WHERE td.tag_id = $1 AND d.archived = false
+            ",
+            tag_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(devices)
+    }
+
+    /// Queues the current limits of the device for it to apply.
+    pub async fn send_to_device(device_id: i32, pool: &PgPool) -> Result<()> {
+        let limits = Self::for_device(device_id, pool)
+            .await?
+            .ok_or_else(|| anyhow!("device {device_id} not found"))?;
+        let command = SafeCommandTx::SetDownloadLimits {
+            limits: limits.into(),
+        };
+
+        let mut tx = pool.begin().await?;
+        let bundle_id =
+            sqlx::query!(r#"INSERT INTO command_bundles DEFAULT VALUES RETURNING uuid"#)
+                .fetch_one(&mut *tx)
+                .await?;
+        sqlx::query!(
+            r#"INSERT INTO command_queue (device_id, cmd, continue_on_error, canceled, bundle)
+            VALUES ($1, $2::jsonb, false, false, $3)"#,
+            device_id,
+            serde_json::to_value(command)?,
+            bundle_id.uuid
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+impl From<DownloadLimits> for DeviceDownloadLimits {
+    fn from(limits: DownloadLimits) -> Self {
+        Self {
+            rate: limits.rate,
+            metered_rate: limits.metered_rate,
+        }
+    }
+}
+
+/// Sends the new limits to every device the change touches.
+pub fn refresh_devices(device_ids: Vec<i32>, pool: PgPool) {
+    tokio::spawn(async move {
+        for device_id in device_ids {
+            if let Err(err) = DownloadLimits::send_to_device(device_id, &pool).await {
+                error!("Failed to send download limits to device {device_id}: {err}");
+            }
+        }
+    });
+}
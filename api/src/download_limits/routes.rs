@@ -0,0 +1,226 @@
+use crate::State;
+use crate::download_limits::refresh_devices;
+use crate::download_limits::schema::DownloadLimits;
+use crate::middlewares::authorization;
+use crate::users::db::CurrentUser;
+use axum::{Extension, Json, extract::Path};
+use axum::{http::StatusCode, response::Result};
+use tracing::{error, warn};
+
+const TAG: &str = "download limits";
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/download-limits",
+    responses(
+        (status = StatusCode::OK, description = "Limits the device downloads with, from its override or its tags", body = DownloadLimits),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve download limits"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_device_download_limits(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<DownloadLimits>, StatusCode> {
+    let limits = DownloadLimits::for_device(device_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get download limits of device {device_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(limits))
+}
+
+#[utoipa::path(
+    put,
+    path = "/devices/:device_id/download-limits",
+    request_body = DownloadLimits,
+    responses(
+        (status = StatusCode::OK, description = "Device override set, returns the resulting limits", body = DownloadLimits),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid download limits"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage devices"),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to set download limits"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn update_device_download_limits(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(limits): Json<DownloadLimits>,
+) -> Result<Json<DownloadLimits>, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    limits.validate().map_err(|err| {
+        warn!("Invalid download limits for device {device_id}: {err}");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    set_device_override(device_id, limits, &state).await?;
+
+    get_device_download_limits(Path(device_id), Extension(state)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/devices/:device_id/download-limits",
+    responses(
+        (status = StatusCode::OK, description = "Device override removed, returns the limits from its tags", body = DownloadLimits),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage devices"),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to remove download limits"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn delete_device_download_limits(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<DownloadLimits>, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    set_device_override(device_id, DownloadLimits::default(), &state).await?;
+
+    get_device_download_limits(Path(device_id), Extension(state)).await
+}
+
+async fn set_device_override(
+    device_id: i32,
+    limits: DownloadLimits,
+    state: &State,
+) -> Result<(), StatusCode> {
+    sqlx::query!(
+        "UPDATE device SET download_rate = $2, download_metered_rate = $3 WHERE id = $1",
+        device_id,
+        limits.rate,
+        limits.metered_rate
+    )
+    .execute(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to set download limits of device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+    .and_then(|result| match result.rows_affected() {
+        0 => Err(StatusCode::NOT_FOUND),
+        _ => Ok(()),
+    })?;
+
+    refresh_devices(vec![device_id], state.pg_pool.clone());
+
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/tags/:tag_id/download-limits",
+    request_body = DownloadLimits,
+    responses(
+        (status = StatusCode::OK, description = "Download limits of the tag set", body = DownloadLimits),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid download limits"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage devices"),
+        (status = StatusCode::NOT_FOUND, description = "Tag not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to set download limits"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn update_tag_download_limits(
+    Path(tag_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(limits): Json<DownloadLimits>,
+) -> Result<Json<DownloadLimits>, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    limits.validate().map_err(|err| {
+        warn!("Invalid download limits for tag {tag_id}: {err}");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    set_tag_limits(tag_id, &limits, &state).await?;
+
+    Ok(Json(limits))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/tags/:tag_id/download-limits",
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Download limits of the tag removed"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage devices"),
+        (status = StatusCode::NOT_FOUND, description = "Tag not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to remove download limits"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn delete_tag_download_limits(
+    Path(tag_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    set_tag_limits(tag_id, &DownloadLimits::default(), &state).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_tag_limits(
+    tag_id: i32,
+    limits: &DownloadLimits,
+    state: &State,
+) -> Result<(), StatusCode> {
+    sqlx::query!(
+        "UPDATE tag SET download_rate = $2, download_metered_rate = $3 WHERE id = $1",
+        tag_id,
+        limits.rate,
+        limits.metered_rate
+    )
+    .execute(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to set download limits of tag {tag_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+    .and_then(|result| match result.rows_affected() {
+        0 => Err(StatusCode::NOT_FOUND),
+        _ => Ok(()),
+    })?;
+
+    let devices = DownloadLimits::tagged_devices(tag_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get devices tagged {tag_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    refresh_devices(devices, state.pg_pool.clone());
+
+    Ok(())
+}
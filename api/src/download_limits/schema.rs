@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+/// Download caps in MB/s, `None` leaves the rate uncapped.
+#[derive(Debug, Default, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DownloadLimits {
+    pub rate: Option<f64>,
+    /// Used instead of `rate` while the default route is cellular.
+    pub metered_rate: Option<f64>,
+}
@@ -1,6 +1,8 @@
 pub mod types;
 
 use crate::State;
+use crate::tunnel::schema::DeviceTunnel;
+use crate::users::db::CurrentUser;
 use axum::{
     Extension, Json,
     extract::{Host, Query},
@@ -8,10 +10,14 @@ use axum::{
 use axum::{http::StatusCode, response::Result};
 use sqlx::types::Uuid;
 use std::collections::HashMap;
-use tracing::error;
+use tracing::{error, warn};
 
 use serde::Deserialize;
-use smith::utils::schema::SafeCommandTx;
+use smith::utils::schema::{
+    ArtifactChecksum, CommandOutcome, OtaChecksums, RestartAt, SafeCommandRequest, SafeCommandTx,
+    SupportBundleProfile,
+};
+use sqlx::PgPool;
 
 #[derive(Deserialize, Debug)]
 pub struct PaginationUuid {
@@ -20,33 +26,187 @@ pub struct PaginationUuid {
     pub limit: Option<i32>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct CommandFilter {
+    /// Only commands that ended like this, like `Timeout`.
+    pub outcome: Option<CommandOutcome>,
+}
+
 pub async fn get_commands() -> Result<Json<Vec<SafeCommandTx>>, StatusCode> {
     let commands = vec![
         SafeCommandTx::Ping,
         SafeCommandTx::Upgrade,
-        SafeCommandTx::Restart,
+        SafeCommandTx::Restart {
+            at: Some(RestartAt::LocalTime("03:00".to_string())),
+            reason: Some("Nightly maintenance".to_string()),
+            force: false,
+        },
+        SafeCommandTx::CancelRestart,
         SafeCommandTx::FreeForm {
             cmd: "echo 'Hello, World!'".to_string(),
         },
-        SafeCommandTx::OpenTunnel { port: None },
-        SafeCommandTx::CloseTunnel,
+        SafeCommandTx::OpenTunnel {
+            port: None,
+            ttl: None,
+            requested_by: None,
+        },
+        SafeCommandTx::CloseTunnel { port: None },
         SafeCommandTx::DownloadOTA {
-            tools: "ota_tools.tbz2".to_string(),
+            profile: Some("jetson".to_string()),
+            tools: Some("ota_tools.tbz2".to_string()),
             payload: "ota_payload_package.tar.gz".to_string(),
             rate: 1.0,
+            expected_version: Some("36.3.0".to_string()),
+            checksums: Some(OtaChecksums {
+                payload: ArtifactChecksum {
+                    size: 4_294_967_296,
+                    sha256: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+                        .to_string(),
+                },
+                tools: Some(ArtifactChecksum {
+                    size: 268_435_456,
+                    sha256: "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752"
+                        .to_string(),
+                }),
+            }),
         },
         SafeCommandTx::CheckOTAStatus,
-        SafeCommandTx::StartOTA,
+        SafeCommandTx::StartOTA {
+            profile: Some("jetson".to_string()),
+        },
+        SafeCommandTx::CollectSupportBundle {
+            profile: SupportBundleProfile::Standard,
+        },
+        SafeCommandTx::PauseDownloads,
+        SafeCommandTx::ResumeDownloads,
+        SafeCommandTx::Plugin {
+            name: "service-status".to_string(),
+            args: serde_json::json!({ "unit": "smithd" }),
+        },
     ];
 
     Ok(Json(commands))
 }
 
+/// Plugins only reach devices that reported having them, older smithd can't
+/// even parse the command.
+pub async fn validate_plugins(
+    devices: &[i32],
+    commands: &[SafeCommandRequest],
+    pool: &PgPool,
+) -> Result<(), StatusCode> {
+    let plugins: Vec<String> = commands
+        .iter()
+        .filter_map(|command| match &command.command {
+            SafeCommandTx::Plugin { name, .. } => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    if plugins.is_empty() {
+        return Ok(());
+    }
+
+    let missing = sqlx::query_scalar!(
+        "SELECT serial_number FROM device
+        WHERE id = ANY($1) AND NOT (COALESCE(plugins, '{}') @> $2::text[])",
+        devices,
+        &plugins
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to check device plugins {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if !missing.is_empty() {
+        warn!("Devices {:?} don't have plugins {:?}", missing, plugins);
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    Ok(())
+}
+
+/// An OTA is only verified against the checksums of its release, smithd
+/// refuses to download one without them.
+pub fn validate_ota(commands: &[SafeCommandRequest]) -> Result<(), StatusCode> {
+    let unverifiable = commands.iter().any(|command| {
+        matches!(
+            &command.command,
+            SafeCommandTx::DownloadOTA { tools, checksums, .. }
+                if checksums
+                    .as_ref()
+                    .is_none_or(|checksums| tools.is_some() && checksums.tools.is_none())
+        )
+    });
+
+    if unverifiable {
+        warn!("DownloadOTA without checksums for all of its artifacts");
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    Ok(())
+}
+
+/// Devices that negotiated capabilities only get commands their smithd
+/// knows, anything else would be held in the queue until it's upgraded.
+pub async fn validate_capabilities(
+    devices: &[i32],
+    commands: &[SafeCommandRequest],
+    pool: &PgPool,
+) -> Result<(), StatusCode> {
+    let mut names: Vec<String> = commands
+        .iter()
+        .map(|command| command.command.capability().to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    let unable = sqlx::query_scalar!(
+        "SELECT serial_number FROM device
+        WHERE id = ANY($1) AND capabilities IS NOT NULL AND NOT (capabilities @> $2::text[])",
+        devices,
+        &names
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to check device capabilities {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if !unable.is_empty() {
+        warn!("Devices {:?} can't handle all of {:?}", unable, names);
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    Ok(())
+}
+
 #[tracing::instrument]
 pub async fn issue_commands_to_devices(
     Extension(state): Extension<State>,
-    Json(bundle_commands): Json<types::BundleCommands>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(mut bundle_commands): Json<types::BundleCommands>,
 ) -> Result<StatusCode, StatusCode> {
+    validate_ota(&bundle_commands.commands)?;
+    validate_plugins(
+        &bundle_commands.devices,
+        &bundle_commands.commands,
+        &state.pg_pool,
+    )
+    .await?;
+    validate_capabilities(
+        &bundle_commands.devices,
+        &bundle_commands.commands,
+        &state.pg_pool,
+    )
+    .await?;
+
+    DeviceTunnel::sign_requests(&mut bundle_commands.commands, &current_user, &state.pg_pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
     let mut tx = state.pg_pool.begin().await.map_err(|err| {
         error!("Failed to start transaction {err}");
         StatusCode::INTERNAL_SERVER_ERROR
@@ -63,19 +223,21 @@ pub async fn issue_commands_to_devices(
     for device_id in &bundle_commands.devices {
         for command in &bundle_commands.commands {
             sqlx::query!(
-                r#"INSERT INTO command_queue (device_id, cmd, continue_on_error, canceled, bundle)
+                r#"INSERT INTO command_queue (device_id, cmd, continue_on_error, canceled, bundle, signature)
                 VALUES (
                     $1,
                     $2::jsonb,
                     $3,
                     false,
-                    $4
+                    $4,
+                    $5::jsonb
                 )"#,
                 device_id,
                 serde_json::to_value(command.command.clone())
                     .expect("error: failed to serialize command into JSON"),
                 command.continue_on_error,
-                bundle_id.uuid
+                bundle_id.uuid,
+                command.signature.as_ref().map(|signature| serde_json::json!(signature))
             )
             .execute(&mut *tx)
             .await
@@ -94,12 +256,24 @@ pub async fn issue_commands_to_devices(
     Ok(StatusCode::CREATED)
 }
 
+/// Keeps the bundles with a command that ended like `$2`, all of them when
+/// it is NULL. Commands cancelled before a device fetched them count as
+/// `cancelled`.
+const BUNDLE_OUTCOME_FILTER: &str = "($2::command_outcome IS NULL OR EXISTS (
+    SELECT 1
+    FROM command_queue q
+    LEFT JOIN command_response r ON q.id = r.command_id
+    WHERE q.bundle = command_bundles.uuid
+        AND (r.outcome = $2 OR ($2 = 'cancelled' AND q.canceled AND r.id IS NULL))
+))";
+
 #[allow(clippy::collapsible_else_if)]
 #[tracing::instrument]
 pub async fn get_bundle_commands(
     host: Host,
     Extension(state): Extension<State>,
     pagination: Query<PaginationUuid>,
+    filter: Query<CommandFilter>,
 ) -> Result<Json<types::BundleWithCommandsPaginated>, StatusCode> {
     if pagination.starting_after.is_some() && pagination.ending_before.is_some() {
         return Err(StatusCode::BAD_REQUEST);
@@ -114,16 +288,16 @@ pub async fn get_bundle_commands(
 
     let where_clause = if let Some(starting_after) = pagination.starting_after {
         format!(
-            "WHERE created_on <= (SELECT created_on FROM command_bundles WHERE uuid = '{}') ORDER BY created_on DESC",
+            "WHERE created_on <= (SELECT created_on FROM command_bundles WHERE uuid = '{}') AND {BUNDLE_OUTCOME_FILTER} ORDER BY created_on DESC",
             starting_after
         )
     } else if let Some(ending_before) = pagination.ending_before {
         format!(
-            "WHERE created_on > (SELECT created_on FROM command_bundles WHERE uuid = '{}') ORDER BY created_on ASC",
+            "WHERE created_on > (SELECT created_on FROM command_bundles WHERE uuid = '{}') AND {BUNDLE_OUTCOME_FILTER} ORDER BY created_on ASC",
             ending_before
         )
     } else {
-        "ORDER BY created_on DESC".to_string()
+        format!("WHERE {BUNDLE_OUTCOME_FILTER} ORDER BY created_on DESC")
     };
 
     let raw_bundles: Vec<types::BundleWithRawResponsesExplicit> = sqlx::query_as(&format!(
@@ -147,7 +321,12 @@ pub async fn get_bundle_commands(
             cr.id as response_id,
             cr.created_at as response_at,
             cr.response as response,
-            cr.status as status
+            cr.status as status,
+            cr.started_at as started_at,
+            cr.finished_at as finished_at,
+            cr.duration_ms as duration_ms,
+            cr.outcome as outcome,
+            cr.error as error
         FROM latest_bundles b
         LEFT JOIN command_queue cq ON b.uuid = cq.bundle
         LEFT JOIN command_response cr ON cq.id = cr.command_id
@@ -155,6 +334,7 @@ pub async fn get_bundle_commands(
         ORDER BY b.created_on DESC;"#,
     ))
     .bind(limit) // Bind the limit parameter
+    .bind(filter.outcome)
     .fetch_all(&mut *tx)
     .await
     .unwrap_or_default();
@@ -176,6 +356,11 @@ pub async fn get_bundle_commands(
             response_at: raw_bundle.response_at,
             response: raw_bundle.response,
             status: raw_bundle.status,
+            started_at: raw_bundle.started_at,
+            finished_at: raw_bundle.finished_at,
+            duration_ms: raw_bundle.duration_ms,
+            outcome: raw_bundle.outcome,
+            error: raw_bundle.error,
         };
 
         map_responses
@@ -189,13 +374,15 @@ pub async fn get_bundle_commands(
     let mut bundles: Vec<types::BundleWithCommands> = Vec::new();
 
     for (uuid, created_on) in map_responses.keys() {
+        let responses = map_responses
+            .get(&(*uuid, *created_on))
+            .expect("error: failed to get device command responses for (UUID, creation date)")
+            .clone();
         bundles.push(types::BundleWithCommands {
             uuid: *uuid,
             created_on: *created_on,
-            responses: map_responses
-                .get(&(*uuid, *created_on))
-                .expect("error: failed to get device command responses for (UUID, creation date)")
-                .clone(),
+            summary: types::BundleSummary::of(&responses),
+            responses,
         });
     }
 
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
-use smith::utils::schema::SafeCommandRequest;
+use smith::utils::schema::{CommandOutcome, SafeCommandRequest};
 use sqlx::types::{Uuid, chrono};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Serialize)]
 pub struct Command {
@@ -23,15 +24,54 @@ pub struct DeviceCommandResponse {
     pub response_at: Option<chrono::DateTime<chrono::Utc>>,
     pub response: Option<serde_json::Value>,
     pub status: Option<i32>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration_ms: Option<i64>,
+    /// Left out by agents that only report a status.
+    pub outcome: Option<CommandOutcome>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BundleWithCommands {
     pub uuid: Uuid,
     pub created_on: chrono::DateTime<chrono::Utc>,
+    pub summary: BundleSummary,
     pub responses: Vec<DeviceCommandResponse>,
 }
 
+/// How the commands of a bundle ended.
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct BundleSummary {
+    /// Not answered yet.
+    pub pending: usize,
+    /// Answered by agents that only report a status.
+    pub unknown: usize,
+    pub outcomes: BTreeMap<CommandOutcome, usize>,
+}
+
+impl BundleSummary {
+    pub fn of(responses: &[DeviceCommandResponse]) -> Self {
+        let mut summary = Self::default();
+        for response in responses {
+            let outcome = match (response.response_id, response.outcome) {
+                (Some(_), Some(outcome)) => outcome,
+                (Some(_), None) => {
+                    summary.unknown += 1;
+                    continue;
+                }
+                (None, _) if response.cancelled => CommandOutcome::Cancelled,
+                (None, _) => {
+                    summary.pending += 1;
+                    continue;
+                }
+            };
+            *summary.outcomes.entry(outcome).or_default() += 1;
+        }
+        summary
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct BundleWithCommandsPaginated {
     pub bundles: Vec<BundleWithCommands>,
@@ -62,6 +102,11 @@ pub struct BundleWithRawResponsesExplicit {
     pub response_at: Option<chrono::DateTime<chrono::Utc>>,
     pub response: Option<serde_json::Value>,
     pub status: Option<i32>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration_ms: Option<i64>,
+    pub outcome: Option<CommandOutcome>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -1,9 +1,78 @@
-use crate::handlers::devices::types::Variable;
+use crate::config_file::schema::ConfigFile;
+use crate::handlers::devices::types::NewVariable;
+use crate::secret::{self, Secrets};
 use axum::http::StatusCode;
 use smith::utils::schema::{SafeCommandRequest, SafeCommandTx};
 use sqlx::PgPool;
+use std::collections::HashMap;
 use tracing::error;
 
+/// Splits a variable into the plaintext and sealed values to store, secrets
+/// only keep the sealed one.
+pub fn seal_variable(
+    variable: &NewVariable,
+    secret: bool,
+    secrets: &Secrets,
+) -> Result<(String, Option<Vec<u8>>), StatusCode> {
+    if !secret {
+        return Ok((variable.value.clone(), None));
+    }
+
+    let sealed = secrets.seal(&variable.value).map_err(|err| {
+        error!("Failed to seal variable {}: {err}", variable.name);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((String::new(), Some(sealed)))
+}
+
+/// How a variable value shows up in the ledger.
+pub fn ledger_value(variable: &NewVariable, secret: bool) -> String {
+    if secret {
+        "<secret>".to_string()
+    } else {
+        format!("\"{}\"", variable.value)
+    }
+}
+
+/// Every variable of the device, secrets as references that are only
+/// revealed when the device fetches the command, see [`secret::reference`].
+pub async fn variables_for_device(
+    pool: &PgPool,
+    device_id: i32,
+) -> anyhow::Result<HashMap<String, String>> {
+    let variables = sqlx::query!(
+        "SELECT id, name, value, secret FROM variable WHERE device = $1",
+        device_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(variables
+        .into_iter()
+        .map(|variable| {
+            let value = match variable.secret {
+                true => secret::reference("variable", variable.id),
+                false => variable.value,
+            };
+            (variable.name, value)
+        })
+        .collect())
+}
+
+/// Names of the secret variables of the device, the device keeps their
+/// values out of support bundles.
+pub async fn secret_variable_names(pool: &PgPool, device_id: i32) -> anyhow::Result<Vec<String>> {
+    let names = sqlx::query_scalar!(
+        "SELECT name FROM variable WHERE device = $1 AND secret ORDER BY name",
+        device_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(names)
+}
+
 pub async fn refresh_device(pg_pool: &PgPool, device_id: i32) -> Result<StatusCode, StatusCode> {
     let mut tx = pg_pool.begin().await.map_err(|err| {
         error!("Failed to start transaction {err}");
@@ -18,49 +87,43 @@ pub async fn refresh_device(pg_pool: &PgPool, device_id: i32) -> Result<StatusCo
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let variables = sqlx::query_as!(
-        Variable,
-        r#"SELECT
-            id,
-            device,
-            name,
-            value
-        FROM variable
-        WHERE device = $1
-        ORDER BY device, name"#,
-        device_id
-    )
-    .fetch_all(&mut *tx)
-    .await
-    .map_err(|err| {
-        error!("Failed to get variables for device {err}");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let files = ConfigFile::render_for_device(device_id, pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to render config files for device {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let variables = variables_for_device(pg_pool, device_id)
+        .await
+        .map_err(|err| {
+            error!("Failed to get variables for device {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
-    let commands: Vec<SafeCommandRequest> = vec![
+    let secret_names = secret_variable_names(pg_pool, device_id)
+        .await
+        .map_err(|err| {
+            error!("Failed to get secret variables for device {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // services that source /root/.teton_environment keep getting it
+    let commands = vec![
         SafeCommandRequest {
-            id: -1,
+            id: 0,
             command: SafeCommandTx::UpdateVariables {
-                variables: variables
-                    .into_iter()
-                    .map(|variable| (variable.name, variable.value))
-                    .collect(),
+                variables,
+                secrets: secret_names,
             },
-            continue_on_error: false,
+            continue_on_error: true,
+            signature: None,
         },
         SafeCommandRequest {
-            id: -2,
-            command: SafeCommandTx::FreeForm {
-                cmd: "systemctl restart capture-and-detect".to_string(),
-            },
-            continue_on_error: false,
-        },
-        SafeCommandRequest {
-            id: -3,
-            command: SafeCommandTx::FreeForm {
-                cmd: "systemctl restart snakebrain".to_string(),
-            },
+            id: 0,
+            command: SafeCommandTx::UpdateConfigFiles { files },
             continue_on_error: false,
+            signature: None,
         },
     ];
 
@@ -1,5 +1,8 @@
 use super::distributions::types::Release;
 use crate::State;
+use crate::agent_config;
+use crate::download_limits;
+use crate::handlers::commands::CommandFilter;
 use crate::handlers::events::PublicEvent;
 use crate::middlewares::authorization;
 use crate::users::db::CurrentUser;
@@ -7,7 +10,7 @@ use axum::extract::Host;
 use axum::{Extension, Json, extract::Path};
 use axum::{http::StatusCode, response::Result};
 use axum_extra::extract::Query;
-use schema::SafeCommandRequest;
+use schema::{CommandOutcome, SafeCommandRequest};
 use serde::Deserialize;
 use sqlx::Row;
 use tracing::{debug, error};
@@ -16,6 +19,7 @@ pub mod types;
 use crate::device::Device;
 use crate::handlers::devices::types::DeviceHealth;
 use crate::handlers::distributions::db::db_get_release_by_id;
+use crate::tunnel::schema::DeviceTunnel;
 use smith::utils::schema;
 
 const DEVICES_TAG: &str = "devices";
@@ -302,7 +306,12 @@ pub async fn get_devices(
                 d.release_id,
                 d.target_release_id,
                 d.system_info,
-                d.modem_id
+                d.modem_id,
+                d.pending_restart,
+                d.agent_config_version,
+                d.plugins,
+                d.protocol_version,
+                d.capabilities
             FROM device d
             JOIN tag_device td ON d.id = td.device_id
             JOIN tag t ON td.tag_id = t.id
@@ -333,7 +342,12 @@ pub async fn get_devices(
             d.release_id,
             d.target_release_id,
             d.system_info,
-            d.modem_id
+            d.modem_id,
+            d.pending_restart,
+            d.agent_config_version,
+            d.plugins,
+            d.protocol_version,
+            d.capabilities
         FROM device d
         WHERE ($1::text IS NULL OR d.serial_number = $1)
           AND ($2::boolean IS NULL OR d.approved = $2)
@@ -384,7 +398,8 @@ pub async fn get_variables(
             id,
             device,
             name,
-            value
+            value,
+            secret
         FROM variable
         ORDER BY device, name"#
     )
@@ -517,6 +532,10 @@ pub async fn delete_tag_from_device(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
+    // the tag may have been capping its downloads or carrying agent config
+    download_limits::refresh_devices(vec![device_id], state.pg_pool.clone());
+    agent_config::refresh_devices(vec![device_id], state.pg_pool.clone());
+
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -564,6 +583,9 @@ pub async fn add_tag_to_device(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
+    download_limits::refresh_devices(vec![device_id], state.pg_pool.clone());
+    agent_config::refresh_devices(vec![device_id], state.pg_pool.clone());
+
     Ok(StatusCode::CREATED)
 }
 
@@ -621,10 +643,48 @@ pub async fn update_variable_for_device(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    let result = sqlx::query!(
-        r#"UPDATE variable SET name = $1, value = $2 WHERE device = $3 AND id = $4"#,
+    let Some(current) = sqlx::query!(
+        r#"
+        SELECT name, value, secret, sealed_value FROM variable
+        WHERE device = $1 AND id = $2
+        FOR UPDATE
+        "#,
+        device_id,
+        variable_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to get variable for device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    else {
+        return Ok(StatusCode::NOT_MODIFIED);
+    };
+
+    // a secret is sealed again whatever it is set to, telling whether it
+    // changed would let anyone confirm a guess of its value
+    let secret = variable.secret.unwrap_or(current.secret);
+    let unchanged_value = !secret && !current.secret && current.value == variable.value;
+    if unchanged_value && current.name == variable.name {
+        return Ok(StatusCode::NOT_MODIFIED);
+    }
+
+    let (value, sealed_value) = if unchanged_value {
+        (current.value, current.sealed_value)
+    } else {
+        helpers::seal_variable(&variable, secret, &state.config.secrets)?
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE variable SET name = $1, value = $2, secret = $3, sealed_value = $4
+        WHERE device = $5 AND id = $6
+        "#,
         variable.name,
-        variable.value,
+        value,
+        secret,
+        sealed_value,
         device_id,
         variable_id
     )
@@ -635,18 +695,24 @@ pub async fn update_variable_for_device(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    if result.rows_affected() == 0 {
-        return Ok(StatusCode::NOT_MODIFIED);
-    }
+    let text = if unchanged_value {
+        format!(
+            "Variable \"{}\" renamed to \"{}\".",
+            current.name, variable.name
+        )
+    } else {
+        format!(
+            "Variable \"{}\" updated with value {}.",
+            variable.name,
+            helpers::ledger_value(&variable, secret)
+        )
+    };
 
     sqlx::query!(
         r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
         device_id,
         "variable",
-        format!(
-            "Variable \"{}\" updated with value \"{}\".",
-            variable.name, variable.value
-        )
+        text
     )
     .execute(&mut *tx)
     .await
@@ -675,7 +741,8 @@ pub async fn get_variables_for_device(
             id,
             device,
             name,
-            value
+            value,
+            secret
         FROM variable
         WHERE device = $1
         ORDER BY device, name"#,
@@ -696,15 +763,23 @@ pub async fn add_variable_to_device(
     Extension(state): Extension<State>,
     Json(variable): Json<types::NewVariable>,
 ) -> Result<StatusCode, StatusCode> {
+    let secret = variable.secret.unwrap_or(false);
+    let (value, sealed_value) = helpers::seal_variable(&variable, secret, &state.config.secrets)?;
+
     let mut tx = state.pg_pool.begin().await.map_err(|err| {
         error!("Failed to start transaction {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
     let result = sqlx::query!(
-        r#"INSERT INTO variable (name, value, device) VALUES ($1, $2, $3)"#,
+        r#"
+        INSERT INTO variable (name, value, secret, sealed_value, device)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
         variable.name,
-        variable.value,
+        value,
+        secret,
+        sealed_value,
         device_id,
     )
     .execute(&mut *tx)
@@ -723,8 +798,9 @@ pub async fn add_variable_to_device(
         device_id,
         "variable",
         format!(
-            "Variable \"{}\" added with value \"{}\".",
-            variable.name, variable.value
+            "Variable \"{}\" added with value {}.",
+            variable.name,
+            helpers::ledger_value(&variable, secret)
         )
     )
     .execute(&mut *tx)
@@ -975,6 +1051,7 @@ pub async fn get_all_commands_for_device(
     Path(device_id): Path<String>,
     Extension(state): Extension<State>,
     pagination: Query<PaginationId>,
+    filter: Query<CommandFilter>,
 ) -> Result<Json<types::CommandsPaginated>, StatusCode> {
     if pagination.starting_after.is_some() && pagination.ending_before.is_some() {
         return Err(StatusCode::BAD_REQUEST);
@@ -1030,17 +1107,24 @@ pub async fn get_all_commands_for_device(
                 cr.id as "response_id?",
                 cr.created_at as "response_at?",
                 cr.response as "response?",
-                cr.status as "status?"
+                cr.status as "status?",
+                cr.started_at as "started_at?",
+                cr.finished_at as "finished_at?",
+                cr.duration_ms as "duration_ms?",
+                cr.outcome as "outcome?: CommandOutcome",
+                cr.error as "error?"
             FROM command_queue cq
             LEFT JOIN command_response cr ON cq.id = cr.command_id
             LEFT JOIN device d ON cq.device_id = d.id
             WHERE cq.device_id = $1
                 AND cq.id < $2
+                AND ($4::command_outcome IS NULL OR cr.outcome = $4)
             ORDER BY cq.created_at DESC
             LIMIT $3::int"#,
             device_id,
             starting_after,
-            limit
+            limit,
+            filter.outcome as Option<CommandOutcome>
         )
         .fetch_all(&mut *tx)
         .await
@@ -1059,17 +1143,24 @@ pub async fn get_all_commands_for_device(
                 cr.id as "response_id?",
                 cr.created_at as "response_at?",
                 cr.response as "response?",
-                cr.status as "status?"
+                cr.status as "status?",
+                cr.started_at as "started_at?",
+                cr.finished_at as "finished_at?",
+                cr.duration_ms as "duration_ms?",
+                cr.outcome as "outcome?: CommandOutcome",
+                cr.error as "error?"
             FROM command_queue cq
             LEFT JOIN command_response cr ON cq.id = cr.command_id
             LEFT JOIN device d ON cq.device_id = d.id
             WHERE cq.device_id = $1
                 AND cq.id > $2
+                AND ($4::command_outcome IS NULL OR cr.outcome = $4)
             ORDER BY cq.created_at ASC
             LIMIT $3::int"#,
             device_id,
             ending_before,
-            limit
+            limit,
+            filter.outcome as Option<CommandOutcome>
         )
         .fetch_all(&mut *tx)
         .await
@@ -1088,15 +1179,22 @@ pub async fn get_all_commands_for_device(
                 cr.id as "response_id?",
                 cr.created_at as "response_at?",
                 cr.response as "response?",
-                cr.status as "status?"
+                cr.status as "status?",
+                cr.started_at as "started_at?",
+                cr.finished_at as "finished_at?",
+                cr.duration_ms as "duration_ms?",
+                cr.outcome as "outcome?: CommandOutcome",
+                cr.error as "error?"
             FROM command_queue cq
             LEFT JOIN command_response cr ON cq.id = cr.command_id
             LEFT JOIN device d ON cq.device_id = d.id
             WHERE cq.device_id = $1
+                AND ($3::command_outcome IS NULL OR cr.outcome = $3)
             ORDER BY cq.created_at DESC
             LIMIT $2::int"#,
             device_id,
-            limit
+            limit,
+            filter.outcome as Option<CommandOutcome>
         )
         .fetch_all(&mut *tx)
         .await
@@ -1359,6 +1457,7 @@ pub async fn update_devices_target_release(
     responses(
         (status = StatusCode::CREATED, description = "Command successfully issue to device"),
         (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::UNPROCESSABLE_ENTITY, description = "Device doesn't have a plugin of the commands"),
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to issue command to device"),
     ),
     security(
@@ -1369,8 +1468,13 @@ pub async fn update_devices_target_release(
 pub async fn issue_commands_to_device(
     Path(device_id): Path<String>,
     Extension(state): Extension<State>,
-    Json(commands): Json<Vec<SafeCommandRequest>>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(mut commands): Json<Vec<SafeCommandRequest>>,
 ) -> Result<StatusCode, StatusCode> {
+    DeviceTunnel::sign_requests(&mut commands, &current_user, &state.pg_pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
     let mut tx = state.pg_pool.begin().await.map_err(|err| {
         error!("Failed to start transaction {err}");
         StatusCode::INTERNAL_SERVER_ERROR
@@ -1404,6 +1508,11 @@ pub async fn issue_commands_to_device(
         }
     };
 
+    crate::handlers::commands::validate_ota(&commands)?;
+    crate::handlers::commands::validate_plugins(&[device_id], &commands, &state.pg_pool).await?;
+    crate::handlers::commands::validate_capabilities(&[device_id], &commands, &state.pg_pool)
+        .await?;
+
     let bundle_id = sqlx::query!("INSERT INTO command_bundles DEFAULT VALUES RETURNING uuid")
         .fetch_one(&mut *tx)
         .await
@@ -1414,13 +1523,14 @@ pub async fn issue_commands_to_device(
 
     for command in commands {
         sqlx::query!(
-            "INSERT INTO command_queue (device_id, cmd, continue_on_error, canceled, bundle)
-            VALUES ($1, $2::jsonb, $3, false, $4)",
+            "INSERT INTO command_queue (device_id, cmd, continue_on_error, canceled, bundle, signature)
+            VALUES ($1, $2::jsonb, $3, false, $4, $5::jsonb)",
             device_id,
             serde_json::to_value(command.command)
                 .expect("error: failed to serialize device command"),
             command.continue_on_error,
-            bundle_id.uuid
+            bundle_id.uuid,
+            command.signature.map(|signature| serde_json::json!(signature))
         )
         .execute(&mut *tx)
         .await
@@ -1468,7 +1578,12 @@ pub async fn get_device_info(
         release_id,
         target_release_id,
         system_info,
-        modem_id
+        modem_id,
+        pending_restart,
+        agent_config_version,
+        plugins,
+        protocol_version,
+        capabilities
         FROM device
         WHERE
             CASE
@@ -1722,7 +1837,15 @@ pub async fn get_network_for_device(
             n.ssid,
             n.name,
             n.description,
-            n.password
+            n.password,
+            n.secret,
+            n.priority,
+            n.eap_method,
+            n.eap_identity,
+            n.ipv4_address,
+            n.ipv4_gateway,
+            n.dns,
+            n.apn
         FROM network n
         JOIN device d ON n.id = d.network_id
         WHERE d.serial_number = $1"#,
@@ -1,5 +1,6 @@
 use crate::handlers::distributions::types::Release;
 use serde::{Deserialize, Serialize};
+use smith::utils::schema::CommandOutcome;
 use sqlx::types::chrono;
 
 #[derive(Debug, Serialize, utoipa::ToSchema, sqlx::FromRow)]
@@ -42,13 +43,17 @@ pub struct Variable {
     pub id: i32,
     pub device: i32,
     pub name: String,
+    /// Always empty for secrets.
     pub value: String,
+    pub secret: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct NewVariable {
     pub name: String,
     pub value: String,
+    /// Keeps what the variable already is when absent, plaintext for new ones.
+    pub secret: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -65,6 +70,12 @@ pub struct DeviceCommandResponse {
     pub response_at: Option<chrono::DateTime<chrono::Utc>>,
     pub response: Option<serde_json::Value>,
     pub status: Option<i32>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration_ms: Option<i64>,
+    /// Left out by agents that only report a status.
+    pub outcome: Option<CommandOutcome>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -1,4 +1,6 @@
 use crate::State;
+use crate::apt::repository;
+use crate::delta;
 use crate::handlers::devices::types::{LeanDevice, LeanResponse};
 use crate::handlers::distributions::db::db_get_latest_distribution_release;
 use axum::{Extension, Json, extract::Path};
@@ -276,6 +278,8 @@ pub async fn create_distribution_release(
         error!("Failed to commit transaction: {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    repository::republish(release.id, state.config, state.pg_pool.clone());
+    delta::prepare(release.id, state.config, state.pg_pool.clone());
 
     Ok(Json(release.id))
 }
@@ -0,0 +1,115 @@
+//! Bodies exchanged with devices in the encoding they negotiate, JSON or
+//! CBOR, see `smith::utils::encoding`.
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::{HeaderValue, StatusCode, request::Parts};
+use axum::response::{IntoResponse, Response};
+use serde::{Serialize, de::DeserializeOwned};
+use smith::utils::encoding::Encoding;
+use std::convert::Infallible;
+use tracing::error;
+
+/// A body in the encoding its `Content-Type` names, JSON when it has none.
+pub struct Encoded<T>(pub T);
+
+/// Like [`Encoded`], `None` when the body is empty.
+pub struct MaybeEncoded<T>(pub Option<T>);
+
+/// Encoding the device asked to be answered in with `Accept`.
+pub struct Accepted(pub Encoding);
+
+/// A response in the encoding the device accepts.
+pub struct Reply<T>(pub Encoding, pub T);
+
+async fn decode<T, S>(req: Request, state: &S) -> Result<Option<T>, Response>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|content_type| content_type.to_str().unwrap_or_default().to_string());
+    let encoding = Encoding::from_content_type(content_type.as_deref())
+        .ok_or_else(|| StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response())?;
+
+    let bytes = Bytes::from_request(req, state)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+
+    encoding
+        .decode(&bytes)
+        .map(Some)
+        .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, format!("{err:#}")).into_response())
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Encoded<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        decode(req, state)
+            .await?
+            .map(Self)
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, "Empty body").into_response())
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for MaybeEncoded<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        decode(req, state).await.map(Self)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Accepted
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accept = parts
+            .headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok());
+        Ok(Self(Encoding::accepted(accept)))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Reply<T> {
+    fn into_response(self) -> Response {
+        let Reply(encoding, value) = self;
+        match encoding.encode(&value) {
+            Ok(bytes) => (
+                [(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static(encoding.content_type()),
+                )],
+                bytes,
+            )
+                .into_response(),
+            Err(err) => {
+                error!("Failed to encode response: {:?}", err);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
@@ -1,39 +1,64 @@
 use crate::State;
 use crate::db::{DBHandler, DeviceWithToken};
 use crate::device::RegistrationError;
+use crate::handlers::encoded::{Accepted, Encoded, MaybeEncoded, Reply};
+use crate::traffic::Traffic;
+use crate::tunnel::schema::DeviceTunnel;
 use axum::http::StatusCode;
 use axum::{Extension, Json};
 use smith::utils::schema::{
-    DeviceRegistration, DeviceRegistrationResponse, HomePost, HomePostResponse,
+    DeviceRegistration, DeviceRegistrationResponse, HomePost, HomePostResponse, PROTOCOL_VERSION,
 };
+use std::sync::Arc;
 use std::time::SystemTime;
 use tracing::{debug, error, info};
 
+/// An empty body is a heartbeat, the device has nothing new to report.
 #[tracing::instrument]
 pub async fn home(
     device: DeviceWithToken,
     Extension(state): Extension<State>,
-    Json(payload): Json<HomePost>,
-) -> (StatusCode, Json<HomePostResponse>) {
+    Accepted(encoding): Accepted,
+    traffic: Option<Extension<Arc<Traffic>>>,
+    MaybeEncoded(payload): MaybeEncoded<HomePost>,
+) -> (StatusCode, Reply<HomePostResponse>) {
+    let Some(mut payload) = payload else {
+        debug!("Received heartbeat from {}", device.serial_number);
+        if let Some(Extension(traffic)) = traffic {
+            traffic.heartbeat();
+        }
+        let capabilities = crate::device::Device::get_capabilities(&device, &state.pg_pool).await;
+        let response = answer(&device, capabilities.as_deref(), &state).await;
+
+        tokio::spawn(async move {
+            crate::device::Device::save_last_ping(&device, &state.pg_pool)
+                .await
+                .unwrap_or_else(|err| {
+                    error!("Error saving last ping: {:?}", err);
+                });
+        });
+
+        return (StatusCode::OK, Reply(encoding, response));
+    };
+
     debug!(
         "Received payload {:?} from {}",
         payload, device.serial_number
     );
 
     let release_id = payload.release_id;
+    let tunnels = std::mem::take(&mut payload.tunnels);
+    let restart = payload.restart.take();
+    let protocol_version = payload.protocol_version;
+    // agents from before the protocol can't have negotiated anything
+    let capabilities = payload.capabilities.take().filter(|_| protocol_version > 0);
     DBHandler::save_responses(&device, payload, &state.pg_pool)
         .await
         .unwrap_or_else(|err| {
             error!("Error saving responses: {:?}", err);
         });
 
-    let response = HomePostResponse {
-        timestamp: SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default(),
-        commands: DBHandler::get_commands(&device, &state.pg_pool).await,
-        target_release_id: crate::device::Device::get_target_release(&device, &state.pg_pool).await,
-    };
+    let response = answer(&device, capabilities.as_deref(), &state).await;
 
     tokio::spawn(async move {
         crate::device::Device::save_release_id(&device, release_id, &state.pg_pool)
@@ -46,15 +71,56 @@ pub async fn home(
             .unwrap_or_else(|err| {
                 error!("Error saving last ping: {:?}", err);
             });
+        DeviceTunnel::save_reported(&device, tunnels, &state.pg_pool)
+            .await
+            .unwrap_or_else(|err| {
+                error!("Error saving tunnels: {:?}", err);
+            });
+        crate::device::Device::save_pending_restart(&device, restart, &state.pg_pool)
+            .await
+            .unwrap_or_else(|err| {
+                error!("Error saving pending restart: {:?}", err);
+            });
+        crate::device::Device::save_protocol(
+            &device,
+            protocol_version,
+            capabilities,
+            &state.pg_pool,
+        )
+        .await
+        .unwrap_or_else(|err| {
+            error!("Error saving protocol: {:?}", err);
+        });
     });
 
-    (StatusCode::OK, Json(response))
+    (StatusCode::OK, Reply(encoding, response))
+}
+
+async fn answer(
+    device: &DeviceWithToken,
+    capabilities: Option<&[String]>,
+    state: &State,
+) -> HomePostResponse {
+    HomePostResponse {
+        timestamp: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default(),
+        commands: DBHandler::get_commands(
+            device,
+            capabilities,
+            &state.pg_pool,
+            &state.config.secrets,
+        )
+        .await,
+        target_release_id: crate::device::Device::get_target_release(device, &state.pg_pool).await,
+        protocol_version: PROTOCOL_VERSION,
+    }
 }
 
 #[tracing::instrument]
 pub async fn register_device(
     Extension(state): Extension<State>,
-    Json(payload): Json<DeviceRegistration>,
+    Encoded(payload): Encoded<DeviceRegistration>,
 ) -> (StatusCode, Json<DeviceRegistrationResponse>) {
     debug!("Registering device {:?}", payload);
 
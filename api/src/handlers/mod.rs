@@ -3,6 +3,7 @@ pub mod commands;
 pub mod devices;
 pub mod distributions;
 pub mod download;
+pub mod encoded;
 pub mod events;
 pub mod health;
 pub mod home;
@@ -14,6 +15,7 @@ pub mod upload;
 
 use crate::State;
 use crate::db::{AuthorizationError, DBHandler, DeviceWithToken};
+use crate::traffic::Traffic;
 use axum::body::Body;
 use axum::{
     Json, async_trait,
@@ -29,7 +31,7 @@ use futures::TryStreamExt;
 use s3::error::S3Error;
 use s3::{Bucket, creds::Credentials};
 use serde::Deserialize;
-use smith::utils::schema::Package;
+use smith::utils::schema::{Package, PackageDelta, ReleasePackage};
 use std::error::Error;
 use tracing::{debug, error};
 
@@ -48,28 +50,35 @@ where
                 .await
                 .map_err(|_| (StatusCode::UNAUTHORIZED,).into_response())?;
 
-        use axum::RequestPartsExt;
-        let Extension(state) = parts
-            .extract::<Extension<State>>()
-            .await
-            .map_err(|err| err.into_response())?;
-
-        let device = DBHandler::validate_token(bearer.token(), &state.pg_pool)
-            .await
-            .map_err(|auth_err| match auth_err {
-                AuthorizationError::UnauthorizedDevice => {
-                    (StatusCode::UNAUTHORIZED,).into_response()
-                }
-                AuthorizationError::DatabaseError(err) => {
-                    error!("Database error: {:?}", err);
-                    (StatusCode::INTERNAL_SERVER_ERROR,).into_response()
-                }
-            })?;
+        let device = device_from_token(parts, bearer.token()).await?;
+        Traffic::device(parts, &device);
 
         Ok(device) // Assuming `Self` can be created from a token
     }
 }
 
+/// Looks up the device the token was issued to.
+pub async fn device_from_token(
+    parts: &mut Parts,
+    token: &str,
+) -> Result<DeviceWithToken, Response> {
+    use axum::RequestPartsExt;
+    let Extension(state) = parts
+        .extract::<Extension<State>>()
+        .await
+        .map_err(|err| err.into_response())?;
+
+    DBHandler::validate_token(token, &state.pg_pool)
+        .await
+        .map_err(|auth_err| match auth_err {
+            AuthorizationError::UnauthorizedDevice => (StatusCode::UNAUTHORIZED,).into_response(),
+            AuthorizationError::DatabaseError(err) => {
+                error!("Database error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR,).into_response()
+            }
+        })
+}
+
 #[derive(Deserialize, Debug)]
 pub struct FetchPackageQuery {
     name: String,
@@ -128,15 +137,15 @@ pub async fn list_release_packages(
     device: DeviceWithToken,
     Path(release_id): Path<i32>,
     Extension(state): Extension<State>,
-) -> Result<Json<Vec<Package>>, Json<Vec<Package>>> {
-    let packages = sqlx::query_as!(
-        Package,
-        "
-        SELECT package.*
+) -> Result<Json<Vec<ReleasePackage>>, Json<Vec<ReleasePackage>>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT package.*, package_index.sha256 AS "sha256?"
         FROM release_packages
         JOIN package ON package.id = release_packages.package_id
+        LEFT JOIN package_index ON package_index.package_id = package.id
         WHERE release_packages.release_id = $1
-        ",
+        "#,
         release_id
     )
     .fetch_all(&state.pg_pool)
@@ -146,5 +155,52 @@ pub async fn list_release_packages(
         Json(vec![])
     })?;
 
+    let package_ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+    let deltas = sqlx::query!(
+        "
+        SELECT package_delta.package_id, package_delta.base_sha256, package_delta.file,
+        package_delta.size, base.version AS base_version
+        FROM package_delta
+        JOIN package base ON base.id = package_delta.base_package_id
+        WHERE package_delta.package_id = ANY($1)
+        ",
+        &package_ids
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get package deltas {err}");
+        Json(vec![])
+    })?;
+
+    let packages = rows
+        .into_iter()
+        .map(|row| ReleasePackage {
+            deltas: deltas
+                .iter()
+                .filter(|delta| delta.package_id == row.id)
+                .map(|delta| {
+                    (
+                        delta.base_sha256.clone(),
+                        PackageDelta {
+                            base_version: delta.base_version.clone(),
+                            file: delta.file.clone(),
+                            size: delta.size,
+                        },
+                    )
+                })
+                .collect(),
+            sha256: row.sha256,
+            package: Package {
+                id: Some(row.id),
+                name: row.name,
+                architecture: Some(row.architecture),
+                version: row.version,
+                file: row.file,
+                created_at: Some(row.created_at),
+            },
+        })
+        .collect();
+
     Ok(Json(packages))
 }
@@ -46,7 +46,15 @@ pub async fn get_networks(
                     n.ssid,
                     n.name,
                     n.description,
-                    n.password
+                    n.password,
+                    n.secret,
+                    n.priority,
+                    n.eap_method,
+                    n.eap_identity,
+                    n.ipv4_address,
+                    n.ipv4_gateway,
+                    n.dns,
+                    n.apn
                 FROM network n
                 JOIN device d ON n.id = d.network_id
                 WHERE d.serial_number = ANY($1)
@@ -67,7 +75,15 @@ pub async fn get_networks(
                     n.ssid,
                     n.name,
                     n.description,
-                    n.password
+                    n.password,
+                    n.secret,
+                    n.priority,
+                    n.eap_method,
+                    n.eap_identity,
+                    n.ipv4_address,
+                    n.ipv4_gateway,
+                    n.dns,
+                    n.apn
                 FROM network n
                 "#
             )
@@ -109,7 +125,15 @@ pub async fn get_network_by_id(
             network.ssid,
             network.name,
             network.description,
-            network.password
+            network.password,
+            network.secret,
+            network.priority,
+            network.eap_method,
+            network.eap_identity,
+            network.ipv4_address,
+            network.ipv4_gateway,
+            network.dns,
+            network.apn
         FROM network
         WHERE network.id = $1
         "#,
@@ -177,17 +201,41 @@ pub async fn create_network(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
+    let (password, sealed_password) = match new_network.password {
+        Some(password) if new_network.secret => {
+            let sealed = state.config.secrets.seal(&password).map_err(|err| {
+                error!("Failed to seal network password {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            (None, Some(sealed))
+        }
+        password => (password, None),
+    };
+
     let result = sqlx::query!(
         r#"
-        INSERT INTO network (network_type, is_network_hidden, ssid, name, description, password)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO network (
+            network_type, is_network_hidden, ssid, name, description, password, secret,
+            sealed_password, priority, eap_method, eap_identity, ipv4_address, ipv4_gateway,
+            dns, apn
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
         "#,
         new_network.network_type as NetworkType,
         new_network.is_network_hidden,
         new_network.ssid,
         new_network.name,
         new_network.description,
-        new_network.password,
+        password,
+        sealed_password.is_some(),
+        sealed_password,
+        new_network.priority,
+        new_network.eap_method,
+        new_network.eap_identity,
+        new_network.ipv4_address,
+        new_network.ipv4_gateway,
+        &new_network.dns,
+        new_network.apn,
     )
     .execute(&mut *tx)
     .await
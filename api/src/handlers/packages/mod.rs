@@ -1,6 +1,7 @@
 use std::io::{Cursor, Read};
 
 use crate::State;
+use crate::apt::repository;
 use crate::handlers::distributions::types::Package;
 use axum::{
     Extension, Json,
@@ -89,7 +90,7 @@ pub async fn release_package(
     debug!("Package Version: {}", control.version());
     debug!("Package Architecture: {}", arch);
 
-    Package::new(
+    let package = Package::new(
         control.name(),
         control.version(),
         arch,
@@ -103,6 +104,18 @@ pub async fn release_package(
         error!("error: Failed to save package: {:?}", err);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+
+    repository::index_package(
+        package.id,
+        &repository::control_paragraph(&control),
+        &buf,
+        &state.pg_pool,
+    )
+    .await
+    .map_err(|err| {
+        error!("error: Failed to index package: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
     Ok(StatusCode::OK)
 }
 
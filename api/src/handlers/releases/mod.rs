@@ -1,4 +1,6 @@
 use crate::State;
+use crate::apt::repository;
+use crate::delta;
 use crate::handlers::distributions;
 use crate::handlers::distributions::db::db_get_release_by_id;
 use axum::extract::Path;
@@ -179,6 +181,8 @@ pub async fn add_package_to_release(
         error!("Failed to add package {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    repository::republish(release_id, state.config, state.pg_pool.clone());
+    delta::prepare(release_id, state.config, state.pg_pool.clone());
 
     Ok(StatusCode::OK)
 }
@@ -268,6 +272,8 @@ pub async fn update_package_for_release(
         error!("Failed to update package {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    repository::republish(release_id, state.config, state.pg_pool.clone());
+    delta::prepare(release_id, state.config, state.pg_pool.clone());
     Ok(StatusCode::OK)
 }
 
@@ -313,6 +319,7 @@ pub async fn delete_package_for_release(
         error!("Failed to remove package {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    repository::republish(release_id, state.config, state.pg_pool.clone());
 
     Ok(StatusCode::NO_CONTENT)
 }
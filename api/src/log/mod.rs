@@ -0,0 +1,66 @@
+use crate::db::DeviceWithToken;
+use crate::log::schema::DeviceLog;
+use smith::utils::schema::LogEntry;
+use sqlx::PgPool;
+use tracing::error;
+
+pub mod routes;
+pub mod schema;
+
+impl DeviceLog {
+    pub async fn save_entries(
+        device: &DeviceWithToken,
+        entries: Vec<LogEntry>,
+        pool: &PgPool,
+    ) -> anyhow::Result<()> {
+        let mut timestamps = Vec::with_capacity(entries.len());
+        let mut units = Vec::with_capacity(entries.len());
+        let mut priorities = Vec::with_capacity(entries.len());
+        let mut messages = Vec::with_capacity(entries.len());
+        for entry in entries {
+            timestamps.push(entry.timestamp);
+            // older agents ship NUL bytes, which text columns refuse
+            units.push(entry.unit.map(|unit| unit.replace('\0', "")));
+            priorities.push(entry.priority);
+            messages.push(entry.message.replace('\0', ""));
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO device_log ("timestamp", device_id, unit, priority, message)
+            SELECT entry.timestamp, $1, entry.unit, entry.priority, entry.message
+            FROM UNNEST($2::timestamptz[], $3::text[], $4::int2[], $5::text[])
+                AS entry("timestamp", unit, priority, message)
+            "#,
+            device.id,
+            &timestamps,
+            &units as &[Option<String>],
+            &priorities,
+            &messages
+        )
+        .execute(pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to save logs for {}: {err}", device.serial_number);
+            anyhow::anyhow!("Failed to save logs")
+        })?;
+
+        Ok(())
+    }
+
+    /// Sets how long pg_partman keeps log partitions around, e.g. `30 days`.
+    pub async fn set_retention(retention: &str, pool: &PgPool) -> anyhow::Result<()> {
+        sqlx::query!(
+            "
+            UPDATE partman.part_config
+            SET retention = $1::text::interval::text
+            WHERE parent_table = 'public.device_log'
+            ",
+            retention
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use crate::State;
+use crate::log::schema::DeviceLog;
+use axum::extract::{Path, Query};
+use axum::{Extension, Json};
+use axum::{http::StatusCode, response::Result};
+use serde::Deserialize;
+use sqlx::types::chrono;
+use tracing::error;
+
+const TAG: &str = "logs";
+
+#[derive(Debug, Deserialize)]
+pub struct LogFilter {
+    unit: Option<String>,
+    level: Option<i16>,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    until: Option<chrono::DateTime<chrono::Utc>>,
+    search: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    500
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/logs",
+    params(
+        ("unit" = Option<String>, Query, description = "Only entries of this systemd unit"),
+        ("level" = Option<i16>, Query, description = "Least important syslog priority, 0 (emerg) to 7 (debug)"),
+        ("since" = Option<String>, Query, description = "RFC 3339 timestamp, defaults to a day ago"),
+        ("until" = Option<String>, Query, description = "RFC 3339 timestamp"),
+        ("search" = Option<String>, Query, description = "Case insensitive text the message contains"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of entries, defaults to 500"),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Log entries of the device, newest first", body = Vec<DeviceLog>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve logs"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_device_logs(
+    Path(device_id): Path<i32>,
+    Query(filter): Query<LogFilter>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<DeviceLog>>, StatusCode> {
+    let logs = sqlx::query_as!(
+        DeviceLog,
+        r#"
+        SELECT id, "timestamp", unit, priority, message
+        FROM device_log
+        WHERE device_id = $1
+          AND "timestamp" >= COALESCE($2, NOW() - INTERVAL '1 day')
+          AND ($3::timestamptz IS NULL OR "timestamp" < $3)
+          AND ($4::text IS NULL OR unit = $4)
+          AND ($5::int2 IS NULL OR priority <= $5)
+          AND ($6::text IS NULL OR message ILIKE '%' || $6 || '%' ESCAPE '\')
+        ORDER BY "timestamp" DESC
+        LIMIT $7
+        "#,
+        device_id,
+        filter.since,
+        filter.until,
+        filter.unit,
+        filter.level,
+        filter.search.as_deref().map(escape_like),
+        filter.limit.clamp(1, 5000)
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!(
+            "error: failed to get logs for device {}: {:?}",
+            device_id, err
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(logs))
+}
+
+/// Makes `%`, `_` and `\` match themselves in a LIKE pattern.
+fn escape_like(search: &str) -> String {
+    search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+use sqlx::types::chrono;
+
+/// A journal entry shipped by a device.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct DeviceLog {
+    pub id: i64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Systemd unit that logged the entry.
+    pub unit: Option<String>,
+    /// Syslog priority, 0 (emerg) to 7 (debug).
+    pub priority: i16,
+    pub message: String,
+}
@@ -27,25 +27,37 @@ use tokio::sync::broadcast::Sender;
 use tokio::sync::{Mutex, broadcast};
 use tower::ServiceBuilder;
 use tower_http::decompression::RequestDecompressionLayer;
-use tracing::info;
+use tracing::{error, info};
 use tracing_subscriber::{EnvFilter, prelude::*};
 use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_scalar::{Scalar, Servable as ScalarServable};
 
+mod agent_config;
+mod apt;
 mod asset;
 mod config;
+mod config_file;
 mod db;
+mod delta;
 mod deployment;
 mod device;
+mod download_limits;
 mod handlers;
+mod log;
 mod middlewares;
 mod modem;
+mod ota;
 mod package;
 mod rollout;
+mod secret;
+mod shell;
 mod storage;
+mod support_bundle;
 mod telemetry;
+mod traffic;
+mod tunnel;
 mod users;
 
 #[derive(Clone, Debug)]
@@ -54,6 +66,7 @@ pub struct State {
     config: &'static Config,
     public_events: Arc<Mutex<Sender<PublicEvent>>>,
     authorization: Arc<AuthorizationConfig>,
+    shell_relay: Arc<shell::relay::ShellRelay>,
 }
 
 fn main() {
@@ -139,6 +152,23 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
         .await
         .expect("sqlx migration failed");
 
+    if let Some(retention) = &config.log_retention {
+        if let Err(err) = log::schema::DeviceLog::set_retention(retention, &pool).await {
+            error!("Failed to set log retention to {retention}: {err}");
+        }
+    }
+
+    let prune_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if let Err(err) = tunnel::schema::DeviceTunnel::prune(&prune_pool).await {
+                error!("Failed to prune tunnels: {err}");
+            }
+        }
+    });
+
     let (tx_message, _rx_message) = broadcast::channel::<PublicEvent>(1);
     let tx_message = Arc::new(Mutex::new(tx_message));
 
@@ -147,6 +177,7 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
         config,
         public_events: tx_message,
         authorization: Arc::new(authorization),
+        shell_relay: Arc::default(),
     };
 
     let recorder_handle = setup_metrics_recorder();
@@ -163,6 +194,7 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
             handlers::network::delete_network_by_id
         ))
         .routes(routes!(handlers::devices::get_devices))
+        .routes(routes!(device::routes::get_device_resources))
         .routes(routes!(
             handlers::devices::get_device_info,
             handlers::devices::delete_device
@@ -174,6 +206,46 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
         ))
         .routes(routes!(modem::routes::get_modem_list))
         .routes(routes!(modem::routes::get_modem_by_id))
+        .routes(routes!(modem::routes::get_modem_history))
+        .routes(routes!(log::routes::get_device_logs))
+        .routes(routes!(traffic::routes::get_device_traffic))
+        .routes(routes!(ota::routes::get_ota_attempts))
+        .routes(routes!(support_bundle::routes::get_support_bundles))
+        .routes(routes!(support_bundle::routes::download_support_bundle))
+        .routes(routes!(tunnel::routes::get_tunnels))
+        .routes(routes!(secret::routes::get_secret_audit))
+        .routes(routes!(
+            config_file::routes::get_config_files,
+            config_file::routes::create_config_file
+        ))
+        .routes(routes!(
+            config_file::routes::get_config_file,
+            config_file::routes::update_config_file,
+            config_file::routes::delete_config_file
+        ))
+        .routes(routes!(
+            agent_config::routes::get_agent_configs,
+            agent_config::routes::create_agent_config
+        ))
+        .routes(routes!(
+            agent_config::routes::update_agent_config,
+            agent_config::routes::delete_agent_config
+        ))
+        .routes(routes!(agent_config::routes::get_device_agent_config))
+        .routes(routes!(
+            download_limits::routes::get_device_download_limits,
+            download_limits::routes::update_device_download_limits,
+            download_limits::routes::delete_device_download_limits
+        ))
+        .routes(routes!(
+            download_limits::routes::update_tag_download_limits,
+            download_limits::routes::delete_tag_download_limits
+        ))
+        .routes(routes!(
+            shell::routes::open_shell_session,
+            shell::routes::get_shell_sessions
+        ))
+        .routes(routes!(shell::routes::download_shell_recording))
         .routes(routes!(
             handlers::distributions::get_distributions,
             handlers::distributions::create_distribution
@@ -279,6 +351,7 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
             get(handlers::commands::get_bundle_commands)
                 .post(handlers::commands::issue_commands_to_devices),
         )
+        .route("/shell/:session_id", get(shell::routes::attach_admin))
         .route(
             "/lean/:filter_kind/:filter_value",
             get(handlers::devices::get_devices_new),
@@ -308,6 +381,7 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
                     .layer(HandleErrorLayer::new(|_| async move {
                         (StatusCode::INTERNAL_SERVER_ERROR, "Unhandled server error")
                     }))
+                    .layer(middleware::from_fn(traffic::track))
                     .layer(RequestDecompressionLayer::new()),
             ),
         )
@@ -318,6 +392,18 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
                     .layer(HandleErrorLayer::new(|_| async move {
                         (StatusCode::INTERNAL_SERVER_ERROR, "Unhandled server error")
                     }))
+                    .layer(middleware::from_fn(traffic::track))
+                    .layer(RequestDecompressionLayer::new()),
+            ),
+        )
+        .route(
+            "/smith/telemetry/logs",
+            post(telemetry::routes::logs).layer(
+                ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_| async move {
+                        (StatusCode::INTERNAL_SERVER_ERROR, "Unhandled server error")
+                    }))
+                    .layer(middleware::from_fn(traffic::track))
                     .layer(RequestDecompressionLayer::new()),
             ),
         )
@@ -333,6 +419,10 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
             "/smith/upload/*path",
             post(handlers::upload::upload_file).layer(DefaultBodyLimit::max(512000000)),
         )
+        .route(
+            "/smith/shell/:session_id",
+            get(shell::routes::attach_device),
+        )
         .route("/smith/download", get(handlers::download::download_file))
         .route(
             "/smith/download/*path",
@@ -342,7 +432,16 @@ async fn start_main_server(config: &'static Config, authorization: Authorization
         .route(
             "/smith/releases/:release_id/packages",
             get(handlers::list_release_packages),
-        );
+        )
+        .route(
+            "/smith/releases/:release_id/apt/dists/*path",
+            get(apt::routes::release_dists),
+        )
+        .route(
+            "/smith/releases/:release_id/apt/pool/:file",
+            get(apt::routes::release_pool),
+        )
+        .route("/smith/apt/key", get(apt::routes::signing_key));
 
     let json_specification = api.to_pretty_json().expect("API docs generation failed");
 
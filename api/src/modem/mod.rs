@@ -1,4 +1,6 @@
+use crate::db::DeviceWithToken;
 use crate::modem::schema::Modem;
+use smith::utils::schema::NewModem;
 use sqlx::PgPool;
 use tracing::error;
 
@@ -6,41 +8,88 @@ pub mod routes;
 pub mod schema;
 
 impl Modem {
+    /// Stores the latest state of the device modem and appends it to the history.
     pub async fn save_modem(
-        serial_number: String,
-        imei: String,
-        network_provider: String,
+        device: &DeviceWithToken,
+        modem: NewModem,
         pool: &PgPool,
     ) -> anyhow::Result<Self> {
-        let modem = sqlx::query_as!(
+        let mut tx = pool.begin().await?;
+
+        let saved = sqlx::query_as!(
             Modem,
             "
-            INSERT INTO modem (imei, network_provider, updated_at)
-             VALUES ($1, $2, NOW())
-             ON CONFLICT (imei) DO UPDATE SET network_provider = $2, updated_at = NOW()
-             RETURNING *;
-             ",
-            imei,
-            network_provider,
+            INSERT INTO modem (
+                imei, network_provider, iccid, access_technology, signal_quality,
+                rx_bytes, tx_bytes, updated_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ON CONFLICT (imei) DO UPDATE SET
+                network_provider = $2,
+                iccid = $3,
+                access_technology = $4,
+                signal_quality = $5,
+                rx_bytes = $6,
+                tx_bytes = $7,
+                updated_at = NOW()
+            RETURNING *
+            ",
+            modem.imei,
+            modem.network_provider,
+            modem.iccid,
+            modem.access_technology,
+            modem.signal_quality,
+            modem.rx_bytes,
+            modem.tx_bytes
         )
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|err| {
             error!("Failed to save modem info {err}");
             anyhow::anyhow!("Failed to save modem info")
         })?;
+
         sqlx::query!(
-            "UPDATE device SET modem_id = $1 WHERE serial_number = $2",
-            modem.id,
-            serial_number
+            "UPDATE device SET modem_id = $1 WHERE id = $2",
+            saved.id,
+            device.id
         )
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|err| {
-            error!("Failed to update device {serial_number} modem_id; {err}");
+            error!(
+                "Failed to update device {} modem_id; {err}",
+                device.serial_number
+            );
             anyhow::anyhow!("Failed to update device info")
         })?;
-        Ok(modem)
+
+        sqlx::query!(
+            "
+            INSERT INTO modem_history (
+                device_id, modem_id, network_provider, iccid, access_technology,
+                signal_quality, rx_bytes, tx_bytes
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ",
+            device.id,
+            saved.id,
+            saved.network_provider,
+            saved.iccid,
+            saved.access_technology,
+            saved.signal_quality,
+            saved.rx_bytes,
+            saved.tx_bytes
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to save modem history {err}");
+            anyhow::anyhow!("Failed to save modem history")
+        })?;
+
+        tx.commit().await?;
+        Ok(saved)
     }
 
     pub async fn clear_modem(serial_number: String, pool: &PgPool) -> anyhow::Result<()> {
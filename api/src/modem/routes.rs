@@ -1,7 +1,10 @@
 use crate::State;
-use crate::modem::schema::Modem;
+use crate::modem::schema::{Modem, ModemHistory};
+use axum::extract::Query;
 use axum::{Extension, Json, extract::Path};
 use axum::{http::StatusCode, response::Result};
+use serde::Deserialize;
+use sqlx::types::chrono;
 use tracing::error;
 
 const TAG: &str = "modems";
@@ -58,3 +61,55 @@ pub async fn get_modem_by_id(
 
     Ok(Json(modem))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryFilter {
+    since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/modem/history",
+    params(
+        ("since" = Option<String>, Query, description = "RFC 3339 timestamp, defaults to 7 days ago"),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Modem reports of the device, oldest first", body = Vec<ModemHistory>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve modem history"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_modem_history(
+    Path(device_id): Path<i32>,
+    Query(filter): Query<HistoryFilter>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<ModemHistory>>, StatusCode> {
+    let history = sqlx::query_as!(
+        ModemHistory,
+        "
+        SELECT
+            modem_id, network_provider, iccid, access_technology, signal_quality,
+            rx_bytes, tx_bytes, recorded_at
+        FROM modem_history
+        WHERE device_id = $1
+            AND recorded_at >= COALESCE($2, NOW() - INTERVAL '7 days')
+        ORDER BY recorded_at
+        ",
+        device_id,
+        filter.since
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!(
+            "error: failed to get modem history for device {}: {:?}",
+            device_id, err
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(history))
+}
@@ -8,4 +8,22 @@ pub struct Modem {
     pub network_provider: String,
     pub updated_at: chrono::DateTime<chrono::Utc>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    pub iccid: Option<String>,
+    pub access_technology: Option<String>,
+    pub signal_quality: Option<i32>,
+    pub rx_bytes: Option<i64>,
+    pub tx_bytes: Option<i64>,
+}
+
+/// A modem report as received from a device.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ModemHistory {
+    pub modem_id: i32,
+    pub network_provider: String,
+    pub iccid: Option<String>,
+    pub access_technology: Option<String>,
+    pub signal_quality: Option<i32>,
+    pub rx_bytes: Option<i64>,
+    pub tx_bytes: Option<i64>,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
 }
@@ -0,0 +1,42 @@
+use crate::State;
+use crate::ota::schema::OtaAttempt;
+use axum::extract::Path;
+use axum::{Extension, Json};
+use axum::{http::StatusCode, response::Result};
+use tracing::error;
+
+const TAG: &str = "ota";
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/ota",
+    responses(
+        (status = StatusCode::OK, description = "OTA attempts of the device, newest first", body = Vec<OtaAttempt>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve OTA attempts"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_ota_attempts(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<OtaAttempt>>, StatusCode> {
+    let attempts = sqlx::query_as!(
+        OtaAttempt,
+        "SELECT * FROM ota_attempt WHERE device_id = $1 ORDER BY started_at DESC",
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!(
+            "error: failed to get OTA attempts for device {}: {:?}",
+            device_id, err
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(attempts))
+}
@@ -0,0 +1,20 @@
+use serde::Serialize;
+use sqlx::types::chrono;
+
+/// An OTA update of a device, as last reported by it.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct OtaAttempt {
+    pub id: i32,
+    pub device_id: i32,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Name of the `[[ota]]` profile in the device magic.toml.
+    pub profile: String,
+    /// `Downloading`, `Verified`, `Applying`, `Rebooting`, `Confirmed` or `Failed`.
+    pub phase: String,
+    pub expected_version: Option<String>,
+    pub previous_version: Option<String>,
+    /// OS version booted after the update.
+    pub version: Option<String>,
+    pub error: Option<String>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
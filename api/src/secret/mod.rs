@@ -0,0 +1,246 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::env;
+
+pub mod routes;
+pub mod schema;
+
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+/// A 256 bit data key plus the GCM tag.
+const WRAPPED_KEY_LEN: usize = 32 + 16;
+/// Start of a [`reference`], config file templates can't contain it as
+/// their placeholders are plain names.
+const REFERENCE_PREFIX: &str = "{{secret:";
+
+/// Stands in for a secret in queued commands. The value is only revealed
+/// when the device fetches the command, so `command_queue` never holds it.
+pub fn reference(kind: &str, secret_id: i32) -> String {
+    format!("{REFERENCE_PREFIX}{kind}:{secret_id}}}}}")
+}
+
+/// Envelope encryption for secret variables and network passwords. Every
+/// value gets its own data key, which is wrapped with the master key read from
+/// the file at `SECRETS_KEY_PATH` (32 random bytes).
+pub struct Secrets {
+    master: Option<Aes256Gcm>,
+}
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secrets")
+            .field("configured", &self.master.is_some())
+            .finish()
+    }
+}
+
+/// What a secret is being decrypted for, recorded in the audit trail.
+struct Reveal<'a> {
+    kind: &'a str,
+    secret_id: i32,
+    name: &'a str,
+    device_id: i32,
+    purpose: &'a str,
+}
+
+impl Secrets {
+    /// Without a key file the API still runs, but secrets can't be stored or
+    /// sent to devices.
+    pub fn from_env() -> Result<Self> {
+        let Ok(path) = env::var("SECRETS_KEY_PATH") else {
+            return Ok(Self { master: None });
+        };
+
+        let key = std::fs::read(&path).with_context(|| format!("Failed to read {path}"))?;
+        if key.len() != 32 {
+            bail!("{path} must contain exactly 32 bytes");
+        }
+
+        Ok(Self {
+            master: Some(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))),
+        })
+    }
+
+    fn master(&self) -> Result<&Aes256Gcm> {
+        self.master
+            .as_ref()
+            .ok_or_else(|| anyhow!("SECRETS_KEY_PATH is not configured"))
+    }
+
+    /// Encrypts the value into `version | key nonce | wrapped key | nonce | ciphertext`.
+    pub fn seal(&self, plaintext: &str) -> Result<Vec<u8>> {
+        let master = self.master()?;
+
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let key_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let wrapped_key = master
+            .encrypt(&key_nonce, data_key.as_slice())
+            .map_err(|_| anyhow!("Failed to wrap data key"))?;
+
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let ciphertext = Aes256Gcm::new(&data_key)
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow!("Failed to encrypt secret"))?;
+
+        let mut sealed = Vec::with_capacity(1 + NONCE_LEN * 2 + WRAPPED_KEY_LEN + ciphertext.len());
+        sealed.push(VERSION);
+        sealed.extend_from_slice(&key_nonce);
+        sealed.extend_from_slice(&wrapped_key);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<String> {
+        let master = self.master()?;
+
+        let (&version, rest) = sealed.split_first().context("Sealed secret is empty")?;
+        if version != VERSION || rest.len() < NONCE_LEN * 2 + WRAPPED_KEY_LEN {
+            bail!("Sealed secret is malformed");
+        }
+        let (key_nonce, rest) = rest.split_at(NONCE_LEN);
+        let (wrapped_key, rest) = rest.split_at(WRAPPED_KEY_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let data_key = master
+            .decrypt(Nonce::from_slice(key_nonce), wrapped_key)
+            .map_err(|_| anyhow!("Failed to unwrap data key"))?;
+        let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt secret"))?;
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// Decrypts a secret for a device. Every access is recorded before
+    /// anything is decrypted, so no value leaves without a trace.
+    async fn reveal(&self, sealed: &[u8], reveal: Reveal<'_>, pool: &PgPool) -> Result<String> {
+        let digest = Sha256::digest(sealed).to_vec();
+        sqlx::query!(
+            "
+            INSERT INTO secret_audit (kind, secret_id, name, device_id, purpose, sealed_digest)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ",
+            reveal.kind,
+            reveal.secret_id,
+            reveal.name,
+            reveal.device_id,
+            reveal.purpose,
+            digest
+        )
+        .execute(pool)
+        .await?;
+
+        self.open(sealed)
+    }
+
+    /// Replaces the [`reference`]s in every string of the command with the
+    /// secrets, each revealed once for `purpose`. A reference only resolves
+    /// to a secret of the device itself, so one that slipped into a plain
+    /// value can't pull in the secrets of another device.
+    pub async fn reveal_references(
+        &self,
+        command: &mut Value,
+        device_id: i32,
+        purpose: &str,
+        pool: &PgPool,
+    ) -> Result<()> {
+        fn strings<'a>(value: &'a mut Value, found: &mut Vec<&'a mut String>) {
+            match value {
+                Value::String(string) if string.contains(REFERENCE_PREFIX) => found.push(string),
+                Value::Array(values) => values.iter_mut().for_each(|value| strings(value, found)),
+                Value::Object(map) => map.values_mut().for_each(|value| strings(value, found)),
+                _ => {}
+            }
+        }
+
+        let mut found = vec![];
+        strings(command, &mut found);
+
+        let mut revealed: HashMap<(String, i32), String> = HashMap::new();
+        for string in found {
+            let mut replaced = String::with_capacity(string.len());
+            let mut rest = string.as_str();
+            while let Some(start) = rest.find(REFERENCE_PREFIX) {
+                let end = rest[start..]
+                    .find("}}")
+                    .map(|end| start + end + 2)
+                    .context("Unclosed secret reference")?;
+                let (kind, secret_id) = rest[start + REFERENCE_PREFIX.len()..end - 2]
+                    .split_once(':')
+                    .context("Malformed secret reference")?;
+                let key = (kind.to_string(), secret_id.parse()?);
+
+                let value = match revealed.get(&key) {
+                    Some(value) => value.clone(),
+                    None => {
+                        let value = self
+                            .reveal_for_device(&key.0, key.1, device_id, purpose, pool)
+                            .await?;
+                        revealed.insert(key, value.clone());
+                        value
+                    }
+                };
+                replaced.push_str(&rest[..start]);
+                replaced.push_str(&value);
+                rest = &rest[end..];
+            }
+            replaced.push_str(rest);
+            *string = replaced;
+        }
+
+        Ok(())
+    }
+
+    async fn reveal_for_device(
+        &self,
+        kind: &str,
+        secret_id: i32,
+        device_id: i32,
+        purpose: &str,
+        pool: &PgPool,
+    ) -> Result<String> {
+        let secret = match kind {
+            "variable" => sqlx::query!(
+                "SELECT name, sealed_value FROM variable WHERE id = $1 AND device = $2 AND secret",
+                secret_id,
+                device_id
+            )
+            .fetch_optional(pool)
+            .await?
+            .map(|variable| (variable.name, variable.sealed_value)),
+            "network" => sqlx::query!(
+                "
+                SELECT n.name, n.sealed_password
+                FROM network n
+                JOIN device d ON d.network_id = n.id
+                WHERE n.id = $1 AND d.id = $2 AND n.secret
+                ",
+                secret_id,
+                device_id
+            )
+            .fetch_optional(pool)
+            .await?
+            .map(|network| (network.name, network.sealed_password)),
+            _ => bail!("Unknown kind of secret {kind}"),
+        };
+        let Some((name, Some(sealed))) = secret else {
+            bail!("{kind} {secret_id} is not a secret of device {device_id}");
+        };
+
+        let reveal = Reveal {
+            kind,
+            secret_id,
+            name: &name,
+            device_id,
+            purpose,
+        };
+        self.reveal(&sealed, reveal, pool).await
+    }
+}
@@ -0,0 +1,70 @@
+use crate::State;
+use crate::middlewares::authorization;
+use crate::secret::schema::SecretAccess;
+use crate::users::db::CurrentUser;
+use axum::extract::Query;
+use axum::{Extension, Json};
+use axum::{http::StatusCode, response::Result};
+use serde::Deserialize;
+use tracing::error;
+
+const TAG: &str = "secrets";
+
+#[derive(Debug, Deserialize)]
+pub struct AuditFilter {
+    device_id: Option<i32>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+#[utoipa::path(
+    get,
+    path = "/secrets/audit",
+    params(
+        ("device_id" = Option<i32>, Query, description = "Only accesses for this device"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of entries, defaults to 100"),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Latest secret accesses", body = Vec<SecretAccess>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read the audit trail"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the audit trail"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_secret_audit(
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Json<Vec<SecretAccess>>, StatusCode> {
+    if !authorization::check(current_user, "secret_audit", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let accesses = sqlx::query_as!(
+        SecretAccess,
+        "
+        SELECT id, kind, secret_id, name, device_id, purpose, accessed_at
+        FROM secret_audit
+        WHERE $1::int4 IS NULL OR device_id = $1
+        ORDER BY accessed_at DESC
+        LIMIT $2
+        ",
+        filter.device_id,
+        filter.limit.clamp(1, 1000)
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("error: failed to get secret audit: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(accesses))
+}
@@ -0,0 +1,15 @@
+use serde::Serialize;
+use sqlx::types::chrono;
+
+/// A secret value that was decrypted, and what for.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct SecretAccess {
+    pub id: i64,
+    /// `variable` or `network`.
+    pub kind: String,
+    pub secret_id: i32,
+    pub name: String,
+    pub device_id: Option<i32>,
+    pub purpose: String,
+    pub accessed_at: chrono::DateTime<chrono::Utc>,
+}
@@ -0,0 +1,150 @@
+use crate::config::Config;
+use crate::shell::schema::ShellSession;
+use crate::storage::Storage;
+use sqlx::PgPool;
+use sqlx::types::Uuid;
+use tracing::error;
+
+pub mod relay;
+pub mod routes;
+pub mod schema;
+
+impl ShellSession {
+    pub async fn get(id: Uuid, pool: &PgPool) -> anyhow::Result<Option<Self>> {
+        let session = sqlx::query_as!(
+            ShellSession,
+            "SELECT * FROM shell_session WHERE id = $1",
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    pub async fn mark_started(&self, pool: &PgPool) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE shell_session SET started_at = NOW() WHERE id = $1",
+            self.id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ends a session one end never attached to, `missing` names that end.
+    pub async fn expire(&self, missing: &str, pool: &PgPool) -> anyhow::Result<()> {
+        let session = sqlx::query!(
+            r#"
+            SELECT d.serial_number, COALESCE(u.email, u.auth0_user_id) AS "user_name!"
+            FROM shell_session s
+            JOIN device d ON d.id = s.device_id
+            JOIN auth.users u ON u.id = s.user_id
+            WHERE s.id = $1
+            "#,
+            self.id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE shell_session SET ended_at = NOW() WHERE id = $1 AND ended_at IS NULL",
+            self.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+            self.device_id,
+            "shell",
+            format!(
+                "Shell session {} by {} on {} expired, the {missing} never attached.",
+                self.id, session.user_name, session.serial_number
+            )
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Uploads the recording to the assets bucket and leaves a trace of the
+    /// session in the device ledger.
+    pub async fn finish(
+        &self,
+        exit_code: Option<i32>,
+        recording: &[u8],
+        pool: &PgPool,
+        config: &'static Config,
+    ) -> anyhow::Result<()> {
+        let session = sqlx::query!(
+            r#"
+            SELECT d.serial_number, COALESCE(u.email, u.auth0_user_id) AS "user_name!"
+            FROM shell_session s
+            JOIN device d ON d.id = s.device_id
+            JOIN auth.users u ON u.id = s.user_id
+            WHERE s.id = $1
+            "#,
+            self.id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let path = format!("shell/{}", session.serial_number);
+        let file_name = format!("{}.cast", self.id);
+        // the key in the assets bucket, downloaded through a presigned link
+        let recording_key = match Storage::save_to_s3(
+            &config.assets_bucket_name,
+            Some(&path),
+            &file_name,
+            recording,
+        )
+        .await
+        {
+            Ok(()) => Some(format!("{path}/{file_name}")),
+            Err(err) => {
+                error!(
+                    "Failed to upload shell session {} recording: {err}",
+                    self.id
+                );
+                None
+            }
+        };
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE shell_session SET ended_at = NOW(), exit_code = $2, recording = $3 WHERE id = $1",
+            self.id,
+            exit_code,
+            recording_key
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+            self.device_id,
+            "shell",
+            format!(
+                "Shell session {} by {} ended. Recording: {}.",
+                self.id,
+                session.user_name,
+                match recording_key {
+                    Some(_) => format!("/devices/{}/shell/{}/recording", self.device_id, self.id),
+                    None => "upload failed".to_string(),
+                }
+            )
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use crate::State;
+use crate::shell::schema::ShellSession;
+use axum::extract::ws::{Message, WebSocket};
+use serde_json::json;
+use smith::utils::schema::ShellControl;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+/// How long one end of a session waits for the other one to show up.
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(120);
+
+enum Waiting {
+    Admin(WebSocket),
+    Device(WebSocket),
+}
+
+/// Pairs the admin and device websockets of a shell session and pipes them
+/// together. Sessions only live in memory, so both ends need to land on the
+/// same API instance.
+#[derive(Default)]
+pub struct ShellRelay {
+    waiting: Mutex<HashMap<Uuid, Waiting>>,
+}
+
+impl std::fmt::Debug for ShellRelay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShellRelay").finish_non_exhaustive()
+    }
+}
+
+impl ShellRelay {
+    pub async fn attach_admin(&self, session: ShellSession, socket: WebSocket, state: State) {
+        let mut waiting = self.waiting.lock().await;
+        match waiting.remove(&session.id) {
+            Some(Waiting::Device(device)) => {
+                drop(waiting);
+                bridge(session, socket, device, state).await;
+            }
+            _ => {
+                waiting.insert(session.id, Waiting::Admin(socket));
+                drop(waiting);
+                self.expire(session, state).await;
+            }
+        }
+    }
+
+    pub async fn attach_device(&self, session: ShellSession, socket: WebSocket, state: State) {
+        let mut waiting = self.waiting.lock().await;
+        match waiting.remove(&session.id) {
+            Some(Waiting::Admin(admin)) => {
+                drop(waiting);
+                bridge(session, admin, socket, state).await;
+            }
+            _ => {
+                waiting.insert(session.id, Waiting::Device(socket));
+                drop(waiting);
+                self.expire(session, state).await;
+            }
+        }
+    }
+
+    /// Closes the end still waiting once [`ATTACH_TIMEOUT`] passes and
+    /// leaves a trace of the session that never started in the ledger.
+    async fn expire(&self, session: ShellSession, state: State) {
+        tokio::time::sleep(ATTACH_TIMEOUT).await;
+        let Some(waiting) = self.waiting.lock().await.remove(&session.id) else {
+            return;
+        };
+
+        info!(
+            "Shell session {} expired before both ends attached",
+            session.id
+        );
+
+        let (socket, missing) = match waiting {
+            Waiting::Admin(socket) => (socket, "device"),
+            Waiting::Device(socket) => (socket, "admin"),
+        };
+        _ = socket.close().await;
+
+        if let Err(err) = session.expire(missing, &state.pg_pool).await {
+            error!("Failed to expire shell session {}: {err}", session.id);
+        }
+    }
+}
+
+async fn bridge(session: ShellSession, mut admin: WebSocket, mut device: WebSocket, state: State) {
+    info!("Shell session {} attached", session.id);
+
+    if let Err(err) = session.mark_started(&state.pg_pool).await {
+        error!("Failed to mark shell session {} started: {err}", session.id);
+    }
+
+    let mut recording = Recording::new(session.cols, session.rows);
+    let mut exit_code = None;
+
+    loop {
+        tokio::select! {
+            msg = device.recv() => {
+                match msg {
+                    Some(Ok(Message::Binary(data))) => {
+                        recording.output(&data);
+                        if admin.send(Message::Binary(data)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        if let Ok(ShellControl::Exit { code }) = serde_json::from_str(&text) {
+                            exit_code = Some(code);
+                        }
+                        _ = admin.send(Message::Text(text)).await;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+            msg = admin.recv() => {
+                match msg {
+                    Some(Ok(Message::Binary(data))) => {
+                        if device.send(Message::Binary(data)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        if let Ok(ShellControl::Resize { cols, rows }) = serde_json::from_str(&text) {
+                            recording.event("r", format!("{cols}x{rows}").as_bytes());
+                        }
+                        _ = device.send(Message::Text(text)).await;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+
+    _ = admin.close().await;
+    _ = device.close().await;
+
+    info!("Shell session {} closed", session.id);
+
+    if let Err(err) = session
+        .finish(exit_code, &recording.data, &state.pg_pool, state.config)
+        .await
+    {
+        error!("Failed to finish shell session {}: {err}", session.id);
+    }
+}
+
+/// Recordings stop growing past this, the rest of the session is left out.
+const RECORDING_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Session recording in asciicast v2 format.
+/// https://docs.asciinema.org/manual/asciicast/v2/
+///
+/// Only what the device prints and resizes are recorded, keystrokes are
+/// not: passwords typed at a prompt don't echo, but they would end up in
+/// the recording otherwise.
+struct Recording {
+    started: Instant,
+    data: Vec<u8>,
+    truncated: bool,
+    /// Start of a character the last output frame split, completed by the
+    /// next one.
+    pending: Vec<u8>,
+}
+
+impl Recording {
+    fn new(cols: i32, rows: i32) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let header = json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": timestamp,
+        });
+
+        let mut data = header.to_string().into_bytes();
+        data.push(b'\n');
+
+        Self {
+            started: Instant::now(),
+            data,
+            truncated: false,
+            pending: vec![],
+        }
+    }
+
+    fn output(&mut self, payload: &[u8]) {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(payload);
+
+        // a UTF-8 character is at most 4 bytes, only its lead byte can start
+        // an incomplete one at the end
+        let split = (bytes.len().saturating_sub(3)..bytes.len())
+            .rev()
+            .find(|&index| bytes[index] & 0xC0 != 0x80)
+            .filter(|&index| {
+                std::str::from_utf8(&bytes[index..]).is_err_and(|err| err.error_len().is_none())
+            })
+            .unwrap_or(bytes.len());
+        self.pending = bytes.split_off(split);
+
+        self.event("o", &bytes);
+    }
+
+    fn event(&mut self, kind: &str, payload: &[u8]) {
+        if self.truncated {
+            return;
+        }
+
+        let mut event = json!([
+            self.started.elapsed().as_secs_f64(),
+            kind,
+            String::from_utf8_lossy(payload)
+        ])
+        .to_string()
+        .into_bytes();
+
+        if self.data.len() + event.len() >= RECORDING_LIMIT {
+            self.truncated = true;
+            event = json!([
+                self.started.elapsed().as_secs_f64(),
+                "o",
+                "\r\n[recording truncated]\r\n"
+            ])
+            .to_string()
+            .into_bytes();
+        }
+
+        self.data.extend(event);
+        self.data.push(b'\n');
+    }
+}
@@ -0,0 +1,273 @@
+use crate::State;
+use crate::db::{DBHandler, DeviceWithToken};
+use crate::middlewares::authorization;
+use crate::shell::schema::{NewShellSession, ShellSession};
+use crate::storage::Storage;
+use crate::users::db::CurrentUser;
+use axum::extract::WebSocketUpgrade;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json, extract::Path};
+use axum::{http::StatusCode, response::Result};
+use smith::utils::schema::{SafeCommandRequest, SafeCommandTx};
+use sqlx::types::Uuid;
+use tracing::error;
+
+const TAG: &str = "shell";
+
+#[utoipa::path(
+    post,
+    path = "/devices/:device_id/shell",
+    request_body = NewShellSession,
+    responses(
+        (status = StatusCode::CREATED, description = "Shell session requested from the device", body = ShellSession),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to open shell sessions"),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to request shell session"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn open_shell_session(
+    Path(device_id): Path<String>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(new_session): Json<NewShellSession>,
+) -> Result<(StatusCode, Json<ShellSession>), StatusCode> {
+    if !authorization::check(current_user.clone(), "shell", "open") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let user_name = current_user
+        .display_name(&state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to fetch user {}: {err}", current_user.user_id);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let device = sqlx::query!(
+        "
+        SELECT id, serial_number
+        FROM device
+        WHERE
+            CASE
+                WHEN $1 ~ '^[0-9]+$' AND length($1) <= 10 THEN
+                    id = $1::int4
+                ELSE
+                    serial_number = $1
+            END
+        ",
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to fetch device id {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let session = sqlx::query_as!(
+        ShellSession,
+        "
+        INSERT INTO shell_session (device_id, user_id, cols, rows)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        ",
+        device.id,
+        current_user.user_id,
+        i32::from(new_session.cols),
+        i32::from(new_session.rows)
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to create shell session {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query!(
+        r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+        device.id,
+        "shell",
+        format!("Shell session {} requested by {}.", session.id, user_name)
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to insert ledger entry for device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    DBHandler::add_commands(
+        &device.serial_number,
+        vec![SafeCommandRequest {
+            id: 0,
+            command: SafeCommandTx::OpenShell {
+                session: session.id.to_string(),
+                cols: new_session.cols,
+                rows: new_session.rows,
+            },
+            continue_on_error: false,
+            signature: None,
+        }],
+        &state.pg_pool,
+    )
+    .await
+    .map_err(|err| {
+        error!("Failed to queue shell command for device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((StatusCode::CREATED, Json(session)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/shell",
+    responses(
+        (status = StatusCode::OK, description = "Shell sessions of the device", body = Vec<ShellSession>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve shell sessions"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_shell_sessions(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<ShellSession>>, StatusCode> {
+    let sessions = sqlx::query_as!(
+        ShellSession,
+        "SELECT * FROM shell_session WHERE device_id = $1 ORDER BY created_at DESC",
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("error: failed to get shell sessions: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(sessions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/shell/:session_id/recording",
+    responses(
+        (status = StatusCode::TEMPORARY_REDIRECT, description = "Redirect to a download link for the recording"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to open shells"),
+        (status = StatusCode::NOT_FOUND, description = "No recording of the session"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the recording"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn download_shell_recording(
+    Path((device_id, session_id)): Path<(i32, Uuid)>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Response, StatusCode> {
+    // the recording holds everything the shell printed
+    if !authorization::check(current_user, "shell", "open") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let key = sqlx::query_scalar!(
+        "SELECT recording FROM shell_session WHERE id = $1 AND device_id = $2",
+        session_id,
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!(
+            "error: failed to get shell session {}: {:?}",
+            session_id, err
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .flatten()
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut response = Storage::download_from_s3(&state.config.assets_bucket_name, None, &key)
+        .await
+        .map_err(|err| {
+            error!("Failed to presign shell recording {}: {:?}", key, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    *response.status_mut() = StatusCode::TEMPORARY_REDIRECT;
+
+    Ok(response)
+}
+
+/// Admin end of a shell session, only the user that requested it can attach.
+pub async fn attach_admin(
+    Path(session_id): Path<Uuid>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, StatusCode> {
+    let session = pending_session(session_id, &state).await?;
+
+    if session.user_id != current_user.user_id {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let relay = state.shell_relay.clone();
+        relay.attach_admin(session, socket, state).await
+    }))
+}
+
+/// Device end of a shell session.
+pub async fn attach_device(
+    device: DeviceWithToken,
+    Path(session_id): Path<Uuid>,
+    Extension(state): Extension<State>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, StatusCode> {
+    let session = pending_session(session_id, &state).await?;
+
+    if session.device_id != device.id {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let relay = state.shell_relay.clone();
+        relay.attach_device(session, socket, state).await
+    }))
+}
+
+async fn pending_session(session_id: Uuid, state: &State) -> Result<ShellSession, StatusCode> {
+    let session = ShellSession::get(session_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to fetch shell session {session_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // sessions can't be resumed once they are over
+    if session.ended_at.is_some() {
+        return Err(StatusCode::GONE);
+    }
+
+    Ok(session)
+}
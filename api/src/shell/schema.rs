@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use sqlx::types::{Uuid, chrono};
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ShellSession {
+    pub id: Uuid,
+    pub device_id: i32,
+    pub user_id: i32,
+    pub cols: i32,
+    pub rows: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub ended_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub exit_code: Option<i32>,
+    /// Key of the recording in the assets bucket, downloaded through
+    /// `/devices/:device_id/shell/:session_id/recording`.
+    pub recording: Option<String>,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct NewShellSession {
+    pub cols: u16,
+    pub rows: u16,
+}
@@ -30,6 +30,16 @@ impl Storage {
         bucket.delete_object(path).await?;
         Ok(())
     }
+    pub async fn load_from_s3(bucket_name: &str, path: &str) -> anyhow::Result<Vec<u8>> {
+        let region = Region::from_default_env()?;
+        let credentials = Credentials::default()?;
+        let bucket = Bucket::new(bucket_name, region, credentials)?;
+        let response = bucket.get_object(path).await?;
+        if response.status_code() != 200 {
+            anyhow::bail!("Failed to load {}: {}", path, response.status_code());
+        }
+        Ok(response.to_vec())
+    }
     pub async fn download_from_s3(
         bucket_name: &str,
         path: Option<&str>,
@@ -0,0 +1,88 @@
+use crate::State;
+use crate::storage::Storage;
+use crate::support_bundle::schema::SupportBundle;
+use axum::extract::Path;
+use axum::response::Response;
+use axum::{Extension, Json};
+use axum::{http::StatusCode, response::Result};
+use tracing::error;
+
+const TAG: &str = "support_bundles";
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/support-bundles",
+    responses(
+        (status = StatusCode::OK, description = "Support bundles of the device, newest first", body = Vec<SupportBundle>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve support bundles"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_support_bundles(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<SupportBundle>>, StatusCode> {
+    let bundles = sqlx::query_as!(
+        SupportBundle,
+        "SELECT * FROM support_bundle WHERE device_id = $1 ORDER BY created_at DESC",
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!(
+            "error: failed to get support bundles for device {}: {:?}",
+            device_id, err
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(bundles))
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/support-bundles/:bundle_id",
+    responses(
+        (status = StatusCode::TEMPORARY_REDIRECT, description = "Redirect to a download link for the bundle"),
+        (status = StatusCode::NOT_FOUND, description = "Support bundle not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve support bundle"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn download_support_bundle(
+    Path((device_id, bundle_id)): Path<(i32, i32)>,
+    Extension(state): Extension<State>,
+) -> Result<Response, StatusCode> {
+    let file = sqlx::query_scalar!(
+        "SELECT file FROM support_bundle WHERE id = $1 AND device_id = $2",
+        bundle_id,
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!(
+            "error: failed to get support bundle {}: {:?}",
+            bundle_id, err
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut response = Storage::download_from_s3(&state.config.assets_bucket_name, None, &file)
+        .await
+        .map_err(|err| {
+            error!("Failed to presign support bundle {}: {:?}", file, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    *response.status_mut() = StatusCode::TEMPORARY_REDIRECT;
+
+    Ok(response)
+}
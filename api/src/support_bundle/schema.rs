@@ -0,0 +1,16 @@
+use serde::Serialize;
+use sqlx::types::chrono;
+
+/// A tar.gz of logs and device state uploaded by a device.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct SupportBundle {
+    pub id: i32,
+    pub device_id: i32,
+    pub command_id: Option<i32>,
+    /// `Standard` or `Full`.
+    pub profile: String,
+    pub file: String,
+    /// Size in bytes.
+    pub size: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
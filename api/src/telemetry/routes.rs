@@ -1,11 +1,13 @@
 use crate::State;
 use crate::db::DeviceWithToken;
+use crate::handlers::encoded::Encoded;
+use crate::log::schema::DeviceLog;
 use crate::modem::schema::Modem;
+use axum::Extension;
 use axum::body::{Body, to_bytes};
 use axum::extract::Request;
 use axum::http::StatusCode;
-use axum::{Extension, Json};
-use serde::Deserialize;
+use smith::utils::schema::{LogEntry, NewModem};
 use tracing::error;
 
 pub async fn victoria(
@@ -45,27 +47,24 @@ pub async fn victoria(
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct NewModem {
-    pub imei: String,
-    pub network_provider: String,
-}
-
 pub async fn modem(
     device: DeviceWithToken,
     Extension(state): Extension<State>,
-    Json(modem): Json<Option<NewModem>>,
+    Encoded(modem): Encoded<Option<NewModem>>,
 ) -> Result<StatusCode, StatusCode> {
+    // modems are keyed by IMEI, without one they would all collapse into a
+    // single row
+    if modem
+        .as_ref()
+        .is_some_and(|modem| modem.imei.trim().is_empty())
+    {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
     tokio::spawn(async move {
         match modem {
             Some(modem) => {
-                let _ = Modem::save_modem(
-                    device.serial_number,
-                    modem.imei,
-                    modem.network_provider,
-                    &state.pg_pool,
-                )
-                .await;
+                let _ = Modem::save_modem(&device, modem, &state.pg_pool).await;
             }
             None => {
                 let _ = Modem::clear_modem(device.serial_number, &state.pg_pool).await;
@@ -74,3 +73,19 @@ pub async fn modem(
     });
     Ok(StatusCode::OK)
 }
+
+pub async fn logs(
+    device: DeviceWithToken,
+    Extension(state): Extension<State>,
+    Encoded(entries): Encoded<Vec<LogEntry>>,
+) -> Result<StatusCode, StatusCode> {
+    if entries.is_empty() {
+        return Ok(StatusCode::OK);
+    }
+
+    DeviceLog::save_entries(&device, entries, &state.pg_pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,172 @@
+//! Bytes each device exchanges with the API per day, to tell what its
+//! protocol costs on metered links.
+
+use crate::State;
+use crate::db::DeviceWithToken;
+use crate::traffic::schema::DeviceTraffic;
+use axum::Extension;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::Request;
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::Response;
+use http_body::{Frame, SizeHint};
+use sqlx::PgPool;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll, ready};
+use tracing::error;
+
+pub mod routes;
+pub mod schema;
+
+impl DeviceTraffic {
+    pub async fn record(
+        device: &DeviceWithToken,
+        sent_bytes: i64,
+        received_bytes: i64,
+        heartbeat: bool,
+        pool: &PgPool,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "
+            INSERT INTO device_traffic (device_id, day, sent_bytes, received_bytes, requests, heartbeats)
+            VALUES ($1, (NOW() AT TIME ZONE 'UTC')::date, $2, $3, 1, $4)
+            ON CONFLICT (device_id, day) DO UPDATE SET
+                sent_bytes = device_traffic.sent_bytes + EXCLUDED.sent_bytes,
+                received_bytes = device_traffic.received_bytes + EXCLUDED.received_bytes,
+                requests = device_traffic.requests + 1,
+                heartbeats = device_traffic.heartbeats + EXCLUDED.heartbeats
+            ",
+            device.id,
+            sent_bytes,
+            received_bytes,
+            heartbeat as i32
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// What a device exchanges in one request, recorded once both bodies went
+/// through and the request and its response are dropped.
+#[derive(Debug)]
+pub struct Traffic {
+    pool: PgPool,
+    /// Set by the `DeviceWithToken` extractor of the handler.
+    device: OnceLock<DeviceWithToken>,
+    /// Set by handlers for requests that carry nothing to report.
+    heartbeat: AtomicBool,
+    sent_bytes: AtomicU64,
+    received_bytes: AtomicU64,
+}
+
+impl Traffic {
+    /// Counts the request for the device, when it is tracked.
+    pub fn device(parts: &Parts, device: &DeviceWithToken) {
+        if let Some(traffic) = parts.extensions.get::<Arc<Traffic>>() {
+            _ = traffic.device.set(device.clone());
+        }
+    }
+
+    pub fn heartbeat(&self) {
+        self.heartbeat.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Traffic {
+    fn drop(&mut self) {
+        // requests without a valid token aren't counted
+        let Some(device) = self.device.take() else {
+            return;
+        };
+        let sent_bytes = *self.sent_bytes.get_mut() as i64;
+        let received_bytes = *self.received_bytes.get_mut() as i64;
+        let heartbeat = *self.heartbeat.get_mut();
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            DeviceTraffic::record(&device, sent_bytes, received_bytes, heartbeat, &pool)
+                .await
+                .unwrap_or_else(|err| {
+                    error!(
+                        "Error saving traffic of {}: {:?}",
+                        device.serial_number, err
+                    );
+                });
+        });
+    }
+}
+
+/// Counts the bytes of the requests of devices and of their responses as
+/// they go through. It runs before the request is decompressed, so what the
+/// device sent is counted as it went over the wire.
+pub async fn track(Extension(state): Extension<State>, request: Request, next: Next) -> Response {
+    let traffic = Arc::new(Traffic {
+        pool: state.pg_pool.clone(),
+        device: OnceLock::new(),
+        heartbeat: AtomicBool::new(false),
+        sent_bytes: AtomicU64::new(0),
+        received_bytes: AtomicU64::new(0),
+    });
+
+    let (mut parts, body) = request.into_parts();
+    parts.extensions.insert(traffic.clone());
+    let body = Body::new(Counted {
+        body,
+        traffic: traffic.clone(),
+        sent: true,
+    });
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let (parts, body) = response.into_parts();
+    let body = Body::new(Counted {
+        body,
+        traffic,
+        sent: false,
+    });
+    Response::from_parts(parts, body)
+}
+
+/// A body that adds its bytes to the traffic of the request.
+struct Counted {
+    body: Body,
+    traffic: Arc<Traffic>,
+    /// Sent by the device, or received by it.
+    sent: bool,
+}
+
+impl HttpBody for Counted {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let frame = ready!(Pin::new(&mut self.body).poll_frame(cx));
+        if let Some(data) = frame
+            .as_ref()
+            .and_then(|frame| frame.as_ref().ok())
+            .and_then(Frame::data_ref)
+        {
+            let bytes = if self.sent {
+                &self.traffic.sent_bytes
+            } else {
+                &self.traffic.received_bytes
+            };
+            bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
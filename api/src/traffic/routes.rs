@@ -0,0 +1,64 @@
+use crate::State;
+use crate::traffic::schema::DeviceTraffic;
+use axum::extract::{Path, Query};
+use axum::{Extension, Json};
+use axum::{http::StatusCode, response::Result};
+use serde::Deserialize;
+use tracing::error;
+
+const TAG: &str = "traffic";
+
+#[derive(Debug, Deserialize)]
+pub struct TrafficFilter {
+    #[serde(default = "default_days")]
+    days: i32,
+}
+
+fn default_days() -> i32 {
+    30
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/:device_id/traffic",
+    params(
+        ("days" = Option<i32>, Query, description = "Number of days back, including today, defaults to 30"),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Bytes the device exchanged with the API per day, newest first", body = Vec<DeviceTraffic>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve traffic"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_device_traffic(
+    Path(device_id): Path<i32>,
+    Query(filter): Query<TrafficFilter>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<DeviceTraffic>>, StatusCode> {
+    let traffic = sqlx::query_as!(
+        DeviceTraffic,
+        "
+        SELECT day, sent_bytes, received_bytes, requests, heartbeats
+        FROM device_traffic
+        WHERE device_id = $1
+          AND day > (NOW() AT TIME ZONE 'UTC')::date - $2::int
+        ORDER BY day DESC
+        ",
+        device_id,
+        filter.days.clamp(1, 366)
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!(
+            "error: failed to get traffic for device {}: {:?}",
+            device_id, err
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(traffic))
+}
@@ -0,0 +1,16 @@
+use serde::Serialize;
+use sqlx::types::chrono;
+
+/// What a device exchanged with the API in a day, bodies only.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct DeviceTraffic {
+    /// UTC day.
+    pub day: chrono::NaiveDate,
+    /// Bytes the device sent, as they went over the wire.
+    pub sent_bytes: i64,
+    /// Bytes the device got back.
+    pub received_bytes: i64,
+    pub requests: i32,
+    /// Pings with nothing to report.
+    pub heartbeats: i32,
+}
@@ -0,0 +1,93 @@
+use crate::db::DeviceWithToken;
+use crate::tunnel::schema::DeviceTunnel;
+use crate::users::db::CurrentUser;
+use smith::utils::schema::{SafeCommandRequest, SafeCommandTx, Tunnel};
+use sqlx::PgPool;
+use tracing::error;
+
+pub mod routes;
+pub mod schema;
+
+impl DeviceTunnel {
+    /// Replaces the tunnels known for a device with the ones it just reported.
+    pub async fn save_reported(
+        device: &DeviceWithToken,
+        tunnels: Vec<Tunnel>,
+        pool: &PgPool,
+    ) -> anyhow::Result<()> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!("DELETE FROM device_tunnel WHERE device_id = $1", device.id)
+            .execute(&mut *tx)
+            .await?;
+
+        for tunnel in tunnels {
+            sqlx::query!(
+                "
+                INSERT INTO device_tunnel
+                    (device_id, local_port, remote_port, opened_at, expires_at, requested_by)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ",
+                device.id,
+                i32::from(tunnel.local_port),
+                i32::from(tunnel.remote_port),
+                tunnel.opened_at,
+                tunnel.expires_at,
+                tunnel.requested_by
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Forgets the tunnels of devices that stopped pinging, nobody reports
+    /// those closing.
+    pub async fn prune(pool: &PgPool) -> anyhow::Result<u64> {
+        let pruned = sqlx::query!(
+            "
+            DELETE FROM device_tunnel t
+            USING device d
+            WHERE d.id = t.device_id
+              AND (t.expires_at < NOW() OR d.last_ping IS NULL OR d.last_ping < NOW() - INTERVAL '5 minutes')
+            "
+        )
+        .execute(pool)
+        .await?;
+        Ok(pruned.rows_affected())
+    }
+
+    /// Stamps every `OpenTunnel` command with the user issuing it, so the device
+    /// can report who asked for each tunnel. Signed commands are left as they
+    /// are, changing them would break the signature.
+    pub async fn sign_requests(
+        commands: &mut [SafeCommandRequest],
+        current_user: &CurrentUser,
+        pool: &PgPool,
+    ) -> anyhow::Result<()> {
+        if !commands.iter().any(|command| {
+            command.signature.is_none()
+                && matches!(command.command, SafeCommandTx::OpenTunnel { .. })
+        }) {
+            return Ok(());
+        }
+
+        let name = current_user.display_name(pool).await.map_err(|err| {
+            error!("Failed to fetch user {}: {err}", current_user.user_id);
+            anyhow::anyhow!("Failed to fetch user")
+        })?;
+
+        for command in commands
+            .iter_mut()
+            .filter(|command| command.signature.is_none())
+        {
+            if let SafeCommandTx::OpenTunnel { requested_by, .. } = &mut command.command {
+                *requested_by = Some(name.clone());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use crate::State;
+use crate::tunnel::schema::DeviceTunnel;
+use axum::{Extension, Json};
+use axum::{http::StatusCode, response::Result};
+use tracing::error;
+
+const TAG: &str = "tunnels";
+
+#[utoipa::path(
+    get,
+    path = "/tunnels",
+    responses(
+        (status = StatusCode::OK, description = "Tunnels currently open across the fleet", body = Vec<DeviceTunnel>),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve tunnels"),
+    ),
+    security(
+        ("Access Token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_tunnels(
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<DeviceTunnel>>, StatusCode> {
+    let tunnels = sqlx::query_as!(
+        DeviceTunnel,
+        "
+        SELECT
+            t.device_id,
+            d.serial_number,
+            t.local_port,
+            t.remote_port,
+            t.opened_at,
+            t.expires_at,
+            t.requested_by,
+            t.reported_at
+        FROM device_tunnel t
+        JOIN device d ON d.id = t.device_id
+        WHERE t.expires_at > NOW()
+        ORDER BY t.opened_at DESC
+        "
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("error: failed to get tunnels: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(tunnels))
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+use sqlx::types::chrono;
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct DeviceTunnel {
+    pub device_id: i32,
+    pub serial_number: String,
+    pub local_port: i32,
+    pub remote_port: i32,
+    pub opened_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub requested_by: Option<String>,
+    pub reported_at: chrono::DateTime<chrono::Utc>,
+}
@@ -91,4 +91,16 @@ impl CurrentUser {
 
         Ok(current_user)
     }
+
+    /// Human readable name for audit trails, the email when we know it.
+    pub async fn display_name(&self, pg_pool: &PgPool) -> Result<String> {
+        let user = sqlx::query!(
+            r#"SELECT COALESCE(email, auth0_user_id) AS "name!" FROM auth.users WHERE id = $1"#,
+            self.user_id
+        )
+        .fetch_one(pg_pool)
+        .await?;
+
+        Ok(user.name)
+    }
 }
@@ -2,6 +2,9 @@ use crate::schema;
 use anyhow::Result;
 use reqwest::Client;
 use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
 
 pub struct SmithAPI {
     domain: String,
@@ -92,18 +95,22 @@ impl SmithAPI {
         Ok(distros)
     }
 
-    pub async fn open_tunnel(&self, device_id: u64) -> Result<()> {
+    pub async fn open_tunnel(&self, device_id: u64, port: u16) -> Result<()> {
         let client = Client::new();
 
         let open_tunnel_command = schema::SafeCommandRequest {
             id: 0,
-            command: schema::SafeCommandTx::OpenTunnel { port: None },
+            command: schema::SafeCommandTx::OpenTunnel {
+                port: Some(port),
+                ttl: None,
+                requested_by: None,
+            },
             continue_on_error: false,
         };
 
         let resp = client
             .post(format!("{}/devices/{device_id}/commands", self.domain))
-            .header("Authorization", &self.bearer_token)
+            .header("Authorization", format!("Bearer {}", &self.bearer_token))
             .json(&serde_json::json!([open_tunnel_command]))
             .send();
 
@@ -117,6 +124,48 @@ impl SmithAPI {
         Ok(())
     }
 
+    /// Asks the device to open a shell session, returns the session id.
+    pub async fn open_shell(&self, device_id: u64, cols: u16, rows: u16) -> Result<String> {
+        let client = Client::new();
+
+        let resp = client
+            .post(format!("{}/devices/{device_id}/shell", self.domain))
+            .header("Authorization", format!("Bearer {}", &self.bearer_token))
+            .json(&serde_json::json!({ "cols": cols, "rows": rows }))
+            .send()
+            .await?;
+
+        if resp.status() != 201 {
+            return Err(anyhow::anyhow!(
+                "Failed to open shell session: {}",
+                resp.status()
+            ));
+        }
+
+        let session: Value = resp.json().await?;
+
+        session["id"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow::anyhow!("Shell session without id"))
+    }
+
+    pub async fn attach_shell(
+        &self,
+        session: &str,
+    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let url = format!("{}/shell/{session}", self.domain.replacen("http", "ws", 1));
+        let mut request = url.into_client_request()?;
+        request.headers_mut().insert(
+            "Authorization",
+            format!("Bearer {}", &self.bearer_token).parse()?,
+        );
+
+        let (socket, _) = connect_async(request).await?;
+
+        Ok(socket)
+    }
+
     pub async fn get_last_command(&self, device_id: u64) -> Result<serde_json::Value> {
         let client = Client::new();
 
@@ -133,4 +182,61 @@ impl SmithAPI {
 
         Ok(last_command.clone())
     }
+
+    pub async fn get_support_bundles(&self, device_id: u64) -> Result<Value> {
+        let client = Client::new();
+
+        let resp = client
+            .get(format!(
+                "{}/devices/{device_id}/support-bundles",
+                self.domain
+            ))
+            .header("Authorization", format!("Bearer {}", &self.bearer_token))
+            .send();
+
+        Ok(resp.await?.error_for_status()?.json().await?)
+    }
+
+    pub async fn collect_support_bundle(
+        &self,
+        device_id: u64,
+        profile: schema::SupportBundleProfile,
+    ) -> Result<()> {
+        let client = Client::new();
+
+        let collect_command = schema::SafeCommandRequest {
+            id: 0,
+            command: schema::SafeCommandTx::CollectSupportBundle { profile },
+            continue_on_error: false,
+        };
+
+        let resp = client
+            .post(format!("{}/devices/{device_id}/commands", self.domain))
+            .header("Authorization", format!("Bearer {}", &self.bearer_token))
+            .json(&serde_json::json!([collect_command]))
+            .send();
+
+        let response_code = resp.await?.status();
+
+        if response_code != 201 {
+            return Err(anyhow::anyhow!("Failed to request support bundle"));
+        }
+
+        Ok(())
+    }
+
+    /// Downloads a support bundle, the API redirects to the bucket.
+    pub async fn download_support_bundle(&self, device_id: u64, bundle_id: u64) -> Result<Vec<u8>> {
+        let client = Client::new();
+
+        let resp = client
+            .get(format!(
+                "{}/devices/{device_id}/support-bundles/{bundle_id}",
+                self.domain
+            ))
+            .header("Authorization", format!("Bearer {}", &self.bearer_token))
+            .send();
+
+        Ok(resp.await?.error_for_status()?.bytes().await?.to_vec())
+    }
 }
@@ -1,5 +1,6 @@
 use clap::{Parser, Subcommand, value_parser};
 use clap_complete::Shell;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -42,6 +43,39 @@ pub enum DevicesCommands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum BundleCommands {
+    /// List the support bundles of a device
+    Ls {
+        serial_number: String,
+
+        #[arg(short, long, default_value = "false")]
+        json: bool,
+    },
+    /// Ask a device to collect a support bundle and download it
+    Collect {
+        serial_number: String,
+
+        /// Also collect a week of journal, the previous boot and the processes
+        #[arg(long, default_value = "false")]
+        full: bool,
+
+        /// Where to save the bundle, defaults to its name in the current directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Download a support bundle
+    Get {
+        serial_number: String,
+
+        bundle_id: u64,
+
+        /// Where to save the bundle, defaults to its name in the current directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Commands to handle current profile to use
@@ -80,9 +114,22 @@ pub enum Commands {
         /// Device serial number to tunnel into
         serial_number: String,
 
-        /// Setup for overview debug
-        #[arg(long)]
-        overview_debug: bool,
+        /// Port on the device to expose
+        #[arg(short, long, default_value = "22")]
+        port: u16,
+    },
+
+    /// Interactive shell on a device, relayed and recorded by Smith
+    Shell {
+        /// Device serial number to open the shell on
+        serial_number: String,
+    },
+
+    /// Support bundles with logs and state collected on a device
+    #[command(alias = "bundle")]
+    Bundles {
+        #[clap(subcommand)]
+        command: BundleCommands,
     },
 
     /// Generate shell completion scripts
@@ -4,20 +4,20 @@ mod cli;
 mod config;
 mod print;
 mod schema;
-mod tunnel;
+mod shell;
 
-use crate::cli::{Cli, Commands, DevicesCommands, DistroCommands};
+use crate::cli::{BundleCommands, Cli, Commands, DevicesCommands, DistroCommands};
 use crate::print::TablePrint;
 use anyhow::Context;
 use api::SmithAPI;
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
 use colored::Colorize;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressStyle};
 use serde_json::Value;
-use std::{io, sync::mpsc, thread, time::Duration};
+use std::path::PathBuf;
+use std::{io, time::Duration};
 use termion::raw::IntoRawMode;
-use tokio::sync::oneshot;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
@@ -149,7 +149,7 @@ async fn main() -> anyhow::Result<()> {
             },
             cli::Commands::Tunnel {
                 serial_number,
-                overview_debug,
+                port,
             } => {
                 let secrets = auth::get_secrets(&config)
                     .await
@@ -170,130 +170,77 @@ async fn main() -> anyhow::Result<()> {
                     &serial_number.bold()
                 );
 
-                let item_uuid = if overview_debug {
-                    "2e6hkkg53lpmcw7qqeuqmnvy64"
-                } else {
-                    "q5dk6wsnchmfrlbl6balq5mg6u"
-                };
-
-                let child = std::process::Command::new("op")
-                    .args([
-                        "item",
-                        "get",
-                        item_uuid,
-                        "--fields",
-                        "username,password",
-                        "--reveal",
-                    ])
-                    .stdout(std::process::Stdio::piped())
-                    .spawn()?;
-
-                let m = MultiProgress::new();
-
-                let pb = m.add(ProgressBar::new_spinner());
+                let pb = ProgressBar::new_spinner();
                 pb.enable_steady_tick(Duration::from_millis(50));
                 pb.set_style(
                     ProgressStyle::with_template("{spinner:.blue} {msg}")
                         .unwrap()
                         .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
                 );
-                pb.set_message("Fetchin password from 1password");
-
-                let (tx_pass, rx_pass) = mpsc::channel();
-                let one_password_handle = tokio::spawn(async move {
-                    let stdout = child.wait_with_output().unwrap();
-                    let fields = String::from_utf8(stdout.stdout).unwrap();
-                    let fields: Vec<&str> = fields.split(',').collect();
-                    let username = fields[0].trim();
-                    let password = fields[1].trim();
-                    let _visible_part = &password[0..3];
-                    let masked_length = password.len() - 3;
-
-                    tx_pass
-                        .send((username.to_string(), password.to_owned().clone()))
-                        .unwrap();
-
-                    pb.finish_with_message(format!(
-                        "{}{} {}{}{}",
-                        "Username: ".bold(),
-                        username,
-                        "Password: ".bold(),
-                        password,
-                        "*".repeat(masked_length)
-                    ));
-                });
-
-                let pb2 = m.add(ProgressBar::new_spinner());
-                pb2.enable_steady_tick(Duration::from_millis(50));
-                pb2.set_style(
-                    ProgressStyle::with_template("{spinner:.blue} {msg}")
-                        .unwrap()
-                        .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
-                );
-                pb2.set_message("Sending request to smith");
+                pb.set_message("Sending request to smith");
 
-                let (tx, rx) = oneshot::channel();
-                let tunnel_openning_handler = tokio::spawn(async move {
-                    api.open_tunnel(id).await.unwrap();
-                    pb2.set_message("Request sent to smith 💻");
+                api.open_tunnel(id, port).await?;
+                pb.set_message("Request sent to smith 💻");
 
-                    let port;
-                    loop {
-                        let response = api.get_last_command(id).await.unwrap();
+                let remote_port;
+                loop {
+                    let response = api.get_last_command(id).await?;
 
-                        if response["fetched"].is_boolean()
-                            && response["fetched"].as_bool().unwrap()
-                        {
-                            pb2.set_message("Command fetched by device 👍");
-                        }
+                    if response["fetched"].is_boolean() && response["fetched"].as_bool().unwrap() {
+                        pb.set_message("Command fetched by device 👍");
+                    }
 
-                        if response["response"].is_object() {
-                            port = response["response"]["OpenTunnel"]["port_server"]
-                                .as_u64()
-                                .unwrap();
+                    if response["response"].is_object() {
+                        remote_port = response["response"]["OpenTunnel"]["port_server"]
+                            .as_u64()
+                            .unwrap_or_default();
+                        break;
+                    }
 
-                            tx.send(port).unwrap();
-                            break;
-                        }
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
 
-                        thread::sleep(Duration::from_secs(1));
-                    }
+                if remote_port == 0 {
+                    pb.finish_with_message("Device failed to open the tunnel");
+                    return Err(anyhow::anyhow!("Failed to open tunnel"));
+                }
 
-                    pb2.finish_with_message(format!("{} {}", "Port:".bold(), port));
-                });
+                pb.finish_with_message(format!(
+                    "{} {}:{}",
+                    "Tunnel open at".bold(),
+                    config.tunnel_server(),
+                    remote_port
+                ));
+            }
+            cli::Commands::Shell { serial_number } => {
+                let secrets = auth::get_secrets(&config)
+                    .await
+                    .with_context(|| "Error getting token")?
+                    .with_context(|| "No Token found, please Login")?;
 
-                let port = rx.await.unwrap();
-                let (username, password) = rx_pass.recv().unwrap();
+                let api = SmithAPI::new(secrets, &config);
 
-                println!("Opening tunnel to port {}", port);
-                println!("Username: {}", username);
-                println!("Password: {}", password);
+                let devices = api.get_devices(Some(serial_number.clone())).await?;
 
-                one_password_handle.await.unwrap();
-                tunnel_openning_handler.await.unwrap();
+                let parsed: Value = serde_json::from_str(&devices)?;
 
-                if !overview_debug {
-                    let mut ssh =
-                        tunnel::Session::connect(username, password, port as u16, &config).await?;
-                    println!("Connected");
+                let id = parsed[0]["id"].as_u64().unwrap();
 
-                    let code = {
-                        let _raw_term = std::io::stdout().into_raw_mode()?;
+                println!("Opening shell on device [{}] {}", id, &serial_number.bold());
 
-                        ssh.call().await?
-                    };
+                let (cols, rows) = termion::terminal_size()?;
+                let session = api.open_shell(id, cols, rows).await?;
+                let socket = api.attach_shell(&session).await?;
 
-                    println!("Exited with code: {}", code);
-                    ssh.close().await?;
-                } else {
-                    tunnel::connect_local_port_to_remote_port(
-                        &config,
-                        username,
-                        password,
-                        port as u16,
-                    )
-                    .await?;
-                }
+                println!("Waiting for the device to attach, this session is recorded");
+
+                let code = {
+                    let _raw_term = std::io::stdout().into_raw_mode()?;
+
+                    shell::call(socket).await?
+                };
+
+                println!("Exited with code: {}", code);
             }
             Commands::Release {
                 release_number,
@@ -357,6 +304,137 @@ async fn main() -> anyhow::Result<()> {
                     return Ok(());
                 }
             }
+            Commands::Bundles { command } => {
+                let secrets = auth::get_secrets(&config)
+                    .await
+                    .with_context(|| "Error getting token")?
+                    .with_context(|| "No Token found, please Login")?;
+
+                let api = SmithAPI::new(secrets, &config);
+
+                match command {
+                    BundleCommands::Ls {
+                        serial_number,
+                        json,
+                    } => {
+                        let id = get_device_id(&api, &serial_number).await?;
+                        let bundles = api.get_support_bundles(id).await?;
+                        if json {
+                            println!("{}", bundles);
+                            return Ok(());
+                        }
+                        let rows: Vec<Vec<String>> = bundles
+                            .as_array()
+                            .map(Vec::as_slice)
+                            .unwrap_or_default()
+                            .iter()
+                            .map(|b| {
+                                vec![
+                                    b["id"].to_string(),
+                                    b["created_at"].as_str().unwrap_or("").to_string(),
+                                    b["profile"].as_str().unwrap_or("").to_string(),
+                                    format!("{:.1} MB", b["size"].as_f64().unwrap_or(0.0) / 1e6),
+                                ]
+                            })
+                            .collect();
+                        TablePrint {
+                            headers: vec![
+                                "Id".to_string(),
+                                "Collected".to_string(),
+                                "Profile".to_string(),
+                                "Size".to_string(),
+                            ],
+                            rows,
+                        }
+                        .print();
+                    }
+                    BundleCommands::Collect {
+                        serial_number,
+                        full,
+                        output,
+                    } => {
+                        let id = get_device_id(&api, &serial_number).await?;
+                        let profile = if full {
+                            schema::SupportBundleProfile::Full
+                        } else {
+                            schema::SupportBundleProfile::Standard
+                        };
+
+                        let pb = ProgressBar::new_spinner();
+                        pb.enable_steady_tick(Duration::from_millis(50));
+                        pb.set_style(
+                            ProgressStyle::with_template("{spinner:.blue} {msg}")
+                                .unwrap()
+                                .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
+                        );
+                        pb.set_message("Sending request to smith");
+
+                        api.collect_support_bundle(id, profile).await?;
+                        pb.set_message("Request sent to smith 💻");
+
+                        let start_time = std::time::Instant::now();
+                        let timeout = Duration::from_secs(10 * 60);
+                        let file = loop {
+                            if start_time.elapsed() > timeout {
+                                pb.finish_with_message("Device did not upload a bundle in time");
+                                return Err(anyhow::anyhow!("Support bundle timed out"));
+                            }
+
+                            let response = api.get_last_command(id).await?;
+
+                            if response["fetched"].as_bool() == Some(true) {
+                                pb.set_message("Device is collecting the bundle 📦");
+                            }
+
+                            if response["response"].is_object() {
+                                match response["response"]["SupportBundleCollected"]["file"]
+                                    .as_str()
+                                {
+                                    Some(file) => break file.to_string(),
+                                    None => {
+                                        pb.finish_with_message(
+                                            "Device failed to collect the bundle",
+                                        );
+                                        return Err(anyhow::anyhow!(
+                                            "Failed to collect support bundle: {}",
+                                            response["response"]
+                                        ));
+                                    }
+                                }
+                            }
+
+                            tokio::time::sleep(Duration::from_secs(2)).await;
+                        };
+
+                        let bundles = api.get_support_bundles(id).await?;
+                        let bundle = bundles
+                            .as_array()
+                            .and_then(|bundles| bundles.iter().find(|b| b["file"] == file.as_str()))
+                            .with_context(|| "Uploaded bundle not found")?;
+
+                        pb.set_message("Downloading bundle");
+                        let path = save_bundle(&api, id, bundle, output).await?;
+                        pb.finish_with_message(format!("{} {}", "Saved".bold(), path.display()));
+                    }
+                    BundleCommands::Get {
+                        serial_number,
+                        bundle_id,
+                        output,
+                    } => {
+                        let id = get_device_id(&api, &serial_number).await?;
+                        let bundles = api.get_support_bundles(id).await?;
+                        let bundle = bundles
+                            .as_array()
+                            .and_then(|bundles| {
+                                bundles.iter().find(|b| b["id"].as_u64() == Some(bundle_id))
+                            })
+                            .with_context(|| format!("No support bundle {bundle_id}"))?;
+
+                        let path = save_bundle(&api, id, bundle, output).await?;
+                        println!("{} {}", "Saved".bold(), path.display());
+                    }
+                }
+            }
             Commands::Completion { shell } => {
                 let mut cmd = Cli::command();
                 let name = env!("CARGO_BIN_NAME");
@@ -372,6 +450,35 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn get_device_id(api: &SmithAPI, serial_number: &str) -> anyhow::Result<u64> {
+    let devices = api.get_devices(Some(serial_number.to_string())).await?;
+    let parsed: Value = serde_json::from_str(&devices)?;
+
+    parsed[0]["id"]
+        .as_u64()
+        .with_context(|| format!("Device {serial_number} not found"))
+}
+
+async fn save_bundle(
+    api: &SmithAPI,
+    device_id: u64,
+    bundle: &Value,
+    output: Option<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    let bundle_id = bundle["id"]
+        .as_u64()
+        .with_context(|| "Support bundle without id")?;
+    let path = output.unwrap_or_else(|| {
+        let file = bundle["file"].as_str().unwrap_or("support-bundle.tar.gz");
+        PathBuf::from(file.rsplit('/').next().unwrap_or(file))
+    });
+
+    let data = api.download_support_bundle(device_id, bundle_id).await?;
+    tokio::fs::write(&path, data).await?;
+
+    Ok(path)
+}
+
 fn get_colored_arch(arch: &str) -> String {
     match arch.to_lowercase().as_str() {
         "amd64" => arch.bright_blue().to_string(),
@@ -14,6 +14,25 @@ pub enum SafeCommandTx {
     Ping,
     OpenTunnel {
         port: Option<u16>,
+        ttl: Option<u64>,
+        requested_by: Option<String>,
     },
-    CloseTunnel,
+    CloseTunnel {
+        port: Option<u16>,
+    },
+    CollectSupportBundle {
+        profile: SupportBundleProfile,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum SupportBundleProfile {
+    Standard,
+    Full,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ShellControl {
+    Resize { cols: u16, rows: u16 },
+    Exit { code: i32 },
 }
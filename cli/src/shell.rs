@@ -0,0 +1,56 @@
+use crate::schema::ShellControl;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Pipes the terminal through the shell session until the remote shell exits,
+/// returning its exit code.
+pub async fn call(mut socket: WebSocketStream<MaybeTlsStream<TcpStream>>) -> anyhow::Result<i32> {
+    let mut stdin = tokio_fd::AsyncFd::try_from(0)?;
+    let mut stdout = tokio_fd::AsyncFd::try_from(1)?;
+    let mut window_change = signal(SignalKind::window_change())?;
+    let mut buf = vec![0; 1024];
+
+    loop {
+        tokio::select! {
+            // There's terminal input available from the user
+            r = stdin.read(&mut buf) => {
+                match r {
+                    Ok(0) => break,
+                    Ok(n) => socket.send(Message::Binary(buf[..n].to_vec())).await?,
+                    Err(e) => return Err(e.into()),
+                }
+            },
+            // The terminal was resized
+            _ = window_change.recv() => {
+                let (cols, rows) = termion::terminal_size()?;
+                let resize = serde_json::to_string(&ShellControl::Resize { cols, rows })?;
+                socket.send(Message::Text(resize)).await?;
+            },
+            // There's output from the device
+            msg = socket.next() => {
+                match msg {
+                    Some(Ok(Message::Binary(data))) => {
+                        stdout.write_all(&data).await?;
+                        stdout.flush().await?;
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        if let Ok(ShellControl::Exit { code }) = serde_json::from_str(&text) {
+                            return Ok(code);
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.into()),
+                }
+            },
+        }
+    }
+
+    _ = socket.close(None).await;
+
+    Ok(-1)
+}
@@ -0,0 +1,631 @@
+use crate::host::{FakeHost, OTA_APPLY, OTA_CHECK, OTA_VERSION};
+use crate::profile::Profile;
+use crate::stats::Stats;
+use crate::tokens::Tokens;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::Value;
+use smith::commander::registry::Plugins;
+use smith::commander::{self, free, plugin};
+use smith::downloader::Bandwidth;
+use smith::magic::structure::ConfigPackage;
+use smith::postman::{Device, PostmanHandle, Reported};
+use smith::shutdown::ShutdownHandler;
+use smith::updater::install;
+use smith::utils::host::{Host, HostCommand};
+use smith::utils::network::NetworkClient;
+use smith::utils::schema::{
+    CommandOutcome, CommandResult, DeviceRegistration, OtaAttempt, OtaPhase, SafeCommandRequest,
+    SafeCommandResponse, SafeCommandRx, SafeCommandTx, SelfUpdateAttempt,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// How long an agent stays silent while it reboots.
+const REBOOT_TIME: Duration = Duration::from_secs(15);
+/// How long a failed release is left alone, unless `Upgrade` is sent.
+const UPGRADE_RETRY: Duration = Duration::from_secs(60);
+/// Version an OTA goes to when the API doesn't expect one.
+const OTA_VERSION_DEFAULT: &str = "36.4.3";
+
+/// What every agent of the run shares.
+pub struct Fleet {
+    pub server: String,
+    pub interval: Duration,
+    pub tokens: Tokens,
+    pub stats: Stats,
+    /// Of the machine running the sim, sent by every agent under its own
+    /// serial number.
+    pub system_info: Value,
+    /// Agents download packages into a directory of their own in here.
+    pub work_dir: PathBuf,
+}
+
+/// One simulated device, running smithd's postman against a fake host.
+pub struct Agent {
+    serial: String,
+    wifi_mac: String,
+    profile: Profile,
+    fleet: Arc<Fleet>,
+    host: Arc<FakeHost>,
+    /// smithd's plugins, run against the fake host.
+    plugins: Plugins,
+    bandwidth: Arc<Bandwidth>,
+    rng: Mutex<StdRng>,
+    state: Mutex<State>,
+    /// Notified when a restart is scheduled.
+    rebooting: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    token: Option<String>,
+    release_id: Option<i32>,
+    target_release_id: Option<i32>,
+    /// Of the commands run since the last post.
+    results: Vec<SafeCommandResponse>,
+    agent_config_version: Option<String>,
+    upgrade: Option<(i32, JoinHandle<Result<()>>)>,
+    failed_upgrade: Option<(i32, Instant)>,
+    /// OTA downloaded or applied, until the device booted into it.
+    ota: Option<OtaAttempt>,
+    /// Last change of an OTA attempt with its sequence, and the sequence
+    /// the API acknowledged.
+    ota_report: Option<(u64, OtaAttempt)>,
+    ota_reported: u64,
+    reboot: bool,
+}
+
+impl Agent {
+    pub fn new(fleet: Arc<Fleet>, prefix: &str, index: usize, profile: Profile) -> Self {
+        let bandwidth = Arc::new(Bandwidth::default());
+        bandwidth.set_rate(profile.download_rate());
+
+        let host = Arc::new(FakeHost::new(profile));
+        let serial = format!("{prefix}-{index:05}");
+        let [.., a, b, c, d] = (index as u64).to_be_bytes();
+
+        Self {
+            state: Mutex::new(State {
+                token: fleet.tokens.get(&serial),
+                ..Default::default()
+            }),
+            serial,
+            wifi_mac: format!("02:00:{a:02x}:{b:02x}:{c:02x}:{d:02x}"),
+            profile,
+            fleet,
+            host: host.clone(),
+            plugins: Plugins::new(host),
+            bandwidth,
+            rng: Mutex::new(StdRng::seed_from_u64(index as u64)),
+            rebooting: Notify::new(),
+        }
+    }
+
+    pub async fn run(self: Arc<Self>, shutdown: CancellationToken) {
+        info!("{} starting as {}", self.serial, self.profile);
+
+        loop {
+            self.boot().await;
+            let postman = ShutdownHandler::new();
+            PostmanHandle::spawn(postman.signals(), self.clone());
+
+            // smithd reboots a minute after acknowledging, plenty of pings
+            // to get the acknowledgement out
+            let rebooting = async {
+                loop {
+                    self.rebooting.notified().await;
+                    time::sleep(self.fleet.interval).await;
+                    if std::mem::take(&mut self.state.lock().unwrap().reboot) {
+                        break;
+                    }
+                }
+            };
+            tokio::select! {
+                _ = rebooting => {}
+                _ = shutdown.cancelled() => {
+                    postman.signals().token.cancel();
+                    break;
+                }
+            }
+
+            postman.signals().token.cancel();
+            _ = self.host.output(HostCommand::new("reboot")).await;
+            if let Some((_, task)) = self.state.lock().unwrap().upgrade.take() {
+                task.abort();
+            }
+
+            tokio::select! {
+                _ = time::sleep(REBOOT_TIME) => {}
+                _ = shutdown.cancelled() => break,
+            }
+        }
+
+        if let Some((_, task)) = self.state.lock().unwrap().upgrade.take() {
+            task.abort();
+        }
+    }
+
+    /// Confirms the OTA the device booted into, like smithd does when it
+    /// starts.
+    async fn boot(&self) {
+        let rebooted = self
+            .state
+            .lock()
+            .unwrap()
+            .ota
+            .take_if(|attempt| attempt.phase == OtaPhase::Rebooting);
+        if let Some(mut attempt) = rebooted {
+            attempt.version = self.os_version().await;
+            let expected = attempt
+                .expected_version
+                .as_deref()
+                .unwrap_or(OTA_VERSION_DEFAULT);
+            attempt.phase = if attempt.version.as_deref() == Some(expected) {
+                OtaPhase::Confirmed
+            } else {
+                OtaPhase::Failed
+            };
+            self.report_ota(attempt);
+        }
+    }
+
+    async fn execute_one(&self, request: SafeCommandRequest) -> SafeCommandResponse {
+        let id = request.id;
+        let (status, command) = match request.command {
+            SafeCommandTx::Ping => (0, SafeCommandRx::Pong),
+            SafeCommandTx::FreeForm { cmd } => {
+                return free::execute(id, self.host.as_ref(), cmd).await;
+            }
+            SafeCommandTx::Upgrade => {
+                let mut state = self.state.lock().unwrap();
+                state.failed_upgrade = None;
+                self.start_upgrade(&mut state);
+                (0, SafeCommandRx::Upgraded)
+            }
+            SafeCommandTx::Restart { .. } => {
+                self.reboot();
+                let message = format!("Restart scheduled for {}", chrono::Utc::now());
+                (0, SafeCommandRx::Restart { message })
+            }
+            SafeCommandTx::CancelRestart => {
+                let message = if std::mem::take(&mut self.state.lock().unwrap().reboot) {
+                    "Restart cancelled"
+                } else {
+                    "No restart was scheduled"
+                };
+                let message = message.to_string();
+                (0, SafeCommandRx::Restart { message })
+            }
+            SafeCommandTx::UpdateNetwork { network } => {
+                let command = HostCommand::new("nmcli").args(["connection", "up", &network.name]);
+                _ = self.host.output(command).await;
+                let message = format!("Network {} applied", network.name);
+                (
+                    0,
+                    SafeCommandRx::NetworkUpdated {
+                        rolled_back: false,
+                        message,
+                    },
+                )
+            }
+            SafeCommandTx::UpdateVariables { .. } => (0, SafeCommandRx::UpdateVariables),
+            SafeCommandTx::UpdateConfigFiles { files } => (
+                0,
+                SafeCommandRx::UpdateConfigFiles {
+                    changed: files.into_iter().map(|file| file.path).collect(),
+                    errors: vec![],
+                },
+            ),
+            SafeCommandTx::DownloadOTA {
+                expected_version, ..
+            } => {
+                let version = expected_version.as_deref().unwrap_or(OTA_VERSION_DEFAULT);
+                self.host.stage_os_version(version);
+                let previous_version = self.os_version().await;
+                self.state.lock().unwrap().ota = Some(OtaAttempt {
+                    started_at: chrono::Utc::now(),
+                    profile: "sim".to_string(),
+                    phase: OtaPhase::Verified,
+                    expected_version,
+                    previous_version,
+                    version: None,
+                    error: None,
+                });
+                (0, SafeCommandRx::DownloadOTA)
+            }
+            SafeCommandTx::CheckOTAStatus => match self.state.lock().unwrap().ota {
+                Some(_) => (
+                    0,
+                    SafeCommandRx::CheckOTAStatus {
+                        status: "Success".to_string(),
+                    },
+                ),
+                None => (
+                    -1,
+                    SafeCommandRx::CheckOTAStatus {
+                        status: "Failed".to_string(),
+                    },
+                ),
+            },
+            SafeCommandTx::StartOTA { .. } => match self.start_ota().await {
+                Ok(()) => (0, SafeCommandRx::DownloadOTA),
+                Err(err) => (-1, failed(err)),
+            },
+            SafeCommandTx::SetDownloadLimits { limits } => {
+                let rate = limits.rate.map(|rate| rate * 1_000_000.0);
+                let rate = match (rate, self.profile.download_rate()) {
+                    (Some(rate), Some(cap)) => Some(rate.min(cap)),
+                    (rate, cap) => rate.or(cap),
+                };
+                self.bandwidth.set_rate(rate);
+                let message = format!("Download limits set to {:?}", limits);
+                (0, SafeCommandRx::Downloads { message })
+            }
+            SafeCommandTx::PauseDownloads => {
+                self.bandwidth.pause();
+                let message = "Downloads paused".to_string();
+                (0, SafeCommandRx::Downloads { message })
+            }
+            SafeCommandTx::ResumeDownloads => {
+                self.bandwidth.resume();
+                let message = "Downloads resumed".to_string();
+                (0, SafeCommandRx::Downloads { message })
+            }
+            SafeCommandTx::UpdateAgentConfig { version, .. } => {
+                self.state.lock().unwrap().agent_config_version = Some(version.clone());
+                (
+                    0,
+                    SafeCommandRx::AgentConfig {
+                        version: Some(version),
+                        reloaded: vec![],
+                        error: None,
+                    },
+                )
+            }
+            SafeCommandTx::Plugin { name, args } => {
+                return plugin::execute(id, &self.plugins, name, args).await;
+            }
+            SafeCommandTx::OpenTunnel { .. }
+            | SafeCommandTx::CloseTunnel { .. }
+            | SafeCommandTx::OpenShell { .. }
+            | SafeCommandTx::CollectSupportBundle { .. } => {
+                return SafeCommandResponse {
+                    id,
+                    command: failed(anyhow::anyhow!("not simulated")),
+                    status: -1,
+                    result: Some(CommandResult::failed(
+                        CommandOutcome::Unsupported,
+                        "not simulated",
+                    )),
+                };
+            }
+        };
+
+        SafeCommandResponse {
+            id,
+            command,
+            status,
+            result: None,
+        }
+    }
+
+    /// Runs the checks and the apply script of the downloaded OTA, the
+    /// device boots into it on the next reboot.
+    async fn start_ota(&self) -> Result<()> {
+        let Some(mut attempt) = self.state.lock().unwrap().ota.take() else {
+            return Err(anyhow::anyhow!("No OTA downloaded"));
+        };
+
+        for script in [OTA_CHECK, OTA_APPLY] {
+            let output = self.host.output(HostCommand::shell(script)).await?;
+            if !output.status.success() {
+                let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                attempt.phase = OtaPhase::Failed;
+                attempt.error = Some(error.clone());
+                self.report_ota(attempt);
+                return Err(anyhow::anyhow!("`{}` failed: {}", script, error));
+            }
+        }
+
+        attempt.phase = OtaPhase::Rebooting;
+        self.report_ota(attempt.clone());
+        self.state.lock().unwrap().ota = Some(attempt);
+        self.reboot();
+        Ok(())
+    }
+
+    fn report_ota(&self, attempt: OtaAttempt) {
+        let mut state = self.state.lock().unwrap();
+        let sequence = state
+            .ota_report
+            .as_ref()
+            .map_or(0, |(sequence, _)| *sequence)
+            + 1;
+        state.ota_report = Some((sequence, attempt));
+    }
+
+    fn reboot(&self) {
+        self.state.lock().unwrap().reboot = true;
+        self.rebooting.notify_one();
+    }
+
+    async fn os_version(&self) -> Option<String> {
+        let output = self
+            .host
+            .output(HostCommand::shell(OTA_VERSION))
+            .await
+            .ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Upgrades to the target release in the background, pings go on
+    /// meanwhile.
+    fn start_upgrade(&self, state: &mut State) {
+        let Some(target) = state.target_release_id else {
+            return;
+        };
+        if state.release_id == Some(target) || state.upgrade.is_some() {
+            return;
+        }
+        if let Some((release, at)) = state.failed_upgrade {
+            if release == target && at.elapsed() < UPGRADE_RETRY {
+                return;
+            }
+        }
+        let Some(token) = state.token.clone() else {
+            return;
+        };
+
+        info!("{} upgrading to release {}", self.serial, target);
+        let task = tokio::spawn(upgrade(
+            self.fleet.clone(),
+            self.host.clone(),
+            self.bandwidth.clone(),
+            self.fleet.work_dir.join(&self.serial),
+            token,
+            target,
+        ));
+        state.upgrade = Some((target, task));
+    }
+
+    async fn check_upgrade(&self) {
+        let finished = self
+            .state
+            .lock()
+            .unwrap()
+            .upgrade
+            .take_if(|(_, task)| task.is_finished());
+        let Some((release, task)) = finished else {
+            return;
+        };
+
+        let result = match task.await {
+            Ok(result) => result,
+            Err(err) => Err(err.into()),
+        };
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(()) => {
+                info!("{} is on release {}", self.serial, release);
+                state.release_id = Some(release);
+                state.failed_upgrade = None;
+            }
+            Err(err) => {
+                warn!(
+                    "{} failed to upgrade to {}: {:?}",
+                    self.serial, release, err
+                );
+                state.failed_upgrade = Some((release, Instant::now()));
+            }
+        }
+    }
+}
+
+impl Device for Agent {
+    fn server(&self) -> BoxFuture<'_, String> {
+        Box::pin(async { self.fleet.server.clone() })
+    }
+
+    fn token(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { self.state.lock().unwrap().token.clone() })
+    }
+
+    fn set_token(&self, token: Option<String>) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            match &token {
+                Some(_) => info!("{} registered", self.serial),
+                None => warn!("{} lost its token", self.serial),
+            }
+            if let Err(err) = self.fleet.tokens.set(&self.serial, token.as_deref()) {
+                warn!("Failed to keep the token of {}: {:?}", self.serial, err);
+            }
+            self.state.lock().unwrap().token = token;
+        })
+    }
+
+    fn registration(&self) -> DeviceRegistration {
+        DeviceRegistration {
+            serial_number: self.serial.clone(),
+            wifi_mac: self.wifi_mac.clone(),
+        }
+    }
+
+    fn system_info(&self) -> BoxFuture<'_, Value> {
+        Box::pin(async {
+            let mut system_info = self.fleet.system_info.clone();
+            system_info["hostname"] = Value::from(self.serial.clone());
+            system_info["device_tree"]["serial_number"] = Value::from(self.serial.clone());
+            system_info
+        })
+    }
+
+    fn agent_config_version(&self) -> Option<String> {
+        self.state.lock().unwrap().agent_config_version.clone()
+    }
+
+    fn plugins(&self) -> BoxFuture<'_, Vec<String>> {
+        Box::pin(self.plugins.names())
+    }
+
+    fn results(&self) -> BoxFuture<'_, Vec<SafeCommandResponse>> {
+        Box::pin(async { std::mem::take(&mut self.state.lock().unwrap().results) })
+    }
+
+    fn insert_results(&self, results: Vec<SafeCommandResponse>) -> BoxFuture<'_, ()> {
+        Box::pin(async { self.state.lock().unwrap().results.extend(results) })
+    }
+
+    fn execute(&self, commands: Vec<SafeCommandRequest>) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            for command in commands {
+                let started_at = chrono::Utc::now();
+                let mut response = self.execute_one(command).await;
+                commander::finish(&mut response, started_at);
+                self.state.lock().unwrap().results.push(response);
+            }
+        })
+    }
+
+    fn reported(&self) -> BoxFuture<'_, Reported> {
+        Box::pin(async {
+            self.check_upgrade().await;
+            Reported {
+                release_id: self.state.lock().unwrap().release_id,
+                tunnels: vec![],
+                restart: None,
+            }
+        })
+    }
+
+    fn set_target_release_id(&self, release_id: Option<i32>) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let mut state = self.state.lock().unwrap();
+            state.target_release_id = release_id;
+            self.start_upgrade(&mut state);
+        })
+    }
+
+    fn ota_unreported(&self) -> BoxFuture<'_, Option<(u64, OtaAttempt)>> {
+        Box::pin(async {
+            let state = self.state.lock().unwrap();
+            state
+                .ota_report
+                .clone()
+                .filter(|(sequence, _)| *sequence > state.ota_reported)
+        })
+    }
+
+    fn ota_reported(&self, sequence: u64) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let mut state = self.state.lock().unwrap();
+            state.ota_reported = state.ota_reported.max(sequence);
+        })
+    }
+
+    fn self_update_unreported(&self) -> Option<(u64, SelfUpdateAttempt)> {
+        None
+    }
+
+    fn self_update_reported(&self, _sequence: u64) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn reached_api(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn problem_starting(&self) -> BoxFuture<'_, Option<u32>> {
+        Box::pin(async { None })
+    }
+
+    fn problem_solved(&self, _problem: u32) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+
+    fn interval(&self) -> Duration {
+        self.fleet.interval
+    }
+
+    fn lost(&self) -> bool {
+        let lost = self.rng.lock().unwrap().gen_bool(self.profile.drop_rate());
+        if lost {
+            debug!("{} lost its ping", self.serial);
+        }
+        lost
+    }
+
+    fn posted(&self, endpoint: &'static str, latency: Duration, status: Option<u16>) {
+        // a 5xx or no answer at all
+        let ok = status.is_some_and(|status| status < 500);
+        self.fleet.stats.record(endpoint, latency, ok, 0);
+    }
+}
+
+/// What smithd's updater and smith-updater do for a release, against the
+/// fake host.
+async fn upgrade(
+    fleet: Arc<Fleet>,
+    host: Arc<FakeHost>,
+    bandwidth: Arc<Bandwidth>,
+    dir: PathBuf,
+    token: String,
+    release: i32,
+) -> Result<()> {
+    let mut network = NetworkClient::new();
+    network.set_hostname(fleet.server.clone());
+    let packages = network.get_release_packages(release, &token).await?;
+
+    let result = async {
+        for package in &packages {
+            let file = &package.package.file;
+            let start = Instant::now();
+            let result = network
+                .get_package_into(&dir, file, &token, &bandwidth)
+                .await;
+            let bytes = match tokio::fs::metadata(dir.join(file)).await {
+                Ok(metadata) if result.is_ok() => metadata.len(),
+                _ => 0,
+            };
+            fleet
+                .stats
+                .record("package", start.elapsed(), result.is_ok(), bytes);
+            result?;
+        }
+
+        let packages: Vec<ConfigPackage> = packages.iter().map(ConfigPackage::from).collect();
+        host.stage(&packages);
+        if install::install(host.as_ref(), &dir, &packages).await? {
+            for package in packages
+                .iter()
+                .filter(|package| matches!(package.name.as_str(), "smith" | "smith_amd64"))
+            {
+                let script = format!(
+                    "sudo apt install {} -y --allow-downgrades",
+                    dir.join(&package.file).display()
+                );
+                host.output(HostCommand::shell(script)).await?;
+            }
+        }
+        install::up_to_date(host.as_ref(), &packages).await
+    }
+    .await;
+
+    _ = tokio::fs::remove_dir_all(&dir).await;
+    result
+}
+
+fn failed(err: anyhow::Error) -> SafeCommandRx {
+    SafeCommandRx::FreeForm {
+        stdout: "".to_string(),
+        stderr: format!("Error: {}", err),
+    }
+}
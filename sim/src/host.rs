@@ -0,0 +1,208 @@
+//! A device that only exists in memory. Knows just enough of dpkg-query, apt,
+//! nmcli, systemctl, reboot and the OTA scripts of the sim profile for
+//! smithd's code to run against it.
+
+use crate::profile::Profile;
+use futures::future::BoxFuture;
+use smith::magic::structure::ConfigPackage;
+use smith::utils::host::{Host, HostCommand, exited};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::process::Output;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Commands of the OTA profile the agents download and apply.
+pub const OTA_CHECK: &str = "sim-ota check";
+pub const OTA_APPLY: &str = "sim-ota apply";
+pub const OTA_VERSION: &str = "sim-ota version";
+
+const INITIAL_OS_VERSION: &str = "36.4.0";
+/// How long an apt install takes.
+const INSTALL_TIME: Duration = Duration::from_millis(200);
+
+pub struct FakeHost {
+    profile: Profile,
+    device: Mutex<Device>,
+}
+
+#[derive(Default)]
+struct Device {
+    /// Installed packages and their versions.
+    packages: HashMap<String, String>,
+    /// Package files apt knows, by file name.
+    files: HashMap<String, ConfigPackage>,
+    os_version: String,
+    /// OS version booted into after the next reboot.
+    applied_os_version: Option<String>,
+}
+
+impl FakeHost {
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            profile,
+            device: Mutex::new(Device {
+                os_version: INITIAL_OS_VERSION.to_string(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Lets apt install the downloaded files of these packages.
+    pub fn stage(&self, packages: &[ConfigPackage]) {
+        let mut device = self.device.lock().unwrap();
+        for package in packages {
+            device.files.insert(package.file.clone(), package.clone());
+        }
+    }
+
+    /// Where the next `sim-ota apply` takes the device.
+    pub fn stage_os_version(&self, version: &str) {
+        self.device.lock().unwrap().applied_os_version = Some(version.to_string());
+    }
+
+    fn dpkg_query(&self, args: &[String]) -> Output {
+        let [show, format, name] = args else {
+            return exited(2, "", "dpkg-query: only --show <package> is simulated\n");
+        };
+        if show != "--show" || format != "--showformat=${Version}" {
+            return exited(
+                2,
+                "",
+                format!("dpkg-query: {show} {format} is not simulated\n"),
+            );
+        }
+
+        match self.device.lock().unwrap().packages.get(name) {
+            Some(version) => exited(0, version.clone(), ""),
+            None => exited(
+                1,
+                "",
+                format!("dpkg-query: no packages found matching {name}\n"),
+            ),
+        }
+    }
+
+    async fn apt_install(&self, path: &str) -> Output {
+        tokio::time::sleep(INSTALL_TIME).await;
+        if self.profile.fails_upgrades() {
+            return exited(
+                100,
+                "",
+                "E: Sub-process /usr/bin/dpkg returned an error code (1)\n",
+            );
+        }
+
+        let file = Path::new(path)
+            .file_name()
+            .map(|file| file.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut device = self.device.lock().unwrap();
+        let Some(package) = device.files.get(&file).cloned() else {
+            return exited(100, "", format!("E: Unable to locate package {path}\n"));
+        };
+        device.packages.insert(package.name, package.version);
+        exited(0, format!("Setting up {file} ...\n"), "")
+    }
+
+    fn ota(&self, script: &str) -> Output {
+        let device = self.device.lock().unwrap();
+        match script {
+            OTA_CHECK => exited(0, "", ""),
+            OTA_APPLY if self.profile.fails_upgrades() => {
+                exited(1, "", "Failed to write the boot partition\n")
+            }
+            OTA_APPLY => match &device.applied_os_version {
+                Some(version) => exited(0, format!("Staged {version}\n"), ""),
+                None => exited(1, "", "No payload downloaded\n"),
+            },
+            OTA_VERSION => exited(0, format!("{}\n", device.os_version), ""),
+            _ => exited(127, "", format!("{script}: not simulated\n")),
+        }
+    }
+
+    fn reboot(&self) -> Output {
+        let mut device = self.device.lock().unwrap();
+        // a failed apply leaves the OS alone
+        if !self.profile.fails_upgrades() {
+            if let Some(version) = device.applied_os_version.take() {
+                device.os_version = version;
+            }
+        }
+        exited(0, "", "")
+    }
+
+    async fn shell(&self, script: &str) -> Output {
+        if let Some(path) = script
+            .strip_prefix("sudo apt install ")
+            .and_then(|rest| rest.split_whitespace().next())
+        {
+            return self.apt_install(path).await;
+        }
+        if script.starts_with("sim-ota") {
+            return self.ota(script);
+        }
+
+        exited(0, format!("simulated: {script}\n"), "")
+    }
+}
+
+impl Host for FakeHost {
+    fn output(&self, command: HostCommand) -> BoxFuture<'_, io::Result<Output>> {
+        Box::pin(async move {
+            let output = match command.program.as_str() {
+                "dpkg-query" => self.dpkg_query(&command.args),
+                "sh" if command.args.first().map(String::as_str) == Some("-c") => {
+                    self.shell(command.args.get(1).map(String::as_str).unwrap_or_default())
+                        .await
+                }
+                "reboot" => self.reboot(),
+                "systemctl" if command.args.first().map(String::as_str) == Some("show") => {
+                    exited(0, "ActiveState=active\nSubState=running\n", "")
+                }
+                "nmcli" | "systemctl" | "chown" | "tar" => exited(0, "", ""),
+                program => exited(127, "", format!("{program}: command not found\n")),
+            };
+            Ok(output)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::updater::install;
+
+    #[tokio::test]
+    async fn installs_packages_like_a_device() {
+        let packages = vec![ConfigPackage {
+            name: "teton-app".to_string(),
+            version: "1.2.0".to_string(),
+            file: "teton-app_1.2.0_arm64.deb".to_string(),
+        }];
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(&packages[0].file), b"deb").unwrap();
+
+        let host = FakeHost::new(Profile::Healthy);
+        assert_eq!(install::installed_version(&host, "teton-app").await, None);
+        host.stage(&packages);
+        install::install(&host, dir.path(), &packages)
+            .await
+            .unwrap();
+        assert_eq!(
+            install::installed_version(&host, "teton-app")
+                .await
+                .as_deref(),
+            Some("1.2.0")
+        );
+        install::up_to_date(&host, &packages).await.unwrap();
+
+        let failing = FakeHost::new(Profile::FailingUpgrades);
+        failing.stage(&packages);
+        install::install(&failing, dir.path(), &packages)
+            .await
+            .unwrap();
+        assert!(install::up_to_date(&failing, &packages).await.is_err());
+    }
+}
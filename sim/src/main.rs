@@ -0,0 +1,134 @@
+//! Runs a fleet of simulated smithd agents in one process to load test an
+//! API. Every agent registers and pings with its own serial number, runs the
+//! commands it gets against a fake device and installs the releases it is
+//! given, downloading their packages for real.
+
+mod agent;
+mod host;
+mod profile;
+mod stats;
+mod tokens;
+
+use agent::{Agent, Fleet};
+use anyhow::{Context, Result};
+use clap::Parser;
+use profile::Mix;
+use smith::utils::host::SystemHost;
+use smith::utils::system::SystemInfo;
+use stats::Stats;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokens::Tokens;
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// API the agents report to, like `server` in magic.toml.
+    #[arg(long, default_value = "http://localhost:8080/smith")]
+    server: String,
+
+    /// Number of agents.
+    #[arg(short, long, default_value_t = 10)]
+    agents: usize,
+
+    /// Serial numbers are the prefix and the number of the agent.
+    #[arg(long, default_value = "sim")]
+    prefix: String,
+
+    /// Behaviour profiles and their weights: healthy, flaky-network,
+    /// failing-upgrades and slow-downloads, e.g. `healthy:8,flaky-network:2`.
+    #[arg(long, default_value = "healthy")]
+    mix: Mix,
+
+    /// Seconds between the pings of an agent.
+    #[arg(long, default_value_t = 20)]
+    interval: u64,
+
+    /// Seconds over which the agents are started.
+    #[arg(long, default_value_t = 10)]
+    ramp_up: u64,
+
+    /// Seconds to run for, until Ctrl-C when left out.
+    #[arg(long)]
+    duration: Option<u64>,
+
+    /// Seconds between stats reports.
+    #[arg(long, default_value_t = 10)]
+    report: u64,
+
+    /// Where the tokens of registered agents are kept across runs.
+    #[arg(long, default_value = "sim-tokens.json")]
+    tokens: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "smith_sim=info".into()),
+        )
+        .init();
+
+    let work_dir = tempfile::tempdir().context("Failed to create the work directory")?;
+    let fleet = Arc::new(Fleet {
+        server: args.server,
+        interval: Duration::from_secs(args.interval),
+        tokens: Tokens::load(args.tokens)?,
+        stats: Stats::new(),
+        system_info: SystemInfo::new(&SystemHost).await.to_value(),
+        work_dir: work_dir.path().to_path_buf(),
+    });
+
+    info!("Starting {} agents against {}", args.agents, fleet.server);
+    let shutdown = CancellationToken::new();
+    let mut agents = vec![];
+    let ramp_up = Duration::from_secs(args.ramp_up);
+    for index in 0..args.agents {
+        let agent = Arc::new(Agent::new(
+            fleet.clone(),
+            &args.prefix,
+            index,
+            args.mix.profile(index, args.agents),
+        ));
+        let delay = ramp_up.mul_f64(index as f64 / args.agents as f64);
+        let shutdown = shutdown.clone();
+        agents.push(tokio::spawn(async move {
+            tokio::select! {
+                _ = time::sleep(delay) => agent.run(shutdown).await,
+                _ = shutdown.cancelled() => {}
+            }
+        }));
+    }
+
+    let deadline = async {
+        match args.duration {
+            Some(duration) => time::sleep(Duration::from_secs(duration)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+
+    let mut report = time::interval(Duration::from_secs(args.report));
+    report.tick().await;
+    loop {
+        tokio::select! {
+            _ = report.tick() => println!("{}", fleet.stats.report()),
+            _ = &mut deadline => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    info!("Stopping agents");
+    shutdown.cancel();
+    for agent in agents {
+        _ = agent.await;
+    }
+
+    println!("{}", fleet.stats.report());
+    Ok(())
+}
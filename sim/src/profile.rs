@@ -0,0 +1,149 @@
+use anyhow::{Context, Result, anyhow};
+use std::fmt;
+use std::str::FromStr;
+
+/// How a simulated agent misbehaves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    Healthy,
+    /// Loses about a third of its pings.
+    FlakyNetwork,
+    /// apt and the OTA apply script always fail.
+    FailingUpgrades,
+    /// Downloads are capped at [`SLOW_DOWNLOAD_RATE`].
+    SlowDownloads,
+}
+
+/// Chance of a flaky agent losing a ping.
+const FLAKY_DROP_RATE: f64 = 0.3;
+/// In bytes per second.
+const SLOW_DOWNLOAD_RATE: f64 = 256.0 * 1024.0;
+
+impl Profile {
+    pub fn drop_rate(self) -> f64 {
+        match self {
+            Self::FlakyNetwork => FLAKY_DROP_RATE,
+            _ => 0.0,
+        }
+    }
+
+    pub fn fails_upgrades(self) -> bool {
+        self == Self::FailingUpgrades
+    }
+
+    /// Cap of the downloads in bytes per second, whatever the API sets.
+    pub fn download_rate(self) -> Option<f64> {
+        match self {
+            Self::SlowDownloads => Some(SLOW_DOWNLOAD_RATE),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "healthy" => Ok(Self::Healthy),
+            "flaky-network" => Ok(Self::FlakyNetwork),
+            "failing-upgrades" => Ok(Self::FailingUpgrades),
+            "slow-downloads" => Ok(Self::SlowDownloads),
+            _ => Err(anyhow!(
+                "unknown profile {s}, expected healthy, flaky-network, failing-upgrades or slow-downloads"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Healthy => "healthy",
+            Self::FlakyNetwork => "flaky-network",
+            Self::FailingUpgrades => "failing-upgrades",
+            Self::SlowDownloads => "slow-downloads",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Weighted profiles, like `healthy:70,flaky-network:30`.
+#[derive(Clone, Debug)]
+pub struct Mix(Vec<(Profile, u32)>);
+
+impl Mix {
+    /// Spreads the profiles over the agents by weight, the same agent
+    /// always gets the same profile.
+    pub fn profile(&self, agent: usize, agents: usize) -> Profile {
+        let total: u32 = self.0.iter().map(|(_, weight)| weight).sum();
+        let position = (agent as f64 + 0.5) / agents as f64 * total as f64;
+
+        let mut cumulative = 0.0;
+        for (profile, weight) in &self.0 {
+            cumulative += *weight as f64;
+            if position < cumulative {
+                return *profile;
+            }
+        }
+        self.0
+            .last()
+            .map(|(profile, _)| *profile)
+            .unwrap_or(Profile::Healthy)
+    }
+}
+
+impl FromStr for Mix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut profiles = vec![];
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (profile, weight) = match entry.split_once(':') {
+                Some((profile, weight)) => (
+                    profile,
+                    weight
+                        .parse()
+                        .with_context(|| format!("invalid weight in {entry}"))?,
+                ),
+                None => (entry, 1),
+            };
+            if weight > 0 {
+                profiles.push((profile.parse()?, weight));
+            }
+        }
+
+        if profiles.is_empty() {
+            return Err(anyhow!("the mix needs at least one profile"));
+        }
+        Ok(Self(profiles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_profiles_by_weight() {
+        let mix: Mix = "healthy:3, flaky-network:1".parse().unwrap();
+        let profiles: Vec<_> = (0..8).map(|agent| mix.profile(agent, 8)).collect();
+        assert_eq!(
+            profiles
+                .iter()
+                .filter(|profile| **profile == Profile::FlakyNetwork)
+                .count(),
+            2
+        );
+        assert_eq!(profiles[0], Profile::Healthy);
+        assert_eq!(profiles[7], Profile::FlakyNetwork);
+
+        assert!("healthy:x".parse::<Mix>().is_err());
+        assert!("sluggish".parse::<Mix>().is_err());
+        assert!("healthy:0".parse::<Mix>().is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Requests the agents made, by endpoint.
+pub struct Stats {
+    started: Instant,
+    endpoints: Mutex<BTreeMap<&'static str, Endpoint>>,
+}
+
+#[derive(Default)]
+struct Endpoint {
+    latencies: Vec<Duration>,
+    /// Requests that didn't get a response, or got a 5xx.
+    errors: u64,
+    bytes: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            endpoints: Mutex::default(),
+        }
+    }
+
+    pub fn record(&self, endpoint: &'static str, latency: Duration, ok: bool, bytes: u64) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let endpoint = endpoints.entry(endpoint).or_default();
+        endpoint.latencies.push(latency);
+        endpoint.bytes += bytes;
+        if !ok {
+            endpoint.errors += 1;
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        let elapsed = self.started.elapsed();
+        let endpoints = self.endpoints.lock().unwrap();
+        let rows = endpoints
+            .iter()
+            .map(|(name, endpoint)| {
+                let mut latencies = endpoint.latencies.clone();
+                latencies.sort();
+                Row {
+                    name,
+                    requests: latencies.len(),
+                    errors: endpoint.errors,
+                    per_second: latencies.len() as f64 / elapsed.as_secs_f64(),
+                    p50: percentile(&latencies, 0.50),
+                    p90: percentile(&latencies, 0.90),
+                    p99: percentile(&latencies, 0.99),
+                    max: latencies.last().copied().unwrap_or_default(),
+                    megabytes_per_second: endpoint.bytes as f64 / 1e6 / elapsed.as_secs_f64(),
+                }
+            })
+            .collect();
+
+        Report { elapsed, rows }
+    }
+}
+
+/// Nearest rank of the sorted latencies.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+pub struct Report {
+    elapsed: Duration,
+    rows: Vec<Row>,
+}
+
+struct Row {
+    name: &'static str,
+    requests: usize,
+    errors: u64,
+    per_second: f64,
+    p50: Duration,
+    p90: Duration,
+    p99: Duration,
+    max: Duration,
+    megabytes_per_second: f64,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "after {:.0?}", self.elapsed)?;
+        writeln!(
+            f,
+            "{:<10} {:>9} {:>7} {:>8} {:>9} {:>9} {:>9} {:>9} {:>8}",
+            "endpoint", "requests", "errors", "req/s", "p50", "p90", "p99", "max", "MB/s"
+        )?;
+        for row in &self.rows {
+            writeln!(
+                f,
+                "{:<10} {:>9} {:>7} {:>8.1} {:>9} {:>9} {:>9} {:>9} {:>8.2}",
+                row.name,
+                row.requests,
+                row.errors,
+                row.per_second,
+                millis(row.p50),
+                millis(row.p90),
+                millis(row.p99),
+                millis(row.max),
+                row.megabytes_per_second
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let latencies: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 0.50), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies[..1], 0.99), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+}
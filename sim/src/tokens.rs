@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Tokens of the registered agents by serial number. The API only hands a
+/// token out once, so they are kept for the next run.
+pub struct Tokens {
+    path: PathBuf,
+    tokens: Mutex<BTreeMap<String, String>>,
+}
+
+impl Tokens {
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let tokens = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        Ok(Self {
+            path,
+            tokens: Mutex::new(tokens),
+        })
+    }
+
+    pub fn get(&self, serial: &str) -> Option<String> {
+        self.tokens.lock().unwrap().get(serial).cloned()
+    }
+
+    /// `None` forgets the token.
+    pub fn set(&self, serial: &str, token: Option<&str>) -> Result<()> {
+        let mut tokens = self.tokens.lock().unwrap();
+        match token {
+            Some(token) => tokens.insert(serial.to_string(), token.to_string()),
+            None => tokens.remove(serial),
+        };
+        std::fs::write(&self.path, serde_json::to_string_pretty(&*tokens)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
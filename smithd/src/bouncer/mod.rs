@@ -1,9 +1,11 @@
-mod report;
+pub(crate) mod report;
 
 use crate::magic::MagicHandle;
 use crate::police::PoliceHandle;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::host::Host;
 use report::InitialCheck;
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 use tracing::{error, info};
 
@@ -12,6 +14,7 @@ struct Bouncer {
     receiver: mpsc::Receiver<BouncerMessage>,
     magic: MagicHandle,
     police: PoliceHandle,
+    host: Arc<dyn Host>,
     problems: Option<u32>,
     checks: Option<Vec<report::InitialCheck>>,
 }
@@ -25,6 +28,7 @@ impl Bouncer {
         receiver: mpsc::Receiver<BouncerMessage>,
         magic: MagicHandle,
         police: PoliceHandle,
+        host: Arc<dyn Host>,
     ) -> Self {
         Self {
             shutdown,
@@ -32,6 +36,7 @@ impl Bouncer {
             magic,
             checks: None,
             police,
+            host,
             problems: None,
         }
     }
@@ -48,7 +53,7 @@ impl Bouncer {
             .collect::<Vec<_>>();
 
         for check in checks.iter_mut() {
-            check.execute().await.unwrap_or_else(|_| {
+            check.execute(self.host.as_ref()).await.unwrap_or_else(|_| {
                 all_checks_passed = false;
             });
         }
@@ -100,9 +105,14 @@ pub struct BouncerHandle {
 }
 
 impl BouncerHandle {
-    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle, police: PoliceHandle) -> Self {
+    pub fn new(
+        shutdown: ShutdownSignals,
+        magic: MagicHandle,
+        police: PoliceHandle,
+        host: Arc<dyn Host>,
+    ) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Bouncer::new(shutdown, receiver, magic, police);
+        let mut actor = Bouncer::new(shutdown, receiver, magic, police, host);
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
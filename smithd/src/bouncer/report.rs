@@ -1,4 +1,5 @@
 use crate::magic::structure::ConfigCheck;
+use crate::utils::host::{Host, HostCommand};
 use anyhow::{Result, anyhow};
 use tracing::{error, info};
 
@@ -21,12 +22,8 @@ impl From<ConfigCheck> for InitialCheck {
 }
 
 impl InitialCheck {
-    pub async fn execute(&mut self) -> Result<()> {
-        let output = tokio::process::Command::new("sh")
-            .arg("-c")
-            .arg(&self.cmd)
-            .output()
-            .await?;
+    pub async fn execute(&mut self, host: &dyn Host) -> Result<()> {
+        let output = host.output(HostCommand::shell(&self.cmd)).await?;
 
         self.success = output.status.success();
         self.data
@@ -0,0 +1,49 @@
+use super::registry::{CommandHandler, unsupported};
+use crate::magic::MagicHandle;
+use crate::utils::schema::{SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use futures::future::BoxFuture;
+use tracing::error;
+
+/// `UpdateAgentConfig`.
+pub(super) struct AgentConfig {
+    pub(super) magic: MagicHandle,
+}
+
+impl CommandHandler for AgentConfig {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::UpdateAgentConfig { version, config } => {
+                    update(id, &self.magic, version, config).await
+                }
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
+
+pub(super) async fn update(
+    id: i32,
+    magic: &MagicHandle,
+    version: String,
+    config: String,
+) -> SafeCommandResponse {
+    let (reloaded, error, status) = match magic.apply_agent_config(version, config).await {
+        Ok(reloaded) => (reloaded.into_iter().map(str::to_string).collect(), None, 0),
+        Err(err) => {
+            error!("Failed to apply agent configuration: {:?}", err);
+            (vec![], Some(format!("{:#}", err)), -1)
+        }
+    };
+
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::AgentConfig {
+            version: magic.agent_config_version(),
+            reloaded,
+            error,
+        },
+        status,
+        result: None,
+    }
+}
@@ -0,0 +1,301 @@
+use super::registry::{CommandHandler, unsupported};
+use crate::bouncer::report::InitialCheck;
+use crate::commander::variable;
+use crate::magic::MagicHandle;
+use crate::utils::host::{Host, HostCommand};
+use crate::utils::schema::{
+    CommandOutcome, CommandResult, SafeCommandResponse, SafeCommandRx, SafeCommandTx,
+    SupportBundleProfile,
+};
+use crate::utils::system::{SystemInfo, get_serial_number};
+use anyhow::{Context, Result, anyhow};
+use futures::future::BoxFuture;
+use reqwest::multipart;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{error, info};
+
+/// Where bundles end up in the assets bucket.
+const UPLOAD_PREFIX: &str = "support-bundles";
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// `CollectSupportBundle`.
+pub(super) struct SupportBundle {
+    pub(super) magic: MagicHandle,
+    pub(super) host: Arc<dyn Host>,
+}
+
+impl CommandHandler for SupportBundle {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::CollectSupportBundle { profile } => {
+                    collect(id, &self.magic, self.host.as_ref(), profile).await
+                }
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
+
+pub(super) async fn collect(
+    id: i32,
+    magic: &MagicHandle,
+    host: &dyn Host,
+    profile: SupportBundleProfile,
+) -> SafeCommandResponse {
+    match bundle(magic, host, profile).await {
+        Ok((file, size)) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::SupportBundleCollected {
+                profile,
+                file,
+                size,
+            },
+            status: 0,
+            result: None,
+        },
+        Err(err) => {
+            error!("Failed to collect support bundle: {:?}", err);
+            let timed_out = err
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(reqwest::Error::is_timeout);
+            let outcome = if timed_out {
+                CommandOutcome::Timeout
+            } else {
+                CommandOutcome::InternalError
+            };
+            SafeCommandResponse {
+                id,
+                command: SafeCommandRx::FreeForm {
+                    stdout: "".to_string(),
+                    stderr: format!("Error: {}", err),
+                },
+                status: -1,
+                result: Some(CommandResult::failed(outcome, format!("{err:#}"))),
+            }
+        }
+    }
+}
+
+async fn bundle(
+    magic: &MagicHandle,
+    host: &dyn Host,
+    profile: SupportBundleProfile,
+) -> Result<(String, u64)> {
+    let dir = tempfile::tempdir()?;
+    let name = format!(
+        "support-bundle-{}-{}",
+        get_serial_number(),
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    let contents = dir.path().join(&name);
+    tokio::fs::create_dir(&contents).await?;
+
+    info!("Collecting {:?} support bundle {}", profile, name);
+    gather(&contents, magic, host, profile).await?;
+    scrub(&contents, magic).await?;
+
+    let archive = dir.path().join(format!("{name}.tar.gz"));
+    let output = host
+        .output(
+            HostCommand::new("tar")
+                .arg("-czf")
+                .arg(archive.to_string_lossy())
+                .arg("-C")
+                .arg(dir.path().to_string_lossy())
+                .arg(&name),
+        )
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let data = tokio::fs::read(&archive).await?;
+    let size = data.len() as u64;
+    let file = upload(magic, &format!("{name}.tar.gz"), data).await?;
+
+    Ok((file, size))
+}
+
+async fn gather(
+    dir: &Path,
+    magic: &MagicHandle,
+    host: &dyn Host,
+    profile: SupportBundleProfile,
+) -> Result<()> {
+    let (since, lines) = match profile {
+        SupportBundleProfile::Standard => ("-24h", "5000"),
+        SupportBundleProfile::Full => ("-7d", "50000"),
+    };
+    capture(
+        host,
+        dir,
+        "journal.txt",
+        "journalctl",
+        &["--no-pager", "--since", since, "--lines", lines],
+    )
+    .await?;
+    capture(host, dir, "dmesg.txt", "dmesg", &["--ctime"]).await?;
+
+    let system_info = serde_json::to_string_pretty(&SystemInfo::new(host).await)?;
+    tokio::fs::write(dir.join("system_info.json"), system_info).await?;
+
+    let magic_file = magic
+        .get_redacted()
+        .await
+        .unwrap_or_else(|| "# no magic.toml loaded\n".to_string());
+    tokio::fs::write(dir.join("magic.toml"), magic_file).await?;
+
+    let state_file = magic
+        .get_redacted_state()
+        .await
+        .unwrap_or_else(|| "# no state.toml loaded\n".to_string());
+    tokio::fs::write(dir.join("state.toml"), state_file).await?;
+
+    let packages = magic
+        .get_packages()
+        .await
+        .into_iter()
+        .map(|package| package.name)
+        .collect::<Vec<_>>();
+    if !packages.is_empty() {
+        let mut args = vec!["-l"];
+        args.extend(packages.iter().map(String::as_str));
+        capture(host, dir, "packages.txt", "dpkg", &args).await?;
+    }
+
+    let mut checks = String::new();
+    for check in magic.get_checks().await {
+        let mut check = InitialCheck::from(check);
+        let result = check.execute(host).await;
+        writeln!(
+            checks,
+            "[{}] [{}] [{}]\n{}\n",
+            if result.is_ok() { " OK " } else { "FAIL" },
+            check.name,
+            check.cmd,
+            check.data.unwrap_or_default()
+        )?;
+    }
+    tokio::fs::write(dir.join("checks.txt"), checks).await?;
+
+    capture(host, dir, "nmcli.txt", "nmcli", &["device", "show"]).await?;
+    capture(host, dir, "addresses.txt", "ip", &["address"]).await?;
+    capture(host, dir, "routes.txt", "ip", &["route"]).await?;
+    capture(host, dir, "disk.txt", "df", &["-h"]).await?;
+
+    if profile == SupportBundleProfile::Full {
+        capture(
+            host,
+            dir,
+            "journal_previous_boot.txt",
+            "journalctl",
+            &["--no-pager", "--boot", "-1", "--lines", lines],
+        )
+        .await?;
+        capture(host, dir, "processes.txt", "ps", &["aux"]).await?;
+    }
+
+    Ok(())
+}
+
+/// Redacts the token and the values of secret variables wherever they
+/// ended up, logs and process lists included.
+async fn scrub(dir: &Path, magic: &MagicHandle) -> Result<()> {
+    let mut secrets = variable::secret_values().await;
+    secrets.extend(magic.get_token().await);
+    // a value contained in another one must not break its redaction
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+    if secrets.is_empty() {
+        return Ok(());
+    }
+
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let content = tokio::fs::read(&path).await?;
+        let mut text = String::from_utf8_lossy(&content).into_owned();
+        let mut redacted = false;
+        for secret in &secrets {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), "<redacted>");
+                redacted = true;
+            }
+        }
+        if redacted {
+            tokio::fs::write(&path, text).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the output of a command to `name`, a failing command is noted in
+/// the file rather than failing the bundle.
+async fn capture(
+    host: &dyn Host,
+    dir: &Path,
+    name: &str,
+    program: &str,
+    args: &[&str],
+) -> Result<()> {
+    let output = timeout(
+        COMMAND_TIMEOUT,
+        host.output(HostCommand::new(program).args(args.iter().copied())),
+    )
+    .await;
+
+    let content = match output {
+        Ok(Ok(output)) => {
+            let mut content = output.stdout;
+            if !output.status.success() {
+                content.extend_from_slice(b"\n# stderr\n");
+                content.extend_from_slice(&output.stderr);
+            }
+            content
+        }
+        Ok(Err(err)) => format!("# failed to run {program}: {err}\n").into_bytes(),
+        Err(_) => format!("# {program} timed out after {COMMAND_TIMEOUT:?}\n").into_bytes(),
+    };
+
+    tokio::fs::write(dir.join(name), content)
+        .await
+        .with_context(|| format!("Failed to write {name}"))
+}
+
+/// Uploads the archive through `/upload`, returns its object key.
+async fn upload(magic: &MagicHandle, name: &str, data: Vec<u8>) -> Result<String> {
+    let token = magic
+        .get_token()
+        .await
+        .ok_or_else(|| anyhow!("Device is not registered"))?;
+    let server = magic.get_server().await;
+
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(data).file_name(name.to_string()),
+    );
+
+    let response = reqwest::Client::builder()
+        .timeout(UPLOAD_TIMEOUT)
+        .build()?
+        .post(format!("{server}/upload/{UPLOAD_PREFIX}"))
+        .header("Authorization", format!("Bearer {}", token))
+        .multipart(form)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("Upload failed with {}", response.status()));
+    }
+
+    Ok(format!("{UPLOAD_PREFIX}/{name}"))
+}
@@ -0,0 +1,163 @@
+use super::registry::{CommandHandler, unsupported};
+use crate::utils::host::{Host, HostCommand};
+use crate::utils::schema::{ConfigFile, SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use anyhow::{Context, Result, anyhow};
+use futures::future::BoxFuture;
+use std::collections::BTreeSet;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::fs;
+use tracing::{info, warn};
+
+/// `UpdateConfigFiles`.
+pub(super) struct ConfigFiles {
+    pub(super) host: Arc<dyn Host>,
+}
+
+impl CommandHandler for ConfigFiles {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::UpdateConfigFiles { files } => {
+                    execute(id, self.host.as_ref(), files).await
+                }
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
+
+pub(super) async fn execute(
+    id: i32,
+    host: &dyn Host,
+    files: Vec<ConfigFile>,
+) -> SafeCommandResponse {
+    let mut changed = vec![];
+    let mut errors = vec![];
+    let mut reload = BTreeSet::new();
+    let mut restart = BTreeSet::new();
+
+    for file in files {
+        match write(host, &file).await {
+            Ok(true) => {
+                info!("Config file {} updated", file.path);
+                reload.extend(file.reload);
+                restart.extend(file.restart);
+                changed.push(file.path);
+            }
+            Ok(false) => {}
+            Err(err) => {
+                warn!("Failed to write config file {}: {:?}", file.path, err);
+                errors.push(format!("{}: {err}", file.path));
+            }
+        }
+    }
+
+    // a restart already picks up the new configuration
+    for unit in reload.difference(&restart) {
+        if let Err(err) = systemctl(host, "reload", unit).await {
+            errors.push(format!("{unit}: {err}"));
+        }
+    }
+    for unit in &restart {
+        if let Err(err) = systemctl(host, "restart", unit).await {
+            errors.push(format!("{unit}: {err}"));
+        }
+    }
+
+    let status = if errors.is_empty() { 0 } else { -1 };
+
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::UpdateConfigFiles { changed, errors },
+        status,
+        result: None,
+    }
+}
+
+/// Writes the file next to its target and renames it into place, so readers
+/// never see it half written. Returns whether anything changed.
+async fn write(host: &dyn Host, file: &ConfigFile) -> Result<bool> {
+    let path = Path::new(&file.path);
+    if !path.is_absolute() {
+        return Err(anyhow!("path is not absolute"));
+    }
+
+    if let Ok(current) = fs::read(path).await {
+        let metadata = fs::metadata(path).await?;
+        if current == file.content.as_bytes()
+            && metadata.permissions().mode() & 0o7777 == file.mode
+            && owned_by(host, path, &file.owner, &file.group).await
+        {
+            return Ok(false);
+        }
+    }
+
+    let parent = path.parent().context("path has no parent")?;
+    let name = path.file_name().context("path has no file name")?;
+    fs::create_dir_all(parent).await?;
+
+    // removed on drop unless it gets renamed into place
+    let temp = tempfile::Builder::new()
+        .prefix(&format!(".{}.", name.to_string_lossy()))
+        .suffix(".smith")
+        .tempfile_in(parent)?
+        .into_temp_path();
+
+    fs::write(&temp, &file.content).await?;
+    fs::set_permissions(&temp, std::fs::Permissions::from_mode(file.mode)).await?;
+    chown(host, &temp, &file.owner, &file.group).await?;
+    temp.persist(path)?;
+
+    Ok(true)
+}
+
+/// Whether the file belongs to `owner:group`, by name or by id.
+async fn owned_by(host: &dyn Host, path: &Path, owner: &str, group: &str) -> bool {
+    let command = HostCommand::new("stat")
+        .arg("-c")
+        .arg("%U:%G %u:%g")
+        .arg(path.to_string_lossy());
+    let Ok(output) = host.output(command).await else {
+        return false;
+    };
+
+    let expected = format!("{owner}:{group}");
+    output.status.success()
+        && String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .any(|ownership| ownership == expected)
+}
+
+async fn chown(host: &dyn Host, path: &Path, owner: &str, group: &str) -> Result<()> {
+    let command = HostCommand::new("chown")
+        .arg(format!("{owner}:{group}"))
+        .arg(path.to_string_lossy());
+    let output = host.output(command).await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "chown failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+async fn systemctl(host: &dyn Host, action: &str, unit: &str) -> Result<()> {
+    info!("Running systemctl {action} {unit}");
+    let output = host
+        .output(HostCommand::new("systemctl").arg(action).arg(unit))
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl {action} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use super::registry::{CommandHandler, unsupported};
+use crate::downloader::DownloaderHandle;
+use crate::utils::schema::{DownloadLimits, SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use futures::future::BoxFuture;
+
+/// `SetDownloadLimits`, `PauseDownloads` and `ResumeDownloads`.
+pub(super) struct Downloads {
+    pub(super) handle: DownloaderHandle,
+}
+
+impl CommandHandler for Downloads {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::SetDownloadLimits { limits } => {
+                    set_limits(id, &self.handle, limits).await
+                }
+                SafeCommandTx::PauseDownloads => pause(id, &self.handle),
+                SafeCommandTx::ResumeDownloads => resume(id, &self.handle),
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
+
+pub(super) async fn set_limits(
+    id: i32,
+    downloader: &DownloaderHandle,
+    limits: DownloadLimits,
+) -> SafeCommandResponse {
+    match downloader.set_limits(limits).await {
+        Ok(()) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::Downloads {
+                message: format!(
+                    "Download limits set to {} MB/s, {} MB/s on metered links",
+                    describe(limits.rate),
+                    describe(limits.metered_rate.or(limits.rate))
+                ),
+            },
+            status: 0,
+            result: None,
+        },
+        Err(e) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::Downloads {
+                message: format!("Error setting download limits: {}", e),
+            },
+            status: -1,
+            result: None,
+        },
+    }
+}
+
+pub(super) fn pause(id: i32, downloader: &DownloaderHandle) -> SafeCommandResponse {
+    downloader.pause();
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::Downloads {
+            message: "Downloads paused".to_string(),
+        },
+        status: 0,
+        result: None,
+    }
+}
+
+pub(super) fn resume(id: i32, downloader: &DownloaderHandle) -> SafeCommandResponse {
+    downloader.resume();
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::Downloads {
+            message: "Downloads resumed".to_string(),
+        },
+        status: 0,
+        result: None,
+    }
+}
+
+fn describe(rate: Option<f64>) -> String {
+    rate.map_or_else(|| "unlimited".to_string(), |rate| rate.to_string())
+}
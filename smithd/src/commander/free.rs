@@ -1,46 +1,106 @@
-use crate::utils::schema::{SafeCommandResponse, SafeCommandRx};
-use anyhow::{Context, Result};
+use super::registry::{CommandHandler, unsupported};
+use crate::utils::host::{Host, HostCommand};
+use crate::utils::schema::{
+    CommandOutcome, CommandResult, SafeCommandResponse, SafeCommandRx, SafeCommandTx,
+};
+use futures::future::BoxFuture;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::{process::Command, time::timeout};
-
-pub(super) async fn execute(id: i32, request: String) -> SafeCommandResponse {
-    match execute_command(&request).await {
-        Ok(output) => {
-            let (status_code, response) = process_output(output);
-            SafeCommandResponse {
-                id,
-                command: response,
-                status: status_code,
+use tokio::time::timeout;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What `sh` exits with when it can't find the program.
+const NOT_FOUND: i32 = 127;
+
+/// `FreeForm`.
+pub(super) struct FreeForm {
+    pub(super) host: Arc<dyn Host>,
+}
+
+impl CommandHandler for FreeForm {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::FreeForm { cmd } => execute(id, self.host.as_ref(), cmd).await,
+                command => unsupported(id, &command),
             }
-        }
-        Err(e) => SafeCommandResponse {
-            id,
-            command: SafeCommandRx::FreeForm {
-                stdout: "".to_string(),
-                stderr: format!("Error: {}", e),
-            },
-            status: -1,
-        },
+        })
     }
 }
 
-async fn execute_command(request: &str) -> Result<std::process::Output> {
-    let future = Command::new("sh")
-        .arg("-c")
-        .kill_on_drop(true)
-        .arg(request)
-        .output();
+pub async fn execute(id: i32, host: &dyn Host, request: String) -> SafeCommandResponse {
+    let future = host.output(HostCommand::shell(&request));
+
+    let (outcome, error) = match timeout(COMMAND_TIMEOUT, future).await {
+        Ok(Ok(output)) => return process_output(id, output),
+        Ok(Err(err)) => (
+            CommandOutcome::InternalError,
+            format!("Failed to run command: {}", err),
+        ),
+        Err(_) => (
+            CommandOutcome::Timeout,
+            format!("Timeout running command ({:?})", COMMAND_TIMEOUT),
+        ),
+    };
 
-    match timeout(Duration::from_secs(60), future).await {
-        Ok(output) => output.context("Failed to run command"),
-        Err(_) => Err(anyhow::anyhow!("Timeout running command (60s)")),
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::FreeForm {
+            stdout: "".to_string(),
+            stderr: format!("Error: {}", error),
+        },
+        status: -1,
+        result: Some(CommandResult::failed(outcome, error)),
     }
 }
 
-fn process_output(output: std::process::Output) -> (i32, SafeCommandRx) {
+fn process_output(id: i32, output: std::process::Output) -> SafeCommandResponse {
     let stdout = String::from_utf8_lossy(&output.stdout).to_string();
     let stderr = String::from_utf8_lossy(&output.stderr).to_string();
     let status_code = output.status.code().unwrap_or(-1);
 
-    (status_code, SafeCommandRx::FreeForm { stdout, stderr })
+    let result = match output.status.code() {
+        Some(0) => None,
+        Some(NOT_FOUND) => Some(CommandResult::failed(
+            CommandOutcome::NonZeroExit,
+            "command not found",
+        )),
+        _ => Some(CommandResult::failed(
+            CommandOutcome::NonZeroExit,
+            format!("{}", output.status),
+        )),
+    };
+
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::FreeForm { stdout, stderr },
+        status: status_code,
+        result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::host::SystemHost;
+
+    #[tokio::test]
+    async fn tells_failures_apart() {
+        let host = SystemHost::shared();
+
+        let response = execute(1, host.as_ref(), "echo hi".to_string()).await;
+        assert_eq!(response.status, 0);
+        assert!(response.result.is_none());
+
+        let response = execute(2, host.as_ref(), "exit 3".to_string()).await;
+        assert_eq!(response.status, 3);
+        let result = response.result.unwrap();
+        assert_eq!(result.outcome, CommandOutcome::NonZeroExit);
+
+        let response = execute(3, host.as_ref(), "no-such-program-here".to_string()).await;
+        let result = response.result.unwrap();
+        assert_eq!(result.outcome, CommandOutcome::NonZeroExit);
+        assert_eq!(result.error.as_deref(), Some("command not found"));
+    }
 }
@@ -1,17 +1,37 @@
 use crate::downloader::DownloaderHandle;
-use crate::filemanager::FileManagerHandle;
+use crate::magic::MagicHandle;
+use crate::network::NetworkHandle;
+use crate::ota::OtaHandle;
+use crate::restart::RestartHandle;
+use crate::shell::ShellHandle;
 use crate::shutdown::ShutdownSignals;
 use crate::tunnel::TunnelHandle;
 use crate::updater::UpdaterHandle;
-use crate::utils::schema::{SafeCommandRequest, SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use crate::utils::host::Host;
+use crate::utils::schema::{
+    CommandOutcome, CommandResult, SafeCommandRequest, SafeCommandResponse, SafeCommandRx,
+    SafeCommandTx,
+};
+use crate::utils::system::get_serial_number;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use registry::{CommandHandler, Plugins, Registry};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
-use tracing::info;
+use tracing::{error, info, warn};
 
-mod free;
+mod agent_config;
+mod bundle;
+mod config;
+mod downloads;
+pub mod free;
 mod network;
 mod ota;
+pub mod plugin;
+pub mod registry;
 mod restart;
+mod shell;
 mod tunnel;
 mod upgrade;
 mod variable;
@@ -20,10 +40,9 @@ struct CommandQueueExecutor {
     shutdown: ShutdownSignals,
     queue: mpsc::Receiver<SafeCommandRequest>,
     responses: mpsc::Sender<SafeCommandResponse>,
-    tunnel_handle: TunnelHandle,
-    updater_handle: UpdaterHandle,
-    downloader_handle: DownloaderHandle,
-    filemanager_handle: FileManagerHandle,
+    magic: MagicHandle,
+    registry: Registry,
+    serial_number: String,
 }
 
 impl CommandQueueExecutor {
@@ -31,60 +50,46 @@ impl CommandQueueExecutor {
         shutdown: ShutdownSignals,
         queue: mpsc::Receiver<SafeCommandRequest>,
         responses: mpsc::Sender<SafeCommandResponse>,
-        tunnel_handle: TunnelHandle,
-        updater_handle: UpdaterHandle,
-        downloader_handle: DownloaderHandle,
-        filemanager_handle: FileManagerHandle,
+        magic: MagicHandle,
+        registry: Registry,
     ) -> Self {
         Self {
             shutdown,
             queue,
             responses,
-            tunnel_handle,
-            updater_handle,
-            downloader_handle,
-            filemanager_handle,
+            magic,
+            registry,
+            serial_number: get_serial_number(),
         }
     }
 
+    /// Runs the command and records how it ended.
     async fn execute_command(&mut self, action: SafeCommandRequest) -> SafeCommandResponse {
-        match action.command {
-            SafeCommandTx::Ping => SafeCommandResponse {
-                id: action.id,
-                command: SafeCommandRx::Pong,
-                status: 0,
-            },
-            SafeCommandTx::UpdateVariables { variables } => {
-                variable::execute(action.id, variables).await
-            }
-            SafeCommandTx::Restart => restart::execute(&action).await,
-            SafeCommandTx::FreeForm { cmd } => free::execute(action.id, cmd).await,
-            SafeCommandTx::OpenTunnel { port } => {
-                tunnel::open_port(action.id, &self.tunnel_handle, port).await
-            }
-            SafeCommandTx::CloseTunnel => tunnel::close_ssh(action.id, &self.tunnel_handle).await,
-            SafeCommandTx::Upgrade => upgrade::upgrade(action.id, &self.updater_handle).await,
-            SafeCommandTx::UpdateNetwork { network } => network::execute(action.id, network).await,
-            SafeCommandTx::DownloadOTA {
-                tools,
-                payload,
-                rate,
-            } => {
-                ota::download_ota(
-                    action.id,
-                    &self.downloader_handle,
-                    &self.filemanager_handle,
-                    &tools,
-                    &payload,
-                    rate,
-                )
-                .await
+        let started_at = Utc::now();
+        let mut response = self.execute_allowed(action).await;
+        finish(&mut response, started_at);
+        response
+    }
+
+    /// Runs the command unless the local policy refuses it.
+    async fn execute_allowed(&mut self, action: SafeCommandRequest) -> SafeCommandResponse {
+        if let Some(policy) = self.magic.get_policy().await {
+            let now = Utc::now();
+            let decision = match policy.check(&action, &self.serial_number, now) {
+                Ok(()) => policy.claim(&action, now).await,
+                Err(reason) => Err(reason),
+            };
+            if let Err(err) = policy.audit(&action, &decision).await {
+                error!("Failed to write to the audit log: {:?}", err);
+                return denied(action.id, "the audit log can't be written".to_string());
             }
-            SafeCommandTx::CheckOTAStatus => {
-                ota::check_ota(action.id, &self.downloader_handle).await
+            if let Err(reason) = decision {
+                warn!("Policy denied command {}: {}", action.id, reason);
+                return denied(action.id, reason);
             }
-            SafeCommandTx::StartOTA => ota::start_ota(action.id, &self.filemanager_handle).await,
         }
+
+        self.registry.dispatch(action.id, action.command).await
     }
 
     async fn run(&mut self) {
@@ -104,6 +109,49 @@ impl CommandQueueExecutor {
     }
 }
 
+/// Fills in the result of the response, keeping the outcome its handler
+/// set.
+pub fn finish(response: &mut SafeCommandResponse, started_at: DateTime<Utc>) {
+    let finished_at = Utc::now();
+    let (outcome, error) = match response.result.take() {
+        Some(result) => (result.outcome, result.error),
+        None => outcome(response),
+    };
+
+    response.result = Some(CommandResult {
+        started_at,
+        finished_at,
+        duration_ms: (finished_at - started_at).num_milliseconds().max(0) as u64,
+        outcome,
+        error,
+    });
+}
+
+/// How a command ended, for handlers that only set the status.
+fn outcome(response: &SafeCommandResponse) -> (CommandOutcome, Option<String>) {
+    match &response.command {
+        SafeCommandRx::PolicyDenied { reason } => {
+            (CommandOutcome::PolicyDenied, Some(reason.clone()))
+        }
+        _ if response.status == 0 => (CommandOutcome::Success, None),
+        // what most handlers report their errors with
+        SafeCommandRx::FreeForm { stderr, .. } if !stderr.trim().is_empty() => (
+            CommandOutcome::InternalError,
+            Some(stderr.trim().to_string()),
+        ),
+        _ => (CommandOutcome::InternalError, None),
+    }
+}
+
+fn denied(id: i32, reason: String) -> SafeCommandResponse {
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::PolicyDenied { reason },
+        status: -1,
+        result: None,
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum State {
     Queued,
@@ -125,7 +173,7 @@ struct Commander {
 
 enum CommanderMessage {
     QueueCommand {
-        action: SafeCommandRequest,
+        action: Box<SafeCommandRequest>,
     },
     QueueResponse {
         action: SafeCommandResponse,
@@ -164,7 +212,7 @@ impl Commander {
                                 state: State::Queued,
                                 response: None,
                             });
-                            _ = self.queue.send(action).await;
+                            _ = self.queue.send(*action).await;
                         }
                         CommanderMessage::GetResults { tx } => {
                             info!("Results size: {}", self.results.len());
@@ -203,22 +251,99 @@ impl Commander {
     }
 }
 
+/// `Ping`.
+struct Ping;
+
+impl CommandHandler for Ping {
+    fn execute(&self, id: i32, _: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            SafeCommandResponse {
+                id,
+                command: SafeCommandRx::Pong,
+                status: 0,
+                result: None,
+            }
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct CommanderHandle {
     sender: mpsc::Sender<CommanderMessage>,
+    plugins: Arc<Plugins>,
 }
 
 impl CommanderHandle {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         shutdown: ShutdownSignals,
         tunnel: TunnelHandle,
         updater: UpdaterHandle,
         downloader: DownloaderHandle,
-        filemanager: FileManagerHandle,
+        shell: ShellHandle,
+        network: NetworkHandle,
+        ota: OtaHandle,
+        restart: RestartHandle,
+        magic: MagicHandle,
+        host: Arc<dyn Host>,
     ) -> Self {
         let (sender, receiver) = mpsc::channel(10);
         let (command_queue_tx, command_queue_rx) = mpsc::channel(10);
         let (response_queue_tx, response_queue_rx) = mpsc::channel(10);
+        let plugins = Arc::new(Plugins::new(host.clone()));
+
+        let mut registry = Registry::default();
+        registry.register(&["Ping"], Ping);
+        registry.register(&["UpdateVariables"], variable::Variables);
+        registry.register(
+            &["UpdateConfigFiles"],
+            config::ConfigFiles { host: host.clone() },
+        );
+        registry.register(
+            &["Restart", "CancelRestart"],
+            restart::Restarts { handle: restart },
+        );
+        registry.register(&["FreeForm"], free::FreeForm { host: host.clone() });
+        registry.register(
+            &["OpenTunnel", "CloseTunnel"],
+            tunnel::Tunnels { handle: tunnel },
+        );
+        registry.register(&["Upgrade"], upgrade::Upgrade { handle: updater });
+        registry.register(&["UpdateNetwork"], network::Networks { handle: network });
+        registry.register(
+            &["DownloadOTA", "CheckOTAStatus", "StartOTA"],
+            ota::Ota { handle: ota },
+        );
+        registry.register(&["OpenShell"], shell::Shell { handle: shell });
+        registry.register(
+            &["CollectSupportBundle"],
+            bundle::SupportBundle {
+                magic: magic.clone(),
+                host,
+            },
+        );
+        registry.register(
+            &["SetDownloadLimits", "PauseDownloads", "ResumeDownloads"],
+            downloads::Downloads { handle: downloader },
+        );
+        registry.register(
+            &["UpdateAgentConfig"],
+            agent_config::AgentConfig {
+                magic: magic.clone(),
+            },
+        );
+        registry.register(
+            &["Plugin"],
+            plugin::Plugin {
+                plugins: plugins.clone(),
+            },
+        );
+        debug_assert!(
+            SafeCommandTx::NAMES
+                .iter()
+                .all(|variant| registry.handles(variant))
+        );
+
         let mut actor = Commander::new(
             shutdown.clone(),
             receiver,
@@ -229,22 +354,27 @@ impl CommanderHandle {
             shutdown,
             command_queue_rx,
             response_queue_tx,
-            tunnel,
-            updater,
-            downloader,
-            filemanager,
+            magic,
+            registry,
         );
         tokio::spawn(async move { actor.run().await });
         tokio::spawn(async move { actor2.run().await });
 
-        Self { sender }
+        Self { sender, plugins }
+    }
+
+    /// Plugins that `SafeCommandTx::Plugin` can run.
+    pub async fn plugins(&self) -> Vec<String> {
+        self.plugins.names().await
     }
 
     pub async fn execute_api_batch(&self, commands: Vec<SafeCommandRequest>) {
         for command in commands {
             _ = self
                 .sender
-                .send(CommanderMessage::QueueCommand { action: command })
+                .send(CommanderMessage::QueueCommand {
+                    action: Box::new(command),
+                })
                 .await;
         }
     }
@@ -1,130 +1,114 @@
-use tracing::warn;
+use tracing::{error, warn};
 
-use crate::downloader::{DownloaderHandle, DownloadingStatus};
-use crate::filemanager::FileManagerHandle;
-use crate::utils::schema::{SafeCommandResponse, SafeCommandRx};
+use super::registry::{CommandHandler, unsupported};
+use crate::downloader::DownloadingStatus;
+use crate::ota::OtaHandle;
+use crate::utils::schema::{OtaChecksums, SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use futures::future::BoxFuture;
 
-struct OTAConstants;
+/// `DownloadOTA`, `CheckOTAStatus` and `StartOTA`.
+pub(super) struct Ota {
+    pub(super) handle: OtaHandle,
+}
 
-impl OTAConstants {
-    const TOOLS_STORAGE: &'static str = "/otatool";
-    const OTA_STORAGE: &'static str = "/ota";
-    const PACKAGE_FILE: &'static str = "ota_payload_package.tar.gz";
-    const TOOLS_FILE: &'static str = "ota_tools.tbz2";
-    const OTA_SCRIPT_DIR: &'static str =
-        "/otatool/Linux_for_Tegra/tools/ota_tools/version_upgrade/";
+impl CommandHandler for Ota {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::DownloadOTA {
+                    profile,
+                    tools,
+                    payload,
+                    rate,
+                    expected_version,
+                    checksums,
+                } => {
+                    download_ota(
+                        id,
+                        &self.handle,
+                        profile.as_deref(),
+                        tools.as_deref(),
+                        &payload,
+                        rate,
+                        expected_version.as_deref(),
+                        checksums,
+                    )
+                    .await
+                }
+                SafeCommandTx::CheckOTAStatus => check_ota(id, &self.handle).await,
+                SafeCommandTx::StartOTA { profile } => {
+                    start_ota(id, &self.handle, profile.as_deref()).await
+                }
+                command => unsupported(id, &command),
+            }
+        })
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn download_ota(
     id: i32,
-    download_handle: &DownloaderHandle,
-    file_handle: &FileManagerHandle,
-    tools_file: &str,
+    ota_handle: &OtaHandle,
+    profile: Option<&str>,
+    tools_file: Option<&str>,
     package_file: &str,
     rate: f64,
+    expected_version: Option<&str>,
+    checksums: Option<OtaChecksums>,
 ) -> SafeCommandResponse {
-    // Create OTA storage folder
-    let arguments: Vec<String> = vec!["-p".to_owned(), OTAConstants::OTA_STORAGE.to_owned()];
-    let _ = file_handle // No errors returned from the function
-        .execute_system_command("mkdir", arguments, None)
-        .await;
-
-    // Create OTA tools storage folder
-    let arguments: Vec<String> = vec!["-p".to_owned(), OTAConstants::TOOLS_STORAGE.to_owned()];
-    let _ = file_handle
-        .execute_system_command("mkdir", arguments, None)
-        .await;
-
-    // Download the OTA tools
-    let remote_file = format!("ota/{}", tools_file);
-    let local_file = format!(
-        "{}/{}",
-        OTAConstants::TOOLS_STORAGE,
-        OTAConstants::TOOLS_FILE
-    );
-    let _ = download_handle
-        .download(remote_file.as_str(), local_file.as_str(), rate)
-        .await;
-
-    // Download the OTA payload package
-    let remote_file = format!("ota/{}", package_file);
-    let local_file = format!(
-        "{}/{}",
-        OTAConstants::OTA_STORAGE,
-        OTAConstants::PACKAGE_FILE
-    );
-    let _ = download_handle
-        .download(remote_file.as_str(), local_file.as_str(), rate)
-        .await;
-
-    SafeCommandResponse {
-        id,
-        command: SafeCommandRx::DownloadOTA,
-        status: 0,
+    match ota_handle
+        .download(
+            profile,
+            tools_file,
+            package_file,
+            rate,
+            expected_version,
+            checksums,
+        )
+        .await
+    {
+        Ok(()) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::DownloadOTA,
+            status: 0,
+            result: None,
+        },
+        Err(err) => failed(id, err),
     }
 }
 
-pub(super) async fn start_ota(id: i32, file_handle: &FileManagerHandle) -> SafeCommandResponse {
-    let local_file = format!(
-        "{}/{}",
-        OTAConstants::OTA_STORAGE,
-        OTAConstants::PACKAGE_FILE
-    );
-    let file = format!(
-        "{}/{}",
-        OTAConstants::TOOLS_STORAGE,
-        OTAConstants::TOOLS_FILE
-    );
-    match file_handle.extract_here(file.as_str()).await {
-        Ok(_) => {
-            let arguments: Vec<String> = vec![local_file];
-            match file_handle
-                .execute_script(
-                    "nv_ota_start.sh",
-                    arguments,
-                    Some(OTAConstants::OTA_SCRIPT_DIR),
-                )
-                .await
-            {
-                Ok(_) => {
-                    // Spawn a thread to reboot the device after a short delay to return success first
-                    let file_handle_clone = file_handle.clone();
-                    tokio::spawn(async move {
-                        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-                        let arguments: Vec<String> = Vec::new();
-                        let _ = file_handle_clone
-                            .execute_system_command("reboot", arguments, None)
-                            .await;
-                    });
-                }
-                Err(_) => {
-                    return SafeCommandResponse {
-                        id,
-                        command: SafeCommandRx::DownloadOTA,
-                        status: -1,
-                    };
-                }
-            }
-        }
-        Err(_) => {
-            return SafeCommandResponse {
-                id,
-                command: SafeCommandRx::DownloadOTA,
-                status: -1,
-            };
-        }
+pub(super) async fn start_ota(
+    id: i32,
+    ota_handle: &OtaHandle,
+    profile: Option<&str>,
+) -> SafeCommandResponse {
+    match ota_handle.start(profile).await {
+        Ok(_) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::DownloadOTA,
+            status: 0,
+            result: None,
+        },
+        Err(err) => failed(id, err),
     }
+}
 
+fn failed(id: i32, err: anyhow::Error) -> SafeCommandResponse {
+    error!("OTA failed: {:?}", err);
     SafeCommandResponse {
         id,
-        command: SafeCommandRx::DownloadOTA,
-        status: 0,
+        command: SafeCommandRx::FreeForm {
+            stdout: "".to_string(),
+            stderr: format!("Error: {}", err),
+        },
+        status: -1,
+        result: None,
     }
 }
 
-pub(super) async fn check_ota(id: i32, download_handle: &DownloaderHandle) -> SafeCommandResponse {
+pub(super) async fn check_ota(id: i32, ota_handle: &OtaHandle) -> SafeCommandResponse {
     warn!("Received check download status message");
-    let result = download_handle.check_download_status().await;
+    let result = ota_handle.download_status().await;
     warn!("Do we return from the check function?");
     let result_unwrapped = match result {
         Ok(result) => result,
@@ -135,6 +119,7 @@ pub(super) async fn check_ota(id: i32, download_handle: &DownloaderHandle) -> Sa
                     status: "Error checking download status".to_string(),
                 },
                 status: -1,
+                result: None,
             };
         }
     };
@@ -142,33 +127,36 @@ pub(super) async fn check_ota(id: i32, download_handle: &DownloaderHandle) -> Sa
     match result_unwrapped {
         DownloadingStatus::Failed => {
             let status = "Failed";
-            return SafeCommandResponse {
+            SafeCommandResponse {
                 id,
                 command: SafeCommandRx::CheckOTAStatus {
                     status: status.to_string(),
                 },
                 status: -1,
-            };
+                result: None,
+            }
         }
         DownloadingStatus::Downloading => {
             let status = "Downloading";
-            return SafeCommandResponse {
+            SafeCommandResponse {
                 id,
                 command: SafeCommandRx::CheckOTAStatus {
                     status: status.to_string(),
                 },
                 status: -1,
-            };
+                result: None,
+            }
         }
         DownloadingStatus::Success => {
             let status = "Success";
-            return SafeCommandResponse {
+            SafeCommandResponse {
                 id,
                 command: SafeCommandRx::CheckOTAStatus {
                     status: status.to_string(),
                 },
                 status: 0,
-            };
+                result: None,
+            }
         }
     }
 }
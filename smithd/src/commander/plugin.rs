@@ -0,0 +1,70 @@
+use super::registry::{CommandHandler, Plugins, unsupported};
+use crate::utils::schema::{
+    CommandOutcome, CommandResult, SafeCommandResponse, SafeCommandRx, SafeCommandTx,
+};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::error;
+
+const PLUGIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `Plugin`, run by the plugin of that name.
+pub(super) struct Plugin {
+    pub(super) plugins: Arc<Plugins>,
+}
+
+impl CommandHandler for Plugin {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::Plugin { name, args } => {
+                    execute(id, &self.plugins, name, args).await
+                }
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
+
+pub async fn execute(id: i32, plugins: &Plugins, name: String, args: Value) -> SafeCommandResponse {
+    let handler = match plugins.get(&name).await {
+        Some(handler) => handler,
+        None => {
+            let error = format!("No plugin named {}", name);
+            return failed(id, name, CommandOutcome::Unsupported, error);
+        }
+    };
+
+    match timeout(PLUGIN_TIMEOUT, handler.execute(args)).await {
+        Ok(Ok(output)) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::Plugin { name, output },
+            status: 0,
+            result: None,
+        },
+        Ok(Err(err)) => {
+            let error = err.to_string();
+            failed(id, name, CommandOutcome::InternalError, error)
+        }
+        Err(_) => {
+            let error = format!("Timeout running plugin ({:?})", PLUGIN_TIMEOUT);
+            failed(id, name, CommandOutcome::Timeout, error)
+        }
+    }
+}
+
+fn failed(id: i32, name: String, outcome: CommandOutcome, error: String) -> SafeCommandResponse {
+    error!("Plugin {} failed: {}", name, error);
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::PluginFailed {
+            name,
+            error: error.clone(),
+        },
+        status: -1,
+        result: Some(CommandResult::failed(outcome, error)),
+    }
+}
@@ -0,0 +1,277 @@
+//! Every command smithd runs goes through the [`Registry`], where each
+//! built-in `SafeCommandTx` variant has a [`CommandHandler`] registered.
+//!
+//! Commands run through `SafeCommandTx::Plugin` don't need a variant of their
+//! own in the schema, the API and the CLI. Their handlers are either compiled
+//! into smithd and registered with [`Plugins`], or executables in
+//! [`PLUGINS_DIR`] named after the plugin. Those get the arguments as JSON on
+//! stdin and print their output as JSON on stdout, nothing printed is `null`.
+//! Exiting with anything but 0 fails the command with stderr as the error.
+
+use crate::utils::host::{Host, HostCommand};
+use crate::utils::schema::{
+    CommandOutcome, CommandResult, SafeCommandResponse, SafeCommandRx, SafeCommandTx,
+};
+use anyhow::{Context, Result, anyhow};
+use futures::future::BoxFuture;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const PLUGINS_DIR: &str = "/etc/smith/commands.d";
+
+/// Runs the `SafeCommandTx` variants it is registered for.
+pub trait CommandHandler: Send + Sync {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse>;
+}
+
+/// Runs a plugin with the arguments of `SafeCommandTx::Plugin`.
+pub trait PluginHandler: Send + Sync {
+    fn execute(&self, args: Value) -> BoxFuture<'_, Result<Value>>;
+}
+
+#[derive(Default)]
+pub struct Registry {
+    handlers: HashMap<&'static str, Arc<dyn CommandHandler>>,
+}
+
+impl Registry {
+    /// Registers the handler for the variants, by their `SafeCommandTx::name`.
+    pub fn register(&mut self, variants: &[&'static str], handler: impl CommandHandler + 'static) {
+        let handler: Arc<dyn CommandHandler> = Arc::new(handler);
+        for variant in variants {
+            self.handlers.insert(variant, handler.clone());
+        }
+    }
+
+    pub fn handles(&self, variant: &str) -> bool {
+        self.handlers.contains_key(variant)
+    }
+
+    pub async fn dispatch(&self, id: i32, command: SafeCommandTx) -> SafeCommandResponse {
+        match self.handlers.get(command.name()) {
+            Some(handler) => handler.execute(id, command).await,
+            None => unsupported(id, &command),
+        }
+    }
+}
+
+/// Answer for a command no handler is registered for.
+pub fn unsupported(id: i32, command: &SafeCommandTx) -> SafeCommandResponse {
+    let reason = format!("smithd has no handler for {}", command.name());
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::Unsupported {
+            reason: reason.clone(),
+        },
+        status: -1,
+        result: Some(CommandResult::failed(CommandOutcome::Unsupported, reason)),
+    }
+}
+
+pub struct Plugins {
+    handlers: BTreeMap<String, Arc<dyn PluginHandler>>,
+    dir: PathBuf,
+    host: Arc<dyn Host>,
+}
+
+impl Plugins {
+    /// With the plugins compiled into smithd.
+    pub fn new(host: Arc<dyn Host>) -> Self {
+        let mut plugins = Self {
+            handlers: BTreeMap::new(),
+            dir: PathBuf::from(PLUGINS_DIR),
+            host: host.clone(),
+        };
+        plugins.register("service-status", ServiceStatus { host });
+        plugins
+    }
+
+    pub fn register(&mut self, name: &str, handler: impl PluginHandler + 'static) {
+        self.handlers.insert(name.to_string(), Arc::new(handler));
+    }
+
+    /// Compiled in handlers win over executables of the same name.
+    pub async fn get(&self, name: &str) -> Option<Arc<dyn PluginHandler>> {
+        if let Some(handler) = self.handlers.get(name) {
+            return Some(handler.clone());
+        }
+
+        let path = self.dir.join(name);
+        if !valid_name(name) || !is_executable(&path).await {
+            return None;
+        }
+        Some(Arc::new(External {
+            path,
+            host: self.host.clone(),
+        }))
+    }
+
+    /// Every plugin that can be run right now.
+    pub async fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.handlers.keys().cloned().collect();
+
+        if let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if valid_name(&name)
+                    && !self.handlers.contains_key(&name)
+                    && is_executable(&entry.path()).await
+                {
+                    names.push(name);
+                }
+            }
+        }
+
+        names.sort();
+        names
+    }
+}
+
+/// Keeps the name from pointing out of the plugins directory.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+async fn is_executable(path: &Path) -> bool {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// An executable of the plugins directory.
+struct External {
+    path: PathBuf,
+    host: Arc<dyn Host>,
+}
+
+impl PluginHandler for External {
+    fn execute(&self, args: Value) -> BoxFuture<'_, Result<Value>> {
+        Box::pin(async move {
+            let command =
+                HostCommand::new(self.path.to_string_lossy()).stdin(serde_json::to_vec(&args)?);
+            let output = self.host.output(command).await?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+
+            if output.stdout.iter().all(u8::is_ascii_whitespace) {
+                return Ok(Value::Null);
+            }
+            serde_json::from_slice(&output.stdout).context("printed invalid JSON")
+        })
+    }
+}
+
+/// State of a systemd unit, `{"unit": "smithd"}`.
+struct ServiceStatus {
+    host: Arc<dyn Host>,
+}
+
+const SERVICE_PROPERTIES: &str = "ActiveState,SubState,MainPID,NRestarts,ActiveEnterTimestamp";
+
+impl PluginHandler for ServiceStatus {
+    fn execute(&self, args: Value) -> BoxFuture<'_, Result<Value>> {
+        Box::pin(async move {
+            let unit = args["unit"]
+                .as_str()
+                .ok_or_else(|| anyhow!("expected {{\"unit\": \"<name>\"}}"))?;
+            let output = self
+                .host
+                .output(
+                    HostCommand::new("systemctl")
+                        .arg("show")
+                        .arg(unit)
+                        .arg(format!("--property={SERVICE_PROPERTIES}")),
+                )
+                .await?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "systemctl failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+
+            let properties: Map<String, Value> = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, value)| (key.to_string(), Value::from(value)))
+                .collect();
+            Ok(Value::Object(properties))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::host::SystemHost;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn runs_executables_of_the_plugins_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let plugin = dir.path().join("echo-args");
+        std::fs::write(&plugin, "#!/bin/sh\ncat\n").unwrap();
+        std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.path().join("not-executable"), "").unwrap();
+
+        let plugins = Plugins {
+            dir: dir.path().to_path_buf(),
+            ..Plugins::new(SystemHost::shared())
+        };
+        assert_eq!(plugins.names().await, ["echo-args", "service-status"]);
+        assert!(plugins.get("not-executable").await.is_none());
+        assert!(plugins.get("../echo-args").await.is_none());
+
+        let output = plugins
+            .get("echo-args")
+            .await
+            .unwrap()
+            .execute(json!({"unit": "smithd"}))
+            .await
+            .unwrap();
+        assert_eq!(output, json!({"unit": "smithd"}));
+    }
+
+    #[tokio::test]
+    async fn dispatches_by_variant() {
+        struct Pong;
+        impl CommandHandler for Pong {
+            fn execute(&self, id: i32, _: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+                Box::pin(async move {
+                    SafeCommandResponse {
+                        id,
+                        command: SafeCommandRx::Pong,
+                        status: 0,
+                        result: None,
+                    }
+                })
+            }
+        }
+
+        let mut registry = Registry::default();
+        registry.register(&["Ping"], Pong);
+
+        let response = registry.dispatch(1, SafeCommandTx::Ping).await;
+        assert!(matches!(response.command, SafeCommandRx::Pong));
+
+        let response = registry.dispatch(2, SafeCommandTx::Upgrade).await;
+        assert!(matches!(
+            response.command,
+            SafeCommandRx::Unsupported { .. }
+        ));
+        assert_eq!(response.id, 2);
+    }
+}
@@ -1,28 +1,64 @@
-use crate::utils::schema::{SafeCommandRequest, SafeCommandResponse, SafeCommandRx};
-use tokio::process::Command;
+use super::registry::{CommandHandler, unsupported};
+use crate::restart::RestartHandle;
+use crate::utils::schema::{RestartAt, SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use futures::future::BoxFuture;
 
-pub(super) async fn execute(request: &SafeCommandRequest) -> SafeCommandResponse {
-    let cmd = Command::new("shutdown").arg("-r").arg("+1").output().await;
+/// `Restart` and `CancelRestart`.
+pub(super) struct Restarts {
+    pub(super) handle: RestartHandle,
+}
 
-    match cmd {
-        Ok(output) => {
-            let status = output.status.code().unwrap_or(1);
-            // TODO: should we get stderr as well?
-            let details = String::from_utf8_lossy(&output.stdout).to_string();
-            SafeCommandResponse {
-                id: request.id,
-                command: SafeCommandRx::Restart { message: details },
-                status,
-            }
-        }
-        Err(e) => {
-            let status = -1;
-            let details = format!("Error executing command: {}", e);
-            SafeCommandResponse {
-                id: request.id,
-                command: SafeCommandRx::Restart { message: details },
-                status,
+impl CommandHandler for Restarts {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::Restart { at, reason, force } => {
+                    schedule(id, &self.handle, at, reason, force).await
+                }
+                SafeCommandTx::CancelRestart => cancel(id, &self.handle).await,
+                command => unsupported(id, &command),
             }
-        }
+        })
+    }
+}
+
+pub(super) async fn schedule(
+    id: i32,
+    restart_handle: &RestartHandle,
+    at: Option<RestartAt>,
+    reason: Option<String>,
+    force: bool,
+) -> SafeCommandResponse {
+    match restart_handle.schedule(at, reason, force).await {
+        Ok(pending) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::Restart {
+                message: format!("Restart scheduled for {}", pending.at),
+            },
+            status: 0,
+            result: None,
+        },
+        Err(e) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::Restart {
+                message: format!("Error scheduling restart: {}", e),
+            },
+            status: -1,
+            result: None,
+        },
+    }
+}
+
+pub(super) async fn cancel(id: i32, restart_handle: &RestartHandle) -> SafeCommandResponse {
+    let message = match restart_handle.cancel().await {
+        Some(pending) => format!("Restart scheduled for {} cancelled", pending.at),
+        None => "No restart was scheduled".to_string(),
+    };
+
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::Restart { message },
+        status: 0,
+        result: None,
     }
 }
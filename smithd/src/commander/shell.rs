@@ -0,0 +1,48 @@
+use super::registry::{CommandHandler, unsupported};
+use crate::shell::ShellHandle;
+use crate::utils::schema::{SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use futures::future::BoxFuture;
+use tracing::error;
+
+/// `OpenShell`.
+pub(super) struct Shell {
+    pub(super) handle: ShellHandle,
+}
+
+impl CommandHandler for Shell {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::OpenShell {
+                    session,
+                    cols,
+                    rows,
+                } => open(id, &self.handle, session, cols, rows).await,
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
+
+pub(super) async fn open(
+    id: i32,
+    shell_handle: &ShellHandle,
+    session: String,
+    cols: u16,
+    rows: u16,
+) -> SafeCommandResponse {
+    let status = match shell_handle.open(session, cols, rows).await {
+        Ok(()) => 0,
+        Err(err) => {
+            error!("Failed to open shell session: {:?}", err);
+            -1
+        }
+    };
+
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::ShellOpened,
+        status,
+        result: None,
+    }
+}
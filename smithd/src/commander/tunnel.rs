@@ -1,12 +1,40 @@
+use super::registry::{CommandHandler, unsupported};
 use crate::tunnel::TunnelHandle;
-use crate::utils::schema::{SafeCommandResponse, SafeCommandRx};
+use crate::utils::schema::{SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use futures::future::BoxFuture;
+use std::time::Duration;
+
+/// `OpenTunnel` and `CloseTunnel`.
+pub(super) struct Tunnels {
+    pub(super) handle: TunnelHandle,
+}
+
+impl CommandHandler for Tunnels {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::OpenTunnel {
+                    port,
+                    ttl,
+                    requested_by,
+                } => open_port(id, &self.handle, port, ttl, requested_by).await,
+                SafeCommandTx::CloseTunnel { port } => close_port(id, &self.handle, port).await,
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
 
 pub(super) async fn open_port(
     id: i32,
     tunnel_handle: &TunnelHandle,
     port: Option<u16>,
+    ttl: Option<u64>,
+    requested_by: Option<String>,
 ) -> SafeCommandResponse {
-    let remote_port = tunnel_handle.start_tunnel(port).await;
+    let remote_port = tunnel_handle
+        .start_tunnel(port, ttl.map(Duration::from_secs), requested_by)
+        .await;
     let status = if remote_port > 0 { 0 } else { -1 };
 
     SafeCommandResponse {
@@ -15,15 +43,22 @@ pub(super) async fn open_port(
             port_server: remote_port,
         },
         status,
+        result: None,
     }
 }
 
-pub(super) async fn close_ssh(id: i32, tunnel_handle: &TunnelHandle) -> SafeCommandResponse {
-    tunnel_handle.stop_ssh_tunnel().await;
+pub(super) async fn close_port(
+    id: i32,
+    tunnel_handle: &TunnelHandle,
+    port: Option<u16>,
+) -> SafeCommandResponse {
+    let closed = tunnel_handle.stop_tunnel(port).await;
+    let status = if closed { 0 } else { -1 };
 
     SafeCommandResponse {
         id,
         command: SafeCommandRx::TunnelClosed,
-        status: 0,
+        status,
+        result: None,
     }
 }
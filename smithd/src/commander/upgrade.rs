@@ -1,5 +1,23 @@
+use super::registry::{CommandHandler, unsupported};
 use crate::updater::UpdaterHandle;
-use crate::utils::schema::{SafeCommandResponse, SafeCommandRx};
+use crate::utils::schema::{SafeCommandResponse, SafeCommandRx, SafeCommandTx};
+use futures::future::BoxFuture;
+
+/// `Upgrade`.
+pub(super) struct Upgrade {
+    pub(super) handle: UpdaterHandle,
+}
+
+impl CommandHandler for Upgrade {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::Upgrade => upgrade(id, &self.handle).await,
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
 
 pub(super) async fn upgrade(id: i32, updater_handle: &UpdaterHandle) -> SafeCommandResponse {
     updater_handle.check_for_updates().await;
@@ -8,5 +26,6 @@ pub(super) async fn upgrade(id: i32, updater_handle: &UpdaterHandle) -> SafeComm
         id,
         command: SafeCommandRx::Upgraded,
         status: 0,
+        result: None,
     }
 }
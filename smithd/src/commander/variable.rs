@@ -1,27 +1,98 @@
-use crate::utils::schema::{SafeCommandResponse, SafeCommandRx};
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::Write;
-
-pub(super) async fn execute(id: i32, variables: HashMap<String, String>) -> SafeCommandResponse {
-    if let Ok(mut file) = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open("/root/.teton_environment")
-    {
-        for (key, value) in variables {
-            _ = writeln!(file, "{}={}", key, value);
-        }
-        return SafeCommandResponse {
+use super::registry::{CommandHandler, unsupported};
+use crate::magic::state::{WriteOptions, write_atomically_with};
+use crate::utils::schema::{
+    CommandOutcome, CommandResult, SafeCommandResponse, SafeCommandRx, SafeCommandTx,
+};
+use futures::future::BoxFuture;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tracing::warn;
+
+/// Environment file services from before config files still source. New
+/// consumers should get a templated config file instead.
+const ENVIRONMENT_FILE: &str = "/root/.teton_environment";
+/// Backup earlier versions kept, it holds the secrets as well.
+const STALE_BACKUP: &str = "/root/.teton_environment.bak";
+/// Comment marking the variable after it as secret.
+const SECRET_MARKER: &str = "# secret: ";
+
+/// `UpdateVariables`.
+pub(super) struct Variables;
+
+impl CommandHandler for Variables {
+    fn execute(&self, id: i32, command: SafeCommandTx) -> BoxFuture<'_, SafeCommandResponse> {
+        Box::pin(async move {
+            match command {
+                SafeCommandTx::UpdateVariables { variables, secrets } => {
+                    execute(id, variables, secrets).await
+                }
+                command => unsupported(id, &command),
+            }
+        })
+    }
+}
+
+pub(super) async fn execute(
+    id: i32,
+    variables: HashMap<String, String>,
+    secrets: Vec<String>,
+) -> SafeCommandResponse {
+    let mut contents = String::new();
+    for name in secrets {
+        contents.push_str(&format!("{SECRET_MARKER}{name}\n"));
+    }
+    for (key, value) in BTreeMap::from_iter(variables) {
+        contents.push_str(&format!("{key}={value}\n"));
+    }
+
+    // only root reads the secrets, and no copy of them is kept around
+    let options = WriteOptions {
+        backup: false,
+        mode: Some(0o600),
+    };
+    let written =
+        write_atomically_with(Path::new(ENVIRONMENT_FILE), contents.as_bytes(), options).await;
+    if written.is_ok() {
+        tokio::fs::remove_file(STALE_BACKUP).await.ok();
+    }
+
+    match written {
+        Ok(()) => SafeCommandResponse {
             id,
             command: SafeCommandRx::UpdateVariables,
             status: 0,
-        };
-    }
-    SafeCommandResponse {
-        id,
-        command: SafeCommandRx::UpdateVariables,
-        status: -1,
+            result: None,
+        },
+        Err(err) => {
+            warn!("Failed to write {ENVIRONMENT_FILE}: {:?}", err);
+            SafeCommandResponse {
+                id,
+                command: SafeCommandRx::UpdateVariables,
+                status: -1,
+                result: Some(CommandResult::failed(
+                    CommandOutcome::InternalError,
+                    format!("Failed to write {ENVIRONMENT_FILE}: {err}"),
+                )),
+            }
+        }
     }
 }
+
+/// Values of the secret variables in the environment file.
+pub(super) async fn secret_values() -> Vec<String> {
+    let Ok(contents) = tokio::fs::read_to_string(ENVIRONMENT_FILE).await else {
+        return vec![];
+    };
+
+    let secrets: Vec<&str> = contents
+        .lines()
+        .filter_map(|line| line.strip_prefix(SECRET_MARKER))
+        .collect();
+
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, value)| secrets.contains(name) && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+        .collect()
+}
@@ -0,0 +1,99 @@
+use crate::magic::migrate;
+use crate::magic::state::{MagicState, WriteOptions, write_atomically_with};
+use crate::magic::structure::MagicFile;
+use anyhow::{Result, anyhow};
+use clap::Subcommand;
+use std::path::PathBuf;
+
+#[derive(Subcommand, Debug)]
+pub(super) enum ConfigCommand {
+    /// Check that magic.toml can be loaded, without starting anything
+    Validate {
+        #[arg(help = "magic.toml to check instead of the one smithd loads", long)]
+        path: Option<PathBuf>,
+    },
+    /// Print magic.toml merged with magic.d as smithd reads it, secrets redacted
+    Show {
+        #[arg(help = "magic.toml to print instead of the one smithd loads", long)]
+        path: Option<PathBuf>,
+    },
+    /// Rewrite magic.toml in the current schema version, keeping a .bak
+    Migrate {
+        #[arg(help = "magic.toml to migrate instead of the one smithd loads", long)]
+        path: Option<PathBuf>,
+    },
+}
+
+pub(super) async fn execute(command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Validate { path } => validate(path).await,
+        ConfigCommand::Show { path } => show(path).await,
+        ConfigCommand::Migrate { path } => migrate(path).await,
+    }
+}
+
+fn locate(path: Option<PathBuf>) -> Result<PathBuf> {
+    path.or_else(MagicFile::locate)
+        .ok_or_else(|| anyhow!("No magic.toml found"))
+}
+
+async fn validate(path: Option<PathBuf>) -> Result<()> {
+    let path = locate(path)?;
+    let (_, version, _) = MagicFile::read_base(&path)?;
+    MagicFile::load_from_path(&path.to_string_lossy())?;
+
+    let drop_ins = MagicFile::drop_ins(&path)?;
+    println!(
+        "{} is valid, with {} drop-ins",
+        path.display(),
+        drop_ins.len()
+    );
+    if version < migrate::CURRENT_VERSION {
+        println!(
+            "It is at magic_version {}, `smithd config migrate` upgrades it to {}",
+            version,
+            migrate::CURRENT_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Prints magic.toml merged with its drop-ins.
+async fn show(path: Option<PathBuf>) -> Result<()> {
+    let path = locate(path)?;
+    let (magic_file, _, _) = MagicFile::load_from_path(&path.to_string_lossy())?;
+    print!("{}", magic_file.to_redacted_string()?);
+    Ok(())
+}
+
+/// Rewrites magic.toml alone, drop-ins are left as they are. Runtime state
+/// it still holds goes to the state file first, so it can't be lost.
+async fn migrate(path: Option<PathBuf>) -> Result<()> {
+    let path = locate(path)?;
+    let (table, version, legacy) = MagicFile::read_base(&path)?;
+    if version == migrate::CURRENT_VERSION {
+        println!("{} is already at magic_version {}", path.display(), version);
+        return Ok(());
+    }
+    MagicFile::load_from_path(&path.to_string_lossy())?;
+
+    let state_path = MagicState::path_for(&path);
+    let mut state = MagicState::load(&state_path)?;
+    if state.adopt(legacy) {
+        state.save(&state_path).await?;
+        println!("Moved runtime state to {}", state_path.display());
+    }
+
+    let options = WriteOptions {
+        backup: true,
+        ..Default::default()
+    };
+    write_atomically_with(&path, toml::to_string_pretty(&table)?.as_bytes(), options).await?;
+    println!(
+        "Migrated {} from magic_version {} to {}, the previous file is kept as .bak",
+        path.display(),
+        version,
+        migrate::CURRENT_VERSION
+    );
+    Ok(())
+}
@@ -4,6 +4,8 @@ use clap::{Parser, Subcommand};
 use tracing::info;
 use zbus::Connection;
 
+mod config;
+mod policy;
 mod status;
 mod upload;
 use status::status;
@@ -33,6 +35,16 @@ enum Commands {
         #[arg(help = "Expose a port to the internet", long)]
         port: u16,
     },
+    /// Check, print or migrate magic.toml
+    Config {
+        #[command(subcommand)]
+        command: config::ConfigCommand,
+    },
+    /// Manage the keys admins sign commands with
+    Policy {
+        #[command(subcommand)]
+        command: policy::PolicyCommand,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -64,6 +76,18 @@ pub async fn execute() -> bool {
         Some(Commands::Tunnel { port }) => {
             _ = expose_port(port).await;
         }
+        Some(Commands::Config { command }) => {
+            if let Err(err) = config::execute(command).await {
+                eprintln!("{:?}", err);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Policy { command }) => {
+            if let Err(err) = policy::execute(command).await {
+                eprintln!("{:?}", err);
+                std::process::exit(1);
+            }
+        }
         None => daemon_should_run = true,
     }
 
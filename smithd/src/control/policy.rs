@@ -0,0 +1,87 @@
+use crate::policy::signature;
+use crate::utils::schema::{SafeCommandRequest, SafeCommandTx};
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{Duration, Utc};
+use clap::Subcommand;
+use std::path::PathBuf;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Subcommand, Debug)]
+pub(super) enum PolicyCommand {
+    /// Create an admin key, prints the public key to add to policy.admin_keys
+    Keygen {
+        #[arg(help = "Where the private key is written", long)]
+        out: PathBuf,
+    },
+    /// Sign a command for a device, prints the request to send to the API
+    Sign {
+        #[arg(help = "Private key from `smithd policy keygen`", long)]
+        key: PathBuf,
+        #[arg(help = "Serial number of the device the command is for", long)]
+        serial_number: String,
+        #[arg(
+            help = "Seconds the signature stays valid",
+            long,
+            default_value_t = 3600
+        )]
+        ttl: i64,
+        #[arg(help = "The command as JSON, like '{\"FreeForm\": {\"cmd\": \"uptime\"}}'")]
+        command: String,
+    },
+}
+
+pub(super) async fn execute(command: PolicyCommand) -> Result<()> {
+    match command {
+        PolicyCommand::Keygen { out } => keygen(out).await,
+        PolicyCommand::Sign {
+            key,
+            serial_number,
+            ttl,
+            command,
+        } => sign(key, serial_number, ttl, command).await,
+    }
+}
+
+async fn keygen(out: PathBuf) -> Result<()> {
+    let pkcs8 = signature::generate()?;
+    let key_pair = signature::key_pair(&pkcs8)?;
+
+    // only readable by its owner from the start
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&out)
+        .await
+        .with_context(|| format!("Failed to create {}", out.display()))?;
+    file.write_all(STANDARD.encode(&pkcs8).as_bytes()).await?;
+    file.write_all(b"\n").await?;
+
+    println!("{}", signature::public_key(&key_pair));
+    Ok(())
+}
+
+async fn sign(key: PathBuf, serial_number: String, ttl: i64, command: String) -> Result<()> {
+    let contents = tokio::fs::read_to_string(&key)
+        .await
+        .with_context(|| format!("Failed to read {}", key.display()))?;
+    let pkcs8 = STANDARD
+        .decode(contents.trim())
+        .with_context(|| format!("{} is not a key from `smithd policy keygen`", key.display()))?;
+    let key_pair = signature::key_pair(&pkcs8)?;
+
+    let command: SafeCommandTx = serde_json::from_str(&command).context("Invalid command")?;
+    let expires_at = Utc::now() + Duration::seconds(ttl);
+    let signature = signature::sign(&key_pair, &serial_number, expires_at, &command)?;
+
+    let request = SafeCommandRequest {
+        id: 0,
+        command,
+        continue_on_error: false,
+        signature: Some(signature),
+    };
+    println!("{}", serde_json::to_string_pretty(&request)?);
+    Ok(())
+}
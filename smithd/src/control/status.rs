@@ -1,6 +1,7 @@
 use crate::dbus::SmithDbusProxy;
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownHandler;
+use crate::utils::host::SystemHost;
 use anyhow::Result;
 use tracing::info;
 use zbus::Connection;
@@ -22,13 +23,13 @@ pub async fn status() -> Result<()> {
 
     let configuration = MagicHandle::new(shutdown.signals());
 
-    configuration.load(None).await;
+    configuration.load(None).await?;
 
     let magic_packages = configuration.get_packages().await;
 
     // check the system version of the packages in the magic file
     for package in magic_packages {
-        let installed_version = package.get_system_version().await?;
+        let installed_version = package.get_system_version(&SystemHost).await?;
         let magic_toml_version = package.version;
 
         println!(
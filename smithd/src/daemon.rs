@@ -3,40 +3,94 @@ use crate::commander::CommanderHandle;
 use crate::dbus::DbusHandle;
 use crate::downloader::DownloaderHandle;
 use crate::filemanager::FileManagerHandle;
+use crate::logs::LogShipperHandle;
 use crate::magic::MagicHandle;
+use crate::modem::ModemHandle;
+use crate::network::NetworkHandle;
+use crate::ota::OtaHandle;
 use crate::police::PoliceHandle;
 use crate::postman::PostmanHandle;
+use crate::restart::RestartHandle;
+use crate::shell::ShellHandle;
 use crate::shutdown::ShutdownHandler;
 use crate::tunnel::TunnelHandle;
 use crate::updater::UpdaterHandle;
+use crate::utils::host::SystemHost;
 use crate::utils::system::SystemInfo;
-use tracing::info;
+use tracing::{error, info};
 
 pub async fn run() {
-    SystemInfo::new().await.print();
+    let host = SystemHost::shared();
+    SystemInfo::new(host.as_ref()).await.print();
 
     let shutdown = ShutdownHandler::new();
 
     let configuration = MagicHandle::new(shutdown.signals());
 
-    configuration.load(None).await;
+    // better not to run at all than to report to the wrong server
+    if let Err(err) = configuration.load(None).await {
+        error!("Refusing to start: {:?}", err);
+        std::process::exit(1);
+    }
 
     let tunnel = TunnelHandle::new(shutdown.signals(), configuration.clone());
 
-    let police = PoliceHandle::new(shutdown.signals());
+    let police = PoliceHandle::new(shutdown.signals(), configuration.clone(), host.clone());
 
-    let updater = UpdaterHandle::new(shutdown.signals(), configuration.clone());
+    let downloader = DownloaderHandle::new(shutdown.signals(), configuration.clone(), host.clone());
 
-    let downloader = DownloaderHandle::new(shutdown.signals(), configuration.clone());
+    let updater = UpdaterHandle::new(
+        shutdown.signals(),
+        configuration.clone(),
+        downloader.clone(),
+        host.clone(),
+    );
+
+    let filemanager =
+        FileManagerHandle::new(shutdown.signals(), configuration.clone(), host.clone());
+
+    let shell = ShellHandle::new(shutdown.signals(), configuration.clone());
+
+    let network = NetworkHandle::new(shutdown.signals(), configuration.clone());
+
+    let _modem = ModemHandle::new(shutdown.signals(), configuration.clone());
 
-    let filemanager = FileManagerHandle::new(shutdown.signals(), configuration.clone());
+    let _logs = LogShipperHandle::new(shutdown.signals(), configuration.clone(), host.clone());
+
+    let ota = OtaHandle::new(
+        shutdown.signals(),
+        configuration.clone(),
+        downloader.clone(),
+        filemanager,
+        host.clone(),
+    );
+
+    let dbus = DbusHandle::new(
+        shutdown.signals(),
+        updater.clone(),
+        downloader.clone(),
+        tunnel.clone(),
+        ota.clone(),
+    );
+
+    let restart = RestartHandle::new(
+        shutdown.signals(),
+        configuration.clone(),
+        dbus,
+        host.clone(),
+    );
 
     let commander = CommanderHandle::new(
         shutdown.signals(),
         tunnel.clone(),
         updater.clone(),
         downloader.clone(),
-        filemanager.clone(),
+        shell,
+        network,
+        ota.clone(),
+        restart.clone(),
+        configuration.clone(),
+        host.clone(),
     );
 
     let _postman = PostmanHandle::new(
@@ -44,18 +98,19 @@ pub async fn run() {
         police.clone(),
         commander.clone(),
         configuration.clone(),
+        tunnel.clone(),
+        ota,
+        restart,
+        host.clone(),
     );
 
-    let _dbus = DbusHandle::new(
+    let bouncer = BouncerHandle::new(
         shutdown.signals(),
-        updater.clone(),
-        downloader.clone(),
-        tunnel.clone(),
-        filemanager.clone(),
+        configuration.clone(),
+        police.clone(),
+        host,
     );
 
-    let bouncer = BouncerHandle::new(shutdown.signals(), configuration.clone(), police.clone());
-
     // this will ensure we have a token
     configuration.wait_while_not_registered().await;
 
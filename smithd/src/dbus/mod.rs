@@ -1,27 +1,40 @@
 use crate::downloader::DownloaderHandle;
-use crate::filemanager::FileManagerHandle;
+use crate::ota::OtaHandle;
 use crate::shutdown::ShutdownSignals;
 use crate::tunnel::TunnelHandle;
 use crate::updater::UpdaterHandle;
-use tracing::info;
+use crate::utils::schema::PendingRestart;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+use zbus::object_server::SignalEmitter;
 use zbus::{connection, interface};
 
 mod client;
 pub(crate) use client::SmithDbusProxy;
 
+const RESTART_PATH: &str = "/ai/teton/smith/Restart";
+
+enum RestartSignal {
+    Scheduled { at: i64, reason: String },
+    Cancelled,
+    Imminent { reason: String, deadline: u64 },
+}
+
 struct DBus {
     shutdown: ShutdownSignals,
+    receiver: mpsc::Receiver<RestartSignal>,
     updater: UpdaterHandle,
     downloader: DownloaderHandle,
     tunnel: TunnelHandle,
-    filemanager: FileManagerHandle,
+    ota: OtaHandle,
 }
 
 struct PackagesInterface {
     updater: UpdaterHandle,
     downloader: DownloaderHandle,
     tunnel: TunnelHandle,
-    filemanager: FileManagerHandle,
+    ota: OtaHandle,
 }
 
 // interface for the D-Bus service, version 1
@@ -43,7 +56,7 @@ impl PackagesInterface {
 
     async fn expose_port(&mut self, port: u16) -> String {
         info!("Exposing port {}", port);
-        let public_port = self.tunnel.start_tunnel(Some(port)).await;
+        let public_port = self.tunnel.start_tunnel(Some(port), None, None).await;
         public_port.to_string()
     }
     async fn download_file_rl(
@@ -67,66 +80,60 @@ impl PackagesInterface {
         }
     }
 
-    async fn start_ota(&self) -> String {
-        match self
-            .filemanager
-            .extract_here("/otatool/ota_tools.tbz2")
-            .await
-        {
-            Ok(_) => {
-                let mut args = Vec::new();
-
-                args.push("/ota/ota_payload_package.tar.gz".to_owned());
-
-                match self
-                    .filemanager
-                    .execute_script(
-                        "nv_ota_start.sh",
-                        args,
-                        Some("/otatool/Linux_for_Tegra/tools/ota_tools/version_upgrade/"),
-                    )
-                    .await
-                {
-                    Ok(script_result) => {
-                        // Only proceed with reboot if script execution was successful
-
-                        let reboot_args = Vec::new();
-
-                        let _ = self
-                            .filemanager
-                            .execute_system_command("reboot", reboot_args, None)
-                            .await;
-
-                        script_result
-                    }
+    /// Lets an app hold the link for itself, e.g. during a video call.
+    async fn pause_downloads(&self) -> String {
+        self.downloader.pause();
+        "Downloads paused".to_string()
+    }
 
-                    Err(e) => {
-                        format!("Script execution failed - {}", e)
-                    }
-                }
-            }
+    async fn resume_downloads(&self) -> String {
+        self.downloader.resume();
+        "Downloads resumed".to_string()
+    }
 
-            Err(err) => {
-                format!("Failed to extract OTA tools - {}", err)
-            }
+    async fn start_ota(&self) -> String {
+        match self.ota.start(None).await {
+            Ok(script_result) => script_result,
+            Err(e) => e.to_string(),
         }
     }
 }
 
+/// Lets apps on the device know about restarts, so they can take a
+/// logind shutdown inhibitor while they shouldn't be interrupted.
+struct RestartInterface;
+
+#[interface(name = "ai.teton.smith.Restart1")]
+impl RestartInterface {
+    /// `at` is in seconds since the epoch.
+    #[zbus(signal)]
+    async fn scheduled(emitter: &SignalEmitter<'_>, at: i64, reason: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn cancelled(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// The restart is due, it waits up to `deadline` seconds for inhibitors to be released.
+    #[zbus(signal)]
+    async fn imminent(emitter: &SignalEmitter<'_>, reason: &str, deadline: u64)
+    -> zbus::Result<()>;
+}
+
 impl DBus {
     fn new(
         shutdown: ShutdownSignals,
+        receiver: mpsc::Receiver<RestartSignal>,
         updater: UpdaterHandle,
         downloader: DownloaderHandle,
         tunnel: TunnelHandle,
-        filemanager: FileManagerHandle,
+        ota: OtaHandle,
     ) -> Self {
         Self {
             shutdown,
+            receiver,
             updater,
             downloader,
             tunnel,
-            filemanager,
+            ota,
         }
     }
 
@@ -136,25 +143,52 @@ impl DBus {
             updater: self.updater.clone(),
             downloader: self.downloader.clone(),
             tunnel: self.tunnel.clone(),
-            filemanager: self.filemanager.clone(),
+            ota: self.ota.clone(),
         };
-        let _conn = connection::Builder::system()
+        let conn = connection::Builder::system()
             .expect("Failed to create D-Bus connection")
             .name("ai.teton.smith")
             .expect("Failed to set D-Bus name")
             .serve_at("/ai/teton/smith/Packages", greeter)
             .expect("Failed to serve D-Bus interface")
+            .serve_at(RESTART_PATH, RestartInterface)
+            .expect("Failed to serve D-Bus interface")
             .build()
             .await
             .expect("Failed to build D-Bus connection");
 
-        // wait for the shutdown signal
-        self.shutdown.token.cancelled().await;
+        loop {
+            tokio::select! {
+                Some(msg) = self.receiver.recv() => {
+                    if let Err(err) = Self::emit(&conn, msg).await {
+                        error!("Failed to emit D-Bus signal: {}", err);
+                    }
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn emit(conn: &zbus::Connection, msg: RestartSignal) -> zbus::Result<()> {
+        let emitter = SignalEmitter::new(conn, RESTART_PATH)?;
+        match msg {
+            RestartSignal::Scheduled { at, reason } => {
+                RestartInterface::scheduled(&emitter, at, &reason).await
+            }
+            RestartSignal::Cancelled => RestartInterface::cancelled(&emitter).await,
+            RestartSignal::Imminent { reason, deadline } => {
+                RestartInterface::imminent(&emitter, &reason, deadline).await
+            }
+        }
     }
 }
 
 #[derive(Clone)]
-pub struct DbusHandle {}
+pub struct DbusHandle {
+    sender: mpsc::Sender<RestartSignal>,
+}
 
 impl DbusHandle {
     pub fn new(
@@ -162,11 +196,32 @@ impl DbusHandle {
         updater: UpdaterHandle,
         downloader: DownloaderHandle,
         tunnel: TunnelHandle,
-        filemanager: FileManagerHandle,
+        ota: OtaHandle,
     ) -> Self {
-        let mut actor = DBus::new(shutdown, updater, downloader, tunnel, filemanager);
+        let (sender, receiver) = mpsc::channel(8);
+        let mut actor = DBus::new(shutdown, receiver, updater, downloader, tunnel, ota);
         tokio::spawn(async move { actor.run().await });
 
-        Self {}
+        Self { sender }
+    }
+
+    pub async fn restart_scheduled(&self, restart: &PendingRestart) {
+        let msg = RestartSignal::Scheduled {
+            at: restart.at.timestamp(),
+            reason: restart.reason.clone().unwrap_or_default(),
+        };
+        _ = self.sender.send(msg).await;
+    }
+
+    pub async fn restart_cancelled(&self) {
+        _ = self.sender.send(RestartSignal::Cancelled).await;
+    }
+
+    pub async fn restart_imminent(&self, restart: &PendingRestart, deadline: Duration) {
+        let msg = RestartSignal::Imminent {
+            reason: restart.reason.clone().unwrap_or_default(),
+            deadline: deadline.as_secs(),
+        };
+        _ = self.sender.send(msg).await;
     }
 }
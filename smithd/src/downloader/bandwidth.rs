@@ -0,0 +1,125 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Bandwidth shared by every download of smithd, so together they stay
+/// under the cap and can be paused at once.
+pub struct Bandwidth {
+    bucket: Mutex<Bucket>,
+    paused: watch::Sender<bool>,
+}
+
+impl Default for Bandwidth {
+    fn default() -> Self {
+        Self {
+            bucket: Mutex::new(Bucket::new(None)),
+            paused: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Bandwidth {
+    /// `None` lifts the cap, in bytes per second otherwise.
+    pub fn set_rate(&self, rate: Option<f64>) {
+        self.bucket.lock().unwrap().set_rate(rate);
+    }
+
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Waits until downloads are paused.
+    pub async fn paused(&self) {
+        _ = self.paused.subscribe().wait_for(|paused| *paused).await;
+    }
+
+    /// Waits until downloads aren't paused.
+    pub async fn resumed(&self) {
+        _ = self.paused.subscribe().wait_for(|paused| !paused).await;
+    }
+
+    /// Waits until `bytes` more may be downloaded. Pausing is up to the
+    /// download, see [`Resumable`](super::Resumable).
+    pub async fn take(&self, bytes: usize) {
+        let wait = self.bucket.lock().unwrap().reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Token bucket holding at most a second worth of bytes. Taking more than
+/// is available is allowed, the next caller waits for the debt instead.
+pub struct Bucket {
+    rate: Option<f64>,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Starts empty, so a download doesn't begin with a burst.
+    pub fn new(rate: Option<f64>) -> Self {
+        Self {
+            rate: rate.filter(|rate| *rate > 0.0),
+            available: 0.0,
+            updated: Instant::now(),
+        }
+    }
+
+    fn set_rate(&mut self, rate: Option<f64>) {
+        self.rate = rate.filter(|rate| *rate > 0.0);
+        if let Some(rate) = self.rate {
+            self.available = self.available.min(rate);
+        }
+    }
+
+    /// How long to wait before the `bytes` taken at `now` are paid for.
+    pub fn reserve(&mut self, bytes: usize, now: Instant) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * rate).min(rate);
+        self.updated = now;
+        self.available -= bytes as f64;
+
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_bytes_over_time() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(Some(1000.0));
+        bucket.updated = start;
+
+        assert_eq!(bucket.reserve(500, start), Duration::from_millis(500));
+        // the next caller pays for the previous debt too
+        assert_eq!(bucket.reserve(500, start), Duration::from_secs(1));
+        // idle time refills up to a second worth of bytes
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(1000, later), Duration::ZERO);
+        assert_eq!(bucket.reserve(100, later), Duration::from_millis(100));
+
+        bucket.set_rate(None);
+        assert_eq!(bucket.reserve(1_000_000, later), Duration::ZERO);
+    }
+}
@@ -1,15 +1,15 @@
+use super::Resumable;
+use super::bandwidth::{Bandwidth, Bucket};
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownHandler;
 use anyhow;
-use futures::StreamExt;
-use governor::{Quota, RateLimiter};
 use reqwest::Client;
-use std::num::NonZeroU32;
+use std::io::SeekFrom;
 use std::path::Path;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tracing::{error, info};
 
 #[derive(Debug, Clone)]
@@ -47,6 +47,7 @@ pub async fn download_package(
     remote_file: String,
     local_file: String,
     rate: f64,
+    bandwidth: Arc<Bandwidth>,
     force_stop: Arc<AtomicBool>,
 ) -> anyhow::Result<String> {
     // Convert the MB rate to bytes/sec
@@ -61,6 +62,7 @@ pub async fn download_package(
         local_file.as_str(),
         remote_file.as_str(),
         bytes_per_second,
+        &bandwidth,
         force_stop,
         None,
     )
@@ -78,6 +80,8 @@ async fn download_file(
 
     bytes_per_second: u64,
 
+    bandwidth: &Bandwidth,
+
     force_stop: Arc<AtomicBool>,
 
     recurse: Option<u32>,
@@ -90,7 +94,7 @@ async fn download_file(
 
     let configuration = MagicHandle::new(shutdown.signals());
 
-    configuration.load(None).await;
+    configuration.load(None).await?;
 
     let client = Client::new();
 
@@ -147,21 +151,12 @@ async fn download_file(
         }
     };
 
-    let response = client.get(presigned_url).send().await?;
-
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "Failed to download file from pre-signed URL: {:?}",
-            response.status()
-        ));
-    }
+    let mut download = Resumable::start(bandwidth, || client.get(presigned_url))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to download file from pre-signed URL: {}", e))?;
 
     // Get the total content length of the object
-    let content_length = response
-        .headers()
-        .get("Content-Length")
-        .and_then(|value| value.to_str().ok())
-        .and_then(|value| value.parse::<u64>().ok());
+    let content_length = download.content_length;
 
     // Create root path if it does not exist
     if let Some(parent) = Path::new(local_path).parent() {
@@ -173,25 +168,12 @@ async fn download_file(
     // Open the file for writing
     let mut file = tokio::fs::File::create(local_path).await?;
 
-    let quota = Quota::per_second(
-        NonZeroU32::new(bytes_per_second as u32).unwrap_or(NonZeroU32::new(1).unwrap()),
-    );
-
-    let limiter = RateLimiter::direct(quota);
-    let mut stream = response.bytes_stream();
+    // this download's own cap, on top of the one shared by all of them
+    let mut bucket = Bucket::new(Some(bytes_per_second as f64));
     let mut downloaded: u64 = 0;
     let start = std::time::Instant::now();
 
-    // Force rate limiter to start empty so we don't have a large burst when starting download
-    let max_burst = bytes_per_second as u32;
-
-    match limiter.check_n(NonZeroU32::new(max_burst).unwrap()) {
-        Ok(_) => (),
-
-        Err(e) => eprintln!("Rate limit exceeded: {}", e),
-    }
-
-    while let Some(chunk_result) = stream.next().await {
+    loop {
         // Check if download should be forcefully stopped
 
         if force_stop.load(std::sync::atomic::Ordering::SeqCst) {
@@ -202,14 +184,17 @@ async fn download_file(
             break;
         }
 
-        match chunk_result {
-            Ok(chunk) => {
-                let chunk_size = NonZeroU32::new(chunk.len() as u32).unwrap();
+        match download.next().await {
+            Ok(Some((offset, chunk))) => {
+                // the server started over instead of resuming
+                if offset != downloaded {
+                    file.set_len(offset).await?;
+                    file.seek(SeekFrom::Start(offset)).await?;
+                    downloaded = offset;
+                }
 
                 // Wait for rate limiter
-                if let Err(e) = limiter.until_n_ready(chunk_size).await {
-                    eprintln!("Rate limit exceeded: {}", e);
-                }
+                tokio::time::sleep(bucket.reserve(chunk.len(), tokio::time::Instant::now())).await;
 
                 // Write chunk to file
                 file.write_all(&chunk).await?;
@@ -217,6 +202,8 @@ async fn download_file(
                 downloaded += chunk.len() as u64;
             }
 
+            Ok(None) => break,
+
             Err(e) => {
                 error!("Error downloading chunk: {}", e);
 
@@ -271,6 +258,7 @@ async fn download_file(
                     local_path,
                     remote_path,
                     bytes_per_second,
+                    bandwidth,
                     force_stop,
                     Some(rec_track),
                 ))
@@ -1,17 +1,28 @@
-use std::sync::Arc;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+mod bandwidth;
 mod download;
+mod resume;
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::host::Host;
 use crate::utils::network::NetworkClient;
+use crate::utils::schema::DownloadLimits;
+use crate::utils::system;
 use anyhow;
+pub use bandwidth::Bandwidth;
 use download::download_package;
+pub use resume::Resumable;
 use tokio::{
     sync::{mpsc, oneshot},
     time,
 };
 use tracing::info;
 
+/// How often the default route is checked for a metered link.
+const ROUTE_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
 #[derive(Debug)]
 enum DownloaderMessage {
     Download {
@@ -20,11 +31,15 @@ enum DownloaderMessage {
         rate: f64,
     },
     CheckStatus {
+        local_files: Vec<String>,
         rpc: oneshot::Sender<anyhow::Result<DownloadingStatus>>,
     },
+    SetLimits {
+        limits: DownloadLimits,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DownloadingStatus {
     Failed,
     Downloading,
@@ -35,11 +50,17 @@ struct Downloader {
     shutdown: ShutdownSignals,
     receiver: mpsc::Receiver<DownloaderMessage>,
     magic: MagicHandle,
+    host: Arc<dyn Host>,
     is_downloading: Arc<AtomicUsize>,
     network: NetworkClient,
     force_stop: Arc<AtomicBool>,
-    last_download_status: Arc<AtomicBool>,
+    /// Status of the last download of each local file.
+    statuses: Arc<Mutex<HashMap<String, DownloadingStatus>>>,
     timeout: u64,
+    bandwidth: Arc<Bandwidth>,
+    limits: DownloadLimits,
+    /// Whether the default route goes through a metered link.
+    metered: bool,
 }
 
 impl Downloader {
@@ -47,22 +68,27 @@ impl Downloader {
         shutdown: ShutdownSignals,
         receiver: mpsc::Receiver<DownloaderMessage>,
         magic: MagicHandle,
+        host: Arc<dyn Host>,
         timeout: u64,
+        bandwidth: Arc<Bandwidth>,
     ) -> Self {
         let network = NetworkClient::new();
         let force_stop = Arc::new(AtomicBool::new(false));
         let is_downloading = Arc::new(AtomicUsize::new(0));
-        let last_download_status = Arc::new(AtomicBool::new(false));
 
         Self {
             shutdown,
             receiver,
             magic,
+            host,
             network,
             is_downloading,
             force_stop,
             timeout,
-            last_download_status,
+            statuses: Arc::default(),
+            bandwidth,
+            limits: DownloadLimits::default(),
+            metered: false,
         }
     }
 
@@ -74,40 +100,95 @@ impl Downloader {
                 rate,
             } => {
                 self.is_downloading.fetch_add(1, Ordering::SeqCst);
+                self.statuses
+                    .lock()
+                    .unwrap()
+                    .insert(local_file.clone(), DownloadingStatus::Downloading);
 
                 let magic = self.magic.clone();
                 let force_stop = self.force_stop.clone();
                 let is_downloading = self.is_downloading.clone();
-                let last_download_status = self.last_download_status.clone();
+                let statuses = self.statuses.clone();
+                let bandwidth = self.bandwidth.clone();
 
                 tokio::spawn(async move {
                     // Do the download
-                    let result =
-                        download_package(magic, remote_file, local_file, rate, force_stop).await;
+                    let result = download_package(
+                        magic,
+                        remote_file,
+                        local_file.clone(),
+                        rate,
+                        bandwidth,
+                        force_stop,
+                    )
+                    .await;
 
-                    if let Ok(_) = &result {
-                        last_download_status.store(true, Ordering::SeqCst);
-                    } else {
-                        last_download_status.store(false, Ordering::SeqCst);
-                    }
+                    let status = match &result {
+                        Ok(_) => DownloadingStatus::Success,
+                        Err(_) => DownloadingStatus::Failed,
+                    };
+                    statuses.lock().unwrap().insert(local_file, status);
 
                     // Reset status
                     is_downloading.fetch_sub(1, Ordering::SeqCst);
                 });
             }
-            DownloaderMessage::CheckStatus { rpc } => {
-                // Check if the thread is currently downloading
-                let mut status = DownloadingStatus::Failed;
-                if self.is_downloading.load(Ordering::SeqCst) > 0 {
-                    status = DownloadingStatus::Downloading;
-                } else {
-                    if self.last_download_status.load(Ordering::SeqCst) {
-                        status = DownloadingStatus::Success;
-                    }
-                }
+            DownloaderMessage::CheckStatus { local_files, rpc } => {
+                // a file that was never downloaded counts as failed
+                let statuses = self.statuses.lock().unwrap();
+                let status = local_files
+                    .iter()
+                    .map(|file| {
+                        statuses
+                            .get(file)
+                            .copied()
+                            .unwrap_or(DownloadingStatus::Failed)
+                    })
+                    .fold(DownloadingStatus::Success, |status, file| {
+                        match (status, file) {
+                            (DownloadingStatus::Failed, _) | (_, DownloadingStatus::Failed) => {
+                                DownloadingStatus::Failed
+                            }
+                            (DownloadingStatus::Downloading, _)
+                            | (_, DownloadingStatus::Downloading) => DownloadingStatus::Downloading,
+                            _ => DownloadingStatus::Success,
+                        }
+                    });
 
                 let _ = rpc.send(Ok(status));
             }
+            DownloaderMessage::SetLimits { limits } => {
+                self.limits = limits;
+                self.magic.set_download_limits(limits).await;
+                self.apply_limits();
+            }
+        }
+    }
+
+    fn apply_limits(&self) {
+        let rate = if self.metered {
+            self.limits.metered_rate.or(self.limits.rate)
+        } else {
+            self.limits.rate
+        };
+        info!(
+            "Downloads limited to {:?} MB/s{}",
+            rate,
+            if self.metered {
+                " on a metered link"
+            } else {
+                ""
+            }
+        );
+        self.bandwidth.set_rate(rate.map(|rate| rate * 1_000_000.0));
+    }
+
+    async fn check_route(&mut self) {
+        let metered = system::is_metered(self.host.as_ref()).await;
+
+        if metered != self.metered {
+            self.metered = metered;
+            self.apply_limits();
         }
     }
 
@@ -118,6 +199,12 @@ impl Downloader {
 
         self.network.set_hostname(hostname);
 
+        let mut changes = self.magic.agent_config_changes();
+        self.limits = self.magic.get_download_limits().await;
+        self.apply_limits();
+
+        let mut route_check_interval = time::interval(ROUTE_CHECK_INTERVAL);
+
         loop {
             tokio::select! {
                 Some(msg) = self.receiver.recv() => {
@@ -125,6 +212,18 @@ impl Downloader {
                     self.handle_message(msg).await;
                 }
 
+                _ = route_check_interval.tick() => {
+                    self.check_route().await;
+                }
+
+                Ok(()) = changes.changed() => {
+                    let limits = self.magic.get_download_limits().await;
+                    if limits != self.limits {
+                        self.limits = limits;
+                        self.apply_limits();
+                    }
+                }
+
                 _ = self.shutdown.token.cancelled() => {
                     let mut count = 1;
 
@@ -148,23 +247,54 @@ impl Downloader {
     }
 }
 
+/// Schedules every download of smithd, OTA artifacts and packages alike, on
+/// one shared bandwidth cap.
 #[derive(Clone)]
-
 pub struct DownloaderHandle {
     sender: mpsc::Sender<DownloaderMessage>,
+    bandwidth: Arc<Bandwidth>,
 }
 
 impl DownloaderHandle {
-    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle, host: Arc<dyn Host>) -> Self {
         let (sender, receiver) = mpsc::channel(8);
 
         let timeout = 60; // 60 second timeout
+        let bandwidth = Arc::new(Bandwidth::default());
 
-        let mut actor = Downloader::new(shutdown, receiver, magic, timeout);
+        let mut actor =
+            Downloader::new(shutdown, receiver, magic, host, timeout, bandwidth.clone());
 
         tokio::spawn(async move { actor.run().await });
 
-        Self { sender }
+        Self { sender, bandwidth }
+    }
+
+    /// For downloads done outside of the downloader, to take their share.
+    pub fn bandwidth(&self) -> Arc<Bandwidth> {
+        self.bandwidth.clone()
+    }
+
+    pub fn pause(&self) {
+        info!("Pausing downloads");
+        self.bandwidth.pause();
+    }
+
+    pub fn resume(&self) {
+        info!("Resuming downloads");
+        self.bandwidth.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.bandwidth.is_paused()
+    }
+
+    /// Keeps the limits in magic.toml and applies them right away.
+    pub async fn set_limits(&self, limits: DownloadLimits) -> anyhow::Result<()> {
+        self.sender
+            .send(DownloaderMessage::SetLimits { limits })
+            .await?;
+        Ok(())
     }
 
     pub async fn download(
@@ -186,12 +316,16 @@ impl DownloaderHandle {
         Ok("Download started, not waiting for result".to_string())
     }
 
-    pub async fn check_download_status(&self) -> anyhow::Result<DownloadingStatus> {
+    /// How the last downloads of these files went, all of them together.
+    pub async fn check_download_status(
+        &self,
+        local_files: Vec<String>,
+    ) -> anyhow::Result<DownloadingStatus> {
         // unwrap because if this fails then we are in a bad state
         let (rpc, receiver) = oneshot::channel();
 
         self.sender
-            .send(DownloaderMessage::CheckStatus { rpc })
+            .send(DownloaderMessage::CheckStatus { local_files, rpc })
             .await
             .unwrap();
 
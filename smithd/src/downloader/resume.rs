@@ -0,0 +1,92 @@
+use super::Bandwidth;
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use reqwest::header::RANGE;
+use reqwest::{RequestBuilder, StatusCode};
+use tracing::{info, warn};
+
+/// A download that closes its connection while downloads are paused, and
+/// carries on with a Range request once they are resumed.
+pub struct Resumable<'a, F> {
+    bandwidth: &'a Bandwidth,
+    /// Builds the request again for every connection.
+    request: F,
+    stream: Option<BoxStream<'static, reqwest::Result<Bytes>>>,
+    offset: u64,
+    /// Length of the whole body, from the first response.
+    pub content_length: Option<u64>,
+}
+
+impl<'a, F: Fn() -> RequestBuilder> Resumable<'a, F> {
+    pub async fn start(bandwidth: &'a Bandwidth, request: F) -> Result<Self> {
+        bandwidth.resumed().await;
+
+        let response = request().send().await?;
+        if !response.status().is_success() {
+            bail!("Failed to download: {}", response.status());
+        }
+
+        Ok(Self {
+            bandwidth,
+            content_length: response.content_length(),
+            stream: Some(response.bytes_stream().boxed()),
+            request,
+            offset: 0,
+        })
+    }
+
+    /// The next chunk with its offset in the body. The offset goes back to
+    /// 0 when the server didn't resume where the download stopped.
+    pub async fn next(&mut self) -> Result<Option<(u64, Bytes)>> {
+        loop {
+            if self.bandwidth.is_paused() {
+                if self.stream.take().is_some() {
+                    info!("Download paused after {} bytes", self.offset);
+                }
+                self.bandwidth.resumed().await;
+            }
+
+            let Some(stream) = &mut self.stream else {
+                self.reconnect().await?;
+                continue;
+            };
+
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = self.bandwidth.paused() => continue,
+            };
+
+            let Some(chunk) = chunk.transpose()? else {
+                return Ok(None);
+            };
+            self.bandwidth.take(chunk.len()).await;
+
+            let offset = self.offset;
+            self.offset += chunk.len() as u64;
+            return Ok(Some((offset, chunk)));
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        let response = (self.request)()
+            .header(RANGE, format!("bytes={}-", self.offset))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                info!("Download resumed after {} bytes", self.offset);
+            }
+            StatusCode::OK => {
+                warn!("Download can't be resumed, starting over");
+                self.offset = 0;
+            }
+            status => bail!("Failed to resume download: {}", status),
+        }
+
+        self.stream = Some(response.bytes_stream().boxed());
+        Ok(())
+    }
+}
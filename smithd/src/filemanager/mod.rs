@@ -5,6 +5,7 @@ use std::sync::{
 mod systemactions;
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::host::Host;
 use crate::utils::network::NetworkClient;
 use anyhow;
 use tokio::{
@@ -20,6 +21,8 @@ struct FileManager {
 
     magic: MagicHandle,
 
+    host: Arc<dyn Host>,
+
     is_processing: Arc<AtomicBool>,
 
     network: NetworkClient,
@@ -68,6 +71,8 @@ impl FileManager {
         receiver: mpsc::Receiver<FileManagerMessage>,
 
         magic: MagicHandle,
+
+        host: Arc<dyn Host>,
     ) -> Self {
         let network = NetworkClient::new();
 
@@ -80,6 +85,8 @@ impl FileManager {
 
             magic,
 
+            host,
+
             network,
 
             is_processing,
@@ -93,8 +100,11 @@ impl FileManager {
 
                 let is_processing = self.is_processing.clone();
 
+                let host = self.host.clone();
+
                 tokio::spawn(async move {
-                    let result = systemactions::extract_file_here(file.as_str()).await;
+                    let result =
+                        systemactions::extract_file_here(host.as_ref(), file.as_str()).await;
 
                     // Return results
 
@@ -111,8 +121,12 @@ impl FileManager {
 
                 let is_processing = self.is_processing.clone();
 
+                let host = self.host.clone();
+
                 tokio::spawn(async move {
-                    let result = systemactions::extract_file(file.as_str(), target.as_str()).await;
+                    let result =
+                        systemactions::extract_file(host.as_ref(), file.as_str(), target.as_str())
+                            .await;
 
                     // Return results
 
@@ -137,10 +151,16 @@ impl FileManager {
 
                 let is_processing = self.is_processing.clone();
 
+                let host = self.host.clone();
+
                 tokio::spawn(async move {
-                    let result =
-                        systemactions::execute_script(file.as_str(), arguments, folder.as_deref())
-                            .await;
+                    let result = systemactions::execute_script(
+                        host.as_ref(),
+                        file.as_str(),
+                        arguments,
+                        folder.as_deref(),
+                    )
+                    .await;
 
                     // Return results
 
@@ -165,8 +185,11 @@ impl FileManager {
 
                 let is_processing = self.is_processing.clone();
 
+                let host = self.host.clone();
+
                 tokio::spawn(async move {
                     let result = systemactions::execute_system_command(
+                        host.as_ref(),
                         command.as_str(),
                         arguments,
                         folder.as_deref(),
@@ -256,10 +279,10 @@ pub struct FileManagerHandle {
 }
 
 impl FileManagerHandle {
-    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle, host: Arc<dyn Host>) -> Self {
         let (sender, receiver) = mpsc::channel(8);
 
-        let mut actor = FileManager::new(shutdown, receiver, magic);
+        let mut actor = FileManager::new(shutdown, receiver, magic, host);
 
         tokio::spawn(async move { actor.run().await });
 
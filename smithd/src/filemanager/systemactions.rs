@@ -1,22 +1,20 @@
+use crate::utils::host::{Host, HostCommand};
 use anyhow;
 use std::path::Path;
 use tracing::info;
 
-pub async fn extract_file_here(file: &str) -> anyhow::Result<String> {
+pub async fn extract_file_here(host: &dyn Host, file: &str) -> anyhow::Result<String> {
     // Set the target to the file location minus the file name
 
     let target = Path::new(file).parent().unwrap().to_str().unwrap();
 
-    extract_file(file, target).await
+    extract_file(host, file, target).await
 }
 
-pub async fn extract_file(file: &str, target: &str) -> anyhow::Result<String> {
-    let output = std::process::Command::new("tar")
-        .arg("-xpf")
-        .arg(file)
-        .arg("-C")
-        .arg(target)
-        .output();
+pub async fn extract_file(host: &dyn Host, file: &str, target: &str) -> anyhow::Result<String> {
+    let output = host
+        .output(HostCommand::new("tar").args(["-xpf", file, "-C", target]))
+        .await;
 
     if let Err(e) = output {
         return Err(anyhow::anyhow!("Failed to extract file: {}", e));
@@ -37,6 +35,8 @@ pub async fn extract_file(file: &str, target: &str) -> anyhow::Result<String> {
 }
 
 pub async fn execute_script(
+    host: &dyn Host,
+
     file: &str,
 
     arguments: Vec<String>,
@@ -49,29 +49,27 @@ pub async fn execute_script(
 
     new_args.extend(arguments);
 
-    execute_system_command("bash", new_args, folder).await
+    execute_system_command(host, "bash", new_args, folder).await
 }
 
 pub async fn execute_system_command(
+    host: &dyn Host,
+
     command: &str,
 
     arguments: Vec<String>,
 
     folder: Option<&str>,
 ) -> anyhow::Result<String> {
-    let mut cmd = std::process::Command::new(command);
-
-    for arg in arguments {
-        cmd.arg(arg);
-    }
+    let mut cmd = HostCommand::new(command).args(arguments);
 
     // Check if a folder string exists, if so, push that folder as workingdir
 
     if let Some(folder) = folder {
-        cmd.current_dir(folder);
+        cmd = cmd.current_dir(folder);
     }
 
-    let output = cmd.output();
+    let output = host.output(cmd).await;
 
     if let Err(e) = output {
         return Err(anyhow::anyhow!("{}", e));
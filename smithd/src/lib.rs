@@ -5,9 +5,16 @@ pub mod daemon;
 pub mod dbus;
 pub mod downloader;
 pub mod filemanager;
+pub mod logs;
 pub mod magic;
+pub mod modem;
+pub mod network;
+pub mod ota;
 pub mod police;
+pub mod policy;
 pub mod postman;
+pub mod restart;
+pub mod shell;
 pub mod shutdown;
 pub mod tunnel;
 pub mod updater;
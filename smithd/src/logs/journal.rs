@@ -0,0 +1,93 @@
+use crate::magic::structure::ConfigLogs;
+use crate::utils::schema::LogEntry;
+use anyhow::Result;
+use serde_json::Value;
+use std::process::Stdio;
+use tokio::process::{Child, Command};
+
+/// Follows the journal as JSON, one entry per line, resuming after `cursor`
+/// when there is one.
+pub(super) fn follow(config: &ConfigLogs, cursor: Option<&str>) -> Result<Child> {
+    let mut command = Command::new("journalctl");
+    command
+        .args(["--follow", "--output=json"])
+        .arg(format!("--priority=0..{}", config.priority.min(7)));
+
+    for unit in &config.units {
+        command.arg(format!("--unit={unit}"));
+    }
+
+    match cursor {
+        Some(cursor) => command.arg(format!("--after-cursor={cursor}")),
+        None => command.arg("--lines=0"),
+    };
+
+    Ok(command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?)
+}
+
+/// Parses a line of `journalctl --output=json` into its cursor and entry.
+pub(super) fn parse(line: &str) -> Option<(String, LogEntry)> {
+    let fields: Value = serde_json::from_str(line).ok()?;
+    let field = |name: &str| fields.get(name).and_then(Value::as_str);
+
+    let cursor = field("__CURSOR")?.to_string();
+    let timestamp =
+        chrono::DateTime::from_timestamp_micros(field("__REALTIME_TIMESTAMP")?.parse().ok()?)?;
+
+    // non UTF-8 messages come as an array of bytes
+    let message = match fields.get("MESSAGE")? {
+        Value::String(message) => message.clone(),
+        Value::Array(bytes) => {
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+                .collect();
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => return None,
+    };
+    // Postgres can't store NUL in text, one would hold back every later batch
+    let strip = |text: &str| text.replace('\0', "");
+
+    Some((
+        cursor,
+        LogEntry {
+            timestamp,
+            unit: field("_SYSTEMD_UNIT").map(strip),
+            priority: field("PRIORITY")
+                .and_then(|priority| priority.parse().ok())
+                .unwrap_or(6),
+            message: strip(&message),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_line() {
+        let line = r#"{"__CURSOR":"s=abc;i=1","__REALTIME_TIMESTAMP":"1718000000123456","PRIORITY":"3","_SYSTEMD_UNIT":"smithd.service","MESSAGE":"failed"}"#;
+        let (cursor, entry) = parse(line).unwrap();
+
+        assert_eq!(cursor, "s=abc;i=1");
+        assert_eq!(entry.timestamp.timestamp_micros(), 1718000000123456);
+        assert_eq!(entry.unit.as_deref(), Some("smithd.service"));
+        assert_eq!(entry.priority, 3);
+        assert_eq!(entry.message, "failed");
+
+        let binary = r#"{"__CURSOR":"s=abc;i=2","__REALTIME_TIMESTAMP":"1718000000123456","MESSAGE":[104,105]}"#;
+        let (_, entry) = parse(binary).unwrap();
+        assert_eq!(entry.message, "hi");
+        assert_eq!(entry.unit, None);
+
+        let nul = r#"{"__CURSOR":"s=abc;i=3","__REALTIME_TIMESTAMP":"1718000000123456","MESSAGE":[104,0,105]}"#;
+        let (_, entry) = parse(nul).unwrap();
+        assert_eq!(entry.message, "hi");
+    }
+}
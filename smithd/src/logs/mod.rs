@@ -0,0 +1,273 @@
+use crate::magic::MagicHandle;
+use crate::magic::state::write_atomically;
+use crate::magic::structure::ConfigLogs;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::host::Host;
+use crate::utils::network::NetworkClient;
+use crate::utils::schema::LogEntry;
+use crate::utils::system;
+use anyhow::{Result, anyhow};
+use chrono::{NaiveDate, Utc};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{error, info, warn};
+
+mod journal;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_BATCH: usize = 500;
+/// Entries kept while the API can't be reached, the oldest go first.
+const MAX_BACKLOG: usize = 10 * MAX_BATCH;
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+/// Journal position of the last entry shipped or dropped, so a restart of
+/// smithd neither ships entries twice nor skips any.
+const CURSOR_FILE: &str = "/var/lib/smith/logs.cursor";
+
+/// Bytes shipped today while on a metered connection.
+struct Budget {
+    day: NaiveDate,
+    spent: u64,
+}
+
+struct LogShipper {
+    shutdown: ShutdownSignals,
+    magic: MagicHandle,
+    host: Arc<dyn Host>,
+    network: NetworkClient,
+    batch: Vec<LogEntry>,
+    /// Journal position of the last entry read, to resume after journalctl exits.
+    cursor: Option<String>,
+    budget: Budget,
+    /// Entries dropped over the metered budget or past the backlog, summed
+    /// up in the next batch that ships.
+    dropped: usize,
+    /// The last attempt failed, wait for the interval instead of retrying on every entry.
+    failing: bool,
+}
+
+impl LogShipper {
+    fn new(shutdown: ShutdownSignals, magic: MagicHandle, host: Arc<dyn Host>) -> Self {
+        Self {
+            shutdown,
+            magic,
+            host,
+            network: NetworkClient::new(),
+            batch: vec![],
+            cursor: std::fs::read_to_string(CURSOR_FILE)
+                .ok()
+                .map(|cursor| cursor.trim().to_string())
+                .filter(|cursor| !cursor.is_empty()),
+            budget: Budget {
+                day: Utc::now().date_naive(),
+                spent: 0,
+            },
+            dropped: 0,
+            failing: false,
+        }
+    }
+
+    async fn flush(&mut self, config: &ConfigLogs) {
+        if self.batch.is_empty() {
+            return;
+        }
+
+        let mut charge = None;
+        if let Some(daily_budget) = config.metered_daily_budget {
+            if system::is_metered(self.host.as_ref()).await {
+                match self.within_budget(daily_budget) {
+                    Some(size) => charge = Some(size),
+                    None => {
+                        self.save_cursor().await;
+                        return;
+                    }
+                }
+            }
+        }
+
+        let result = self.ship().await;
+        self.failing = result.is_err();
+        match result {
+            Ok(()) => {
+                self.batch.clear();
+                self.dropped = 0;
+                self.budget.spent += charge.unwrap_or_default();
+                self.save_cursor().await;
+            }
+            Err(err) => {
+                error!("Failed to ship logs: {}", err);
+                let overflow = self.batch.len().saturating_sub(MAX_BACKLOG);
+                self.batch.drain(..overflow);
+                self.dropped += overflow;
+            }
+        }
+    }
+
+    /// Size of the batch when it fits in today's budget, it's dropped when
+    /// it doesn't. Only charged once the API took it.
+    fn within_budget(&mut self, daily_budget: u64) -> Option<u64> {
+        let today = Utc::now().date_naive();
+        if self.budget.day != today {
+            self.budget = Budget {
+                day: today,
+                spent: 0,
+            };
+        }
+
+        // what goes over the wire is compressed, so this errs on the safe side
+        let size = serde_json::to_vec(&self.batch).map_or(0, |json| json.len() as u64);
+        if self.budget.spent + size > daily_budget {
+            if self.dropped == 0 {
+                warn!("Metered daily log budget used up, dropping logs until tomorrow");
+            }
+            self.dropped += self.batch.len();
+            self.batch.clear();
+            return None;
+        }
+
+        Some(size)
+    }
+
+    async fn ship(&mut self) -> Result<()> {
+        let token = self
+            .magic
+            .get_token()
+            .await
+            .ok_or_else(|| anyhow!("Device is not registered"))?;
+        self.network.set_hostname(self.magic.get_server().await);
+
+        let mut entries = self.batch.clone();
+        if self.dropped > 0 {
+            entries.push(LogEntry {
+                timestamp: Utc::now(),
+                unit: Some("smithd.service".to_string()),
+                priority: 4,
+                message: format!(
+                    "Dropped {} log entries over the metered daily budget or while the API was unreachable",
+                    self.dropped
+                ),
+            });
+        }
+
+        let (status, _) = self
+            .network
+            .send_compressed_post(&token, "/telemetry/logs", &entries)
+            .await?;
+
+        if !status.is_success() {
+            return Err(anyhow!("API answered {status}"));
+        }
+
+        Ok(())
+    }
+
+    /// Everything read is shipped or dropped by now, so the journal is
+    /// resumed after the last entry read.
+    async fn save_cursor(&self) {
+        let Some(cursor) = &self.cursor else {
+            return;
+        };
+
+        if let Err(err) = write_atomically(Path::new(CURSOR_FILE), cursor.as_bytes()).await {
+            warn!("Failed to save the journal cursor: {:?}", err);
+        }
+    }
+
+    /// Reads the journal until we shut down or the `[logs]` section
+    /// changes, errors when journalctl exits.
+    async fn follow(
+        &mut self,
+        config: &ConfigLogs,
+        changes: &mut watch::Receiver<Option<String>>,
+    ) -> Result<()> {
+        let mut child = journal::follow(config, self.cursor.as_deref())?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("journalctl has no stdout"))?;
+        let mut lines = BufReader::new(stdout).lines();
+
+        let mut flush_interval = time::interval(FLUSH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Some(line) = line? else {
+                        return Err(anyhow!("journalctl exited"));
+                    };
+                    let Some((cursor, entry)) = journal::parse(&line) else {
+                        continue;
+                    };
+                    self.cursor = Some(cursor);
+                    self.batch.push(entry);
+                    if self.batch.len() >= MAX_BATCH && !self.failing {
+                        self.flush(config).await;
+                    }
+                }
+                _ = flush_interval.tick() => {
+                    self.flush(config).await;
+                }
+                Ok(()) = changes.changed() => {
+                    if self.magic.get_logs().await.as_ref() != Some(config) {
+                        self.flush(config).await;
+                        return Ok(());
+                    }
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    self.flush(config).await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut changes = self.magic.agent_config_changes();
+
+        loop {
+            let Some(config) = self.magic.get_logs().await else {
+                info!("Log shipping is not configured");
+                tokio::select! {
+                    Ok(()) = changes.changed() => continue,
+                    _ = self.shutdown.token.cancelled() => break,
+                }
+            };
+
+            info!("Log shipper is runnning");
+
+            match self.follow(&config, &mut changes).await {
+                Ok(()) if self.shutdown.token.is_cancelled() => break,
+                Ok(()) => {
+                    info!("Log shipping configuration changed");
+                    continue;
+                }
+                Err(err) => warn!("Failed to follow the journal: {}", err),
+            }
+
+            tokio::select! {
+                _ = time::sleep(RESTART_DELAY) => {}
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+
+        info!("Log shipper shutting down");
+    }
+}
+
+/// Ships journal entries selected in the `[logs]` section of magic.toml.
+#[derive(Clone)]
+pub struct LogShipperHandle;
+
+impl LogShipperHandle {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle, host: Arc<dyn Host>) -> Self {
+        let mut actor = LogShipper::new(shutdown, magic, host);
+        tokio::spawn(async move { actor.run().await });
+
+        Self
+    }
+}
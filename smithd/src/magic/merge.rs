@@ -0,0 +1,131 @@
+use anyhow::{Result, anyhow};
+use toml::{Table, Value};
+
+/// Arrays of tables whose entries are matched by `name`, so a drop-in can
+/// replace one entry and leave the others.
+const NAMED: [&str; 3] = ["check", "metric", "ota"];
+
+/// Merges a `magic.d` drop-in into magic.toml. Tables like `[tunnel]` merge
+/// key by key, `[[check]]`, `[[metric]]` and `[[ota]]` entries replace the
+/// entry of the same name or are added, anything else is replaced whole.
+pub fn merge(base: &mut Table, fragment: Table) -> Result<()> {
+    if fragment
+        .get("meta")
+        .and_then(|meta| meta.get("magic_version"))
+        .is_some()
+    {
+        return Err(anyhow!("magic_version can only be set in magic.toml"));
+    }
+
+    merge_tables(base, fragment, true)
+}
+
+fn merge_tables(base: &mut Table, fragment: Table, top: bool) -> Result<()> {
+    for (key, value) in fragment {
+        let replacement = match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(value)) => {
+                merge_tables(base, value, false)?;
+                None
+            }
+            (Some(Value::Array(base)), Value::Array(value))
+                if top && NAMED.contains(&key.as_str()) =>
+            {
+                merge_named(&key, base, value)?;
+                None
+            }
+            (_, value) => Some(value),
+        };
+        if let Some(value) = replacement {
+            base.insert(key, value);
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_named(kind: &str, base: &mut Vec<Value>, entries: Vec<Value>) -> Result<()> {
+    for entry in entries {
+        let name = entry
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("every {} needs a name", kind))?;
+        match base
+            .iter_mut()
+            .find(|existing| existing.get("name").and_then(Value::as_str) == Some(name))
+        {
+            Some(existing) => *existing = entry,
+            None => base.push(entry),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_drop_ins() {
+        let mut base: Table = toml::from_str(
+            r#"
+[meta]
+magic_version = 3
+server = "https://api.smith.teton.ai/smith"
+
+[tunnel]
+server = "bore.pub"
+secret = "hunter2"
+
+[scheduler]
+app = ["a", "b"]
+
+[[check]]
+name = "disk"
+cmd = "df"
+
+[[check]]
+name = "camera"
+cmd = "ls /dev/video0"
+"#,
+        )
+        .unwrap();
+
+        let fragment: Table = toml::from_str(
+            r#"
+[tunnel]
+server = "tunnel.example.com"
+
+[scheduler]
+app = ["c"]
+
+[[check]]
+name = "disk"
+cmd = "df -h"
+
+[[check]]
+name = "modem"
+cmd = "mmcli -L"
+"#,
+        )
+        .unwrap();
+        merge(&mut base, fragment).unwrap();
+
+        assert_eq!(
+            base["tunnel"]["server"].as_str(),
+            Some("tunnel.example.com")
+        );
+        assert_eq!(base["tunnel"]["secret"].as_str(), Some("hunter2"));
+        assert_eq!(base["scheduler"]["app"].as_array().unwrap().len(), 1);
+
+        let checks = base["check"].as_array().unwrap();
+        let commands: Vec<_> = checks
+            .iter()
+            .map(|check| check["cmd"].as_str().unwrap())
+            .collect();
+        assert_eq!(commands, ["df -h", "ls /dev/video0", "mmcli -L"]);
+
+        let versioned: Table = toml::from_str("[meta]\nmagic_version = 2\n").unwrap();
+        assert!(merge(&mut base, versioned).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{Result, anyhow};
+use toml::{Table, Value};
+
+/// Version of magic.toml this smithd reads and writes.
+pub const CURRENT_VERSION: i32 = 3;
+
+/// Upgrades a file by one version, `MIGRATIONS[0]` takes version 1 to 2.
+/// Runtime state found in the file is moved to the second table.
+type Migration = fn(&mut Table, &mut Table) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[v1_to_v2, v2_to_v3];
+
+/// Version of a file that may still need migrating, files from before
+/// `magic_version` existed are version 1.
+pub fn version(file: &Table) -> Result<i32> {
+    match file.get("meta").and_then(|meta| meta.get("magic_version")) {
+        None => Ok(1),
+        Some(Value::Integer(version)) => i32::try_from(*version)
+            .map_err(|_| anyhow!("magic_version {} is out of range", version)),
+        Some(other) => Err(anyhow!("magic_version must be a number, not {}", other)),
+    }
+}
+
+/// Brings the file up to `CURRENT_VERSION`, returns the version it was at
+/// and the runtime state that has to move to the state file.
+pub fn migrate(file: &mut Table) -> Result<(i32, Table)> {
+    let from = version(file)?;
+    if from < 1 {
+        return Err(anyhow!("magic_version {} doesn't exist", from));
+    }
+    if from > CURRENT_VERSION {
+        return Err(anyhow!(
+            "magic_version {} is newer than this smithd supports ({})",
+            from,
+            CURRENT_VERSION
+        ));
+    }
+
+    let mut state = Table::new();
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(from as usize - 1) {
+        migration(file, &mut state)?;
+        let version = index as i64 + 2;
+        meta(file)?.insert("magic_version".to_string(), Value::Integer(version));
+    }
+
+    Ok((from, state))
+}
+
+fn meta(file: &mut Table) -> Result<&mut Table> {
+    file.entry("meta")
+        .or_insert_with(|| Value::Table(Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("[meta] must be a table"))
+}
+
+/// Version 2 only started recording the version.
+fn v1_to_v2(_file: &mut Table, _state: &mut Table) -> Result<()> {
+    Ok(())
+}
+
+/// Version 3 leaves everything smithd changes itself to the state file.
+fn v2_to_v3(file: &mut Table, state: &mut Table) -> Result<()> {
+    let meta = meta(file)?;
+    for key in ["token", "release_id", "target_release_id"] {
+        if let Some(value) = meta.remove(key) {
+            state.insert(key.to_string(), value);
+        }
+    }
+    if let Some(packages) = file.remove("package") {
+        state.insert("package".to_string(), packages);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_unversioned_files() {
+        let mut file: Table = toml::from_str(
+            r#"
+[meta]
+server = "https://api.smith.teton.ai/smith"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(migrate(&mut file).unwrap().0, 1);
+        assert_eq!(version(&file).unwrap(), CURRENT_VERSION);
+        assert_eq!(migrate(&mut file).unwrap().0, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn moves_state_out() {
+        let mut file: Table = toml::from_str(
+            r#"
+[meta]
+magic_version = 2
+server = "https://api.smith.teton.ai/smith"
+token = "secret"
+release_id = 7
+
+[[package]]
+name = "smith"
+version = "0.2.60"
+file = "smith_0.2.60_arm64.deb"
+"#,
+        )
+        .unwrap();
+
+        let (from, state) = migrate(&mut file).unwrap();
+        assert_eq!(from, 2);
+        assert!(file.get("package").is_none());
+        assert!(file["meta"].get("token").is_none());
+        assert_eq!(state["token"].as_str(), Some("secret"));
+        assert_eq!(state["release_id"].as_integer(), Some(7));
+        assert_eq!(state["package"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn refuses_newer_files() {
+        let mut file: Table = toml::from_str(
+            r#"
+[meta]
+magic_version = 99
+server = "https://api.smith.teton.ai/smith"
+"#,
+        )
+        .unwrap();
+
+        assert!(migrate(&mut file).is_err());
+    }
+}
@@ -1,8 +1,15 @@
+pub mod merge;
+pub mod migrate;
+pub mod state;
 pub mod structure;
 
+use crate::policy::Policy;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::{DownloadLimits, PendingRestart};
+use anyhow::anyhow;
+use state::MagicState;
 use std::path::PathBuf;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
 use tracing::{debug, error, info, warn};
 
 struct Magic {
@@ -10,11 +17,16 @@ struct Magic {
     receiver: mpsc::Receiver<MagicMessage>,
     configuration: Option<structure::MagicFile>,
     path: Option<PathBuf>,
+    state: MagicState,
+    state_path: Option<PathBuf>,
+    /// Version of the agent configuration in use, bumped once actors can
+    /// pick up a new one.
+    agent_config: watch::Sender<Option<String>>,
 }
 
 enum MagicMessage {
     Load {
-        signal: oneshot::Sender<()>,
+        signal: oneshot::Sender<anyhow::Result<()>>,
         path: Option<String>,
     },
     GetChecks {
@@ -23,6 +35,36 @@ enum MagicMessage {
     GetTunnelDetails {
         sender: oneshot::Sender<structure::ConfigTunnel>,
     },
+    GetRedacted {
+        sender: oneshot::Sender<Option<String>>,
+    },
+    GetRedactedState {
+        sender: oneshot::Sender<Option<String>>,
+    },
+    GetLogs {
+        sender: oneshot::Sender<Option<structure::ConfigLogs>>,
+    },
+    GetDownloadLimits {
+        sender: oneshot::Sender<DownloadLimits>,
+    },
+    GetPolice {
+        sender: oneshot::Sender<structure::ConfigPolice>,
+    },
+    GetPolicy {
+        sender: oneshot::Sender<Option<Policy>>,
+    },
+    ApplyAgentConfig {
+        version: String,
+        config: String,
+        rpc: oneshot::Sender<anyhow::Result<Vec<&'static str>>>,
+    },
+    SetDownloadLimits {
+        limits: DownloadLimits,
+    },
+    GetOtaProfile {
+        name: Option<String>,
+        sender: oneshot::Sender<Option<structure::ConfigOta>>,
+    },
     GetPackages {
         sender: oneshot::Sender<Vec<structure::ConfigPackage>>,
     },
@@ -44,6 +86,13 @@ enum MagicMessage {
     SetTargetReleaseId {
         target_release_id: Option<i32>,
     },
+    GetPendingRestart {
+        rpc: oneshot::Sender<Option<PendingRestart>>,
+    },
+    SetPendingRestart {
+        pending: Option<PendingRestart>,
+        rpc: oneshot::Sender<()>,
+    },
     GetToken {
         rpc: oneshot::Sender<Option<String>>,
     },
@@ -53,27 +102,112 @@ enum MagicMessage {
 }
 
 impl Magic {
-    fn new(shutdown: ShutdownSignals, receiver: mpsc::Receiver<MagicMessage>) -> Self {
+    fn new(
+        shutdown: ShutdownSignals,
+        receiver: mpsc::Receiver<MagicMessage>,
+        agent_config: watch::Sender<Option<String>>,
+    ) -> Self {
         Self {
             shutdown,
             receiver,
             configuration: None,
             path: None,
+            state: MagicState::default(),
+            state_path: None,
+            agent_config,
+        }
+    }
+
+    async fn save_state(&self) {
+        match &self.state_path {
+            Some(path) => {
+                if let Err(err) = self.state.save(path).await {
+                    error!("Failed to save state to {}: {:?}", path.display(), err);
+                }
+            }
+            None => {
+                warn!("No path to write to");
+            }
+        }
+    }
+
+    /// Writes the managed drop-in and reloads magic.toml with it, putting
+    /// the previous drop-in back when the result doesn't load. Returns the
+    /// sections that changed.
+    async fn apply_agent_config(
+        &mut self,
+        version: String,
+        config: String,
+    ) -> anyhow::Result<Vec<&'static str>> {
+        let path = self
+            .path
+            .clone()
+            .ok_or_else(|| anyhow!("smithd runs without a magic.toml"))?;
+        structure::MagicFile::parse_managed(&config)?;
+
+        let drop_in = structure::MagicFile::managed_drop_in(&path);
+        if let Some(dir) = drop_in.parent() {
+            tokio::fs::create_dir_all(dir).await?;
         }
+        let previous = tokio::fs::read(&drop_in).await.ok();
+        state::write_atomically(&drop_in, config.as_bytes()).await?;
+
+        let configuration = match structure::MagicFile::load_from_path(&path.to_string_lossy()) {
+            Ok((configuration, _, _)) => configuration,
+            Err(err) => {
+                let restored = match previous {
+                    Some(previous) => state::write_atomically(&drop_in, &previous).await,
+                    None => tokio::fs::remove_file(&drop_in).await.map_err(Into::into),
+                };
+                if let Err(err) = restored {
+                    error!("Failed to restore {}: {:?}", drop_in.display(), err);
+                }
+                return Err(err);
+            }
+        };
+
+        let changed = match &self.configuration {
+            Some(current) => configuration.changed_sections(current),
+            None => structure::MANAGED_SECTIONS.to_vec(),
+        };
+        self.configuration = Some(configuration);
+        self.state.agent_config_version = Some(version.clone());
+        self.save_state().await;
+        self.agent_config.send_replace(Some(version));
+
+        Ok(changed)
     }
+
     async fn handle_message(&mut self, msg: MagicMessage) {
         match msg {
             MagicMessage::Load { path, signal } => {
-                match structure::MagicFile::load(path) {
-                    Ok((conf, path)) => {
+                match structure::MagicFile::load(path).and_then(|(conf, legacy, path)| {
+                    let state_path = path.as_deref().map(MagicState::path_for);
+                    let state = state_path
+                        .as_deref()
+                        .map(MagicState::load)
+                        .transpose()?
+                        .unwrap_or_default();
+                    Ok((conf, legacy, path, state_path, state))
+                }) {
+                    Ok((conf, legacy, path, state_path, state)) => {
                         self.configuration = Some(conf);
+                        self.state_path = state_path;
                         self.path = path;
+                        self.state = state;
+                        if self.state.adopt(legacy) {
+                            info!("Moving runtime state out of magic.toml");
+                            self.save_state().await;
+                        }
+                        self.agent_config
+                            .send_replace(self.state.agent_config_version.clone());
+                        _ = signal.send(Ok(()));
                     }
                     Err(err) => {
                         error!("Failed to load Magic from file: {:?}", err);
+                        _ = signal.send(Err(err));
                     }
                 }
-                signal.send(()).unwrap();
             }
             MagicMessage::GetChecks { sender } => {
                 debug!("Getting Magic checks");
@@ -93,13 +227,78 @@ impl Magic {
                     _ = sender.send(structure::ConfigTunnel::default());
                 }
             }
+            MagicMessage::GetRedacted { sender } => {
+                debug!("Getting redacted Magic");
+                let redacted = self.configuration.as_ref().and_then(|conf| {
+                    conf.to_redacted_string()
+                        .inspect_err(|err| error!("Failed to redact Magic: {:?}", err))
+                        .ok()
+                });
+                _ = sender.send(redacted);
+            }
+            MagicMessage::GetRedactedState { sender } => {
+                debug!("Getting redacted state");
+                let redacted = self
+                    .state
+                    .to_redacted_string()
+                    .inspect_err(|err| error!("Failed to redact state: {:?}", err))
+                    .ok();
+                _ = sender.send(redacted);
+            }
+            MagicMessage::GetLogs { sender } => {
+                debug!("Getting Magic Logs");
+                _ = sender.send(self.configuration.as_ref().and_then(|conf| conf.get_logs()));
+            }
+            MagicMessage::GetDownloadLimits { sender } => {
+                debug!("Getting Magic Download Limits");
+                let configured = self
+                    .configuration
+                    .as_ref()
+                    .and_then(|conf| conf.get_download_limits());
+                _ = sender.send(self.state.downloads.or(configured).unwrap_or_default());
+            }
+            MagicMessage::GetPolice { sender } => {
+                debug!("Getting Magic Police");
+                let police = self
+                    .configuration
+                    .as_ref()
+                    .map(|conf| conf.get_police())
+                    .unwrap_or_default();
+                _ = sender.send(police);
+            }
+            MagicMessage::GetPolicy { sender } => {
+                debug!("Getting Magic Policy");
+                let policy = self
+                    .configuration
+                    .as_ref()
+                    .and_then(|conf| conf.get_policy())
+                    .map(|policy| Policy::new(policy, self.path.as_deref()));
+                _ = sender.send(policy);
+            }
+            MagicMessage::ApplyAgentConfig {
+                version,
+                config,
+                rpc,
+            } => {
+                info!("Applying agent configuration {}", version);
+                _ = rpc.send(self.apply_agent_config(version, config).await);
+            }
+            MagicMessage::SetDownloadLimits { limits } => {
+                debug!("Setting Magic Download Limits");
+                self.state.downloads = Some(limits);
+                self.save_state().await;
+            }
+            MagicMessage::GetOtaProfile { name, sender } => {
+                debug!("Getting Magic OTA profile {:?}", name);
+                let profile = match &self.configuration {
+                    Some(conf) => conf.get_ota_profile(name.as_deref()),
+                    None => structure::MagicFile::default().get_ota_profile(name.as_deref()),
+                };
+                _ = sender.send(profile);
+            }
             MagicMessage::GetPackages { sender } => {
                 debug!("Getting Magic Packages");
-                if let Some(conf) = &self.configuration {
-                    _ = sender.send(conf.get_packages());
-                } else {
-                    _ = sender.send(vec![]);
-                }
+                _ = sender.send(self.state.packages.clone().unwrap_or_default());
             }
             MagicMessage::GetServer { sender } => {
                 debug!("Getting Magic Server");
@@ -112,94 +311,52 @@ impl Magic {
             }
             MagicMessage::GetReleaseId { rpc } => {
                 debug!("Getting Magic Release Id");
-                if let Some(conf) = &self.configuration {
-                    _ = rpc.send(conf.get_release_id());
-                } else {
-                    warn!("No release id configured, using default");
-                    _ = rpc.send(None);
-                }
+                _ = rpc.send(self.state.release_id);
             }
             MagicMessage::SetReleaseId { release_id } => {
-                if let Some(conf) = &mut self.configuration {
-                    let current_release_id = conf.meta.release_id;
-                    if current_release_id == release_id {
-                        return;
-                    }
-                    debug!("Setting Magic Release Id");
-                    conf.set_release_id(release_id);
-                    match &self.path {
-                        Some(path) => {
-                            _ = conf.write_to_file(path.to_str().unwrap()).await;
-                        }
-                        None => {
-                            warn!("No path to write to");
-                        }
-                    }
+                if self.state.release_id == release_id {
+                    return;
                 }
+                debug!("Setting Magic Release Id");
+                self.state.release_id = release_id;
+                self.save_state().await;
             }
             MagicMessage::GetTargetReleaseId { rpc } => {
                 debug!("Getting Magic Target Release Id");
-                if let Some(conf) = &self.configuration {
-                    _ = rpc.send(conf.get_target_release_id());
-                } else {
-                    warn!("No target release id configured, using default");
-                    _ = rpc.send(None);
-                }
+                _ = rpc.send(self.state.target_release_id);
             }
             MagicMessage::SetTargetReleaseId { target_release_id } => {
-                if let Some(conf) = &mut self.configuration {
-                    let current_target_release_id = conf.meta.target_release_id;
-                    if current_target_release_id == target_release_id {
-                        return;
-                    }
-                    debug!("Setting Magic Target Release Id");
-                    conf.set_target_release_id(target_release_id);
-                    match &self.path {
-                        Some(path) => {
-                            _ = conf.write_to_file(path.to_str().unwrap()).await;
-                        }
-                        None => {
-                            warn!("No path to write to");
-                        }
-                    }
+                if self.state.target_release_id == target_release_id {
+                    return;
+                }
+                debug!("Setting Magic Target Release Id");
+                self.state.target_release_id = target_release_id;
+                self.save_state().await;
+            }
+            MagicMessage::GetPendingRestart { rpc } => {
+                _ = rpc.send(self.state.pending_restart.clone());
+            }
+            MagicMessage::SetPendingRestart { pending, rpc } => {
+                if self.state.pending_restart != pending {
+                    debug!("Setting pending restart");
+                    self.state.pending_restart = pending;
+                    self.save_state().await;
                 }
+                _ = rpc.send(());
             }
             MagicMessage::SetPackages { packages } => {
                 debug!("Setting Magic Packages");
-                if let Some(conf) = &mut self.configuration {
-                    conf.set_packages(packages);
-                    match &self.path {
-                        Some(path) => {
-                            _ = conf.write_to_file(path.to_str().unwrap()).await;
-                        }
-                        None => {
-                            warn!("No path to write to");
-                        }
-                    }
-                }
+                self.state.packages = Some(packages);
+                self.save_state().await;
             }
             MagicMessage::GetToken { rpc } => {
-                debug!("Getting Magic Token From Magic File");
-                if let Some(conf) = &self.configuration {
-                    _ = rpc.send(conf.get_token());
-                } else {
-                    warn!("No token configured, using default");
-                    _ = rpc.send(None);
-                }
+                debug!("Getting Magic Token From State");
+                _ = rpc.send(self.state.token.clone());
             }
             MagicMessage::SetToken { token } => {
                 debug!("Setting Magic Token");
-                if let Some(conf) = &mut self.configuration {
-                    conf.set_token(token);
-                    match &self.path {
-                        Some(path) => {
-                            _ = conf.write_to_file(path.to_str().unwrap()).await;
-                        }
-                        None => {
-                            warn!("No path to write to");
-                        }
-                    }
-                }
+                self.state.token = token;
+                self.save_state().await;
             }
         }
     }
@@ -223,22 +380,29 @@ impl Magic {
 #[derive(Clone)]
 pub struct MagicHandle {
     sender: mpsc::Sender<MagicMessage>,
+    agent_config: watch::Receiver<Option<String>>,
 }
 
 impl MagicHandle {
     pub fn new(shutdown: ShutdownSignals) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Magic::new(shutdown, receiver);
+        let (agent_config_sender, agent_config) = watch::channel(None);
+        let mut actor = Magic::new(shutdown, receiver, agent_config_sender);
         tokio::spawn(async move { actor.run().await });
 
-        Self { sender }
+        Self {
+            sender,
+            agent_config,
+        }
     }
 
-    pub async fn load(&self, path: Option<String>) {
+    /// Fails on a magic.toml that can't be read or is invalid, rather than
+    /// carrying on with the defaults.
+    pub async fn load(&self, path: Option<String>) -> anyhow::Result<()> {
         let (signal, done) = oneshot::channel();
         let message = MagicMessage::Load { path, signal };
         _ = self.sender.send(message).await;
-        done.await.unwrap();
+        done.await?
     }
 
     pub async fn get_release_id(&self) -> Option<i32> {
@@ -265,6 +429,23 @@ impl MagicHandle {
         _ = self.sender.send(msg).await;
     }
 
+    pub async fn get_pending_restart(&self) -> Option<PendingRestart> {
+        let (rpc, receiver) = oneshot::channel();
+        _ = self
+            .sender
+            .send(MagicMessage::GetPendingRestart { rpc })
+            .await;
+        receiver.await.ok().flatten()
+    }
+
+    /// Returns once the state is saved.
+    pub async fn set_pending_restart(&self, pending: Option<PendingRestart>) {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::SetPendingRestart { pending, rpc };
+        _ = self.sender.send(msg).await;
+        _ = receiver.await;
+    }
+
     pub async fn get_checks(&self) -> Vec<structure::ConfigCheck> {
         let (sender, receiver) = oneshot::channel();
         let msg = MagicMessage::GetChecks { sender };
@@ -279,6 +460,102 @@ impl MagicHandle {
         receiver.await.unwrap()
     }
 
+    /// The loaded magic.toml without its secrets, for support bundles.
+    pub async fn get_redacted(&self) -> Option<String> {
+        let (sender, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetRedacted { sender };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap()
+    }
+
+    /// state.toml as [`Self::get_redacted`] has it.
+    pub async fn get_redacted_state(&self) -> Option<String> {
+        let (sender, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetRedactedState { sender };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap()
+    }
+
+    /// The `[logs]` section, `None` when logs are not shipped.
+    pub async fn get_logs(&self) -> Option<structure::ConfigLogs> {
+        let (sender, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetLogs { sender };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap()
+    }
+
+    /// Limits set through the API, otherwise the `[downloads]` section, no
+    /// limits when neither is there.
+    pub async fn get_download_limits(&self) -> DownloadLimits {
+        let (sender, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetDownloadLimits { sender };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap()
+    }
+
+    /// The `[police]` section, the defaults when there is none.
+    pub async fn get_police(&self) -> structure::ConfigPolice {
+        let (sender, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetPolice { sender };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap()
+    }
+
+    /// The `[policy]` section, commands aren't restricted when there is none.
+    pub async fn get_policy(&self) -> Option<Policy> {
+        let (sender, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetPolicy { sender };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap()
+    }
+
+    /// Makes agent configuration pushed from the API the managed drop-in,
+    /// returns the sections that changed.
+    pub async fn apply_agent_config(
+        &self,
+        version: String,
+        config: String,
+    ) -> anyhow::Result<Vec<&'static str>> {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::ApplyAgentConfig {
+            version,
+            config,
+            rpc,
+        };
+        _ = self.sender.send(msg).await;
+        receiver.await?
+    }
+
+    /// Version of the agent configuration pushed from the API, `None`
+    /// before the first one.
+    pub fn agent_config_version(&self) -> Option<String> {
+        self.agent_config.borrow().clone()
+    }
+
+    /// Changes whenever new agent configuration was applied, for actors to
+    /// reload what they read from magic.toml.
+    pub fn agent_config_changes(&self) -> watch::Receiver<Option<String>> {
+        let mut changes = self.agent_config.clone();
+        changes.borrow_and_update();
+        changes
+    }
+
+    pub async fn set_download_limits(&self, limits: DownloadLimits) {
+        let msg = MagicMessage::SetDownloadLimits { limits };
+        _ = self.sender.send(msg).await;
+    }
+
+    /// The named `[[ota]]` profile, the built-in Jetson one when no name is given.
+    pub async fn get_ota_profile(&self, name: Option<&str>) -> Option<structure::ConfigOta> {
+        let (sender, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetOtaProfile {
+            name: name.map(str::to_string),
+            sender,
+        };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap()
+    }
+
     pub async fn get_packages(&self) -> Vec<structure::ConfigPackage> {
         let (sender, receiver) = oneshot::channel();
         let msg = MagicMessage::GetPackages { sender };
@@ -0,0 +1,265 @@
+use crate::magic::structure::ConfigPackage;
+use crate::utils::schema::{DownloadLimits, PendingRestart};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+const STATE_FILE: &str = "/var/lib/smith/state.toml";
+const ETC_MAGIC_FILE: &str = "/etc/smith/magic.toml";
+
+/// Everything smithd changes at runtime, kept out of magic.toml so tools
+/// managing the configuration don't fight with the daemon over it.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct MagicState {
+    pub token: Option<String>,
+    pub release_id: Option<i32>,
+    pub target_release_id: Option<i32>,
+    /// Version of the agent configuration last pushed from the API.
+    pub agent_config_version: Option<String>,
+    /// Set through the API, wins over the `[downloads]` of magic.toml.
+    pub downloads: Option<DownloadLimits>,
+    #[serde(rename = "package")]
+    pub packages: Option<Vec<ConfigPackage>>,
+    /// Restart scheduled through the API, kept across smithd restarts.
+    pub pending_restart: Option<PendingRestart>,
+}
+
+impl MagicState {
+    /// The state of the installed magic.toml lives in /var/lib/smith, any
+    /// other magic.toml keeps its state next to it.
+    pub fn path_for(magic_file: &Path) -> PathBuf {
+        if magic_file == Path::new(ETC_MAGIC_FILE) {
+            PathBuf::from(STATE_FILE)
+        } else {
+            magic_file.with_file_name("state.toml")
+        }
+    }
+
+    /// The state file, or the backup [`MagicState::save`] keeps when it
+    /// can't be read. Fails when neither can, starting over would lose the
+    /// token of the device.
+    pub fn load(path: &Path) -> Result<Self> {
+        let backup = path.with_file_name(format!(
+            "{}.bak",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        match Self::read(path) {
+            Ok(Some(state)) => return Ok(state),
+            Ok(None) if !backup.exists() => return Ok(Self::default()),
+            Ok(None) => warn!("{} is missing, using its backup", path.display()),
+            Err(err) => warn!(
+                "{} is unreadable, using its backup: {:?}",
+                path.display(),
+                err
+            ),
+        }
+
+        Self::read(&backup)
+            .and_then(|state| state.ok_or_else(|| anyhow!("there is no backup")))
+            .with_context(|| format!("Failed to read state {}", path.display()))
+    }
+
+    /// `None` when there is no such file.
+    fn read(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Some(toml::from_str(&contents)?))
+    }
+
+    /// The state as TOML with the token left out.
+    pub fn to_redacted_string(&self) -> Result<String> {
+        let mut value = toml::Value::try_from(self)?;
+        crate::magic::structure::redact(&mut value);
+        Ok(toml::to_string_pretty(&value)?)
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let options = WriteOptions {
+            backup: true,
+            ..Default::default()
+        };
+        write_atomically_with(path, toml::to_string_pretty(self)?.as_bytes(), options).await
+    }
+
+    /// Takes over what an older magic.toml still held and the state file
+    /// doesn't, returns whether anything was taken.
+    pub fn adopt(&mut self, legacy: MagicState) -> bool {
+        fn take<T>(current: &mut Option<T>, legacy: Option<T>) -> bool {
+            let taken = current.is_none() && legacy.is_some();
+            if taken {
+                *current = legacy;
+            }
+            taken
+        }
+
+        // not short-circuiting, every field has to be looked at
+        take(&mut self.token, legacy.token)
+            | take(&mut self.release_id, legacy.release_id)
+            | take(&mut self.target_release_id, legacy.target_release_id)
+            | take(&mut self.downloads, legacy.downloads)
+            | take(&mut self.packages, legacy.packages)
+    }
+}
+
+/// How [`write_atomically_with`] replaces a file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteOptions {
+    /// Keep the previous file as `.bak`.
+    pub backup: bool,
+    /// Mode of the file. Unset, a replaced file keeps its mode and a new
+    /// one gets 0644.
+    pub mode: Option<u32>,
+}
+
+/// Replaces the file in one rename, keeping its mode.
+pub async fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    write_atomically_with(path, contents, WriteOptions::default()).await
+}
+
+pub async fn write_atomically_with(
+    path: &Path,
+    contents: &[u8],
+    options: WriteOptions,
+) -> Result<()> {
+    let path = path.to_path_buf();
+    let contents = contents.to_vec();
+    tokio::task::spawn_blocking(move || {
+        replace(&path, options, |file| Ok(file.write_all(&contents)?))
+    })
+    .await?
+}
+
+/// Blocking, has `write` fill a new file next to `path` and renames it over
+/// `path` once synced. The new file is removed when `write` fails.
+pub fn replace(
+    path: &Path,
+    options: WriteOptions,
+    write: impl FnOnce(&mut std::fs::File) -> Result<()>,
+) -> Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", path.display()))?
+        .to_string_lossy();
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+
+    let mode = match (options.mode, std::fs::metadata(path)) {
+        (Some(mode), _) => mode,
+        (None, Ok(metadata)) => metadata.permissions().mode() & 0o7777,
+        (None, Err(err)) if err.kind() == std::io::ErrorKind::NotFound => 0o644,
+        (None, Err(err)) => return Err(err.into()),
+    };
+
+    // removed on drop unless it gets renamed into place, and only readable
+    // by us until the mode is set
+    let mut temporary = tempfile::Builder::new()
+        .prefix(&format!(".{}.", name))
+        .suffix(".smith")
+        .tempfile_in(dir)?;
+    write(temporary.as_file_mut())?;
+    temporary
+        .as_file()
+        .set_permissions(std::fs::Permissions::from_mode(mode))?;
+    temporary.as_file().sync_all()?;
+
+    if options.backup && path.exists() {
+        std::fs::copy(path, path.with_file_name(format!("{}.bak", name)))?;
+    }
+    temporary.persist(path)?;
+
+    // the rename only survives a power cut once the directory is synced
+    std::fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adopts_only_missing_state() {
+        let mut state = MagicState {
+            token: Some("current".to_string()),
+            ..Default::default()
+        };
+
+        let adopted = state.adopt(MagicState {
+            token: Some("legacy".to_string()),
+            release_id: Some(7),
+            ..Default::default()
+        });
+        assert!(adopted);
+        assert_eq!(state.token.as_deref(), Some("current"));
+        assert_eq!(state.release_id, Some(7));
+
+        assert!(!state.adopt(MagicState::default()));
+    }
+
+    #[tokio::test]
+    async fn keeps_backup_when_saving() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.toml");
+
+        let mut state = MagicState {
+            release_id: Some(1),
+            ..Default::default()
+        };
+        state.save(&path).await.unwrap();
+        state.release_id = Some(2);
+        state.save(&path).await.unwrap();
+
+        assert_eq!(MagicState::load(&path).unwrap(), state);
+        let backup = std::fs::read_to_string(dir.path().join("state.toml.bak")).unwrap();
+        assert!(backup.contains("release_id = 1"));
+        // nothing but the state and its backup is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // a torn state file falls back to the backup, and without one there
+        // is no state to start with
+        std::fs::write(&path, "release_id = ").unwrap();
+        assert_eq!(MagicState::load(&path).unwrap().release_id, Some(1));
+        std::fs::remove_file(dir.path().join("state.toml.bak")).unwrap();
+        assert!(MagicState::load(&path).is_err());
+        assert_eq!(
+            MagicState::load(&dir.path().join("missing.toml")).unwrap(),
+            MagicState::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_mode_when_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        write_atomically(&path, b"one").await.unwrap();
+        assert_eq!(mode(&path), 0o644);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomically(&path, b"two").await.unwrap();
+        assert_eq!(mode(&path), 0o640);
+
+        let options = WriteOptions {
+            mode: Some(0o600),
+            ..Default::default()
+        };
+        write_atomically_with(&path, b"three", options)
+            .await
+            .unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(std::fs::read(&path).unwrap(), b"three");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,39 +1,42 @@
-use anyhow::{Context, Result};
+use crate::magic::state::MagicState;
+use crate::magic::{merge, migrate};
+use crate::utils::host::Host;
+use crate::utils::schema::{DownloadLimits, ReleasePackage};
+use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt; // for write_all()
-use tracing::{error, info};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MagicFile {
     pub meta: ConfigMeta,
     pub tunnel: Option<ConfigTunnel>,
     pub scheduler: Option<ConfigScheduler>,
+    pub logs: Option<ConfigLogs>,
+    pub downloads: Option<DownloadLimits>,
+    pub police: Option<ConfigPolice>,
+    #[serde(rename = "ota")]
+    pub otas: Option<Vec<ConfigOta>>,
     #[serde(rename = "check")]
     pub checks: Option<Vec<ConfigCheck>>,
     #[serde(rename = "metric")]
     pub metrics: Option<Vec<ConfigMetric>>,
-    #[serde(rename = "package")]
-    pub packages: Option<Vec<ConfigPackage>>,
+    pub policy: Option<ConfigPolicy>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ConfigMeta {
     pub magic_version: i32,
     pub server: String,
-    pub release_id: Option<i32>,
-    pub target_release_id: Option<i32>,
-    pub token: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ConfigCheck {
     pub name: String,
     pub cmd: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ConfigMetric {
     pub log_only: bool,
     pub name: String,
@@ -47,26 +50,21 @@ pub struct ConfigPackage {
     pub file: String,
 }
 
+impl From<&ReleasePackage> for ConfigPackage {
+    fn from(package: &ReleasePackage) -> Self {
+        Self {
+            name: package.package.name.clone(),
+            version: package.package.version.clone(),
+            file: package.package.file.clone(),
+        }
+    }
+}
+
 impl ConfigPackage {
-    // TODO: use this function more
-    pub async fn get_system_version(&self) -> Result<String> {
-        let name = &self.name;
-        let output = tokio::process::Command::new("dpkg")
-            .arg("-l")
-            .arg(name)
-            .output()
+    pub async fn get_system_version(&self, host: &dyn Host) -> Result<String> {
+        crate::updater::install::installed_version(host, &self.name)
             .await
-            .unwrap();
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let lines: Vec<&str> = stdout.lines().collect();
-        let package_info = lines.get(5).with_context(|| "Failed to get package info")?;
-        let fields: Vec<&str> = package_info.split_whitespace().collect();
-        let version = fields
-            .get(2)
-            .with_context(|| "Failed to get package version")?;
-
-        Ok(version.to_string())
+            .with_context(|| "Failed to get package version")
     }
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -89,27 +87,261 @@ pub struct ConfigScheduler {
     pub app: Vec<String>,
 }
 
+/// Journal entries shipped to the API.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigLogs {
+    /// Systemd units to follow, all of them when empty.
+    #[serde(default)]
+    pub units: Vec<String>,
+    /// Least important syslog priority shipped, 0 (emerg) to 7 (debug).
+    #[serde(default = "default_log_priority")]
+    pub priority: u8,
+    /// Bytes that may be shipped per day while on a metered connection.
+    pub metered_daily_budget: Option<u64>,
+}
+
+fn default_log_priority() -> u8 {
+    // warning
+    4
+}
+
+/// When the police reboots a device whose checks keep failing.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigPolice {
+    #[serde(default = "default_police_enabled")]
+    pub enabled: bool,
+    /// Seconds after smithd starts before a problem can lead to a reboot,
+    /// so a device that boots into a problem can still be reached.
+    #[serde(default = "default_police_grace")]
+    pub grace: u64,
+    /// Seconds a problem has to last before the device is rebooted.
+    #[serde(default = "default_police_delay")]
+    pub delay: u64,
+}
+
+impl Default for ConfigPolice {
+    fn default() -> Self {
+        Self {
+            enabled: default_police_enabled(),
+            grace: default_police_grace(),
+            delay: default_police_delay(),
+        }
+    }
+}
+
+fn default_police_enabled() -> bool {
+    true
+}
+
+fn default_police_grace() -> u64 {
+    15 * 60
+}
+
+fn default_police_delay() -> u64 {
+    5 * 60
+}
+
+/// Commands the device refuses whatever the API sends. Only set locally,
+/// the API can't manage it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigPolicy {
+    /// Command variants that may run, like `FreeForm`, all of them when not set.
+    pub allow: Option<Vec<String>>,
+    /// Command variants that never run, even when allowed.
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Regexes a `FreeForm` command has to match in full, any command when
+    /// not set.
+    pub free_form: Option<Vec<String>>,
+    /// Paths `UpdateConfigFiles` can't write to or under. The directory of
+    /// magic.toml always is.
+    #[serde(default)]
+    pub forbidden_paths: Vec<PathBuf>,
+    /// Local ports tunnels may be opened to, any port when not set.
+    pub tunnel_ports: Option<Vec<u16>>,
+    /// Only run commands signed by one of `admin_keys`.
+    #[serde(default)]
+    pub require_signature: bool,
+    /// Base64 Ed25519 public keys, from `smithd policy keygen`.
+    #[serde(default)]
+    pub admin_keys: Vec<String>,
+    /// Every decision is appended to it as a JSON line.
+    #[serde(default = "default_audit_log")]
+    pub audit_log: PathBuf,
+    /// Nonces of the signed commands that ran, kept until they expire.
+    #[serde(default = "default_used_signatures")]
+    pub used_signatures: PathBuf,
+}
+
+impl Default for ConfigPolicy {
+    fn default() -> Self {
+        Self {
+            allow: None,
+            deny: vec![],
+            free_form: None,
+            forbidden_paths: vec![],
+            tunnel_ports: None,
+            require_signature: false,
+            admin_keys: vec![],
+            audit_log: default_audit_log(),
+            used_signatures: default_used_signatures(),
+        }
+    }
+}
+
+fn default_audit_log() -> PathBuf {
+    PathBuf::from("/var/log/smith/audit.log")
+}
+
+fn default_used_signatures() -> PathBuf {
+    PathBuf::from("/var/lib/smith/used_signatures.json")
+}
+
+/// How an OTA update is downloaded and applied on a hardware family.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigOta {
+    pub name: String,
+    /// Where the tools archive is downloaded to and extracted.
+    pub tools_dir: Option<String>,
+    /// Where the payload is downloaded to.
+    pub payload_dir: String,
+    /// Local file name of the tools archive, profiles without one only download a payload.
+    pub tools_file: Option<String>,
+    /// Local file name of the payload.
+    pub payload_file: String,
+    /// Commands that all have to succeed before the update is applied.
+    #[serde(default)]
+    pub checks: Vec<String>,
+    /// Shell command applying the update, `{tools}` and `{payload}` are
+    /// replaced with the paths of the downloaded artifacts.
+    pub apply: String,
+    /// Working directory of the apply command.
+    pub apply_dir: Option<String>,
+    #[serde(default)]
+    pub reboot: OtaReboot,
+    /// Shell command printing the running OS version, `VERSION_ID` from
+    /// /etc/os-release when not set.
+    pub version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OtaReboot {
+    /// The device is left to reboot on its own, or by a later command.
+    Never,
+    Immediate,
+    /// Reboot shortly after, so the result of the command can still be reported.
+    #[default]
+    Delayed,
+}
+
+impl ConfigOta {
+    /// Used when no profile is named, and when magic.toml doesn't define one called `jetson`.
+    pub const DEFAULT: &str = "jetson";
+
+    /// NVIDIA's image based OTA for Jetson boards.
+    pub fn jetson() -> Self {
+        Self {
+            name: Self::DEFAULT.to_string(),
+            tools_dir: Some("/otatool".to_string()),
+            payload_dir: "/ota".to_string(),
+            tools_file: Some("ota_tools.tbz2".to_string()),
+            payload_file: "ota_payload_package.tar.gz".to_string(),
+            checks: vec![],
+            apply: "bash nv_ota_start.sh {payload}".to_string(),
+            apply_dir: Some(
+                "/otatool/Linux_for_Tegra/tools/ota_tools/version_upgrade/".to_string(),
+            ),
+            reboot: OtaReboot::Delayed,
+            // "# R35 (release), REVISION: 4.1, ..." becomes 35.4.1
+            version: Some(
+                r"sed -n 's/^# R\([0-9]*\) (release), REVISION: \([0-9.]*\).*/\1.\2/p' /etc/nv_tegra_release"
+                    .to_string(),
+            ),
+        }
+    }
+
+    pub fn tools_path(&self) -> Option<String> {
+        let dir = self.tools_dir.as_deref().unwrap_or(&self.payload_dir);
+        self.tools_file
+            .as_ref()
+            .map(|file| format!("{}/{}", dir.trim_end_matches('/'), file))
+    }
+
+    pub fn payload_path(&self) -> String {
+        format!(
+            "{}/{}",
+            self.payload_dir.trim_end_matches('/'),
+            self.payload_file
+        )
+    }
+
+    /// The apply command with the artifact paths filled in.
+    pub fn apply_command(&self) -> String {
+        self.apply
+            .replace("{tools}", &self.tools_path().unwrap_or_default())
+            .replace("{payload}", &self.payload_path())
+    }
+}
+
+/// Where magic.toml is looked for when no path is given, in order.
+/// Keys, or suffixes after an `_`, of values [`redact`] leaves out.
+const CREDENTIAL_KEYS: [&str; 5] = ["secret", "token", "password", "psk", "private_key"];
+
+/// Replaces every credential in the TOML, however deep, with `<redacted>`.
+pub fn redact(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                let credential = CREDENTIAL_KEYS.iter().any(|credential| {
+                    key == *credential || key.ends_with(&format!("_{credential}"))
+                });
+                if credential && !value.is_table() && !value.is_array() {
+                    *value = toml::Value::String("<redacted>".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+const LOCATIONS: [&str; 2] = ["./magic.toml", "/etc/smith/magic.toml"];
+
+/// Drop-in holding the agent configuration pushed from the API. Local
+/// drop-ins sorting after it still win.
+const MANAGED_DROP_IN: &str = "50-managed.toml";
+
+/// Sections the API may manage, anything else stays with the device.
+pub const MANAGED_SECTIONS: [&str; 5] = ["check", "metric", "police", "logs", "downloads"];
+
 impl MagicFile {
-    pub fn autoload() -> Result<(Self, Option<PathBuf>)> {
-        // check if a magic.toml exists in the current directory
-        // if it does call load with the path location
-        let magic_in_cwd = std::path::Path::new("./magic.toml");
-        let magic_in_etc = std::path::Path::new("/etc/smith/magic.toml");
-
-        if magic_in_cwd.exists() {
-            info!("Loading magic.toml: LOCAL");
-            // unwrapping here I think its safe, because we construct the path ourselves
-            Self::load_from_path(magic_in_cwd.to_str().unwrap())
-        } else if magic_in_etc.exists() {
-            info!("Loading magic.toml: ETC");
-            Self::load_from_path(magic_in_etc.to_str().unwrap())
-        } else {
-            error!("Loading magic.toml: NO MAGIC FILE FOUND");
-            Ok((Self::default(), None))
+    /// The magic.toml smithd loads when no path is given.
+    pub fn locate() -> Option<PathBuf> {
+        LOCATIONS
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+            .map(Path::to_path_buf)
+    }
+
+    pub fn autoload() -> Result<(Self, MagicState, Option<PathBuf>)> {
+        match Self::locate() {
+            Some(path) => {
+                info!("Loading magic.toml: {}", path.display());
+                Self::load_from_path(&path.to_string_lossy())
+            }
+            None => Err(anyhow!(
+                "No magic.toml found, looked for {}",
+                LOCATIONS.join(" and ")
+            )),
         }
     }
 
-    pub fn load(location: Option<String>) -> Result<(Self, Option<PathBuf>)> {
+    pub fn load(location: Option<String>) -> Result<(Self, MagicState, Option<PathBuf>)> {
         if let Some(location) = location {
             info!("Loading magic.toml: {}", location);
             Self::load_from_path(&location)
@@ -118,22 +350,213 @@ impl MagicFile {
         }
     }
 
-    pub fn load_from_path(location: &str) -> Result<(Self, Option<PathBuf>)> {
-        let contents = std::fs::read_to_string(location).unwrap();
-        let magic_file: MagicFile = toml::from_str(&contents)
-            .with_context(|| format!("Failed to parse magic file: {}", location))?;
+    /// magic.toml merged with its drop-ins. Also returns the runtime state
+    /// an older magic.toml still held, for the state file to take over.
+    pub fn load_from_path(location: &str) -> Result<(Self, MagicState, Option<PathBuf>)> {
+        let path = Path::new(location);
+        let (mut table, version, legacy) = Self::read_base(path)?;
+        if version < migrate::CURRENT_VERSION {
+            warn!(
+                "{} is at magic_version {}, `smithd config migrate` upgrades it to {}",
+                location,
+                version,
+                migrate::CURRENT_VERSION
+            );
+        }
+
+        for drop_in in Self::drop_ins(path)? {
+            info!("Loading magic.toml drop-in: {}", drop_in.display());
+            let contents = std::fs::read_to_string(&drop_in)
+                .with_context(|| format!("Failed to read drop-in: {}", drop_in.display()))?;
+            let fragment: toml::Table = toml::from_str(&contents)
+                .with_context(|| format!("Failed to parse drop-in: {}", drop_in.display()))?;
+            merge::merge(&mut table, fragment)
+                .with_context(|| format!("Invalid drop-in: {}", drop_in.display()))?;
+        }
+
+        let magic_file =
+            Self::from_table(table).with_context(|| format!("Invalid magic file: {}", location))?;
+
+        Ok((magic_file, legacy, Some(PathBuf::from(location))))
+    }
+
+    /// magic.toml alone, migrated to the current version, with the version
+    /// it was written in and the runtime state it held.
+    pub fn read_base(path: &Path) -> Result<(toml::Table, i32, MagicState)> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read magic file: {}", path.display()))?;
+        let mut table: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse magic file: {}", path.display()))?;
+        let (version, state) = migrate::migrate(&mut table)
+            .with_context(|| format!("Failed to migrate magic file: {}", path.display()))?;
+        let state = state
+            .try_into()
+            .with_context(|| format!("Invalid state in magic file: {}", path.display()))?;
+
+        Ok((table, version, state))
+    }
+
+    /// Where the agent configuration pushed from the API is kept.
+    pub fn managed_drop_in(path: &Path) -> PathBuf {
+        path.with_file_name("magic.d").join(MANAGED_DROP_IN)
+    }
+
+    /// Reads agent configuration pushed from the API, refusing sections
+    /// the API doesn't manage.
+    pub fn parse_managed(contents: &str) -> Result<toml::Table> {
+        let fragment: toml::Table = toml::from_str(contents)?;
+        if let Some(section) = fragment
+            .keys()
+            .find(|section| !MANAGED_SECTIONS.contains(&section.as_str()))
+        {
+            return Err(anyhow!("{} can't be managed from the API", section));
+        }
+
+        Ok(fragment)
+    }
+
+    /// The `*.toml` files of the `magic.d` folder next to magic.toml, in
+    /// the order they apply.
+    pub fn drop_ins(path: &Path) -> Result<Vec<PathBuf>> {
+        let dir = path.with_file_name("magic.d");
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", dir.display()));
+            }
+        };
+
+        let mut drop_ins = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        drop_ins.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "toml")
+        });
+        drop_ins.sort();
+
+        Ok(drop_ins)
+    }
+
+    /// Reads a single magic file of any supported version, returns it
+    /// migrated to the current one together with the version it was
+    /// written in.
+    pub fn parse(contents: &str) -> Result<(Self, i32)> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        let (version, _) = migrate::migrate(&mut table)?;
 
-        Ok((magic_file, Some(PathBuf::from(location))))
+        Ok((Self::from_table(table)?, version))
     }
 
-    pub async fn write_to_file(&self, path: &str) -> Result<()> {
-        let string = toml::to_string_pretty(&self)?;
-        let mut file = File::create(path).await?;
-        file.write_all(string.as_bytes()).await?;
-        info!("Wrote magic file to: {}", path);
+    fn from_table(table: toml::Table) -> Result<Self> {
+        let magic_file: MagicFile = table.try_into()?;
+        magic_file.validate()?;
+        Ok(magic_file)
+    }
+
+    /// Everything wrong with the file that parsing doesn't catch.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if !self.meta.server.starts_with("https://") && !self.meta.server.starts_with("http://") {
+            problems.push(format!("meta.server {:?} is not a URL", self.meta.server));
+        }
+        if self
+            .tunnel
+            .as_ref()
+            .is_some_and(|tunnel| tunnel.server.is_empty())
+        {
+            problems.push("tunnel.server is empty".to_string());
+        }
+        if let Some(logs) = &self.logs {
+            if logs.priority > 7 {
+                problems.push(format!("logs.priority {} is above 7", logs.priority));
+            }
+        }
+        if let Some(downloads) = &self.downloads {
+            if [downloads.rate, downloads.metered_rate]
+                .into_iter()
+                .flatten()
+                .any(|rate| !rate.is_finite() || rate <= 0.0)
+            {
+                problems.push("downloads rates must be positive".to_string());
+            }
+        }
+
+        let checks = self.checks.iter().flatten();
+        named(
+            "check",
+            checks.clone().map(|check| check.name.as_str()),
+            &mut problems,
+        );
+        for check in checks.filter(|check| check.cmd.is_empty()) {
+            problems.push(format!("check {:?} has no cmd", check.name));
+        }
+
+        let metrics = self.metrics.iter().flatten();
+        named(
+            "metric",
+            metrics.clone().map(|metric| metric.name.as_str()),
+            &mut problems,
+        );
+        for metric in metrics.filter(|metric| metric.cmd.is_empty()) {
+            problems.push(format!("metric {:?} has no cmd", metric.name));
+        }
+
+        let otas = self.otas.iter().flatten();
+        named(
+            "ota",
+            otas.clone().map(|ota| ota.name.as_str()),
+            &mut problems,
+        );
+        for ota in otas {
+            let dirs = std::iter::once(&ota.payload_dir).chain(&ota.tools_dir);
+            if dirs.into_iter().any(|dir| !dir.starts_with('/')) {
+                problems.push(format!("ota {:?} has a relative directory", ota.name));
+            }
+            if ota.apply.is_empty() {
+                problems.push(format!("ota {:?} has no apply command", ota.name));
+            }
+        }
+
+        if let Some(policy) = &self.policy {
+            for pattern in policy.free_form.iter().flatten() {
+                if let Err(err) = regex::Regex::new(pattern) {
+                    problems.push(format!(
+                        "policy.free_form {:?} is invalid: {}",
+                        pattern, err
+                    ));
+                }
+            }
+            for key in &policy.admin_keys {
+                if crate::policy::signature::decode_key(key).is_err() {
+                    problems.push(format!("policy.admin_keys {:?} is not an Ed25519 key", key));
+                }
+            }
+            if policy.require_signature && policy.admin_keys.is_empty() {
+                problems.push("policy.require_signature is set without admin_keys".to_string());
+            }
+        }
+
+        problems
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            return Err(anyhow!(problems.join(", ")));
+        }
         Ok(())
     }
 
+    /// The file as TOML with secrets and credentials left out.
+    pub fn to_redacted_string(&self) -> Result<String> {
+        let mut value = toml::Value::try_from(self)?;
+        redact(&mut value);
+        Ok(toml::to_string_pretty(&value)?)
+    }
+
     pub fn get_checks(&self) -> Vec<ConfigCheck> {
         self.checks.clone().unwrap_or_default()
     }
@@ -145,52 +568,77 @@ impl MagicFile {
         }
     }
 
-    pub fn get_packages(&self) -> Vec<ConfigPackage> {
-        self.packages.clone().unwrap_or_default()
-    }
-
-    pub fn set_packages(&mut self, packages: Vec<ConfigPackage>) {
-        self.packages = Some(packages);
+    pub fn get_logs(&self) -> Option<ConfigLogs> {
+        self.logs.clone()
     }
 
-    pub fn get_server(&self) -> String {
-        self.meta.server.clone()
+    pub fn get_download_limits(&self) -> Option<DownloadLimits> {
+        self.downloads
     }
 
-    pub fn get_release_id(&self) -> Option<i32> {
-        self.meta.release_id
+    pub fn get_police(&self) -> ConfigPolice {
+        self.police.clone().unwrap_or_default()
     }
 
-    pub fn set_release_id(&mut self, release_id: Option<i32>) {
-        self.meta.release_id = release_id;
+    pub fn get_policy(&self) -> Option<ConfigPolicy> {
+        self.policy.clone()
     }
 
-    pub fn get_target_release_id(&self) -> Option<i32> {
-        self.meta.target_release_id
+    /// Sections that differ from `previous`, for the actors using them to
+    /// pick the change up.
+    pub fn changed_sections(&self, previous: &Self) -> Vec<&'static str> {
+        [
+            ("check", self.checks != previous.checks),
+            ("metric", self.metrics != previous.metrics),
+            ("police", self.police != previous.police),
+            ("logs", self.logs != previous.logs),
+            ("downloads", self.downloads != previous.downloads),
+        ]
+        .into_iter()
+        .filter_map(|(section, changed)| changed.then_some(section))
+        .collect()
     }
 
-    pub fn set_target_release_id(&mut self, target_release_id: Option<i32>) {
-        self.meta.target_release_id = target_release_id;
+    /// The named OTA profile, or the default one.
+    pub fn get_ota_profile(&self, name: Option<&str>) -> Option<ConfigOta> {
+        let name = name.unwrap_or(ConfigOta::DEFAULT);
+        self.otas
+            .iter()
+            .flatten()
+            .find(|profile| profile.name == name)
+            .cloned()
+            .or_else(|| (name == ConfigOta::DEFAULT).then(ConfigOta::jetson))
     }
 
-    pub fn get_token(&self) -> Option<String> {
-        self.meta.token.clone()
-    }
-
-    pub fn set_token(&mut self, token: Option<String>) {
-        self.meta.token = token;
+    pub fn get_server(&self) -> String {
+        self.meta.server.clone()
     }
 }
 
 impl Default for MagicFile {
     fn default() -> Self {
-        let default_magic = r#"
+        let default_magic = format!(
+            r#"
 [meta]
-magic_version = 2
+magic_version = {}
 server 		  = "https://api.smith.teton.ai/smith"
-"#;
+"#,
+            migrate::CURRENT_VERSION
+        );
         // this unwrap is safe because we know the default_magic is valid toml
-        toml::from_str(default_magic).unwrap()
+        toml::from_str(&default_magic).unwrap()
+    }
+}
+
+/// Notes names that are empty or taken by another entry of the same kind.
+fn named<'a>(kind: &str, names: impl Iterator<Item = &'a str>, problems: &mut Vec<String>) {
+    let mut seen = std::collections::HashSet::new();
+    for name in names {
+        if name.is_empty() {
+            problems.push(format!("a {} has no name", kind));
+        } else if !seen.insert(name) {
+            problems.push(format!("{} {:?} is defined twice", kind, name));
+        }
     }
 }
 
@@ -199,7 +647,148 @@ mod tests {
 
     #[test]
     fn it_works() {
-        // test that we can load the default magic file
-        super::MagicFile::autoload().unwrap();
+        // test that we can load the default magic file, and that there is
+        // nothing to run with without one
+        match super::MagicFile::locate() {
+            Some(_) => _ = super::MagicFile::autoload().unwrap(),
+            None => assert!(super::MagicFile::autoload().is_err()),
+        }
+    }
+
+    #[test]
+    fn redacts_secrets() {
+        let magic = super::MagicFile {
+            tunnel: Some(super::ConfigTunnel {
+                server: "bore.pub".to_string(),
+                secret: "hunter3".to_string(),
+            }),
+            ..Default::default()
+        };
+
+        let redacted = magic.to_redacted_string().unwrap();
+        assert!(!redacted.contains("hunter"));
+        assert!(redacted.contains("bore.pub"));
+
+        let state = super::MagicState {
+            token: Some("hunter4".to_string()),
+            release_id: Some(7),
+            ..Default::default()
+        };
+        let redacted = state.to_redacted_string().unwrap();
+        assert!(!redacted.contains("hunter"));
+        assert!(redacted.contains("release_id = 7"));
+    }
+
+    #[test]
+    fn ota_profiles() {
+        let magic: super::MagicFile = toml::from_str(
+            r#"
+[meta]
+magic_version = 2
+server = "https://api.smith.teton.ai/smith"
+
+[[ota]]
+name = "rockchip"
+payload_dir = "/data/ota/"
+payload_file = "update.img"
+checks = ["test -b /dev/mmcblk0"]
+apply = "rkupdate {payload}"
+reboot = "immediate"
+"#,
+        )
+        .unwrap();
+
+        let rockchip = magic.get_ota_profile(Some("rockchip")).unwrap();
+        assert_eq!(rockchip.tools_path(), None);
+        assert_eq!(rockchip.apply_command(), "rkupdate /data/ota/update.img");
+        assert_eq!(rockchip.reboot, super::OtaReboot::Immediate);
+
+        assert_eq!(
+            magic.get_ota_profile(None),
+            Some(super::ConfigOta::jetson())
+        );
+        assert_eq!(
+            super::ConfigOta::jetson().apply_command(),
+            "bash nv_ota_start.sh /ota/ota_payload_package.tar.gz"
+        );
+        assert_eq!(magic.get_ota_profile(Some("unknown")), None);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let result = super::MagicFile::parse(
+            r#"
+[meta]
+magic_version = 2
+server = "api.smith.teton.ai"
+
+[[check]]
+name = "disk"
+cmd = "df"
+
+[[check]]
+name = "disk"
+cmd = "du"
+"#,
+        );
+        let error = format!("{:?}", result.unwrap_err());
+        assert!(error.contains("meta.server"));
+        assert!(error.contains("check \"disk\" is defined twice"));
+
+        assert!(super::MagicFile::parse("[meta]\nserver = 3\n").is_err());
+    }
+
+    #[test]
+    fn merges_drop_ins_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("magic.toml");
+        std::fs::write(
+            &path,
+            "[meta]\nmagic_version = 2\nserver = \"https://old\"\ntoken = \"secret\"\n",
+        )
+        .unwrap();
+        std::fs::create_dir(dir.path().join("magic.d")).unwrap();
+        std::fs::write(
+            dir.path().join("magic.d/20-server.toml"),
+            "[meta]\nserver = \"https://second\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("magic.d/10-server.toml"),
+            "[meta]\nserver = \"https://first\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("magic.d/notes.txt"), "ignored").unwrap();
+
+        let (magic, legacy, _) = super::MagicFile::load_from_path(path.to_str().unwrap()).unwrap();
+        assert_eq!(magic.get_server(), "https://second");
+        assert_eq!(legacy.token.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn managed_drop_in_only_holds_managed_sections() {
+        assert!(super::MagicFile::parse_managed("[tunnel]\nserver = \"evil\"\n").is_err());
+        assert!(super::MagicFile::parse_managed("[meta]\nserver = \"https://evil\"\n").is_err());
+
+        let fragment = super::MagicFile::parse_managed(
+            r#"
+[police]
+enabled = false
+
+[[check]]
+name = "disk"
+cmd = "df"
+"#,
+        )
+        .unwrap();
+
+        let previous = super::MagicFile::default();
+        let mut table = toml::Table::try_from(&previous).unwrap();
+        super::merge::merge(&mut table, fragment).unwrap();
+        let current: super::MagicFile = table.try_into().unwrap();
+
+        assert!(!current.get_police().enabled);
+        assert_eq!(current.get_police().delay, 5 * 60);
+        assert_eq!(current.changed_sections(&previous), ["check", "police"]);
     }
 }
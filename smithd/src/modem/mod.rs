@@ -0,0 +1,150 @@
+use crate::magic::MagicHandle;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::network::NetworkClient;
+use crate::utils::schema::NewModem;
+use anyhow::{Result, anyhow};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use tracing::{debug, error, info, warn};
+
+mod reader;
+
+/// How often ModemManager is read.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Data counters change all the time, so they alone only trigger a report
+/// this often.
+const COUNTERS_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+struct Modem {
+    shutdown: ShutdownSignals,
+    magic: MagicHandle,
+    network: NetworkClient,
+    /// Last report the API accepted, the outer `None` means nothing was sent yet.
+    reported: Option<Option<NewModem>>,
+    reported_at: Instant,
+    /// Whether the last read failed, so the failure is only warned about once.
+    read_failed: bool,
+}
+
+impl Modem {
+    fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+        Self {
+            shutdown,
+            magic,
+            network: NetworkClient::new(),
+            reported: None,
+            reported_at: Instant::now(),
+            read_failed: false,
+        }
+    }
+
+    async fn check(&mut self) {
+        let modem = match reader::read().await {
+            Ok(modem) => {
+                self.read_failed = false;
+                modem
+            }
+            Err(err) => {
+                // ModemManager is not running on devices without a modem
+                if self.read_failed {
+                    debug!("Failed to read modem: {}", err);
+                } else {
+                    warn!("Failed to read modem: {}", err);
+                }
+                self.read_failed = true;
+                None
+            }
+        };
+
+        // the IMEI shows up a little after the modem, until then it can't
+        // be told apart from any other modem
+        if modem.as_ref().is_some_and(|modem| modem.imei.is_empty()) {
+            debug!("Modem has no IMEI yet, not reporting it");
+            return;
+        }
+
+        if !self.should_report(&modem) {
+            return;
+        }
+
+        match self.report(&modem).await {
+            Ok(()) => {
+                self.reported = Some(modem);
+                self.reported_at = Instant::now();
+            }
+            Err(err) => error!("Failed to report modem: {}", err),
+        }
+    }
+
+    fn should_report(&self, modem: &Option<NewModem>) -> bool {
+        let Some(reported) = &self.reported else {
+            return true;
+        };
+
+        match (reported, modem) {
+            (Some(reported), Some(modem)) => {
+                let counters_only = NewModem {
+                    rx_bytes: reported.rx_bytes,
+                    tx_bytes: reported.tx_bytes,
+                    ..modem.clone()
+                } == *reported;
+
+                !counters_only
+                    || (modem != reported && self.reported_at.elapsed() >= COUNTERS_INTERVAL)
+            }
+            (reported, modem) => reported != modem,
+        }
+    }
+
+    async fn report(&mut self, modem: &Option<NewModem>) -> Result<()> {
+        let token = self
+            .magic
+            .get_token()
+            .await
+            .ok_or_else(|| anyhow!("Device is not registered"))?;
+        self.network.set_hostname(self.magic.get_server().await);
+
+        let (status, _) = self
+            .network
+            .send_compressed_post(&token, "/telemetry/modem", modem)
+            .await?;
+
+        if !status.is_success() {
+            return Err(anyhow!("API answered {status}"));
+        }
+
+        Ok(())
+    }
+
+    async fn run(&mut self) {
+        info!("Modem task is runnning");
+
+        let mut interval = time::interval(POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    self.check().await;
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+
+        info!("Modem task shutting down");
+    }
+}
+
+/// Reads the modem from ModemManager and reports it to the API when it changes.
+#[derive(Clone)]
+pub struct ModemHandle;
+
+impl ModemHandle {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+        let mut actor = Modem::new(shutdown, magic);
+        tokio::spawn(async move { actor.run().await });
+
+        Self
+    }
+}
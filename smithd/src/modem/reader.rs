@@ -0,0 +1,145 @@
+use crate::utils::schema::NewModem;
+use anyhow::Result;
+use std::collections::HashMap;
+use zbus::fdo::{ObjectManagerProxy, PropertiesProxy};
+use zbus::names::InterfaceName;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+const SERVICE: &str = "org.freedesktop.ModemManager1";
+const MODEM: &str = "org.freedesktop.ModemManager1.Modem";
+const MODEM_3GPP: &str = "org.freedesktop.ModemManager1.Modem.Modem3gpp";
+const SIM: &str = "org.freedesktop.ModemManager1.Sim";
+const BEARER: &str = "org.freedesktop.ModemManager1.Bearer";
+
+type Properties = HashMap<String, OwnedValue>;
+
+/// Reads the first modem known to ModemManager.
+pub(super) async fn read() -> Result<Option<NewModem>> {
+    let connection = zbus::Connection::system().await?;
+    let manager = ObjectManagerProxy::builder(&connection)
+        .destination(SERVICE)?
+        .path("/org/freedesktop/ModemManager1")?
+        .build()
+        .await?;
+
+    let objects = manager.get_managed_objects().await?;
+    let Some(interfaces) = objects.values().find(|interfaces| {
+        interfaces
+            .keys()
+            .any(|interface| interface.as_str() == MODEM)
+    }) else {
+        return Ok(None);
+    };
+
+    let get = |interface: &str| {
+        interfaces
+            .iter()
+            .find(|(name, _)| name.as_str() == interface)
+            .map(|(_, properties)| properties)
+    };
+    let modem = get(MODEM).cloned().unwrap_or_default();
+    let modem_3gpp = get(MODEM_3GPP).cloned().unwrap_or_default();
+
+    let imei = string(&modem_3gpp, "Imei")
+        .or_else(|| string(&modem, "EquipmentIdentifier"))
+        .unwrap_or_default();
+    let network_provider = string(&modem_3gpp, "OperatorName").unwrap_or_default();
+
+    let signal_quality = modem
+        .get("SignalQuality")
+        .and_then(|value| <(u32, bool)>::try_from(value.try_clone().ok()?).ok())
+        .map(|(quality, _recent)| quality as i32);
+
+    let access_technology = modem
+        .get("AccessTechnologies")
+        .and_then(|value| u32::try_from(value).ok())
+        .and_then(access_technology)
+        .map(str::to_string);
+
+    let iccid = match path(&modem, "Sim") {
+        Some(sim) => string(&properties(&connection, &sim, SIM).await?, "SimIdentifier"),
+        None => None,
+    };
+
+    let (mut rx_bytes, mut tx_bytes) = (None, None);
+    if let Some(bearers) = modem
+        .get("Bearers")
+        .and_then(|value| Vec::<OwnedObjectPath>::try_from(value.try_clone().ok()?).ok())
+    {
+        for bearer in bearers {
+            let bearer = properties(&connection, &bearer, BEARER).await?;
+            let Some(stats) = bearer.get("Stats").and_then(|value| {
+                HashMap::<String, OwnedValue>::try_from(value.try_clone().ok()?).ok()
+            }) else {
+                continue;
+            };
+            let counter = |key: &str| stats.get(key).and_then(|value| u64::try_from(value).ok());
+            if let Some(rx) = counter("rx-bytes") {
+                *rx_bytes.get_or_insert(0) += rx as i64;
+            }
+            if let Some(tx) = counter("tx-bytes") {
+                *tx_bytes.get_or_insert(0) += tx as i64;
+            }
+        }
+    }
+
+    Ok(Some(NewModem {
+        imei,
+        network_provider,
+        iccid,
+        access_technology,
+        signal_quality,
+        rx_bytes,
+        tx_bytes,
+    }))
+}
+
+async fn properties(
+    connection: &zbus::Connection,
+    path: &OwnedObjectPath,
+    interface: &'static str,
+) -> Result<Properties> {
+    let proxy = PropertiesProxy::builder(connection)
+        .destination(SERVICE)?
+        .path(path.as_ref())?
+        .build()
+        .await?;
+
+    Ok(proxy
+        .get_all(InterfaceName::from_static_str(interface)?)
+        .await?)
+}
+
+fn string(properties: &Properties, key: &str) -> Option<String> {
+    properties
+        .get(key)
+        .and_then(|value| String::try_from(value.try_clone().ok()?).ok())
+        .filter(|value| !value.is_empty())
+}
+
+fn path(properties: &Properties, key: &str) -> Option<OwnedObjectPath> {
+    properties
+        .get(key)
+        .and_then(|value| OwnedObjectPath::try_from(value.try_clone().ok()?).ok())
+        .filter(|path| path.as_str() != "/")
+}
+
+/// Most capable technology in a `MMModemAccessTechnology` bitmask.
+fn access_technology(mask: u32) -> Option<&'static str> {
+    const TECHNOLOGIES: [(u32, &str); 8] = [
+        (1 << 15, "5gnr"),
+        // plain, cat-m and nb-iot
+        ((1 << 14) | (1 << 16) | (1 << 17), "lte"),
+        (1 << 9, "hspa+"),
+        ((1 << 6) | (1 << 7) | (1 << 8), "hspa"),
+        (1 << 5, "umts"),
+        (1 << 4, "edge"),
+        (1 << 3, "gprs"),
+        ((1 << 1) | (1 << 2), "gsm"),
+    ];
+
+    TECHNOLOGIES
+        .iter()
+        .find(|(bits, _)| mask & bits != 0)
+        .map(|(_, name)| *name)
+}
@@ -0,0 +1,343 @@
+use crate::magic::MagicHandle;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::Network;
+use anyhow::{Result, anyhow};
+use proxy::{
+    ActiveConnectionProxy, NetworkManagerProxy, NetworkSettingsProxy, Settings,
+    SettingsConnectionProxy,
+};
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant};
+use tracing::{error, info, warn};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+mod profile;
+mod proxy;
+
+/// How long a new profile gets to reach the API before it is rolled back.
+const CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(90);
+const CONNECTIVITY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum Outcome {
+    /// The profile was already active with the same settings.
+    Unchanged,
+    Applied,
+    /// The API wasn't reachable through the new profile, the previous one is back.
+    RolledBack(String),
+}
+
+struct NetworkConfigurator {
+    shutdown: ShutdownSignals,
+    receiver: mpsc::Receiver<NetworkMessage>,
+    magic: MagicHandle,
+    client: reqwest::Client,
+}
+
+enum NetworkMessage {
+    Apply {
+        network: Box<Network>,
+        rpc: oneshot::Sender<Result<Outcome>>,
+    },
+}
+
+/// A profile found in NetworkManager, with its secrets.
+struct Existing {
+    path: OwnedObjectPath,
+    settings: Settings,
+}
+
+impl NetworkConfigurator {
+    fn new(
+        shutdown: ShutdownSignals,
+        receiver: mpsc::Receiver<NetworkMessage>,
+        magic: MagicHandle,
+    ) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(CONNECTIVITY_INTERVAL)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            shutdown,
+            receiver,
+            magic,
+            client,
+        }
+    }
+
+    async fn handle_message(&mut self, msg: NetworkMessage) {
+        match msg {
+            NetworkMessage::Apply { network, rpc } => {
+                _ = rpc.send(self.apply(&network).await);
+            }
+        }
+    }
+
+    async fn apply(&self, network: &Network) -> Result<Outcome> {
+        let desired = profile::settings(network)?;
+
+        let connection = zbus::Connection::system().await?;
+        let manager = NetworkManagerProxy::new(&connection).await?;
+        let settings = NetworkSettingsProxy::new(&connection).await?;
+
+        let existing = find(&connection, &settings, &network.name).await?;
+        if let Some(existing) = &existing {
+            if profile::matches(&existing.settings, &desired)
+                && is_active(&connection, &manager, &existing.path).await
+            {
+                info!("Network {} is already active", network.name);
+                return Ok(Outcome::Unchanged);
+            }
+        }
+
+        let previous = primary(&connection, &manager).await;
+
+        let path = match &existing {
+            Some(existing) => {
+                let mut updated = desired;
+                // keep the profile identity so autoconnect history carries over
+                if let Some(uuid) = existing
+                    .settings
+                    .get("connection")
+                    .and_then(|connection| connection.get("uuid"))
+                {
+                    set_uuid(&mut updated, uuid.try_clone()?);
+                }
+                connection_proxy(&connection, &existing.path)
+                    .await?
+                    .update(updated)
+                    .await?;
+                existing.path.clone()
+            }
+            None => {
+                let mut added = desired;
+                set_uuid(&mut added, OwnedValue::try_from(Value::from(uuid()))?);
+                settings.add_connection(added).await?
+            }
+        };
+
+        info!("Activating network {}", network.name);
+        let reason = match manager.activate_connection(&path, &root(), &root()).await {
+            Ok(_) if self.reachable().await => return Ok(Outcome::Applied),
+            Ok(_) => format!("API not reachable within {CONNECTIVITY_TIMEOUT:?}"),
+            Err(err) => format!("Failed to activate: {err}"),
+        };
+
+        warn!("Rolling back network {}: {reason}", network.name);
+        self.rollback(&connection, &manager, &path, existing, previous)
+            .await?;
+
+        Ok(Outcome::RolledBack(reason))
+    }
+
+    async fn rollback(
+        &self,
+        connection: &zbus::Connection,
+        manager: &NetworkManagerProxy<'_>,
+        path: &OwnedObjectPath,
+        existing: Option<Existing>,
+        previous: Option<OwnedObjectPath>,
+    ) -> Result<()> {
+        let proxy = connection_proxy(connection, path).await?;
+        match existing {
+            Some(existing) => proxy.update(existing.settings).await?,
+            None => proxy.delete().await?,
+        }
+
+        if let Some(previous) = previous {
+            manager
+                .activate_connection(&previous, &root(), &root())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Polls the API until it answers or the timeout runs out. Any HTTP
+    /// response will do, it only has to get through.
+    async fn reachable(&self) -> bool {
+        let server = self.magic.get_server().await;
+        let deadline = Instant::now() + CONNECTIVITY_TIMEOUT;
+
+        while Instant::now() < deadline {
+            if self.client.get(&server).send().await.is_ok() {
+                return true;
+            }
+            time::sleep(CONNECTIVITY_INTERVAL).await;
+        }
+
+        false
+    }
+
+    async fn run(&mut self) {
+        info!("Network task is runnning");
+
+        loop {
+            tokio::select! {
+                Some(msg) = self.receiver.recv() => {
+                    self.handle_message(msg).await;
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+
+        info!("Network task shutting down");
+    }
+}
+
+fn root() -> ObjectPath<'static> {
+    ObjectPath::from_static_str_unchecked("/")
+}
+
+fn set_uuid(settings: &mut Settings, uuid: OwnedValue) {
+    settings
+        .entry("connection".to_string())
+        .or_default()
+        .insert("uuid".to_string(), uuid);
+}
+
+/// Random version 4 uuid for new profiles.
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes.iter().fold(String::new(), |mut hex, byte| {
+        _ = write!(hex, "{byte:02x}");
+        hex
+    });
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+async fn connection_proxy<'a>(
+    connection: &zbus::Connection,
+    path: &'a OwnedObjectPath,
+) -> Result<SettingsConnectionProxy<'a>> {
+    Ok(SettingsConnectionProxy::builder(connection)
+        .path(path.as_ref())?
+        .build()
+        .await?)
+}
+
+async fn find(
+    connection: &zbus::Connection,
+    settings: &NetworkSettingsProxy<'_>,
+    name: &str,
+) -> Result<Option<Existing>> {
+    for path in settings.list_connections().await? {
+        let proxy = connection_proxy(connection, &path).await?;
+        let mut current = proxy.get_settings().await?;
+
+        let id = current
+            .get("connection")
+            .and_then(|connection| connection.get("id"))
+            .and_then(|id| id.downcast_ref::<&str>().ok());
+        if id != Some(name) {
+            continue;
+        }
+
+        // groups without secrets answer with an error
+        for group in profile::SECRET_GROUPS {
+            if !current.contains_key(group) {
+                continue;
+            }
+            if let Ok(secrets) = proxy.get_secrets(group).await {
+                for (group, values) in secrets {
+                    current.entry(group).or_default().extend(values);
+                }
+            }
+        }
+
+        return Ok(Some(Existing {
+            path,
+            settings: current,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Settings path of the connection currently carrying the default route.
+async fn primary(
+    connection: &zbus::Connection,
+    manager: &NetworkManagerProxy<'_>,
+) -> Option<OwnedObjectPath> {
+    let active = manager.primary_connection().await.ok()?;
+    if active.as_str() == "/" {
+        return None;
+    }
+
+    ActiveConnectionProxy::builder(connection)
+        .path(active.as_ref())
+        .ok()?
+        .build()
+        .await
+        .ok()?
+        .connection()
+        .await
+        .ok()
+}
+
+async fn is_active(
+    connection: &zbus::Connection,
+    manager: &NetworkManagerProxy<'_>,
+    path: &OwnedObjectPath,
+) -> bool {
+    let Ok(active) = manager.active_connections().await else {
+        return false;
+    };
+
+    for active in active {
+        let Ok(builder) = ActiveConnectionProxy::builder(connection).path(active.as_ref()) else {
+            continue;
+        };
+        if let Ok(proxy) = builder.build().await {
+            if proxy.connection().await.ok().as_ref() == Some(path) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+#[derive(Clone)]
+pub struct NetworkHandle {
+    sender: mpsc::Sender<NetworkMessage>,
+}
+
+impl NetworkHandle {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+        let (sender, receiver) = mpsc::channel(8);
+        let mut actor = NetworkConfigurator::new(shutdown, receiver, magic);
+        tokio::spawn(async move { actor.run().await });
+
+        Self { sender }
+    }
+
+    /// Configures the network through NetworkManager, falling back to the
+    /// previous profile if the API can't be reached afterwards.
+    pub async fn apply(&self, network: Network) -> Result<Outcome> {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = NetworkMessage::Apply {
+            network: Box::new(network),
+            rpc,
+        };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_else(|err| {
+            error!("Network task did not answer: {}", err);
+            Err(anyhow!("Network task is not running"))
+        })
+    }
+}
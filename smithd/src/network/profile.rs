@@ -0,0 +1,229 @@
+use super::proxy::Settings;
+use crate::utils::schema::{Network, NetworkType};
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Groups holding secrets, NetworkManager leaves them out of `GetSettings`.
+pub(super) const SECRET_GROUPS: [&str; 3] = ["802-11-wireless-security", "802-1x", "gsm"];
+
+/// Builds the NetworkManager connection profile for a network. The uuid is
+/// left out, it is only needed when the profile is added.
+pub(super) fn settings(network: &Network) -> Result<Settings> {
+    let mut settings = Settings::new();
+
+    let connection_type = match network.network_type {
+        NetworkType::Wifi => "802-11-wireless",
+        NetworkType::Ethernet => "802-3-ethernet",
+        NetworkType::Dongle => "gsm",
+    };
+    set(&mut settings, "connection", "id", network.name.as_str())?;
+    set(&mut settings, "connection", "type", connection_type)?;
+    set(&mut settings, "connection", "autoconnect", true)?;
+    set(
+        &mut settings,
+        "connection",
+        "autoconnect-priority",
+        network.priority,
+    )?;
+
+    match network.network_type {
+        NetworkType::Wifi => wifi(&mut settings, network)?,
+        NetworkType::Ethernet => {
+            settings.entry("802-3-ethernet".to_string()).or_default();
+        }
+        NetworkType::Dongle => {
+            settings.entry("gsm".to_string()).or_default();
+            if let Some(apn) = &network.apn {
+                set(&mut settings, "gsm", "apn", apn.as_str())?;
+            }
+            if let Some(password) = &network.password {
+                set(&mut settings, "gsm", "password", password.as_str())?;
+            }
+        }
+    }
+
+    ipv4(&mut settings, network)?;
+    set(&mut settings, "ipv6", "method", "auto")?;
+
+    Ok(settings)
+}
+
+fn wifi(settings: &mut Settings, network: &Network) -> Result<()> {
+    let ssid = network.ssid.as_ref().unwrap_or(&network.name);
+    set(
+        settings,
+        "802-11-wireless",
+        "ssid",
+        ssid.as_bytes().to_vec(),
+    )?;
+    set(settings, "802-11-wireless", "mode", "infrastructure")?;
+    set(
+        settings,
+        "802-11-wireless",
+        "hidden",
+        network.is_network_hidden,
+    )?;
+
+    let security = "802-11-wireless-security";
+    match (&network.eap_method, &network.password) {
+        (Some(method), password) => {
+            set(settings, security, "key-mgmt", "wpa-eap")?;
+            set(settings, "802-1x", "eap", vec![method.to_lowercase()])?;
+            if let Some(identity) = &network.eap_identity {
+                set(settings, "802-1x", "identity", identity.as_str())?;
+            }
+            if let Some(password) = password {
+                set(settings, "802-1x", "password", password.as_str())?;
+            }
+            set(settings, "802-1x", "phase2-auth", "mschapv2")?;
+        }
+        (None, Some(password)) => {
+            set(settings, security, "key-mgmt", "wpa-psk")?;
+            set(settings, security, "psk", password.as_str())?;
+        }
+        // open network
+        (None, None) => {}
+    }
+
+    Ok(())
+}
+
+fn ipv4(settings: &mut Settings, network: &Network) -> Result<()> {
+    let Some(address) = &network.ipv4_address else {
+        return set(settings, "ipv4", "method", "auto");
+    };
+
+    let (address, prefix) = address
+        .split_once('/')
+        .context("Static address must be in CIDR notation")?;
+    let address: Ipv4Addr = address.parse()?;
+    let prefix: u32 = prefix.parse()?;
+    if prefix > 32 {
+        return Err(anyhow!("Invalid prefix length {prefix}"));
+    }
+
+    let mut address_data: HashMap<String, Value> = HashMap::new();
+    address_data.insert("address".to_string(), address.to_string().into());
+    address_data.insert("prefix".to_string(), prefix.into());
+
+    set(settings, "ipv4", "method", "manual")?;
+    set(settings, "ipv4", "address-data", vec![address_data])?;
+    if let Some(gateway) = &network.ipv4_gateway {
+        let gateway: Ipv4Addr = gateway.parse()?;
+        set(settings, "ipv4", "gateway", gateway.to_string())?;
+    }
+
+    // NetworkManager wants the addresses in network byte order
+    let dns = network
+        .dns
+        .iter()
+        .map(|dns| Ok(u32::from_ne_bytes(dns.parse::<Ipv4Addr>()?.octets())))
+        .collect::<Result<Vec<u32>>>()?;
+    if !dns.is_empty() {
+        set(settings, "ipv4", "dns", dns)?;
+    }
+
+    Ok(())
+}
+
+fn set<'a>(
+    settings: &mut Settings,
+    group: &str,
+    key: &str,
+    value: impl Into<Value<'a>>,
+) -> Result<()> {
+    let value = OwnedValue::try_from(value.into())?;
+    settings
+        .entry(group.to_string())
+        .or_default()
+        .insert(key.to_string(), value);
+    Ok(())
+}
+
+/// Whether the current profile already has every value we would set.
+pub(super) fn matches(current: &Settings, desired: &Settings) -> bool {
+    desired.iter().all(|(group, values)| {
+        current.get(group).is_some_and(|current| {
+            values
+                .iter()
+                .all(|(key, value)| current.get(key) == Some(value))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(network_type: NetworkType) -> Network {
+        Network {
+            id: 1,
+            network_type,
+            is_network_hidden: false,
+            ssid: None,
+            name: "office".to_string(),
+            description: None,
+            password: None,
+            secret: false,
+            priority: 500,
+            eap_method: None,
+            eap_identity: None,
+            ipv4_address: None,
+            ipv4_gateway: None,
+            dns: vec![],
+            apn: None,
+        }
+    }
+
+    #[test]
+    fn static_ethernet() {
+        let mut ethernet = network(NetworkType::Ethernet);
+        ethernet.ipv4_address = Some("10.0.0.5/24".to_string());
+        ethernet.ipv4_gateway = Some("10.0.0.1".to_string());
+        ethernet.dns = vec!["1.1.1.1".to_string()];
+
+        let settings = settings(&ethernet).unwrap();
+
+        assert!(settings.contains_key("802-3-ethernet"));
+        let ipv4 = &settings["ipv4"];
+        assert_eq!(
+            ipv4["method"],
+            OwnedValue::try_from(Value::from("manual")).unwrap()
+        );
+        assert_eq!(
+            ipv4["dns"],
+            OwnedValue::try_from(Value::from(vec![u32::from_ne_bytes([1, 1, 1, 1])])).unwrap()
+        );
+        assert!(matches(&settings, &settings));
+    }
+
+    #[test]
+    fn enterprise_wifi() {
+        let mut wifi = network(NetworkType::Wifi);
+        wifi.eap_method = Some("PEAP".to_string());
+        wifi.eap_identity = Some("device".to_string());
+        wifi.password = Some("hunter2".to_string());
+
+        let settings = settings(&wifi).unwrap();
+
+        assert_eq!(
+            settings["802-11-wireless-security"]["key-mgmt"],
+            OwnedValue::try_from(Value::from("wpa-eap")).unwrap()
+        );
+        assert!(!settings["802-11-wireless-security"].contains_key("psk"));
+        assert_eq!(
+            settings["802-1x"]["eap"],
+            OwnedValue::try_from(Value::from(vec!["peap".to_string()])).unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_address() {
+        let mut ethernet = network(NetworkType::Ethernet);
+        ethernet.ipv4_address = Some("10.0.0.5".to_string());
+
+        assert!(settings(&ethernet).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+use zbus::{Result, proxy};
+
+/// Connection settings as NetworkManager hands them out, `a{sa{sv}}`.
+pub(super) type Settings = HashMap<String, HashMap<String, OwnedValue>>;
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager"
+)]
+pub(super) trait NetworkManager {
+    fn activate_connection(
+        &self,
+        connection: &ObjectPath<'_>,
+        device: &ObjectPath<'_>,
+        specific_object: &ObjectPath<'_>,
+    ) -> Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn primary_connection(&self) -> Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn active_connections(&self) -> Result<Vec<OwnedObjectPath>>;
+
+    /// `NMMetered` of the primary connection.
+    #[zbus(property)]
+    fn metered(&self) -> Result<u32>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Settings",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager/Settings"
+)]
+pub(super) trait NetworkSettings {
+    fn list_connections(&self) -> Result<Vec<OwnedObjectPath>>;
+
+    fn add_connection(&self, connection: Settings) -> Result<OwnedObjectPath>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Settings.Connection",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+pub(super) trait SettingsConnection {
+    fn get_settings(&self) -> Result<Settings>;
+
+    fn get_secrets(&self, setting_name: &str) -> Result<Settings>;
+
+    fn update(&self, properties: Settings) -> Result<()>;
+
+    fn delete(&self) -> Result<()>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Connection.Active",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+pub(super) trait ActiveConnection {
+    #[zbus(property)]
+    fn connection(&self) -> Result<OwnedObjectPath>;
+}
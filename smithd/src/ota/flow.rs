@@ -0,0 +1,222 @@
+use crate::downloader::DownloaderHandle;
+use crate::filemanager::FileManagerHandle;
+use crate::magic::structure::{ConfigOta, OtaReboot};
+use crate::ota::state::ExpectedArtifact;
+use crate::utils::host::{Host, HostCommand};
+use crate::utils::schema::OtaChecksums;
+use anyhow::{Context, Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tracing::info;
+
+/// Long enough for the command result to reach the API.
+const REBOOT_DELAY: Duration = Duration::from_secs(10);
+
+/// Starts downloading the artifacts of a profile, the downloader keeps track
+/// of how it goes.
+pub(super) async fn download(
+    profile: &ConfigOta,
+    downloader: &DownloaderHandle,
+    filemanager: &FileManagerHandle,
+    tools: Option<&str>,
+    payload: &str,
+    rate: f64,
+) -> Result<()> {
+    for dir in [Some(&profile.payload_dir), profile.tools_dir.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        filemanager
+            .execute_system_command("mkdir", vec!["-p".to_owned(), dir.clone()], None)
+            .await?;
+    }
+
+    match (tools, profile.tools_path()) {
+        (Some(tools), Some(local_file)) => {
+            downloader
+                .download(&format!("ota/{}", tools), &local_file, rate)
+                .await?;
+        }
+        (None, None) => {}
+        (Some(_), None) => {
+            return Err(anyhow!("OTA profile {} takes no tools", profile.name));
+        }
+        (None, Some(_)) => {
+            return Err(anyhow!("OTA profile {} needs tools", profile.name));
+        }
+    }
+
+    downloader
+        .download(&format!("ota/{}", payload), &profile.payload_path(), rate)
+        .await?;
+
+    Ok(())
+}
+
+/// Runs the pre-flight checks and the apply command of a downloaded update.
+pub(super) async fn apply(profile: &ConfigOta, filemanager: &FileManagerHandle) -> Result<String> {
+    for check in &profile.checks {
+        filemanager
+            .execute_system_command("sh", vec!["-c".to_owned(), check.clone()], None)
+            .await
+            .map_err(|err| anyhow!("Pre-flight check `{}` failed: {}", check, err))?;
+    }
+
+    if let Some(tools) = profile.tools_path() {
+        filemanager
+            .extract_here(&tools)
+            .await
+            .map_err(|err| anyhow!("Failed to extract OTA tools - {}", err))?;
+    }
+
+    info!("Applying OTA with profile {}", profile.name);
+    let output = filemanager
+        .execute_system_command(
+            "sh",
+            vec!["-c".to_owned(), profile.apply_command()],
+            profile.apply_dir.as_deref(),
+        )
+        .await
+        .map_err(|err| anyhow!("Script execution failed - {}", err))?;
+
+    Ok(output)
+}
+
+pub(super) async fn reboot(policy: OtaReboot, filemanager: &FileManagerHandle) {
+    match policy {
+        OtaReboot::Never => {}
+        OtaReboot::Immediate => {
+            let _ = filemanager
+                .execute_system_command("reboot", vec![], None)
+                .await;
+        }
+        OtaReboot::Delayed => {
+            let filemanager = filemanager.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(REBOOT_DELAY).await;
+                let _ = filemanager
+                    .execute_system_command("reboot", vec![], None)
+                    .await;
+            });
+        }
+    }
+}
+
+/// Pairs the artifacts of a profile with their checksums, every artifact
+/// needs one.
+pub(super) fn expected(
+    profile: &ConfigOta,
+    checksums: Option<OtaChecksums>,
+) -> Result<Vec<ExpectedArtifact>> {
+    let checksums =
+        checksums.ok_or_else(|| anyhow!("The OTA has no checksums to verify it against"))?;
+
+    let mut artifacts = vec![ExpectedArtifact {
+        path: profile.payload_path(),
+        checksum: checksums.payload,
+    }];
+    match (profile.tools_path(), checksums.tools) {
+        (Some(path), Some(checksum)) => artifacts.push(ExpectedArtifact { path, checksum }),
+        (Some(_), None) => return Err(anyhow!("The OTA tools have no checksum")),
+        (None, _) => {}
+    }
+
+    Ok(artifacts)
+}
+
+/// Whether the artifacts on disk match the release, by size first and
+/// SHA-256 then.
+pub(super) async fn verify(artifacts: &[ExpectedArtifact]) -> Result<()> {
+    if artifacts.is_empty() {
+        return Err(anyhow!("No checksums to verify the OTA against"));
+    }
+
+    for artifact in artifacts {
+        let mut file = tokio::fs::File::open(&artifact.path)
+            .await
+            .with_context(|| format!("{} is missing", artifact.path))?;
+
+        let size = file.metadata().await?.len();
+        if size != artifact.checksum.size {
+            return Err(anyhow!(
+                "{} is {} bytes instead of {}",
+                artifact.path,
+                size,
+                artifact.checksum.size
+            ));
+        }
+
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 1 << 20];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        let sha256 = format!("{:x}", hasher.finalize());
+        if !sha256.eq_ignore_ascii_case(&artifact.checksum.sha256) {
+            return Err(anyhow!("{} doesn't match its checksum", artifact.path));
+        }
+    }
+
+    Ok(())
+}
+
+/// The running OS version as the profile reads it.
+pub(super) async fn os_version(host: &dyn Host, profile: &ConfigOta) -> Result<String> {
+    let Some(cmd) = &profile.version else {
+        let os_release = tokio::fs::read_to_string("/etc/os-release").await?;
+        return os_release
+            .lines()
+            .find_map(|line| line.strip_prefix("VERSION_ID="))
+            .map(|version| version.trim_matches('"').to_string())
+            .ok_or_else(|| anyhow!("No VERSION_ID in /etc/os-release"));
+    };
+
+    let output = host.output(HostCommand::shell(cmd)).await?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || version.is_empty() {
+        return Err(anyhow!(
+            "`{}` didn't print a version: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::ArtifactChecksum;
+
+    #[tokio::test]
+    async fn verifies_against_the_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("payload.tar.gz");
+        std::fs::write(&path, b"test").unwrap();
+
+        let artifact = |size, sha256: &str| ExpectedArtifact {
+            path: path.to_string_lossy().into_owned(),
+            checksum: ArtifactChecksum {
+                size,
+                sha256: sha256.to_string(),
+            },
+        };
+        let sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+        assert!(verify(&[artifact(4, sha256)]).await.is_ok());
+        assert!(verify(&[artifact(5, sha256)]).await.is_err());
+        assert!(
+            verify(&[artifact(4, &sha256.replace('9', "0"))])
+                .await
+                .is_err()
+        );
+        assert!(verify(&[]).await.is_err());
+    }
+}
@@ -0,0 +1,433 @@
+use crate::downloader::{DownloaderHandle, DownloadingStatus};
+use crate::filemanager::FileManagerHandle;
+use crate::magic::MagicHandle;
+use crate::magic::structure::ConfigOta;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::host::Host;
+use crate::utils::schema::{OtaAttempt, OtaChecksums, OtaPhase};
+use anyhow::{Result, anyhow};
+use state::OtaState;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use tracing::{error, info, warn};
+
+mod flow;
+mod state;
+
+/// How often a running download is checked on.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+enum OtaMessage {
+    Download {
+        profile: Option<String>,
+        tools: Option<String>,
+        payload: String,
+        rate: f64,
+        expected_version: Option<String>,
+        checksums: Option<OtaChecksums>,
+        rpc: oneshot::Sender<Result<()>>,
+    },
+    Start {
+        profile: Option<String>,
+        rpc: oneshot::Sender<Result<String>>,
+    },
+    Unreported {
+        rpc: oneshot::Sender<Option<(u64, OtaAttempt)>>,
+    },
+    DownloadStatus {
+        rpc: oneshot::Sender<Result<DownloadingStatus>>,
+    },
+    Reported {
+        sequence: u64,
+    },
+}
+
+struct Ota {
+    shutdown: ShutdownSignals,
+    receiver: mpsc::Receiver<OtaMessage>,
+    magic: MagicHandle,
+    downloader: DownloaderHandle,
+    filemanager: FileManagerHandle,
+    host: Arc<dyn Host>,
+    state: OtaState,
+    /// The apply command runs in the background, its result comes back here.
+    applied_tx: mpsc::Sender<Result<String>>,
+    applied_rx: mpsc::Receiver<Result<String>>,
+    /// Profile being applied and who asked for it.
+    applying: Option<(ConfigOta, oneshot::Sender<Result<String>>)>,
+}
+
+impl Ota {
+    fn new(
+        shutdown: ShutdownSignals,
+        receiver: mpsc::Receiver<OtaMessage>,
+        magic: MagicHandle,
+        downloader: DownloaderHandle,
+        filemanager: FileManagerHandle,
+        host: Arc<dyn Host>,
+    ) -> Self {
+        let (applied_tx, applied_rx) = mpsc::channel(1);
+        Self {
+            shutdown,
+            receiver,
+            magic,
+            downloader,
+            filemanager,
+            host,
+            state: OtaState::load(),
+            applied_tx,
+            applied_rx,
+            applying: None,
+        }
+    }
+
+    async fn handle_message(&mut self, msg: OtaMessage) {
+        match msg {
+            OtaMessage::Download {
+                profile,
+                tools,
+                payload,
+                rate,
+                expected_version,
+                checksums,
+                rpc,
+            } => {
+                let result = self
+                    .download(profile, tools, payload, rate, expected_version, checksums)
+                    .await;
+                _ = rpc.send(result);
+            }
+            OtaMessage::Start { profile, rpc } => match self.prepare(profile).await {
+                Ok(profile) => {
+                    let filemanager = self.filemanager.clone();
+                    let applied = self.applied_tx.clone();
+                    let background = profile.clone();
+                    tokio::spawn(async move {
+                        _ = applied
+                            .send(flow::apply(&background, &filemanager).await)
+                            .await;
+                    });
+                    self.applying = Some((profile, rpc));
+                }
+                Err(err) => {
+                    _ = rpc.send(Err(err));
+                }
+            },
+            OtaMessage::Unreported { rpc } => {
+                _ = rpc.send(self.state.unreported());
+            }
+            OtaMessage::DownloadStatus { rpc } => {
+                _ = rpc.send(self.download_status().await);
+            }
+            OtaMessage::Reported { sequence } => {
+                if sequence > self.state.reported {
+                    self.state.reported = sequence;
+                    self.save().await;
+                }
+            }
+        }
+    }
+
+    async fn profile(&self, name: Option<&str>) -> Result<ConfigOta> {
+        self.magic
+            .get_ota_profile(name)
+            .await
+            .ok_or_else(|| anyhow!("Unknown OTA profile {}", name.unwrap_or(ConfigOta::DEFAULT)))
+    }
+
+    async fn download(
+        &mut self,
+        profile: Option<String>,
+        tools: Option<String>,
+        payload: String,
+        rate: f64,
+        expected_version: Option<String>,
+        checksums: Option<OtaChecksums>,
+    ) -> Result<()> {
+        let phase = self.state.phase();
+        if matches!(
+            phase,
+            OtaPhase::Downloading | OtaPhase::Applying | OtaPhase::Rebooting
+        ) {
+            return Err(anyhow!("Another OTA is still {:?}", phase));
+        }
+
+        let profile = self.profile(profile.as_deref()).await?;
+        let artifacts = flow::expected(&profile, checksums)?;
+        flow::download(
+            &profile,
+            &self.downloader,
+            &self.filemanager,
+            tools.as_deref(),
+            &payload,
+            rate,
+        )
+        .await?;
+
+        let previous_version = flow::os_version(self.host.as_ref(), &profile).await.ok();
+        self.state.begin(
+            &profile.name,
+            OtaPhase::Downloading,
+            expected_version,
+            previous_version,
+        );
+        self.state.artifacts = artifacts;
+        self.save().await;
+
+        Ok(())
+    }
+
+    /// How the downloads of the current attempt are going.
+    async fn download_status(&self) -> Result<DownloadingStatus> {
+        let files = self
+            .state
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.path.clone())
+            .collect();
+        self.downloader.check_download_status(files).await
+    }
+
+    /// Moves a downloaded attempt to `Applying`. Artifacts that got on the
+    /// device some other way start a new attempt.
+    async fn prepare(&mut self, name: Option<String>) -> Result<ConfigOta> {
+        let profile = match (self.state.phase(), &self.state.attempt) {
+            (OtaPhase::Verified, Some(attempt)) => {
+                if name.as_ref().is_some_and(|name| *name != attempt.profile) {
+                    return Err(anyhow!(
+                        "The downloaded OTA is for profile {}",
+                        attempt.profile
+                    ));
+                }
+                self.profile(Some(&attempt.profile)).await?
+            }
+            (OtaPhase::Downloading, _) => return Err(anyhow!("The OTA is still downloading")),
+            (OtaPhase::Applying | OtaPhase::Rebooting, _) => {
+                return Err(anyhow!("The OTA is already applied"));
+            }
+            // e.g. a download smithd restarted in the middle of, still
+            // checked against the release it was started for
+            _ => {
+                let profile = self.profile(name.as_deref()).await?;
+                let artifacts = self.state.artifacts.clone();
+                if !artifacts.iter().any(|a| a.path == profile.payload_path()) {
+                    return Err(anyhow!(
+                        "No OTA was downloaded for profile {}",
+                        profile.name
+                    ));
+                }
+                flow::verify(&artifacts).await?;
+                let previous_version = flow::os_version(self.host.as_ref(), &profile).await.ok();
+                self.state
+                    .begin(&profile.name, OtaPhase::Verified, None, previous_version);
+                self.state.artifacts = artifacts;
+                profile
+            }
+        };
+
+        self.state.boot_id = Some(boot_id().await?);
+        self.state.set_phase(OtaPhase::Applying, None);
+        self.save().await;
+
+        Ok(profile)
+    }
+
+    async fn applied(&mut self, result: Result<String>) {
+        let Some((profile, rpc)) = self.applying.take() else {
+            return;
+        };
+
+        match &result {
+            Ok(_) => {
+                info!("OTA applied, waiting for the reboot");
+                self.state.set_phase(OtaPhase::Rebooting, None);
+                self.save().await;
+                flow::reboot(profile.reboot, &self.filemanager).await;
+            }
+            Err(err) => {
+                error!("Failed to apply OTA: {:?}", err);
+                self.state
+                    .set_phase(OtaPhase::Failed, Some(err.to_string()));
+                self.save().await;
+            }
+        }
+
+        _ = rpc.send(result);
+    }
+
+    async fn check_download(&mut self) {
+        if self.state.phase() != OtaPhase::Downloading {
+            return;
+        }
+
+        match self.download_status().await {
+            Ok(DownloadingStatus::Downloading) => return,
+            Ok(DownloadingStatus::Success) => match flow::verify(&self.state.artifacts).await {
+                Ok(()) => self.state.set_phase(OtaPhase::Verified, None),
+                Err(err) => self
+                    .state
+                    .set_phase(OtaPhase::Failed, Some(err.to_string())),
+            },
+            Ok(DownloadingStatus::Failed) => self
+                .state
+                .set_phase(OtaPhase::Failed, Some("Download failed".to_string())),
+            Err(err) => {
+                warn!("Failed to check the OTA download: {}", err);
+                return;
+            }
+        }
+
+        self.save().await;
+    }
+
+    /// Picks up where the attempt was before smithd stopped.
+    async fn resume(&mut self) {
+        let sequence = self.state.sequence;
+        self.state.interrupted();
+
+        let boot_id = match boot_id().await {
+            Ok(boot_id) => boot_id,
+            Err(err) => {
+                error!("Failed to tell whether the device rebooted: {:?}", err);
+                return;
+            }
+        };
+
+        if self.state.rebooted(&boot_id) {
+            let profile = self.state.attempt.as_ref().map(|a| a.profile.clone());
+            let version = match self.profile(profile.as_deref()).await {
+                Ok(profile) => flow::os_version(self.host.as_ref(), &profile).await,
+                Err(err) => Err(err),
+            };
+            self.state.confirm(version);
+        }
+
+        if self.state.sequence != sequence {
+            info!("Resumed OTA, now {:?}", self.state.phase());
+            self.save().await;
+        }
+    }
+
+    async fn save(&self) {
+        if let Err(err) = self.state.save().await {
+            error!("Failed to save the OTA state: {:?}", err);
+        }
+    }
+
+    async fn run(&mut self) {
+        info!("OTA task is runnning");
+
+        self.resume().await;
+
+        let mut poll_interval = time::interval(POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                Some(msg) = self.receiver.recv() => {
+                    self.handle_message(msg).await;
+                }
+                Some(result) = self.applied_rx.recv() => {
+                    self.applied(result).await;
+                }
+                _ = poll_interval.tick() => {
+                    self.check_download().await;
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+
+        info!("OTA task shutting down");
+    }
+}
+
+async fn boot_id() -> Result<String> {
+    let boot_id = tokio::fs::read_to_string("/proc/sys/kernel/random/boot_id").await?;
+    let boot_id = boot_id.trim();
+    if boot_id.is_empty() {
+        return Err(anyhow!("The boot id is empty"));
+    }
+
+    Ok(boot_id.to_string())
+}
+
+/// Runs OTA updates following the `[[ota]]` profiles of magic.toml, keeping
+/// track of each attempt across the reboot into the new OS.
+#[derive(Clone)]
+pub struct OtaHandle {
+    sender: mpsc::Sender<OtaMessage>,
+}
+
+impl OtaHandle {
+    pub fn new(
+        shutdown: ShutdownSignals,
+        magic: MagicHandle,
+        downloader: DownloaderHandle,
+        filemanager: FileManagerHandle,
+        host: Arc<dyn Host>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(8);
+        let mut actor = Ota::new(shutdown, receiver, magic, downloader, filemanager, host);
+        tokio::spawn(async move { actor.run().await });
+
+        Self { sender }
+    }
+
+    /// Starts a new attempt by downloading its artifacts.
+    pub async fn download(
+        &self,
+        profile: Option<&str>,
+        tools: Option<&str>,
+        payload: &str,
+        rate: f64,
+        expected_version: Option<&str>,
+        checksums: Option<OtaChecksums>,
+    ) -> Result<()> {
+        let (rpc, receiver) = oneshot::channel();
+        self.sender
+            .send(OtaMessage::Download {
+                profile: profile.map(str::to_string),
+                tools: tools.map(str::to_string),
+                payload: payload.to_string(),
+                rate,
+                expected_version: expected_version.map(str::to_string),
+                checksums,
+                rpc,
+            })
+            .await?;
+        receiver.await?
+    }
+
+    /// Applies the downloaded update, returns once the apply command finished.
+    pub async fn start(&self, profile: Option<&str>) -> Result<String> {
+        let (rpc, receiver) = oneshot::channel();
+        self.sender
+            .send(OtaMessage::Start {
+                profile: profile.map(str::to_string),
+                rpc,
+            })
+            .await?;
+        receiver.await?
+    }
+
+    /// How the downloads of the current attempt are going.
+    pub async fn download_status(&self) -> Result<DownloadingStatus> {
+        let (rpc, receiver) = oneshot::channel();
+        self.sender.send(OtaMessage::DownloadStatus { rpc }).await?;
+        receiver.await?
+    }
+
+    /// The current attempt when the API hasn't seen its latest change.
+    pub async fn unreported(&self) -> Option<(u64, OtaAttempt)> {
+        let (rpc, receiver) = oneshot::channel();
+        _ = self.sender.send(OtaMessage::Unreported { rpc }).await;
+        receiver.await.ok().flatten()
+    }
+
+    pub async fn reported(&self, sequence: u64) {
+        _ = self.sender.send(OtaMessage::Reported { sequence }).await;
+    }
+}
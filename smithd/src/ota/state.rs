@@ -0,0 +1,205 @@
+use crate::magic::state::write_atomically;
+use crate::utils::schema::{ArtifactChecksum, OtaAttempt, OtaPhase};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::warn;
+
+const STATE_FILE: &str = "/var/lib/smith/ota.json";
+
+/// An artifact on disk and the checksum it has to match.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(super) struct ExpectedArtifact {
+    pub path: String,
+    pub checksum: ArtifactChecksum,
+}
+
+/// The current OTA attempt as kept on disk.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct OtaState {
+    pub attempt: Option<OtaAttempt>,
+    /// Boot the update was applied in, any other boot means we rebooted.
+    pub boot_id: Option<String>,
+    /// Artifacts of the attempt as released, the download is only verified
+    /// once they match.
+    #[serde(default)]
+    pub artifacts: Vec<ExpectedArtifact>,
+    /// Bumped on every change, the API has received up to `reported`.
+    pub sequence: u64,
+    pub reported: u64,
+}
+
+impl OtaState {
+    pub fn load() -> Self {
+        let Ok(contents) = std::fs::read_to_string(STATE_FILE) else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable OTA state: {}", err);
+            Self::default()
+        })
+    }
+
+    /// Writes the state next to the old one first, so a power cut leaves
+    /// either of them in place.
+    pub async fn save(&self) -> Result<()> {
+        write_atomically(Path::new(STATE_FILE), &serde_json::to_vec_pretty(self)?).await
+    }
+
+    pub fn phase(&self) -> OtaPhase {
+        self.attempt
+            .as_ref()
+            .map(|attempt| attempt.phase)
+            .unwrap_or_default()
+    }
+
+    /// Replaces the previous attempt, whatever became of it.
+    pub fn begin(
+        &mut self,
+        profile: &str,
+        phase: OtaPhase,
+        expected_version: Option<String>,
+        previous_version: Option<String>,
+    ) {
+        self.attempt = Some(OtaAttempt {
+            started_at: chrono::Utc::now(),
+            profile: profile.to_string(),
+            phase,
+            expected_version,
+            previous_version,
+            version: None,
+            error: None,
+        });
+        self.boot_id = None;
+        self.sequence += 1;
+    }
+
+    pub fn set_phase(&mut self, phase: OtaPhase, error: Option<String>) {
+        if let Some(attempt) = &mut self.attempt {
+            attempt.phase = phase;
+            attempt.error = error;
+            self.sequence += 1;
+        }
+    }
+
+    /// Fails an attempt smithd was in the middle of when it stopped, those
+    /// steps can't be picked up again.
+    pub fn interrupted(&mut self) {
+        match self.phase() {
+            OtaPhase::Downloading => self.set_phase(
+                OtaPhase::Failed,
+                Some("smithd restarted while downloading".to_string()),
+            ),
+            OtaPhase::Applying => self.set_phase(
+                OtaPhase::Failed,
+                Some("smithd restarted while applying".to_string()),
+            ),
+            _ => {}
+        }
+    }
+
+    /// Whether the device booted since the update was applied.
+    pub fn rebooted(&self, boot_id: &str) -> bool {
+        self.phase() == OtaPhase::Rebooting && self.boot_id.as_deref() != Some(boot_id)
+    }
+
+    /// Settles an attempt once the device booted again, checking it runs the
+    /// expected OS version.
+    pub fn confirm(&mut self, version: Result<String>) {
+        let Some(attempt) = &mut self.attempt else {
+            return;
+        };
+
+        let (phase, error) = match version {
+            Ok(version) => {
+                let error = attempt
+                    .expected_version
+                    .as_ref()
+                    .filter(|expected| **expected != version)
+                    .map(|expected| format!("Booted into {}, expected {}", version, expected));
+                attempt.version = Some(version);
+                match error {
+                    Some(error) => (OtaPhase::Failed, Some(error)),
+                    None => (OtaPhase::Confirmed, None),
+                }
+            }
+            Err(err) => (
+                OtaPhase::Failed,
+                Some(format!("Failed to read the OS version: {}", err)),
+            ),
+        };
+
+        self.set_phase(phase, error);
+    }
+
+    /// The attempt when it changed since the API last received it.
+    pub fn unreported(&self) -> Option<(u64, OtaAttempt)> {
+        if self.sequence <= self.reported {
+            return None;
+        }
+
+        self.attempt.clone().map(|attempt| (self.sequence, attempt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(expected_version: Option<&str>) -> OtaState {
+        let mut state = OtaState::default();
+        state.begin(
+            "jetson",
+            OtaPhase::Verified,
+            expected_version.map(str::to_string),
+            Some("35.4.1".to_string()),
+        );
+        state.set_phase(OtaPhase::Applying, None);
+        state.boot_id = Some("first".to_string());
+        state.set_phase(OtaPhase::Rebooting, None);
+        state
+    }
+
+    #[test]
+    fn confirms_after_reboot() {
+        let mut state = applied(Some("36.3.0"));
+        assert!(!state.rebooted("first"));
+        assert!(state.rebooted("second"));
+
+        state.confirm(Ok("36.3.0".to_string()));
+        let attempt = state.attempt.clone().unwrap();
+        assert_eq!(attempt.phase, OtaPhase::Confirmed);
+        assert_eq!(attempt.version.as_deref(), Some("36.3.0"));
+
+        let (sequence, _) = state.unreported().unwrap();
+        state.reported = sequence;
+        assert!(state.unreported().is_none());
+    }
+
+    #[test]
+    fn fails_on_unexpected_version() {
+        let mut state = applied(Some("36.3.0"));
+        state.confirm(Ok("35.4.1".to_string()));
+
+        let attempt = state.attempt.unwrap();
+        assert_eq!(attempt.phase, OtaPhase::Failed);
+        assert_eq!(
+            attempt.error.as_deref(),
+            Some("Booted into 35.4.1, expected 36.3.0")
+        );
+    }
+
+    #[test]
+    fn fails_when_interrupted() {
+        let mut state = applied(None);
+        state.set_phase(OtaPhase::Applying, None);
+        state.interrupted();
+        assert_eq!(state.phase(), OtaPhase::Failed);
+
+        let mut state = OtaState::default();
+        state.interrupted();
+        assert_eq!(state.phase(), OtaPhase::Idle);
+        assert!(state.unreported().is_none());
+    }
+}
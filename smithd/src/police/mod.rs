@@ -7,16 +7,27 @@
 //! It does this by issuing a dealayed restart after 5 minutes. If the problem
 //! is solved before the restart is issued, the restart is cancelled. 🤞
 //!
+//! The delay, the grace period after start and whether to restart at all
+//! come from the `[police]` section of magic.toml.
+//!
+use crate::magic::MagicHandle;
+use crate::magic::structure::ConfigPolice;
 use crate::shutdown::ShutdownSignals;
-use tokio::process::Command;
+use crate::utils::host::{Host, HostCommand};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Handle;
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
 use tracing::error;
 use tracing::{info, warn};
 
 struct Police {
     shutdown: ShutdownSignals,
-    should_restart: bool,
+    magic: MagicHandle,
+    host: Arc<dyn Host>,
+    policy: ConfigPolice,
+    started: Instant,
     restart: Option<tokio::task::JoinHandle<()>>,
     receiver: mpsc::Receiver<PoliceMessage>,
     next_id: u32,
@@ -32,37 +43,63 @@ enum PoliceMessage {
 }
 
 impl Police {
-    fn new(shutdown: ShutdownSignals, receiver: mpsc::Receiver<PoliceMessage>) -> Self {
+    fn new(
+        shutdown: ShutdownSignals,
+        magic: MagicHandle,
+        host: Arc<dyn Host>,
+        receiver: mpsc::Receiver<PoliceMessage>,
+    ) -> Self {
         Police {
             shutdown,
-            should_restart: false,
+            magic,
+            host,
+            policy: ConfigPolice::default(),
+            started: Instant::now(),
             restart: None,
             receiver,
             next_id: 0,
             problems: Vec::new(),
         }
     }
+    fn should_restart(&self) -> bool {
+        self.policy.enabled && self.started.elapsed() >= Duration::from_secs(self.policy.grace)
+    }
+
+    async fn reload_policy(&mut self) {
+        self.policy = self.magic.get_police().await;
+        info!("Police policy: {:?}", self.policy);
+        if !self.policy.enabled {
+            if let Some(restart) = self.restart.take() {
+                info!("Police restarts disabled, restart aborted");
+                restart.abort();
+            }
+        }
+    }
+
     fn handle_message(&mut self, msg: PoliceMessage) {
         match msg {
             PoliceMessage::ProblemStarting { respond_to } => {
-                // There is no restart scheduled, so we will do it in 5 minutes
-                let response = if self.should_restart {
+                // There is no restart scheduled, so we will do it after the delay
+                let response = if self.should_restart() {
                     self.next_id += 1;
                     self.problems.push(self.next_id);
                     if self.restart.is_none() {
                         let handle = Handle::current();
+                        let delay = Duration::from_secs(self.policy.delay);
+                        let host = self.host.clone();
                         // spawn doesn need to be awaited in order to run
-                        let restart_handle = handle.spawn(async {
-                            warn!("Restarting in 5 minutes");
-                            // Sleep for 5 minutes instead of scheduling reboot right away because
+                        let restart_handle = handle.spawn(async move {
+                            warn!("Restarting in {:?}", delay);
+                            // Sleep instead of scheduling reboot right away because
                             // when scheduling we will no longer be able to login via ssh as a user
                             // might locks us out of the system
-                            tokio::time::sleep(std::time::Duration::from_secs(5 * 60)).await;
+                            tokio::time::sleep(delay).await;
                             error!("Restarting now!");
-                            Command::new("reboot")
-                                .arg("now")
-                                .spawn()
-                                .expect("Failed to spawn shutdown command");
+                            if let Err(err) =
+                                host.output(HostCommand::new("reboot").arg("now")).await
+                            {
+                                error!("Failed to run reboot: {}", err);
+                            }
                         });
                         self.restart = Some(restart_handle);
                     } else {
@@ -93,20 +130,16 @@ impl Police {
     async fn run(&mut self) {
         info!("Police runnning");
 
-        let mut enable_by_default =
-            tokio::time::interval(tokio::time::Duration::from_secs(60 * 15));
-
-        // the first tick is immediate
-        enable_by_default.tick().await;
+        let mut changes = self.magic.agent_config_changes();
+        self.reload_policy().await;
 
         loop {
             tokio::select! {
                 Some(msg) = self.receiver.recv() => {
                     self.handle_message(msg);
                 }
-                _ = enable_by_default.tick() => {
-                    info!("Enabling police restarts by default");
-                    self.should_restart = true;
+                Ok(()) = changes.changed() => {
+                    self.reload_policy().await;
                 }
                 _ = self.shutdown.token.cancelled() => {
                     break;
@@ -124,9 +157,9 @@ pub struct PoliceHandle {
 }
 
 impl PoliceHandle {
-    pub fn new(shutdown: ShutdownSignals) -> Self {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle, host: Arc<dyn Host>) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Police::new(shutdown, receiver);
+        let mut actor = Police::new(shutdown, magic, host, receiver);
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
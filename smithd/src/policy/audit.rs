@@ -0,0 +1,52 @@
+//! Append-only record of every decision of the policy, one JSON object per
+//! line. Command arguments that may hold secrets, like the content of config
+//! files, are left out.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Serialize, Debug)]
+pub struct Entry<'a> {
+    pub timestamp: DateTime<Utc>,
+    /// Id of the command in the API.
+    pub id: i32,
+    /// Variant of the command, like `FreeForm`.
+    pub command: &'a str,
+    /// What the command acts on: the shell command, the config file paths,
+    /// the tunnel port or the plugin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub allowed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a str>,
+    /// Admin key whose signature was checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed_by: Option<&'a str>,
+}
+
+pub async fn append(path: &Path, entry: &Entry<'_>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(&line).await?;
+    file.sync_data().await?;
+
+    Ok(())
+}
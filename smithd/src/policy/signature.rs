@@ -0,0 +1,154 @@
+//! Commands signed by an admin key, so a device whose policy requires it
+//! doesn't have to trust whoever can reach the API.
+//!
+//! The signature covers the serial number of the device, the expiry, a
+//! nonce and the command, so it can't be replayed on another device, once
+//! expired or a second time, see [`super::replay`].
+//!
+//! The command is signed as canonical JSON: object keys sorted, no
+//! whitespace. What serde_json prints depends on its features and the field
+//! order of the schema, a signature over it could break between the smithd
+//! that signed and the one that verifies.
+
+use crate::utils::schema::{CommandSignature, SafeCommandTx};
+use anyhow::{Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use serde_json::Value;
+
+/// Changes whenever what is signed does.
+const MESSAGE_VERSION: &str = "smith-command-v1";
+
+fn message(
+    serial_number: &str,
+    expires_at: DateTime<Utc>,
+    nonce: &str,
+    command: &SafeCommandTx,
+) -> Result<Vec<u8>> {
+    let mut message = format!(
+        "{}\n{}\n{}\n{}\n",
+        MESSAGE_VERSION,
+        serial_number,
+        expires_at.timestamp(),
+        nonce
+    );
+    canonical(&serde_json::to_value(command)?, &mut message);
+    Ok(message.into_bytes())
+}
+
+/// Writes `value` as JSON with the keys of its objects sorted.
+fn canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            out.push('{');
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::from(key.as_str()).to_string());
+                out.push(':');
+                canonical(value, out);
+            }
+            out.push('}');
+        }
+        // strings are escaped and numbers printed the same way whatever the
+        // features
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// A new key pair as a PKCS#8 document.
+pub fn generate() -> Result<Vec<u8>> {
+    let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow!("Failed to generate a key"))?;
+    Ok(document.as_ref().to_vec())
+}
+
+pub fn key_pair(pkcs8: &[u8]) -> Result<Ed25519KeyPair> {
+    Ed25519KeyPair::from_pkcs8(pkcs8).map_err(|err| anyhow!("Invalid key: {}", err))
+}
+
+/// The base64 public key, as listed in `policy.admin_keys`.
+pub fn public_key(key_pair: &Ed25519KeyPair) -> String {
+    STANDARD.encode(key_pair.public_key())
+}
+
+pub fn decode_key(key: &str) -> Result<Vec<u8>> {
+    let key = STANDARD.decode(key.trim())?;
+    if key.len() != 32 {
+        return Err(anyhow!("expected 32 bytes, got {}", key.len()));
+    }
+    Ok(key)
+}
+
+pub fn sign(
+    key_pair: &Ed25519KeyPair,
+    serial_number: &str,
+    expires_at: DateTime<Utc>,
+    command: &SafeCommandTx,
+) -> Result<CommandSignature> {
+    let mut nonce = [0; 16];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("Failed to generate a nonce"))?;
+    let nonce = STANDARD.encode(nonce);
+
+    let signature = key_pair.sign(&message(serial_number, expires_at, &nonce, command)?);
+    Ok(CommandSignature {
+        key: public_key(key_pair),
+        expires_at,
+        nonce,
+        signature: STANDARD.encode(signature),
+    })
+}
+
+/// Why the signature isn't valid for this device at `now`, if it isn't.
+pub fn verify(
+    signature: &CommandSignature,
+    admin_keys: &[String],
+    serial_number: &str,
+    command: &SafeCommandTx,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let key = decode_key(&signature.key).map_err(|err| format!("invalid signing key: {err}"))?;
+    if !admin_keys
+        .iter()
+        .any(|admin| decode_key(admin).is_ok_and(|admin| admin == key))
+    {
+        return Err(format!("{} is not an admin key", signature.key));
+    }
+    if signature.expires_at < now {
+        return Err(format!("the signature expired at {}", signature.expires_at));
+    }
+    if signature.nonce.is_empty() {
+        return Err("the signature has no nonce".to_string());
+    }
+
+    let bytes = STANDARD
+        .decode(&signature.signature)
+        .map_err(|_| "the signature is not base64".to_string())?;
+    let message = message(
+        serial_number,
+        signature.expires_at,
+        &signature.nonce,
+        command,
+    )
+    .map_err(|err| format!("failed to serialize the command: {err}"))?;
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(&message, &bytes)
+        .map_err(|_| "invalid signature".to_string())
+}
@@ -0,0 +1,202 @@
+//! The device side of the postman: what it reports to the API and what runs
+//! the commands it brings back. smithd's actors on a device, a fake device
+//! running its commands against a fake [`Host`](crate::utils::host::Host)
+//! in smith-sim.
+
+use crate::commander::CommanderHandle;
+use crate::magic::MagicHandle;
+use crate::ota::OtaHandle;
+use crate::police::PoliceHandle;
+use crate::restart::RestartHandle;
+use crate::tunnel::TunnelHandle;
+use crate::updater::self_update::{Heartbeat, SelfUpdateState};
+use crate::utils::host::Host;
+use crate::utils::network::NetworkClient;
+use crate::utils::schema::{
+    DeviceRegistration, OtaAttempt, PendingRestart, SafeCommandRequest, SafeCommandResponse,
+    SelfUpdateAttempt, Tunnel,
+};
+use crate::utils::system::SystemInfo;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// State of the device every post carries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reported {
+    pub release_id: Option<i32>,
+    pub tunnels: Vec<Tunnel>,
+    pub restart: Option<PendingRestart>,
+}
+
+pub trait Device: Send + Sync {
+    /// API to report to.
+    fn server(&self) -> BoxFuture<'_, String>;
+
+    fn token(&self) -> BoxFuture<'_, Option<String>>;
+
+    /// `None` forgets the token, the device registers again.
+    fn set_token(&self, token: Option<String>) -> BoxFuture<'_, ()>;
+
+    fn registration(&self) -> DeviceRegistration;
+
+    fn system_info(&self) -> BoxFuture<'_, Value>;
+
+    fn agent_config_version(&self) -> Option<String>;
+
+    fn plugins(&self) -> BoxFuture<'_, Vec<String>>;
+
+    /// Results of the commands run since the last post.
+    fn results(&self) -> BoxFuture<'_, Vec<SafeCommandResponse>>;
+
+    /// Results to send with the next post.
+    fn insert_results(&self, results: Vec<SafeCommandResponse>) -> BoxFuture<'_, ()>;
+
+    /// Runs commands of the API, their results are taken with [`results`](Self::results).
+    fn execute(&self, commands: Vec<SafeCommandRequest>) -> BoxFuture<'_, ()>;
+
+    fn reported(&self) -> BoxFuture<'_, Reported>;
+
+    fn set_target_release_id(&self, release_id: Option<i32>) -> BoxFuture<'_, ()>;
+
+    /// The OTA attempt with its sequence, when it changed since the API
+    /// acknowledged it.
+    fn ota_unreported(&self) -> BoxFuture<'_, Option<(u64, OtaAttempt)>>;
+
+    fn ota_reported(&self, sequence: u64) -> BoxFuture<'_, ()>;
+
+    /// The settled self-update with its sequence, when the API doesn't have
+    /// it yet.
+    fn self_update_unreported(&self) -> Option<(u64, SelfUpdateAttempt)>;
+
+    fn self_update_reported(&self, sequence: u64) -> BoxFuture<'_, Result<()>>;
+
+    /// Tells the updater this smithd reached the API.
+    fn reached_api(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// Id of the problem the API being unreachable is reported as.
+    fn problem_starting(&self) -> BoxFuture<'_, Option<u32>>;
+
+    fn problem_solved(&self, problem: u32) -> BoxFuture<'_, ()>;
+
+    /// Time between two pings.
+    fn interval(&self) -> Duration {
+        Duration::from_secs(20)
+    }
+
+    /// Whether the next ping is lost on the way, simulated devices drop
+    /// some on flaky links.
+    fn lost(&self) -> bool {
+        false
+    }
+
+    /// Called after every request to the API, with its status code when it
+    /// was answered.
+    fn posted(&self, _endpoint: &'static str, _latency: Duration, _status: Option<u16>) {}
+}
+
+/// smithd's actors.
+pub struct Actors {
+    pub police: PoliceHandle,
+    pub commander: CommanderHandle,
+    pub magic: MagicHandle,
+    pub tunnel: TunnelHandle,
+    pub ota: OtaHandle,
+    pub restart: RestartHandle,
+    pub network: NetworkClient,
+    pub host: Arc<dyn Host>,
+}
+
+impl Device for Actors {
+    fn server(&self) -> BoxFuture<'_, String> {
+        Box::pin(self.magic.get_server())
+    }
+
+    fn token(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(self.magic.get_token())
+    }
+
+    fn set_token(&self, token: Option<String>) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            match token {
+                Some(token) => self.magic.set_token(&token).await,
+                None => self.magic.delete_token().await,
+            }
+        })
+    }
+
+    fn registration(&self) -> DeviceRegistration {
+        DeviceRegistration {
+            serial_number: self.network.get_serial(),
+            wifi_mac: self.network.get_mac_wlan0(),
+        }
+    }
+
+    fn system_info(&self) -> BoxFuture<'_, Value> {
+        Box::pin(async { SystemInfo::new(self.host.as_ref()).await.to_value() })
+    }
+
+    fn agent_config_version(&self) -> Option<String> {
+        self.magic.agent_config_version()
+    }
+
+    fn plugins(&self) -> BoxFuture<'_, Vec<String>> {
+        Box::pin(self.commander.plugins())
+    }
+
+    fn results(&self) -> BoxFuture<'_, Vec<SafeCommandResponse>> {
+        Box::pin(self.commander.get_results())
+    }
+
+    fn insert_results(&self, results: Vec<SafeCommandResponse>) -> BoxFuture<'_, ()> {
+        Box::pin(self.commander.insert_result(results))
+    }
+
+    fn execute(&self, commands: Vec<SafeCommandRequest>) -> BoxFuture<'_, ()> {
+        Box::pin(self.commander.execute_api_batch(commands))
+    }
+
+    fn reported(&self) -> BoxFuture<'_, Reported> {
+        Box::pin(async {
+            Reported {
+                release_id: self.magic.get_release_id().await,
+                tunnels: self.tunnel.list_tunnels().await,
+                restart: self.restart.pending().await,
+            }
+        })
+    }
+
+    fn set_target_release_id(&self, release_id: Option<i32>) -> BoxFuture<'_, ()> {
+        Box::pin(self.magic.set_target_release_id(release_id))
+    }
+
+    fn ota_unreported(&self) -> BoxFuture<'_, Option<(u64, OtaAttempt)>> {
+        Box::pin(self.ota.unreported())
+    }
+
+    fn ota_reported(&self, sequence: u64) -> BoxFuture<'_, ()> {
+        Box::pin(self.ota.reported(sequence))
+    }
+
+    fn self_update_unreported(&self) -> Option<(u64, SelfUpdateAttempt)> {
+        SelfUpdateState::load().unreported()
+    }
+
+    fn self_update_reported(&self, sequence: u64) -> BoxFuture<'_, Result<()>> {
+        Box::pin(SelfUpdateState::acknowledge(sequence))
+    }
+
+    fn reached_api(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Heartbeat::new().write().await })
+    }
+
+    fn problem_starting(&self) -> BoxFuture<'_, Option<u32>> {
+        Box::pin(self.police.report_problem_starting())
+    }
+
+    fn problem_solved(&self, problem: u32) -> BoxFuture<'_, ()> {
+        Box::pin(self.police.report_problem_solved(problem))
+    }
+}
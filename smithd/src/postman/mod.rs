@@ -1,55 +1,102 @@
 use crate::commander::CommanderHandle;
 use crate::magic::MagicHandle;
+use crate::ota::OtaHandle;
 use crate::police::PoliceHandle;
+use crate::restart::RestartHandle;
 use crate::shutdown::ShutdownSignals;
-use crate::utils::network::NetworkClient;
+use crate::tunnel::TunnelHandle;
+use crate::utils::host::Host;
+use crate::utils::network::{self, NetworkClient};
 use crate::utils::schema::{
-    DeviceRegistration, DeviceRegistrationResponse, HomePost, HomePostResponse,
-    SafeCommandResponse, SafeCommandRx,
+    DeviceRegistration, DeviceRegistrationResponse, HomePost, HomePostResponse, OtaPhase,
+    SafeCommandResponse, SafeCommandRx, SelfUpdatePhase, UndecodedCommand,
 };
-use crate::utils::system::SystemInfo;
+use crate::utils::system;
 use anyhow::{Result, anyhow};
 use reqwest::{Response, StatusCode};
+use serde_json::Value;
 use std::fmt::Write;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::{sync::mpsc, time};
 use tracing::{error, info, warn};
 
+mod device;
+
+pub use device::{Actors, Device, Reported};
+
 struct Postman {
     shutdown: ShutdownSignals,
-    police: PoliceHandle,
     receiver: mpsc::Receiver<PostmanMessage>,
-    commander: CommanderHandle,
-    magic: MagicHandle,
+    device: Arc<dyn Device>,
     network: NetworkClient,
     hostname: String,
     token: Option<String>,
     problems: Option<u32>,
+    /// Last system info the API has received.
+    system_info: Option<Value>,
+    /// Latest system info read, sent with the next post until the API has it.
+    latest_system_info: Option<Value>,
+    /// System info carried by the post in flight, acknowledged when it
+    /// succeeds.
+    pending_system_info: Option<Value>,
+    /// Change of the OTA attempt sent but not yet acknowledged.
+    pending_ota: Option<u64>,
+    /// Settled self-update sent but not yet acknowledged.
+    pending_self_update: Option<u64>,
+    /// Plugins the API last received, reported again when `commands.d`
+    /// changes.
+    plugins: Option<Vec<String>>,
+    /// Plugins carried by the post in flight.
+    pending_plugins: Option<Vec<String>>,
+    /// Whether this smithd told the updater it reached the API.
+    heartbeat_written: bool,
+    /// Protocol of the API, as of its last answer.
+    api_protocol_version: u32,
+    /// State the API last received, pings with the same state and no
+    /// responses are sent as heartbeats.
+    reported: Option<Reported>,
 }
 
+/// Every state the API keeps is sent in full this often, whatever it
+/// acknowledged, in case it lost some of it.
+const FULL_REPORT_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Protocol from which the API takes pings with an empty body.
+const HEARTBEAT_PROTOCOL_VERSION: u32 = 2;
+/// Protocol from which the API applies system info deltas, older ones only
+/// get full snapshots.
+const SYSTEM_INFO_DELTA_PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Debug)]
 enum PostmanMessage {}
 
 impl Postman {
     fn new(
         shutdown: ShutdownSignals,
-        police: PoliceHandle,
         receiver: mpsc::Receiver<PostmanMessage>,
-        commander: CommanderHandle,
-        magic: MagicHandle,
+        device: Arc<dyn Device>,
     ) -> Self {
         let network = NetworkClient::default();
 
         Self {
             shutdown,
-            police,
             receiver,
-            commander,
+            device,
             network,
-            magic,
             token: None,
             hostname: "".to_owned(),
             problems: None,
+            system_info: None,
+            latest_system_info: None,
+            pending_system_info: None,
+            pending_ota: None,
+            pending_self_update: None,
+            plugins: None,
+            pending_plugins: None,
+            heartbeat_written: false,
+            api_protocol_version: 0,
+            reported: None,
         }
     }
 
@@ -58,35 +105,43 @@ impl Postman {
     async fn run(&mut self) {
         info!("Postman runnning");
 
-        self.hostname = self.magic.get_server().await;
+        self.hostname = self.device.server().await;
         self.network.set_hostname(self.hostname.clone());
 
-        self.token = self.magic.get_token().await;
+        self.token = self.device.token().await;
 
-        self.commander
-            .insert_result(vec![
+        self.latest_system_info = Some(self.device.system_info().await);
+
+        self.device
+            .insert_results(vec![
                 SafeCommandResponse {
                     id: -1,
-                    command: SafeCommandRx::GetVariables,
+                    command: SafeCommandRx::GetConfigFiles,
                     status: 0,
+                    result: None,
                 },
                 SafeCommandResponse {
-                    id: -2,
-                    command: SafeCommandRx::UpdateSystemInfo {
-                        system_info: SystemInfo::new().await.to_value(),
-                    },
+                    id: -3,
+                    command: SafeCommandRx::GetVariables,
                     status: 0,
+                    result: None,
                 },
                 SafeCommandResponse {
                     id: -4,
                     command: SafeCommandRx::GetNetwork,
                     status: 0,
+                    result: None,
                 },
+                self.agent_config_response(),
             ])
             .await;
 
-        let mut keep_alive_interval = time::interval(Duration::from_secs(20));
+        let mut keep_alive_interval = time::interval(self.device.interval());
         let mut update_interval = time::interval(Duration::from_secs(300));
+        // the system info was just read
+        update_interval.reset();
+        let mut full_report_interval = time::interval(FULL_REPORT_INTERVAL);
+        full_report_interval.reset();
 
         loop {
             tokio::select! {
@@ -99,30 +154,62 @@ impl Postman {
                         continue;
                     }
 
-                    let responses = self.commander.get_results().await;
-                    let release_id = self.magic.get_release_id().await;
+                    if self.device.lost() {
+                        continue;
+                    }
 
-                    let ping_home_body = HomePost::new(responses, release_id);
+                    let mut responses = self.device.results().await;
+                    if let Some(response) = self.system_info_response() {
+                        responses.push(response);
+                    }
+                    if let Some((sequence, attempt)) = self.device.ota_unreported().await {
+                        responses.push(SafeCommandResponse {
+                            id: -5,
+                            status: if attempt.phase == OtaPhase::Failed { -1 } else { 0 },
+                            command: SafeCommandRx::OtaStatus { attempt },
+                            result: None,
+                        });
+                        self.pending_ota = Some(sequence);
+                    }
+                    if let Some(response) = self.plugins_response().await {
+                        responses.push(response);
+                    }
+                    if let Some((sequence, attempt)) = self.device.self_update_unreported() {
+                        responses.push(SafeCommandResponse {
+                            id: -6,
+                            status: if attempt.phase == SelfUpdatePhase::Confirmed { 0 } else { -1 },
+                            command: SafeCommandRx::SelfUpdateStatus { attempt },
+                            result: None,
+                        });
+                        self.pending_self_update = Some(sequence);
+                    }
+                    let reported = self.device.reported().await;
 
-                    let response = self.ping_home(ping_home_body).await;
+                    let ping_home_body = if responses.is_empty()
+                        && self.api_protocol_version >= HEARTBEAT_PROTOCOL_VERSION
+                        && self.reported.as_ref() == Some(&reported)
+                    {
+                        None
+                    } else {
+                        Some(HomePost::new(
+                            responses,
+                            reported.release_id,
+                            reported.tunnels.clone(),
+                            reported.restart.clone(),
+                        ))
+                    };
+
+                    let response = self.ping_home(ping_home_body, reported).await;
                     let target_release_id = response.target_release_id;
-                    self.magic.set_target_release_id(target_release_id).await;
+                    self.device.set_target_release_id(target_release_id).await;
 
-                    self.commander.execute_api_batch(response.commands).await;
+                    self.device.execute(response.commands).await;
                 }
                 _ = update_interval.tick() => {
-                    self.commander
-                        .insert_result(vec![
-                            // Keep the system info in sync.
-                            SafeCommandResponse {
-                                id: -2,
-                                command: SafeCommandRx::UpdateSystemInfo {
-                                    system_info: SystemInfo::new().await.to_value(),
-                                },
-                                status: 0,
-                            },
-                        ])
-                        .await;
+                    self.latest_system_info = Some(self.device.system_info().await);
+                }
+                _ = full_report_interval.tick() => {
+                    self.full_report().await;
                 }
                 _ = self.shutdown.token.cancelled() => {
                     break;
@@ -133,20 +220,106 @@ impl Postman {
         info!("Postman task shut down");
     }
 
+    /// Forgets what the API acknowledged, so the next ping carries the
+    /// system info, the plugins, the agent config and the state in full, and
+    /// negotiates the protocol and the encoding again.
+    async fn full_report(&mut self) {
+        info!("Sending a full report");
+        self.system_info = None;
+        self.latest_system_info = Some(self.device.system_info().await);
+        self.plugins = None;
+        self.reported = None;
+        self.renegotiate();
+        self.device
+            .insert_results(vec![self.agent_config_response()])
+            .await;
+    }
+
+    /// Version of the agent config in use.
+    fn agent_config_response(&self) -> SafeCommandResponse {
+        SafeCommandResponse {
+            id: -7,
+            command: SafeCommandRx::AgentConfig {
+                version: self.device.agent_config_version(),
+                reloaded: vec![],
+                error: None,
+            },
+            status: 0,
+            result: None,
+        }
+    }
+
+    /// The API answering may not be the one that last did, after it was
+    /// unreachable or rolled back: nothing newer than the first protocol is
+    /// assumed until it answers again.
+    fn renegotiate(&mut self) {
+        self.api_protocol_version = 0;
+        self.network.renegotiate();
+    }
+
+    /// Keeps the system info in sync, sending only what changed since the
+    /// API last received it when it understands deltas. A post that fails
+    /// leaves the latest system info to be sent with the next one.
+    fn system_info_response(&mut self) -> Option<SafeCommandResponse> {
+        let system_info = self.latest_system_info.as_ref()?;
+
+        let command = match &self.system_info {
+            Some(acknowledged)
+                if self.api_protocol_version >= SYSTEM_INFO_DELTA_PROTOCOL_VERSION =>
+            {
+                match system::changes(acknowledged, system_info) {
+                    Some(changes) => SafeCommandRx::SystemInfoChanged { changes },
+                    None => {
+                        self.latest_system_info = None;
+                        return None;
+                    }
+                }
+            }
+            Some(acknowledged) if acknowledged == system_info => {
+                self.latest_system_info = None;
+                return None;
+            }
+            _ => SafeCommandRx::UpdateSystemInfo {
+                system_info: system_info.clone(),
+            },
+        };
+        self.pending_system_info = Some(system_info.clone());
+
+        Some(SafeCommandResponse {
+            id: -2,
+            command,
+            status: 0,
+            result: None,
+        })
+    }
+
+    /// The plugins, when they changed since the API last received them.
+    async fn plugins_response(&mut self) -> Option<SafeCommandResponse> {
+        let plugins = self.device.plugins().await;
+        if self.plugins.as_ref() == Some(&plugins) {
+            return None;
+        }
+        self.pending_plugins = Some(plugins.clone());
+
+        Some(SafeCommandResponse {
+            id: -8,
+            command: SafeCommandRx::Plugins { plugins },
+            status: 0,
+            result: None,
+        })
+    }
+
     async fn ensure_token(&mut self) -> Result<(), anyhow::Error> {
         if self.token.is_none() {
             warn!("!NO TOKEN! trying to register device");
 
-            let response = self
-                .register_device(DeviceRegistration {
-                    serial_number: self.network.get_serial(),
-                    wifi_mac: self.network.get_mac_wlan0(),
-                })
-                .await?;
+            let response = self.register_device(self.device.registration()).await?;
 
             if response.0 == StatusCode::OK {
                 let registration_response = response.1.json::<DeviceRegistrationResponse>().await?;
-                self.magic.set_token(&registration_response.token).await;
+                self.device
+                    .set_token(Some(registration_response.token.clone()))
+                    .await;
                 self.token = Some(registration_response.token);
             } else {
                 error!("Failed to register device: {:?}", response.0);
@@ -156,23 +329,67 @@ impl Postman {
         Ok(())
     }
 
-    async fn ping_home(&mut self, message: HomePost) -> HomePostResponse {
+    /// Sends a heartbeat when there is no `message`.
+    async fn ping_home(
+        &mut self,
+        message: Option<HomePost>,
+        reported: Reported,
+    ) -> HomePostResponse {
         let token = self.token.clone().unwrap_or_default();
+        // only acknowledged by the answer to the post that carried it
+        let carried_system_info = self.pending_system_info.take();
+        let carried_plugins = self.pending_plugins.take();
 
-        let result = self
-            .network
-            .send_compressed_post(&token, "/home", &message)
-            .await;
+        let start = Instant::now();
+        let result = match &message {
+            Some(message) => {
+                self.network
+                    .send_compressed_post(&token, "/home", message)
+                    .await
+            }
+            None => self.network.send_heartbeat(&token, "/home").await,
+        };
+        self.device.posted(
+            "home",
+            start.elapsed(),
+            result.as_ref().ok().map(|(status, _)| status.as_u16()),
+        );
 
         match result {
             Ok((status_code, response)) => match status_code {
                 StatusCode::OK => {
                     info!("Posting successful");
+                    if let Some(system_info) = carried_system_info {
+                        if self.latest_system_info.as_ref() == Some(&system_info) {
+                            self.latest_system_info = None;
+                        }
+                        self.system_info = Some(system_info);
+                    }
+                    if let Some(plugins) = carried_plugins {
+                        self.plugins = Some(plugins);
+                    }
+                    if let Some(sequence) = self.pending_ota.take() {
+                        self.device.ota_reported(sequence).await;
+                    }
+                    if let Some(sequence) = self.pending_self_update.take() {
+                        if let Err(err) = self.device.self_update_reported(sequence).await {
+                            error!("Failed to save self-update state: {}", err);
+                        }
+                    }
+                    if !self.heartbeat_written {
+                        match self.device.reached_api().await {
+                            Ok(()) => self.heartbeat_written = true,
+                            Err(err) => error!("Failed to write heartbeat: {}", err),
+                        }
+                    }
                     if let Some(problem) = self.problems {
-                        self.police.report_problem_solved(problem).await;
+                        self.device.problem_solved(problem).await;
                         self.problems = None;
                     };
-                    response.json().await.unwrap_or_default()
+                    self.reported = Some(reported);
+                    let response = self.decode(response).await;
+                    self.api_protocol_version = response.protocol_version;
+                    response
                 }
                 StatusCode::UNAUTHORIZED => {
                     warn!("Token expired, we are going to delete the token");
@@ -184,6 +401,9 @@ impl Postman {
                         "Posting failed with status: {:?} {:?}",
                         status_code, response
                     );
+                    // the API may have been rolled back, the next ping
+                    // reports everything
+                    self.renegotiate();
                     HomePostResponse::default()
                 }
             },
@@ -195,14 +415,45 @@ impl Postman {
                     e = src;
                 }
                 error!("POST FAILURE: {}", s);
+                self.renegotiate();
                 if self.problems.is_none() {
-                    self.problems = self.police.report_problem_starting().await;
+                    self.problems = self.device.problem_starting().await;
                 }
                 HomePostResponse::default()
             }
         }
     }
 
+    /// The API's answer, commands this smithd doesn't know are answered as
+    /// unsupported instead of costing it the whole batch.
+    async fn decode(&self, response: Response) -> HomePostResponse {
+        let decoded = match network::decode::<Value>(response).await {
+            Ok(value) => HomePostResponse::decode(value).map_err(anyhow::Error::from),
+            Err(err) => Err(err),
+        };
+
+        match decoded {
+            Ok((home, undecoded)) => {
+                if !undecoded.is_empty() {
+                    warn!("Received {} commands we don't know", undecoded.len());
+                    self.device
+                        .insert_results(
+                            undecoded
+                                .into_iter()
+                                .map(UndecodedCommand::response)
+                                .collect(),
+                        )
+                        .await;
+                }
+                home
+            }
+            Err(err) => {
+                error!("Failed to decode the response of the API: {:?}", err);
+                HomePostResponse::default()
+            }
+        }
+    }
+
     async fn register_device(
         &mut self,
         message: DeviceRegistration,
@@ -211,14 +462,22 @@ impl Postman {
 
         let token = self.token.clone().unwrap_or_default();
 
-        self.network
+        let start = Instant::now();
+        let result = self
+            .network
             .send_compressed_post(&token, &url, &message)
-            .await
+            .await;
+        self.device.posted(
+            "register",
+            start.elapsed(),
+            result.as_ref().ok().map(|(status, _)| status.as_u16()),
+        );
+        result
     }
 
     async fn unregister_device(&mut self) {
         self.token = None;
-        self.magic.delete_token().await;
+        self.device.set_token(None).await;
     }
 }
 
@@ -228,14 +487,34 @@ pub struct PostmanHandle {
 }
 
 impl PostmanHandle {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         shutdown: ShutdownSignals,
         police: PoliceHandle,
         commander: CommanderHandle,
         magic: MagicHandle,
+        tunnel: TunnelHandle,
+        ota: OtaHandle,
+        restart: RestartHandle,
+        host: Arc<dyn Host>,
     ) -> Self {
+        let device = Actors {
+            police,
+            commander,
+            magic,
+            tunnel,
+            ota,
+            restart,
+            network: NetworkClient::default(),
+            host,
+        };
+        Self::spawn(shutdown, Arc::new(device))
+    }
+
+    /// Runs the postman for any device, like the simulated ones of smith-sim.
+    pub fn spawn(shutdown: ShutdownSignals, device: Arc<dyn Device>) -> Self {
         let (_sender, receiver) = mpsc::channel(8);
-        let mut actor = Postman::new(shutdown, police, receiver, commander, magic);
+        let mut actor = Postman::new(shutdown, receiver, device);
         tokio::spawn(async move { actor.run().await });
 
         Self { _sender }
@@ -0,0 +1,13 @@
+use zbus::{Result, proxy};
+
+/// An inhibitor lock as listed by logind: what, who, why, mode, uid and pid.
+pub(super) type Inhibitor = (String, String, String, String, u32, u32);
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+pub(super) trait Login {
+    fn list_inhibitors(&self) -> Result<Vec<Inhibitor>>;
+}
@@ -0,0 +1,334 @@
+use crate::dbus::DbusHandle;
+use crate::magic::MagicHandle;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::host::{Host, HostCommand};
+use crate::utils::schema::{PendingRestart, RestartAt};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local, NaiveTime, TimeZone, Utc};
+use logind::LoginProxy;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant};
+use tracing::{error, info, warn};
+
+mod logind;
+
+/// Used when a restart doesn't say when, like `shutdown -r +1` did.
+const DEFAULT_DELAY: u64 = 60;
+/// How often the schedule, and then the inhibitors, are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// How long apps may hold back a due restart.
+const INHIBITOR_DEADLINE: Duration = Duration::from_secs(10 * 60);
+
+enum RestartMessage {
+    Schedule {
+        at: Option<RestartAt>,
+        reason: Option<String>,
+        force: bool,
+        rpc: oneshot::Sender<Result<PendingRestart>>,
+    },
+    Cancel {
+        rpc: oneshot::Sender<Option<PendingRestart>>,
+    },
+    Pending {
+        rpc: oneshot::Sender<Option<PendingRestart>>,
+    },
+}
+
+struct Restarter {
+    shutdown: ShutdownSignals,
+    receiver: mpsc::Receiver<RestartMessage>,
+    magic: MagicHandle,
+    dbus: DbusHandle,
+    host: Arc<dyn Host>,
+    /// Kept in the state file as well, so it survives smithd restarting.
+    pending: Option<PendingRestart>,
+    /// Set once the restart is due, apps holding an inhibitor are waited for until then.
+    deadline: Option<Instant>,
+}
+
+impl Restarter {
+    fn new(
+        shutdown: ShutdownSignals,
+        receiver: mpsc::Receiver<RestartMessage>,
+        magic: MagicHandle,
+        dbus: DbusHandle,
+        host: Arc<dyn Host>,
+    ) -> Self {
+        Self {
+            shutdown,
+            receiver,
+            magic,
+            dbus,
+            host,
+            pending: None,
+            deadline: None,
+        }
+    }
+
+    async fn handle_message(&mut self, msg: RestartMessage) {
+        match msg {
+            RestartMessage::Schedule {
+                at,
+                reason,
+                force,
+                rpc,
+            } => {
+                let result = resolve(at.as_ref(), Local::now()).map(|at| PendingRestart {
+                    at,
+                    reason,
+                    force,
+                });
+                if let Ok(pending) = &result {
+                    info!("Restart scheduled for {}", pending.at);
+                    self.dbus.restart_scheduled(pending).await;
+                    self.set_pending(Some(pending.clone())).await;
+                    self.deadline = None;
+                }
+                _ = rpc.send(result);
+            }
+            RestartMessage::Cancel { rpc } => {
+                let cancelled = self.pending.clone();
+                self.set_pending(None).await;
+                self.deadline = None;
+                if cancelled.is_some() {
+                    info!("Restart cancelled");
+                    self.dbus.restart_cancelled().await;
+                }
+                _ = rpc.send(cancelled);
+            }
+            RestartMessage::Pending { rpc } => {
+                _ = rpc.send(self.pending.clone());
+            }
+        }
+    }
+
+    async fn set_pending(&mut self, pending: Option<PendingRestart>) {
+        self.magic.set_pending_restart(pending.clone()).await;
+        self.pending = pending;
+    }
+
+    /// Picks up the restart pending before smithd stopped. One that was due
+    /// already is dropped, the device just came up or smithd would have
+    /// restarted it.
+    async fn resume(&mut self) {
+        let Some(pending) = self.magic.get_pending_restart().await else {
+            return;
+        };
+
+        if pending.at <= Utc::now() {
+            info!("Dropping the restart that was due at {}", pending.at);
+            self.set_pending(None).await;
+        } else {
+            info!("Restart still scheduled for {}", pending.at);
+            self.dbus.restart_scheduled(&pending).await;
+            self.pending = Some(pending);
+        }
+    }
+
+    async fn check(&mut self) {
+        let Some(pending) = &self.pending else {
+            return;
+        };
+        if Utc::now() < pending.at {
+            return;
+        }
+
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => {
+                let wait = if pending.force {
+                    Duration::ZERO
+                } else {
+                    INHIBITOR_DEADLINE
+                };
+                self.dbus.restart_imminent(pending, wait).await;
+                *self.deadline.insert(Instant::now() + wait)
+            }
+        };
+
+        if Instant::now() < deadline {
+            match inhibitors().await {
+                Ok(inhibitors) if !inhibitors.is_empty() => {
+                    info!("Restart held back by {}", inhibitors.join(", "));
+                    return;
+                }
+                Ok(_) => {}
+                Err(err) => warn!("Failed to list inhibitors: {}", err),
+            }
+        }
+
+        warn!(
+            "Restarting now: {}",
+            pending.reason.as_deref().unwrap_or("-")
+        );
+        // cleared first, the device must not restart again once it's back
+        self.set_pending(None).await;
+        self.deadline = None;
+        match self.host.output(HostCommand::new("reboot")).await {
+            Ok(output) if !output.status.success() => error!(
+                "Failed to restart: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Ok(_) => {}
+            Err(err) => error!("Failed to restart: {}", err),
+        }
+    }
+
+    async fn run(&mut self) {
+        info!("Restart task is runnning");
+
+        self.resume().await;
+
+        let mut check_interval = time::interval(CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                Some(msg) = self.receiver.recv() => {
+                    self.handle_message(msg).await;
+                }
+                _ = check_interval.tick() => {
+                    self.check().await;
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+
+        info!("Restart task shutting down");
+    }
+}
+
+/// When to restart, `now` being in the device's time zone.
+fn resolve<Tz: TimeZone>(at: Option<&RestartAt>, now: DateTime<Tz>) -> Result<DateTime<Utc>> {
+    match at {
+        None => Ok(now.to_utc() + Duration::from_secs(DEFAULT_DELAY)),
+        Some(RestartAt::In(seconds)) => Ok(now.to_utc() + Duration::from_secs(*seconds)),
+        Some(RestartAt::Time(at)) => {
+            if *at < now.to_utc() {
+                return Err(anyhow!("{} has already passed", at));
+            }
+            Ok(*at)
+        }
+        Some(RestartAt::LocalTime(time)) => {
+            let time = NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|err| anyhow!("Invalid time {}, expected HH:MM: {}", time, err))?;
+            let mut at = now.date_naive().and_time(time);
+            if at <= now.naive_local() {
+                at += chrono::TimeDelta::days(1);
+            }
+
+            // a time skipped by daylight saving happens an hour later
+            let zone = now.timezone();
+            zone.from_local_datetime(&at)
+                .earliest()
+                .or_else(|| {
+                    zone.from_local_datetime(&(at + chrono::TimeDelta::hours(1)))
+                        .earliest()
+                })
+                .map(|at| at.to_utc())
+                .ok_or_else(|| anyhow!("{} doesn't exist in the local time zone", at))
+        }
+    }
+}
+
+/// Apps blocking shutdown, as `who (why)`.
+async fn inhibitors() -> Result<Vec<String>> {
+    let connection = zbus::Connection::system().await?;
+    let login = LoginProxy::new(&connection).await?;
+
+    Ok(login
+        .list_inhibitors()
+        .await?
+        .into_iter()
+        .filter(|(what, _, _, mode, _, _)| {
+            mode == "block" && what.split(':').any(|what| what == "shutdown")
+        })
+        .map(|(_, who, why, _, _, _)| format!("{} ({})", who, why))
+        .collect())
+}
+
+#[derive(Clone)]
+pub struct RestartHandle {
+    sender: mpsc::Sender<RestartMessage>,
+}
+
+impl RestartHandle {
+    pub fn new(
+        shutdown: ShutdownSignals,
+        magic: MagicHandle,
+        dbus: DbusHandle,
+        host: Arc<dyn Host>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(8);
+        let mut actor = Restarter::new(shutdown, receiver, magic, dbus, host);
+        tokio::spawn(async move { actor.run().await });
+
+        Self { sender }
+    }
+
+    pub async fn schedule(
+        &self,
+        at: Option<RestartAt>,
+        reason: Option<String>,
+        force: bool,
+    ) -> Result<PendingRestart> {
+        let (rpc, receiver) = oneshot::channel();
+        self.sender
+            .send(RestartMessage::Schedule {
+                at,
+                reason,
+                force,
+                rpc,
+            })
+            .await?;
+        receiver.await?
+    }
+
+    /// The restart that was cancelled, if one was pending.
+    pub async fn cancel(&self) -> Option<PendingRestart> {
+        let (rpc, receiver) = oneshot::channel();
+        _ = self.sender.send(RestartMessage::Cancel { rpc }).await;
+        receiver.await.ok().flatten()
+    }
+
+    pub async fn pending(&self) -> Option<PendingRestart> {
+        let (rpc, receiver) = oneshot::channel();
+        _ = self.sender.send(RestartMessage::Pending { rpc }).await;
+        receiver.await.ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn resolves_local_time() {
+        let zone = FixedOffset::east_opt(2 * 3600).unwrap();
+        let now = zone.with_ymd_and_hms(2025, 6, 30, 14, 0, 0).unwrap();
+        let at = |at: RestartAt| resolve(Some(&at), now).unwrap().to_rfc3339();
+
+        assert_eq!(
+            at(RestartAt::LocalTime("03:00".to_string())),
+            "2025-07-01T01:00:00+00:00"
+        );
+        assert_eq!(
+            at(RestartAt::LocalTime("15:30".to_string())),
+            "2025-06-30T13:30:00+00:00"
+        );
+        assert_eq!(at(RestartAt::In(90)), "2025-06-30T12:01:30+00:00");
+
+        assert!(resolve(Some(&RestartAt::LocalTime("3am".to_string())), now).is_err());
+        assert!(
+            resolve(
+                Some(&RestartAt::Time(now.to_utc() - Duration::from_secs(1))),
+                now
+            )
+            .is_err()
+        );
+    }
+}
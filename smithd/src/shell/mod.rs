@@ -0,0 +1,126 @@
+use crate::magic::MagicHandle;
+use crate::shutdown::ShutdownSignals;
+use anyhow::{Result, anyhow};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+mod session;
+
+const MAX_SESSIONS: usize = 4;
+
+struct Shell {
+    shutdown: ShutdownSignals,
+    receiver: mpsc::Receiver<ShellMessage>,
+    magic: MagicHandle,
+    sessions: JoinSet<()>,
+}
+
+enum ShellMessage {
+    Open {
+        session: String,
+        cols: u16,
+        rows: u16,
+        rpc: oneshot::Sender<Result<()>>,
+    },
+}
+
+impl Shell {
+    fn new(
+        shutdown: ShutdownSignals,
+        receiver: mpsc::Receiver<ShellMessage>,
+        magic: MagicHandle,
+    ) -> Self {
+        Self {
+            shutdown,
+            receiver,
+            magic,
+            sessions: JoinSet::new(),
+        }
+    }
+
+    async fn handle_message(&mut self, msg: ShellMessage) {
+        match msg {
+            ShellMessage::Open {
+                session,
+                cols,
+                rows,
+                rpc,
+            } => {
+                _ = rpc.send(self.open(session, cols, rows).await);
+            }
+        }
+    }
+
+    async fn open(&mut self, session: String, cols: u16, rows: u16) -> Result<()> {
+        // forget about sessions that already ended
+        while self.sessions.try_join_next().is_some() {}
+
+        if self.sessions.len() >= MAX_SESSIONS {
+            return Err(anyhow!("Too many shell sessions open"));
+        }
+
+        let server = self.magic.get_server().await;
+        let token = self
+            .magic
+            .get_token()
+            .await
+            .ok_or_else(|| anyhow!("Device is not registered"))?;
+
+        let session = session::Session::start(&server, &token, session, cols, rows).await?;
+        self.sessions.spawn(session.relay());
+
+        Ok(())
+    }
+
+    async fn run(&mut self) {
+        info!("Shell task is runnning");
+
+        loop {
+            tokio::select! {
+                Some(msg) = self.receiver.recv() => {
+                    self.handle_message(msg).await;
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+
+        // dropping the sessions kills the shells
+        self.sessions.shutdown().await;
+
+        info!("Shell task shutting down");
+    }
+}
+
+#[derive(Clone)]
+pub struct ShellHandle {
+    sender: mpsc::Sender<ShellMessage>,
+}
+
+impl ShellHandle {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+        let (sender, receiver) = mpsc::channel(8);
+        let mut actor = Shell::new(shutdown, receiver, magic);
+        tokio::spawn(async move { actor.run().await });
+
+        Self { sender }
+    }
+
+    /// Spawns a login shell and attaches it to the relay for `session` on the API.
+    pub async fn open(&self, session: String, cols: u16, rows: u16) -> Result<()> {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = ShellMessage::Open {
+            session,
+            cols,
+            rows,
+            rpc,
+        };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_else(|err| {
+            error!("Shell task did not answer: {}", err);
+            Err(anyhow!("Shell task is not running"))
+        })
+    }
+}
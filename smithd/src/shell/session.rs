@@ -0,0 +1,130 @@
+use crate::utils::schema::ShellControl;
+use anyhow::Result;
+use futures_util::{SinkExt, StreamExt};
+use pty_process::{OwnedReadPty, OwnedWritePty, Size};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Child;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::AUTHORIZATION;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use tracing::{info, warn};
+
+const SHELL: &str = "/bin/bash";
+
+/// A login shell running in a pty, attached to the relay websocket on the API.
+pub(super) struct Session {
+    id: String,
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    child: Child,
+    reader: OwnedReadPty,
+    writer: OwnedWritePty,
+}
+
+impl Session {
+    pub(super) async fn start(
+        server: &str,
+        token: &str,
+        id: String,
+        cols: u16,
+        rows: u16,
+    ) -> Result<Self> {
+        // https://api/smith -> wss://api/smith/shell/<id>
+        let url = format!("{}/shell/{}", server.replacen("http", "ws", 1), id);
+        let mut request = url.into_client_request()?;
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {token}").parse()?);
+
+        let (socket, _) = connect_async(request).await?;
+
+        let (pty, pts) = pty_process::open()?;
+        pty.resize(Size::new(rows, cols))?;
+        let child = pty_process::Command::new(SHELL)
+            .arg("--login")
+            .env("TERM", "xterm-256color")
+            .current_dir("/root")
+            .kill_on_drop(true)
+            .spawn(pts)?;
+        let (reader, writer) = pty.into_split();
+
+        info!("Shell session {} started", id);
+
+        Ok(Self {
+            id,
+            socket,
+            child,
+            reader,
+            writer,
+        })
+    }
+
+    /// Relays terminal data until either the shell exits or the other end goes away.
+    pub(super) async fn relay(mut self) {
+        let code = match self.pump().await {
+            Ok(code) => code,
+            Err(err) => {
+                warn!("Shell session {} failed: {}", self.id, err);
+                None
+            }
+        };
+
+        let code = match code {
+            Some(code) => code,
+            None => {
+                _ = self.child.start_kill();
+                self.child
+                    .wait()
+                    .await
+                    .ok()
+                    .and_then(|status| status.code())
+                    .unwrap_or(-1)
+            }
+        };
+
+        if let Ok(exit) = serde_json::to_string(&ShellControl::Exit { code }) {
+            _ = self.socket.send(Message::Text(exit)).await;
+        }
+        _ = self.socket.close(None).await;
+
+        info!("Shell session {} ended with code {}", self.id, code);
+    }
+
+    /// Returns the exit code if the shell exited on its own.
+    async fn pump(&mut self) -> Result<Option<i32>> {
+        let mut buf = vec![0; 4096];
+
+        loop {
+            tokio::select! {
+                read = self.reader.read(&mut buf) => {
+                    match read {
+                        Ok(n) if n > 0 => {
+                            self.socket.send(Message::Binary(buf[..n].to_vec())).await?;
+                        }
+                        // reading from the pty fails once the shell is gone
+                        _ => {
+                            let status = self.child.wait().await?;
+                            return Ok(Some(status.code().unwrap_or(-1)));
+                        }
+                    }
+                }
+                msg = self.socket.next() => {
+                    match msg {
+                        Some(Ok(Message::Binary(data))) => {
+                            self.writer.write_all(&data).await?;
+                        }
+                        Some(Ok(Message::Text(text))) => {
+                            if let Ok(ShellControl::Resize { cols, rows }) = serde_json::from_str(&text) {
+                                self.writer.resize(Size::new(rows, cols))?;
+                            }
+                        }
+                        Some(Ok(Message::Close(_))) | None => return Ok(None),
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => return Err(err.into()),
+                    }
+                }
+            }
+        }
+    }
+}
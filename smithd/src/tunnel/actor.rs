@@ -1,13 +1,17 @@
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::Tunnel;
 use bore_cli::client::Client;
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{self, Duration};
 use tracing::{error, info};
 
 struct ForwardConnection {
-    created_at: time::Instant,
+    opened_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    requested_by: Option<String>,
     remote: u16,
     task: tokio::task::JoinHandle<()>,
 }
@@ -15,10 +19,16 @@ struct ForwardConnection {
 pub enum ActorMessage {
     ForwardPort {
         local: u16,
+        ttl: Duration,
+        requested_by: Option<String>,
         remote: oneshot::Sender<u16>,
     },
     ClosePort {
         local: u16,
+        closed: oneshot::Sender<bool>,
+    },
+    ListPorts {
+        tunnels: oneshot::Sender<Vec<Tunnel>>,
     },
 }
 
@@ -46,12 +56,23 @@ impl Actor {
 
     async fn handle_message(&mut self, msg: ActorMessage, server: &str, secret: &str) {
         match msg {
-            ActorMessage::ForwardPort { local, remote } => {
+            ActorMessage::ForwardPort {
+                local,
+                ttl,
+                requested_by,
+                remote,
+            } => {
+                let expires_at = Utc::now() + ttl;
+
                 // check if there is already a ForwardConnection for this port
-                if self.ports.contains_key(&local) {
+                if let Some(conn) = self.ports.get_mut(&local) {
                     error!("Port {} is already forwarded", local);
-                    let remote_port = self.ports.get(&local).unwrap().remote;
-                    remote.send(remote_port).unwrap();
+                    // asking again for the same port keeps it open for at least the new ttl
+                    if expires_at > conn.expires_at {
+                        conn.expires_at = expires_at;
+                        conn.requested_by = requested_by;
+                    }
+                    _ = remote.send(conn.remote);
                     return;
                 }
 
@@ -87,25 +108,46 @@ impl Actor {
                     ForwardConnection {
                         remote: port,
                         task: handle,
-                        created_at: time::Instant::now(),
+                        opened_at: Utc::now(),
+                        expires_at,
+                        requested_by,
                     },
                 );
             }
-            ActorMessage::ClosePort { local } => {
-                if let Some(conn) = self.ports.remove(&local) {
+            ActorMessage::ClosePort { local, closed } => {
+                let conn = self.ports.remove(&local);
+                if let Some(conn) = &conn {
+                    info!("Closing port {}", local);
                     conn.task.abort();
                 }
+                _ = closed.send(conn.is_some());
+            }
+            ActorMessage::ListPorts { tunnels } => {
+                // forget about connections that were dropped by the server
+                self.ports.retain(|_, conn| !conn.task.is_finished());
+
+                let list = self
+                    .ports
+                    .iter()
+                    .map(|(local, conn)| Tunnel {
+                        local_port: *local,
+                        remote_port: conn.remote,
+                        opened_at: conn.opened_at,
+                        expires_at: conn.expires_at,
+                        requested_by: conn.requested_by.clone(),
+                    })
+                    .collect();
+                _ = tunnels.send(list);
             }
         }
     }
 
     async fn timeout_old_tunnels(&mut self) {
-        let now = time::Instant::now();
+        let now = Utc::now();
         let mut to_remove = Vec::new();
-        let timeout_duration = Duration::from_secs(60 * 30);
 
         for (port, conn) in &self.ports {
-            if now.duration_since(conn.created_at) > timeout_duration {
+            if now > conn.expires_at {
                 to_remove.push(*port);
             }
         }
@@ -123,8 +165,8 @@ impl Actor {
 
         let details = self.magic.get_tunnel_details().await;
 
-        // check tunnels still open every 10 minutes
-        let mut timeout_tunnels = time::interval(Duration::from_secs(60 * 10));
+        // check for expired tunnels every minute
+        let mut timeout_tunnels = time::interval(Duration::from_secs(60));
         timeout_tunnels.tick().await;
 
         loop {
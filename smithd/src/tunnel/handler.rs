@@ -1,7 +1,12 @@
 use super::actor::{Actor, ActorMessage};
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::Tunnel;
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::Duration;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(60 * 30);
+const MAX_TTL: Duration = Duration::from_secs(60 * 60 * 24);
 
 #[derive(Clone)]
 pub struct Handler {
@@ -17,20 +22,43 @@ impl Handler {
         Self { sender }
     }
 
-    pub async fn start_tunnel(&self, port: Option<u16>) -> u16 {
+    /// Forwards `port` (ssh by default) and returns the remote port, or 0 on failure.
+    /// The tunnel is closed after `ttl`, 30 minutes if not given and at most a day.
+    pub async fn start_tunnel(
+        &self,
+        port: Option<u16>,
+        ttl: Option<Duration>,
+        requested_by: Option<String>,
+    ) -> u16 {
         let local = port.unwrap_or(22);
+        let ttl = ttl.unwrap_or(DEFAULT_TTL).min(MAX_TTL);
         let (sender, receiver) = oneshot::channel();
         let msg = ActorMessage::ForwardPort {
             local,
+            ttl,
+            requested_by,
             remote: sender,
         };
         _ = self.sender.send(msg).await;
-        receiver.await.unwrap()
+        receiver.await.unwrap_or_default()
     }
 
-    pub async fn stop_ssh_tunnel(&self) {
-        let local = 22;
-        let msg = ActorMessage::ClosePort { local };
+    /// Closes the tunnel for `port` (ssh by default), returns false if none was open.
+    pub async fn stop_tunnel(&self, port: Option<u16>) -> bool {
+        let local = port.unwrap_or(22);
+        let (sender, receiver) = oneshot::channel();
+        let msg = ActorMessage::ClosePort {
+            local,
+            closed: sender,
+        };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    pub async fn list_tunnels(&self) -> Vec<Tunnel> {
+        let (sender, receiver) = oneshot::channel();
+        let msg = ActorMessage::ListPorts { tunnels: sender };
         _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
     }
 }
@@ -57,10 +57,19 @@ secret = "{random_secret}"
 
     let shutdown = ShutdownHandler::new();
     let configuration = MagicHandle::new(shutdown.signals());
-    configuration.load(Some(path)).await;
+    configuration.load(Some(path)).await.unwrap();
     let tunnel = super::TunnelHandle::new(shutdown.signals(), configuration);
 
-    let resp = tunnel.start_tunnel(Some(local_port)).await;
+    let resp = tunnel.start_tunnel(Some(local_port), None, None).await;
 
     assert_ne!(resp, 0);
+
+    let tunnels = tunnel.list_tunnels().await;
+    assert_eq!(tunnels.len(), 1);
+    assert_eq!(tunnels[0].local_port, local_port);
+    assert_eq!(tunnels[0].remote_port, resp);
+    assert!(tunnels[0].expires_at > tunnels[0].opened_at);
+
+    assert!(tunnel.stop_tunnel(Some(local_port)).await);
+    assert!(tunnel.list_tunnels().await.is_empty());
 }
@@ -1,9 +1,14 @@
+use super::{delta, install};
+use crate::downloader::DownloaderHandle;
 use crate::magic::MagicHandle;
+use crate::magic::structure::ConfigPackage;
 use crate::shutdown::{ShutdownHandler, ShutdownSignals};
+use crate::utils::host::{Host, HostCommand};
 use crate::utils::network::NetworkClient;
+use crate::utils::schema::ReleasePackage;
 use anyhow::Context;
 use anyhow::Result;
-use tokio::process::Command;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 use tokio::time;
@@ -28,6 +33,8 @@ pub struct Actor {
     shutdown: ShutdownSignals,
     receiver: mpsc::Receiver<ActorMessage>,
     magic: MagicHandle,
+    downloader: DownloaderHandle,
+    host: Arc<dyn Host>,
     status: Status,
     network: NetworkClient,
     last_update: Option<Result<time::Instant>>,
@@ -39,12 +46,16 @@ impl Actor {
         shutdown: ShutdownSignals,
         receiver: mpsc::Receiver<ActorMessage>,
         magic: MagicHandle,
+        downloader: DownloaderHandle,
+        host: Arc<dyn Host>,
     ) -> Self {
         let network = NetworkClient::new();
         Self {
             shutdown,
             receiver,
             magic,
+            downloader,
+            host,
             network,
             status: Status::Idle,
             last_update: None,
@@ -144,10 +155,8 @@ impl Actor {
 
     async fn check_for_updates(&self) -> Result<()> {
         // apt update on check for updates
-        Command::new("sh")
-            .arg("-c")
-            .arg("apt update -y")
-            .output()
+        self.host
+            .output(HostCommand::shell("apt update -y"))
             .await
             .with_context(|| "Failed to run apt update")?;
 
@@ -182,39 +191,60 @@ impl Actor {
         for package in target_packages.iter() {
             info!(
                 "Remote: {} {} {}",
-                package.name, package.version, package.file
+                package.package.name, package.package.version, package.package.file
             );
         }
 
         let mut up_to_date = true;
         // compare the packages and check if we need to update
-        for target_package in target_packages.iter() {
-            let package_not_on_magic_file = !local_packages.contains(target_package);
-            let package_not_installed = tokio::process::Command::new("dpkg")
-                .arg("-l")
-                .arg(&target_package.name)
-                .output()
-                .await
-                .map(|output| !output.status.success())
-                .unwrap_or(true);
+        for target in target_packages.iter() {
+            let target_package = ConfigPackage::from(target);
+            let package_not_on_magic_file = !local_packages.contains(&target_package);
+            let package_not_installed =
+                install::installed_version(self.host.as_ref(), &target_package.name)
+                    .await
+                    .is_none();
 
             if package_not_on_magic_file || package_not_installed {
                 info!("Package {} is not installed", target_package.name);
                 up_to_date = false;
                 // we need to install the package
-                self.network
-                    .get_package(&target_package.file, &token)
-                    .await?;
+                self.fetch_package(target, &local_packages, &token).await?;
             }
         }
 
         if !up_to_date {
-            self.magic.set_packages(target_packages).await;
+            self.magic
+                .set_packages(target_packages.iter().map(ConfigPackage::from).collect())
+                .await;
         }
 
         Ok(())
     }
 
+    /// Rebuilds the package from a delta when the API has one for the
+    /// version on the device, downloads all of it otherwise.
+    async fn fetch_package(
+        &self,
+        target: &ReleasePackage,
+        local_packages: &[ConfigPackage],
+        token: &str,
+    ) -> Result<()> {
+        let bandwidth = self.downloader.bandwidth();
+        match delta::rebuild(&self.network, target, local_packages, token, &bandwidth).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(err) => warn!(
+                "Failed to rebuild {} from a delta, downloading it instead: {:?}",
+                target.package.name, err
+            ),
+        }
+
+        self.network
+            .get_package(&target.package.file, token, &bandwidth)
+            .await
+    }
+
     async fn upgrade_device(&self) -> Result<()> {
         // Check if previous update was successful
         match self.last_update {
@@ -235,112 +265,14 @@ impl Actor {
         }
 
         let packages_from_magic = self.magic.get_packages().await;
+        let packages_folder = std::env::current_dir()?.join("packages");
+        let update_smith =
+            install::install(self.host.as_ref(), &packages_folder, &packages_from_magic).await?;
 
-        // check if all packages are available locally
-        for package in packages_from_magic.iter() {
-            info!("Checking package: {}", package.name);
-            let package_name = &package.name;
-            let package_file = &package.file;
-
-            // check if package is available locally
-            let path = std::env::current_dir()?;
-            let packages_folder = path.join("packages");
-            let package_file = packages_folder.join(package_file);
-
-            if package_file.exists() {
-                info!("Package {} exists locally", package_name);
-                continue;
-            } else {
-                info!("Package {} does not exist locally", package_name);
-                return Err(anyhow::anyhow!(
-                    "Package {} does not exist locally",
-                    package_name
-                ));
-            }
-        }
-
-        // now install packages
-        let mut update_smith = false;
-        for package in packages_from_magic.into_iter() {
-            let package_name = package.name;
-            let package_file = package.file;
-            let package_version = package.version;
-
-            let path = std::env::current_dir()?;
-            let packages_folder = path.join("packages");
-            let package_file = packages_folder.join(&package_file);
-
-            // check if version on system is the one we should be running
-            let output = match Command::new("dpkg")
-                .arg("-l")
-                .arg(&package_name)
-                .output()
-                .await
-            {
-                Ok(output) => output,
-                Err(e) => {
-                    error!("Failed to execute dpkg command for {}: {}", package_name, e);
-                    continue;
-                }
-            };
-
-            let mut package_installed = false;
-            if output.status.success() {
-                if let Ok(stdout) = String::from_utf8(output.stdout) {
-                    let lines: Vec<&str> = stdout.lines().collect();
-                    if let Some(package_info) = lines.get(5) {
-                        let fields: Vec<&str> = package_info.split_whitespace().collect();
-                        if let Some(version) = fields.get(2) {
-                            info!("> {} | {} => {}", package_name, version, &package_version);
-                            package_installed = version == &package_version;
-                        } else {
-                            error!("Failed to get version for package {}", package_name);
-                        }
-                    } else {
-                        error!("Failed to get package info for {}", package_name);
-                    }
-                } else {
-                    error!("Failed to parse dpkg output for {}", package_name);
-                }
-            }
-
-            if !package_installed {
-                if package_name == "smith" || package_name == "smith_amd64" {
-                    update_smith = true;
-                    continue;
-                }
-                let install_command = format!(
-                    "sudo apt install {} -y --allow-downgrades",
-                    package_file.display()
-                );
-                match Command::new("sh")
-                    .arg("-c")
-                    .arg(&install_command)
-                    .output()
-                    .await
-                {
-                    Ok(status) => {
-                        if status.status.success() {
-                            info!("Successfully installed package {}", package_name);
-                        } else {
-                            let stderr = String::from_utf8_lossy(&status.stderr);
-                            error!("Failed to install package {}: {}", package_name, stderr);
-                        }
-                    }
-                    Err(e) => {
-                        error!(
-                            "Failed to execute install command for {}: {}",
-                            package_name, e
-                        );
-                    }
-                }
-            }
-        }
         if update_smith {
-            let status = Command::new("sh")
-                .arg("-c")
-                .arg("sudo systemctl start smith-updater")
-                .output()
+            let status = self
+                .host
+                .output(HostCommand::shell("sudo systemctl start smith-updater"))
                 .await
                 .with_context(|| "Failed to stop smith service")?;
 
@@ -358,24 +290,11 @@ impl Actor {
     async fn are_packages_up_to_date(&self) -> Result<()> {
         let shutdown = ShutdownHandler::new();
         let configuration = MagicHandle::new(shutdown.signals());
-        configuration.load(None).await;
+        configuration.load(None).await?;
 
         let magic_packages = configuration.get_packages().await;
 
-        // check the system version of the packages in the magic file
-        for package in magic_packages {
-            let installed_version = package.get_system_version().await?;
-            let magic_toml_version = package.version;
-
-            if magic_toml_version != installed_version {
-                return Err(anyhow::anyhow!(
-                    "Package {} is not up to date",
-                    package.name
-                ));
-            }
-        }
-
-        Ok(())
+        install::up_to_date(self.host.as_ref(), &magic_packages).await
     }
 
     pub async fn run(&mut self) {
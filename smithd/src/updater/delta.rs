@@ -0,0 +1,141 @@
+use crate::downloader::Bandwidth;
+use crate::magic::state::{WriteOptions, replace};
+use crate::magic::structure::ConfigPackage;
+use crate::utils::network::NetworkClient;
+use crate::utils::schema::ReleasePackage;
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Write};
+use tracing::info;
+
+/// zstd can't reach back further than 2 GiB.
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// Rebuilds the package from a version of it that is still in the packages
+/// folder, when the API has a delta from it. Returns whether it did.
+pub(super) async fn rebuild(
+    network: &NetworkClient,
+    target: &ReleasePackage,
+    local_packages: &[ConfigPackage],
+    token: &str,
+    bandwidth: &Bandwidth,
+) -> Result<bool> {
+    let Some(sha256) = &target.sha256 else {
+        return Ok(false);
+    };
+    if target.deltas.is_empty() {
+        return Ok(false);
+    }
+
+    let packages_folder = std::env::current_dir()?.join("packages");
+    let path = packages_folder.join(&target.package.file);
+    if path.exists() {
+        return Ok(false);
+    }
+
+    for base in local_packages
+        .iter()
+        .filter(|base| base.name == target.package.name)
+    {
+        let Ok(base_data) = tokio::fs::read(packages_folder.join(&base.file)).await else {
+            continue;
+        };
+        let (base_data, base_sha256) = tokio::task::spawn_blocking(move || {
+            let sha256 = format!("{:x}", Sha256::digest(&base_data));
+            (base_data, sha256)
+        })
+        .await?;
+        let Some(delta) = target.deltas.get(&base_sha256) else {
+            continue;
+        };
+
+        info!(
+            "Rebuilding {} {} from {} with a {} byte delta",
+            target.package.name, target.package.version, delta.base_version, delta.size
+        );
+        let patch = network.get_delta(&delta.file, token, bandwidth).await?;
+
+        let expected = sha256.clone();
+        tokio::task::spawn_blocking(move || {
+            replace(&path, WriteOptions::default(), |file| {
+                apply(&base_data, &patch, file, &expected)
+            })
+        })
+        .await??;
+
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Writes `base` patched by `patch` to `file`, checking it hashes to `sha256`.
+fn apply(base: &[u8], patch: &[u8], file: &mut File, sha256: &str) -> Result<()> {
+    let mut decoder = zstd::stream::Decoder::with_ref_prefix(patch, base)?;
+    decoder.window_log_max(MAX_WINDOW_LOG)?;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = decoder.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+
+    let rebuilt = format!("{:x}", hasher.finalize());
+    if rebuilt != sha256 {
+        return Err(anyhow!(
+            "Rebuilt package has SHA256 {}, expected {}",
+            rebuilt,
+            sha256
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut encoder = zstd::stream::Encoder::with_ref_prefix(Vec::new(), 19, base).unwrap();
+        encoder.long_distance_matching(true).unwrap();
+        encoder.write_all(target).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn applies_delta() {
+        let base: Vec<u8> = (0..200_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut target = base.clone();
+        target[4_000..4_100].fill(7);
+        let patch = diff(&base, &target);
+        assert!(patch.len() < target.len() / 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("package.deb");
+        let sha256 = format!("{:x}", Sha256::digest(&target));
+
+        replace(&output, WriteOptions::default(), |file| {
+            apply(&base, &patch, file, &sha256)
+        })
+        .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), target);
+
+        // a package that doesn't check out never replaces the one there
+        let wrong = format!("{:x}", Sha256::digest(&base));
+        assert!(
+            replace(&output, WriteOptions::default(), |file| {
+                apply(&base, &patch, file, &wrong)
+            })
+            .is_err()
+        );
+        assert_eq!(std::fs::read(&output).unwrap(), target);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,7 +1,10 @@
 use super::actor::Actor;
 use super::actor::ActorMessage;
+use crate::downloader::DownloaderHandle;
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::host::Host;
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 
 #[derive(Clone)]
@@ -10,9 +13,14 @@ pub struct Handler {
 }
 
 impl Handler {
-    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+    pub fn new(
+        shutdown: ShutdownSignals,
+        magic: MagicHandle,
+        downloader: DownloaderHandle,
+        host: Arc<dyn Host>,
+    ) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Actor::new(shutdown, receiver, magic);
+        let mut actor = Actor::new(shutdown, receiver, magic, downloader, host);
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
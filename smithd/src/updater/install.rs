@@ -0,0 +1,95 @@
+use crate::magic::structure::ConfigPackage;
+use crate::utils::host::{Host, HostCommand};
+use anyhow::{Result, anyhow};
+use std::path::Path;
+use tracing::{error, info};
+
+/// Packages smithd can't install itself, the updater does.
+const SMITH: [&str; 2] = ["smith", "smith_amd64"];
+
+/// Version of the package as `dpkg-query` shows it, `None` when it isn't
+/// installed.
+pub async fn installed_version(host: &dyn Host, name: &str) -> Option<String> {
+    let command = HostCommand::new("dpkg-query")
+        .args(["--show", "--showformat=${Version}"])
+        .arg(name);
+    let output = match host.output(command).await {
+        Ok(output) => output,
+        Err(err) => {
+            error!("Failed to execute dpkg-query for {}: {}", name, err);
+            return None;
+        }
+    };
+
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+/// Installs every package from `packages_dir` whose installed version
+/// differs. Returns whether smith itself is out of date, which is left to
+/// the updater.
+pub async fn install(
+    host: &dyn Host,
+    packages_dir: &Path,
+    packages: &[ConfigPackage],
+) -> Result<bool> {
+    // check if all packages are available locally
+    for package in packages {
+        if !packages_dir.join(&package.file).exists() {
+            info!("Package {} does not exist locally", package.name);
+            return Err(anyhow!("Package {} does not exist locally", package.name));
+        }
+    }
+
+    let mut update_smith = false;
+    for package in packages {
+        let installed = installed_version(host, &package.name).await;
+        info!(
+            "> {} | {} => {}",
+            package.name,
+            installed.as_deref().unwrap_or("none"),
+            package.version
+        );
+        if installed.as_deref() == Some(package.version.as_str()) {
+            continue;
+        }
+
+        if SMITH.contains(&package.name.as_str()) {
+            update_smith = true;
+            continue;
+        }
+
+        let install_command = format!(
+            "sudo apt install {} -y --allow-downgrades",
+            packages_dir.join(&package.file).display()
+        );
+        match host.output(HostCommand::shell(install_command)).await {
+            Ok(output) if output.status.success() => {
+                info!("Successfully installed package {}", package.name);
+            }
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                error!("Failed to install package {}: {}", package.name, stderr);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to execute install command for {}: {}",
+                    package.name, e
+                );
+            }
+        }
+    }
+
+    Ok(update_smith)
+}
+
+/// Errors unless every package is installed in its version.
+pub async fn up_to_date(host: &dyn Host, packages: &[ConfigPackage]) -> Result<()> {
+    for package in packages {
+        if installed_version(host, &package.name).await.as_deref() != Some(&package.version) {
+            return Err(anyhow!("Package {} is not up to date", package.name));
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,7 @@
 mod actor;
+mod delta;
 mod handler;
+pub mod install;
+pub mod self_update;
 
 pub use handler::Handler as UpdaterHandle;
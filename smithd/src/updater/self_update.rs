@@ -0,0 +1,169 @@
+use crate::magic::state::write_atomically;
+use crate::utils::schema::{SelfUpdateAttempt, SelfUpdatePhase};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use tracing::warn;
+
+const STATE_FILE: &str = "/var/lib/smith/self_update.json";
+/// Held by whoever changes the state, smithd and the updater both do.
+const LOCK_FILE: &str = "/var/lib/smith/self_update.lock";
+const HEARTBEAT_FILE: &str = "/var/lib/smith/heartbeat.json";
+/// The smith package that is known to work, to roll back to.
+pub const KEPT_PACKAGE: &str = "/var/lib/smith/smith.deb";
+
+/// Written by smithd once it reached the API, the updater waits for it
+/// before keeping a new version.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Heartbeat {
+    pub version: String,
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            at: chrono::Utc::now(),
+        }
+    }
+
+    pub fn read() -> Option<Self> {
+        let contents = std::fs::read_to_string(HEARTBEAT_FILE).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub async fn write(&self) -> Result<()> {
+        write_atomically(Path::new(HEARTBEAT_FILE), &serde_json::to_vec(self)?).await
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The last self-update as kept on disk. The updater moves it along,
+/// smithd only reports it once settled and marks it reported.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SelfUpdateState {
+    pub attempt: Option<SelfUpdateAttempt>,
+    /// Bumped on every change, the API has received up to `reported`.
+    pub sequence: u64,
+    pub reported: u64,
+}
+
+impl SelfUpdateState {
+    pub fn load() -> Self {
+        let Ok(contents) = std::fs::read_to_string(STATE_FILE) else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable self-update state: {}", err);
+            Self::default()
+        })
+    }
+
+    async fn save(&self) -> Result<()> {
+        write_atomically(Path::new(STATE_FILE), &serde_json::to_vec_pretty(self)?).await
+    }
+
+    /// Applies the change to the state on disk, locked so a change of the
+    /// other process in between isn't lost.
+    pub async fn update<T>(change: impl FnOnce(&mut Self) -> T) -> Result<T> {
+        let _lock = tokio::task::spawn_blocking(lock).await??;
+        let mut state = Self::load();
+        let changed = change(&mut state);
+        state.save().await?;
+        Ok(changed)
+    }
+
+    pub fn phase(&self) -> Option<SelfUpdatePhase> {
+        self.attempt.as_ref().map(|attempt| attempt.phase)
+    }
+
+    /// Replaces the previous attempt, whatever became of it.
+    pub fn begin(&mut self, version: &str, previous_version: Option<String>) {
+        self.attempt = Some(SelfUpdateAttempt {
+            started_at: chrono::Utc::now(),
+            previous_version,
+            version: version.to_string(),
+            phase: SelfUpdatePhase::Installing,
+            error: None,
+        });
+        self.sequence += 1;
+    }
+
+    pub fn set_phase(&mut self, phase: SelfUpdatePhase, error: Option<String>) {
+        if let Some(attempt) = &mut self.attempt {
+            attempt.phase = phase;
+            attempt.error = error;
+            self.sequence += 1;
+        }
+    }
+
+    /// The attempt once settled, when the API hasn't received it yet.
+    pub fn unreported(&self) -> Option<(u64, SelfUpdateAttempt)> {
+        if self.sequence <= self.reported {
+            return None;
+        }
+
+        self.attempt
+            .clone()
+            .filter(|attempt| attempt.phase.is_settled())
+            .map(|attempt| (self.sequence, attempt))
+    }
+
+    /// Records that the API received the attempt up to `sequence`.
+    pub async fn acknowledge(sequence: u64) -> Result<()> {
+        Self::update(|state| state.reported = state.reported.max(sequence)).await
+    }
+}
+
+/// Blocks until the state is ours, until the file is dropped.
+fn lock() -> Result<std::fs::File> {
+    if let Some(dir) = Path::new(LOCK_FILE).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(LOCK_FILE)?;
+
+    // SAFETY: the descriptor is open for as long as `file` lives
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_settled_attempts_once() {
+        let mut state = SelfUpdateState::default();
+        state.begin("0.2.60", Some("0.2.59".to_string()));
+        assert!(state.unreported().is_none());
+
+        state.set_phase(SelfUpdatePhase::Verifying, None);
+        assert!(state.unreported().is_none());
+
+        state.set_phase(
+            SelfUpdatePhase::RolledBack,
+            Some("no heartbeat".to_string()),
+        );
+        let (sequence, attempt) = state.unreported().unwrap();
+        assert_eq!(attempt.phase, SelfUpdatePhase::RolledBack);
+        assert_eq!(attempt.previous_version.as_deref(), Some("0.2.59"));
+
+        state.reported = sequence;
+        assert!(state.unreported().is_none());
+    }
+}
@@ -0,0 +1,149 @@
+//! Encodings smithd and the API exchange bodies in. CBOR is a lot smaller
+//! than JSON on metered links, JSON stays for whoever predates it.
+
+use anyhow::{Context, Result};
+use serde::{Serialize, de::DeserializeOwned};
+
+pub const JSON: &str = "application/json";
+pub const CBOR: &str = "application/cbor";
+
+/// `Accept` of smithd, CBOR whenever the API can.
+pub const ACCEPT: &str = "application/cbor, application/json;q=0.5";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => JSON,
+            Self::Cbor => CBOR,
+        }
+    }
+
+    /// Encoding of a body with this `Content-Type`, JSON when it has none.
+    pub fn from_content_type(content_type: Option<&str>) -> Option<Self> {
+        let Some(content_type) = content_type else {
+            return Some(Self::Json);
+        };
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        [Self::Json, Self::Cbor]
+            .into_iter()
+            .find(|encoding| media_type.eq_ignore_ascii_case(encoding.content_type()))
+    }
+
+    /// Encoding to answer a request with this `Accept` in. CBOR only when
+    /// it's listed and not liked less than JSON.
+    pub fn accepted(accept: Option<&str>) -> Self {
+        let quality = |encoding: Self| {
+            accept
+                .into_iter()
+                .flat_map(|accept| accept.split(','))
+                .filter_map(|range| {
+                    let mut parameters = range.split(';');
+                    let media_type = parameters.next()?.trim();
+                    if !media_type.eq_ignore_ascii_case(encoding.content_type()) {
+                        return None;
+                    }
+                    Some(
+                        parameters
+                            .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                            .and_then(|quality| quality.parse::<f32>().ok())
+                            .unwrap_or(1.0),
+                    )
+                })
+                .fold(0.0, f32::max)
+        };
+
+        let cbor = quality(Self::Cbor);
+        if cbor > 0.0 && cbor >= quality(Self::Json) {
+            Self::Cbor
+        } else {
+            Self::Json
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => serde_json::to_vec(value).context("Failed to encode JSON"),
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes).context("Failed to encode CBOR")?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Self::Json => serde_json::from_slice(bytes).context("Failed to decode JSON"),
+            Self::Cbor => ciborium::from_reader(bytes).context("Failed to decode CBOR"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::{HomePostResponse, SafeCommandRequest, SafeCommandTx};
+    use serde_json::{Value, json};
+
+    #[test]
+    fn negotiates() {
+        assert_eq!(Encoding::from_content_type(None), Some(Encoding::Json));
+        assert_eq!(
+            Encoding::from_content_type(Some("application/json; charset=utf-8")),
+            Some(Encoding::Json)
+        );
+        assert_eq!(
+            Encoding::from_content_type(Some("Application/CBOR")),
+            Some(Encoding::Cbor)
+        );
+        assert_eq!(Encoding::from_content_type(Some("text/plain")), None);
+
+        assert_eq!(Encoding::accepted(None), Encoding::Json);
+        assert_eq!(Encoding::accepted(Some("*/*")), Encoding::Json);
+        assert_eq!(Encoding::accepted(Some(ACCEPT)), Encoding::Cbor);
+        assert_eq!(
+            Encoding::accepted(Some("application/json, application/cbor;q=0.1")),
+            Encoding::Json
+        );
+        assert_eq!(
+            Encoding::accepted(Some("application/cbor;q=0")),
+            Encoding::Json
+        );
+    }
+
+    #[test]
+    fn cbor_decodes_like_json() {
+        let response = HomePostResponse {
+            commands: vec![SafeCommandRequest {
+                id: 7,
+                command: SafeCommandTx::Plugin {
+                    name: "service-status".to_string(),
+                    args: json!({ "unit": "smithd", "lines": 20, "follow": false }),
+                },
+                ..Default::default()
+            }],
+            target_release_id: Some(3),
+            ..Default::default()
+        };
+
+        let cbor = Encoding::Cbor.encode(&response).unwrap();
+        let json = Encoding::Json.encode(&response).unwrap();
+        assert!(cbor.len() < json.len());
+
+        // smithd decodes commands one by one from a `Value`, whatever the
+        // encoding
+        let value: Value = Encoding::Cbor.decode(&cbor).unwrap();
+        assert_eq!(value, Encoding::Json.decode::<Value>(&json).unwrap());
+        let (decoded, undecoded) = HomePostResponse::decode(value).unwrap();
+        assert!(undecoded.is_empty());
+        assert_eq!(decoded.target_release_id, Some(3));
+        assert_eq!(decoded.commands[0].id, 7);
+    }
+}
@@ -0,0 +1,154 @@
+//! The system side of smithd. Actors run their one-off commands (dpkg, apt,
+//! systemctl, reboot, check scripts) through a [`Host`] instead of spawning
+//! them directly, so a fake one can stand in for the device, see smith-sim.
+//!
+//! Long running processes that are streamed from, like journalctl or the
+//! shell's pty, are still spawned directly.
+
+use futures::future::BoxFuture;
+use std::fmt;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+/// A program with its arguments, run to completion by a [`Host`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    pub dir: Option<PathBuf>,
+    /// Written to the standard input, which is closed otherwise.
+    pub stdin: Option<Vec<u8>>,
+}
+
+impl HostCommand {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            args: vec![],
+            dir: None,
+            stdin: None,
+        }
+    }
+
+    /// `sh -c script`.
+    pub fn shell(script: impl Into<String>) -> Self {
+        Self::new("sh").arg("-c").arg(script)
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    pub fn stdin(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+}
+
+impl fmt::Display for HostCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+pub trait Host: Send + Sync {
+    /// Runs the command to completion. Dropping the future stops it.
+    fn output(&self, command: HostCommand) -> BoxFuture<'_, io::Result<Output>>;
+}
+
+/// The device smithd runs on.
+pub struct SystemHost;
+
+impl SystemHost {
+    pub fn shared() -> Arc<dyn Host> {
+        Arc::new(Self)
+    }
+}
+
+impl Host for SystemHost {
+    fn output(&self, command: HostCommand) -> BoxFuture<'_, io::Result<Output>> {
+        Box::pin(async move {
+            let mut process = tokio::process::Command::new(&command.program);
+            process.args(&command.args).kill_on_drop(true);
+            if let Some(dir) = &command.dir {
+                process.current_dir(dir);
+            }
+            let Some(input) = &command.stdin else {
+                return process.output().await;
+            };
+
+            let mut child = process
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                let input = input.clone();
+                // written alongside reading the output, so neither pipe fills
+                // up, the process may exit without reading all of it
+                tokio::spawn(async move { _ = stdin.write_all(&input).await });
+            }
+            child.wait_with_output().await
+        })
+    }
+}
+
+/// Output of a command that exited with `code`, for fake hosts.
+pub fn exited(code: i32, stdout: impl Into<Vec<u8>>, stderr: impl Into<Vec<u8>>) -> Output {
+    Output {
+        status: ExitStatus::from_raw((code & 0xff) << 8),
+        stdout: stdout.into(),
+        stderr: stderr.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_commands_on_the_system() {
+        let output = SystemHost
+            .output(HostCommand::shell("echo hello; exit 3"))
+            .await
+            .unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"hello\n");
+
+        let faked = exited(3, "hello\n", "");
+        assert_eq!(faked.status.code(), output.status.code());
+
+        let piped = SystemHost
+            .output(HostCommand::new("cat").stdin("piped"))
+            .await
+            .unwrap();
+        assert_eq!(piped.stdout, b"piped");
+        assert_eq!(
+            HostCommand::new("dpkg").args(["-l", "smith"]).to_string(),
+            "dpkg -l smith"
+        );
+    }
+}
@@ -1,3 +1,6 @@
+pub mod encoding;
+pub mod host;
 pub mod network;
+pub mod resources;
 pub mod schema;
 pub mod system;
@@ -1,17 +1,41 @@
-use crate::magic::structure::ConfigPackage;
+use crate::downloader::{Bandwidth, Resumable};
+use crate::utils::encoding::{self, Encoding};
+use crate::utils::schema::ReleasePackage;
 use anyhow::{Context, Result, anyhow};
 use flate2::{Compression, write::GzEncoder};
-use futures_util::StreamExt;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
 use reqwest::{Response, StatusCode};
-use std::{env, io::Write, time::Duration};
-use tokio::io::AsyncWriteExt;
+use serde::de::DeserializeOwned;
+use std::io::SeekFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, io::Write, path::Path, time::Duration};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tokio::time;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 pub struct NetworkClient {
     hostname: String,
     id: String,
     client: reqwest::Client,
+    /// Without an overall timeout, a capped or paused download can take as
+    /// long as it needs as long as data keeps coming.
+    downloads: reqwest::Client,
+    /// Cleared once the API turned CBOR down, it only gets JSON from then on.
+    cbor: AtomicBool,
+}
+
+/// Body of an API response, in the encoding its `Content-Type` names.
+pub async fn decode<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(str::to_string);
+    let encoding = Encoding::from_content_type(content_type.as_deref())
+        .ok_or_else(|| anyhow!("Unexpected content type {:?}", content_type))?;
+
+    let bytes = response.bytes().await?;
+    encoding.decode(&bytes)
 }
 
 impl Default for NetworkClient {
@@ -27,6 +51,11 @@ impl NetworkClient {
             .gzip(true)
             .build()
             .unwrap();
+        let downloads = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .read_timeout(Duration::from_secs(5 * 60))
+            .build()
+            .unwrap();
 
         let id = crate::utils::system::get_serial_number();
 
@@ -36,6 +65,8 @@ impl NetworkClient {
             id,
             hostname,
             client,
+            downloads,
+            cbor: AtomicBool::new(true),
         }
     }
 
@@ -55,40 +86,93 @@ impl NetworkClient {
         self.hostname = hostname;
     }
 
+    /// Tries CBOR again, the API may have been upgraded since it turned it
+    /// down.
+    pub fn renegotiate(&self) {
+        self.cbor.store(true, Ordering::Relaxed);
+    }
+
     pub async fn send_compressed_post<T: serde::Serialize>(
         &self,
         token: &str,
         endpoint: &str,
         message: &T,
     ) -> Result<(StatusCode, Response)> {
-        let client = self.client.clone();
         let url = format!("{}{}", self.hostname, endpoint);
 
+        let encoding = if self.cbor.load(Ordering::Relaxed) {
+            Encoding::Cbor
+        } else {
+            Encoding::Json
+        };
+        let mut response = self.post_encoded(token, &url, encoding, message).await?;
+
+        // APIs from before CBOR only take JSON
+        if encoding == Encoding::Cbor && response.status() == StatusCode::UNSUPPORTED_MEDIA_TYPE {
+            warn!("{} doesn't take CBOR, falling back to JSON", url);
+            self.cbor.store(false, Ordering::Relaxed);
+            response = self
+                .post_encoded(token, &url, Encoding::Json, message)
+                .await?;
+        }
+
+        let status_code = response.status();
+
+        Ok((status_code, response))
+    }
+
+    async fn post_encoded<T: serde::Serialize>(
+        &self,
+        token: &str,
+        url: &str,
+        encoding: Encoding,
+        message: &T,
+    ) -> Result<Response> {
         let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        let json = serde_json::to_vec(&message).unwrap_or_default();
-        encoder.write_all(&json)?;
+        encoder.write_all(&encoding.encode(message)?)?;
 
         let compressed_data = encoder.finish()?;
 
-        let request = client
-            .post(&url)
+        let response = self
+            .client
+            .post(url)
             .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", "application/json")
+            .header(CONTENT_TYPE, encoding.content_type())
+            .header(ACCEPT, encoding::ACCEPT)
             .header("Content-Encoding", "gzip")
             .body(compressed_data)
             .send()
             .await?;
 
-        let status_code = request.status();
+        Ok(response)
+    }
+
+    /// Posts without a body, for when there is nothing to report.
+    pub async fn send_heartbeat(
+        &self,
+        token: &str,
+        endpoint: &str,
+    ) -> Result<(StatusCode, Response)> {
+        let url = format!("{}{}", self.hostname, endpoint);
+
+        let response = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .header(ACCEPT, encoding::ACCEPT)
+            .send()
+            .await?;
+
+        let status_code = response.status();
 
-        Ok((status_code, request))
+        Ok((status_code, response))
     }
 
     pub async fn get_release_packages(
         &self,
         release_id: i32,
         token: &str,
-    ) -> Result<Vec<ConfigPackage>> {
+    ) -> Result<Vec<ReleasePackage>> {
         let url = format!("{}/releases/{}/packages", self.hostname, release_id);
         let response = self
             .client
@@ -103,14 +187,54 @@ impl NetworkClient {
             .with_context(|| "Failed to Parse JSON respone")
     }
 
-    pub async fn get_package(&self, package_name: &str, token: &str) -> Result<()> {
-        let path = env::current_dir()?;
+    /// Downloads a package delta, small enough to be kept in memory.
+    pub async fn get_delta(
+        &self,
+        file: &str,
+        token: &str,
+        bandwidth: &Bandwidth,
+    ) -> Result<Vec<u8>> {
+        let url = format!("{}/package", self.hostname);
+        let request = || {
+            self.downloads
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", token))
+                .query(&[("name", file)])
+        };
+        let mut download = Resumable::start(bandwidth, request)
+            .await
+            .with_context(|| format!("Failed to get delta {}", file))?;
+
+        let mut delta = Vec::new();
+        while let Some((offset, data)) = download.next().await? {
+            delta.truncate(offset as usize);
+            delta.extend_from_slice(&data);
+        }
 
-        let mut local_packages_folder = path.clone();
-        local_packages_folder.push("packages");
+        Ok(delta)
+    }
+
+    pub async fn get_package(
+        &self,
+        package_name: &str,
+        token: &str,
+        bandwidth: &Bandwidth,
+    ) -> Result<()> {
+        let packages = env::current_dir()?.join("packages");
+        self.get_package_into(&packages, package_name, token, bandwidth)
+            .await
+    }
 
-        let mut local_package_path = local_packages_folder.clone();
-        local_package_path.push(package_name);
+    /// Downloads the package into `local_packages_folder`, unless it is
+    /// already there.
+    pub async fn get_package_into(
+        &self,
+        local_packages_folder: &Path,
+        package_name: &str,
+        token: &str,
+        bandwidth: &Bandwidth,
+    ) -> Result<()> {
+        let local_package_path = local_packages_folder.join(package_name);
 
         let mut local_package_path_tmp = local_package_path.clone();
         local_package_path_tmp.set_extension("tmp");
@@ -122,30 +246,29 @@ impl NetworkClient {
             info!("Package does not exist locally, fetching...");
         }
 
-        let query = vec![("name", package_name)];
         let url = format!("{}/package", self.hostname);
-        let stream = self
-            .client
-            .get(url)
-            .header("Authorization", format!("Bearer {}", token))
-            .timeout(Duration::from_secs(10 * 60))
-            .query(&query)
-            .send()
-            .await?;
-
-        if stream.status() != 200 {
-            return Err(anyhow!("Failed to get package"));
-        }
-
-        let mut response = stream.bytes_stream();
+        let request = || {
+            self.downloads
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", token))
+                .query(&[("name", package_name)])
+        };
+        let mut download = Resumable::start(bandwidth, request)
+            .await
+            .context("Failed to get package")?;
 
         let start_time = time::Instant::now();
 
-        tokio::fs::create_dir_all(&local_packages_folder).await?;
+        tokio::fs::create_dir_all(local_packages_folder).await?;
         let mut file = tokio::fs::File::create(&local_package_path_tmp).await?;
         let mut total_bytes = 0u64;
-        while let Some(chunk) = response.next().await {
-            let data = chunk?;
+        while let Some((offset, data)) = download.next().await? {
+            // the API doesn't resume, the package is downloaded again
+            if offset != total_bytes {
+                file.set_len(offset).await?;
+                file.seek(SeekFrom::Start(offset)).await?;
+                total_bytes = offset;
+            }
             total_bytes += data.len() as u64;
             file.write_all(&data).await?;
         }
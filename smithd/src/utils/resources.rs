@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time;
+
+/// How long CPU time is sampled to compute the usage.
+const CPU_SAMPLE: Duration = Duration::from_millis(500);
+const TEGRASTATS: &str = "/usr/bin/tegrastats";
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Resources {
+    /// Busy CPU time over all cores, in percent.
+    pub cpu_usage: u8,
+    pub load_average: [f32; 3],
+    pub memory: Memory,
+    pub swap: Memory,
+    pub disks: Vec<Disk>,
+    pub thermal_zones: Vec<ThermalZone>,
+    /// Seconds since boot.
+    pub uptime: u64,
+    /// Only on Jetson boards.
+    pub tegrastats: Option<Tegrastats>,
+}
+
+/// Sizes in bytes.
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Memory {
+    pub total: u64,
+    pub used: u64,
+}
+
+/// Sizes in bytes.
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Disk {
+    pub mount: String,
+    pub total: u64,
+    pub used: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ThermalZone {
+    pub name: String,
+    /// Degrees Celsius.
+    pub temperature: f32,
+    /// The zone reached its first passive trip point, so the kernel is
+    /// slowing things down.
+    pub throttling: bool,
+}
+
+/// Load in percent, as reported by `tegrastats`.
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Tegrastats {
+    pub gpu: u8,
+    pub emc: u8,
+}
+
+impl Resources {
+    pub async fn new() -> Resources {
+        let meminfo = tokio::fs::read_to_string("/proc/meminfo")
+            .await
+            .unwrap_or_default();
+
+        Resources {
+            cpu_usage: cpu_usage().await,
+            load_average: load_average().await,
+            memory: memory(&meminfo, "MemTotal", "MemAvailable"),
+            swap: memory(&meminfo, "SwapTotal", "SwapFree"),
+            disks: disks().await,
+            thermal_zones: thermal_zones().await,
+            uptime: tokio::fs::read_to_string("/proc/uptime")
+                .await
+                .unwrap_or_default()
+                .split_whitespace()
+                .next()
+                .and_then(|uptime| uptime.parse::<f64>().ok())
+                .unwrap_or_default() as u64,
+            tegrastats: tegrastats().await,
+        }
+    }
+}
+
+/// Idle and total jiffies from the aggregated `cpu` line of `/proc/stat`.
+async fn cpu_times() -> Option<(u64, u64)> {
+    let stat = tokio::fs::read_to_string("/proc/stat").await.ok()?;
+    let times = stat
+        .lines()
+        .find(|line| line.starts_with("cpu "))?
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|time| time.parse::<u64>().ok())
+        .collect::<Vec<_>>();
+
+    // idle and iowait
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    Some((idle, times.iter().sum()))
+}
+
+async fn cpu_usage() -> u8 {
+    let Some((idle_before, total_before)) = cpu_times().await else {
+        return 0;
+    };
+    time::sleep(CPU_SAMPLE).await;
+    let Some((idle, total)) = cpu_times().await else {
+        return 0;
+    };
+
+    let total = total.saturating_sub(total_before);
+    if total == 0 {
+        return 0;
+    }
+    let busy = total.saturating_sub(idle.saturating_sub(idle_before));
+    (busy * 100 / total) as u8
+}
+
+async fn load_average() -> [f32; 3] {
+    let loadavg = tokio::fs::read_to_string("/proc/loadavg")
+        .await
+        .unwrap_or_default();
+
+    let mut load_average = [0.0; 3];
+    for (load, value) in load_average.iter_mut().zip(loadavg.split_whitespace()) {
+        *load = value.parse().unwrap_or_default();
+    }
+    load_average
+}
+
+fn memory(meminfo: &str, total: &str, available: &str) -> Memory {
+    let kilobytes = |key: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or_default()
+            * 1024
+    };
+
+    let total = kilobytes(total);
+    Memory {
+        total,
+        used: total.saturating_sub(kilobytes(available)),
+    }
+}
+
+async fn disks() -> Vec<Disk> {
+    let output = Command::new("df")
+        .args([
+            "-P", "-B1", "-x", "tmpfs", "-x", "devtmpfs", "-x", "squashfs", "-x", "overlay",
+        ])
+        .output()
+        .await;
+
+    match output {
+        Ok(output) => parse_df(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => vec![],
+    }
+}
+
+/// Parses `df -P -B1`, whose columns are filesystem, size, used, available,
+/// capacity and mount point.
+fn parse_df(output: &str) -> Vec<Disk> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 {
+                return None;
+            }
+
+            Some(Disk {
+                mount: fields[5..].join(" "),
+                total: fields[1].parse().ok()?,
+                used: fields[2].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+async fn thermal_zones() -> Vec<ThermalZone> {
+    let mut zones = vec![];
+    let Ok(mut entries) = tokio::fs::read_dir("/sys/class/thermal").await else {
+        return zones;
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with("thermal_zone")
+        {
+            continue;
+        }
+
+        let path = entry.path();
+        let Some(temperature) = millidegrees(&path.join("temp")).await else {
+            continue;
+        };
+
+        zones.push(ThermalZone {
+            name: tokio::fs::read_to_string(path.join("type"))
+                .await
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            temperature: (temperature as f32 / 100.0).round() / 10.0,
+            throttling: passive_trip(&path)
+                .await
+                .is_some_and(|trip| temperature >= trip),
+        });
+    }
+
+    zones.sort_by(|a, b| a.name.cmp(&b.name));
+    zones
+}
+
+async fn millidegrees(path: &Path) -> Option<i64> {
+    tokio::fs::read_to_string(path)
+        .await
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Temperature of the first passive trip point of a thermal zone.
+async fn passive_trip(zone: &Path) -> Option<i64> {
+    for trip in 0.. {
+        let trip_type = tokio::fs::read_to_string(zone.join(format!("trip_point_{trip}_type")))
+            .await
+            .ok()?;
+        if trip_type.trim() == "passive" {
+            return millidegrees(&zone.join(format!("trip_point_{trip}_temp"))).await;
+        }
+    }
+
+    None
+}
+
+/// Reads a single line of `tegrastats`, which otherwise keeps printing.
+async fn tegrastats() -> Option<Tegrastats> {
+    if !Path::new(TEGRASTATS).exists() {
+        return None;
+    }
+
+    let mut child = Command::new(TEGRASTATS)
+        .args(["--interval", "500"])
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .ok()?;
+    let mut lines = BufReader::new(child.stdout.take()?).lines();
+
+    let line = time::timeout(Duration::from_secs(3), lines.next_line())
+        .await
+        .ok()?
+        .ok()??;
+    _ = child.kill().await;
+
+    parse_tegrastats(&line)
+}
+
+/// Picks the GPU and memory controller load out of a line such as
+/// `RAM 2050/7620MB ... EMC_FREQ 4%@1600 GR3D_FREQ 12%@1300 ...`.
+fn parse_tegrastats(line: &str) -> Option<Tegrastats> {
+    let load = |name: &str| {
+        let mut fields = line.split_whitespace();
+        fields.find(|field| *field == name)?;
+        fields.next()?.split('%').next()?.parse::<u8>().ok()
+    };
+
+    Some(Tegrastats {
+        gpu: load("GR3D_FREQ")?,
+        emc: load("EMC_FREQ").unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn df_output() {
+        let output = "\
+Filesystem        1-blocks        Used   Available Capacity Mounted on
+/dev/mmcblk0p1 30000000000 27000000000  3000000000      90% /
+/dev/sda1        500000000   100000000   400000000      20% /media/usb stick
+";
+
+        assert_eq!(
+            parse_df(output),
+            vec![
+                Disk {
+                    mount: "/".to_string(),
+                    total: 30_000_000_000,
+                    used: 27_000_000_000,
+                },
+                Disk {
+                    mount: "/media/usb stick".to_string(),
+                    total: 500_000_000,
+                    used: 100_000_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tegrastats_line() {
+        let line = "RAM 2050/7620MB (lfb 1x4MB) SWAP 0/3810MB (cached 0MB) \
+            CPU [12%@1190,3%@1190,off,off] EMC_FREQ 4%@1600 GR3D_FREQ 57%@1300 \
+            CPU@45.5C GPU@43C";
+
+        assert_eq!(parse_tegrastats(line), Some(Tegrastats { gpu: 57, emc: 4 }));
+        assert_eq!(parse_tegrastats("RAM 2050/7620MB"), None);
+    }
+}
@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use sqlx;
 use sqlx::Type;
@@ -6,25 +6,71 @@ use std::collections::HashMap;
 use std::time;
 use std::time::Duration;
 
+/// Version of the protocol between smithd and the API, bumped when one side
+/// can't rely on the other understanding it anymore.
+///
+/// 1: commands are decoded one by one and the agent reports the commands
+/// it can run.
+/// 2: bodies may be CBOR, and the agent pings with an empty body when it
+/// has nothing to report.
+pub const PROTOCOL_VERSION: u32 = 2;
+
 // POST That the device does
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct HomePost {
     pub timestamp: Duration,
     pub responses: Vec<SafeCommandResponse>,
     pub release_id: Option<i32>,
+    #[serde(default)]
+    pub tunnels: Vec<Tunnel>,
+    #[serde(default)]
+    pub restart: Option<PendingRestart>,
+    /// 0 for agents from before [`PROTOCOL_VERSION`].
+    #[serde(default)]
+    pub protocol_version: u32,
+    /// `SafeCommandTx` variants the agent can run, see
+    /// [`SafeCommandTx::capability`]. Unknown for agents from before it,
+    /// those only get the [`SafeCommandTx::BASELINE`].
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
 }
 
 impl HomePost {
-    pub fn new(responses: Vec<SafeCommandResponse>, release_id: Option<i32>) -> Self {
+    pub fn new(
+        responses: Vec<SafeCommandResponse>,
+        release_id: Option<i32>,
+        tunnels: Vec<Tunnel>,
+        restart: Option<PendingRestart>,
+    ) -> Self {
         let timestamp = time::Instant::now().elapsed();
         Self {
             timestamp,
             responses,
             release_id,
+            tunnels,
+            restart,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Some(
+                SafeCommandTx::NAMES
+                    .iter()
+                    .chain(&SafeCommandTx::SHAPES)
+                    .map(|name| name.to_string())
+                    .collect(),
+            ),
         }
     }
 }
 
+/// A port currently forwarded through the tunnel server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tunnel {
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub opened_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub requested_by: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct CreateSession {
     pub token: String,
@@ -40,11 +86,81 @@ pub struct Package {
     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+/// A package of a release as devices fetch it, with the deltas that
+/// rebuild it from older versions.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ReleasePackage {
+    #[serde(flatten)]
+    pub package: Package,
+    /// SHA256 of the .deb, to check it once rebuilt.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Keyed by the SHA256 of the .deb the delta applies to.
+    #[serde(default)]
+    pub deltas: HashMap<String, PackageDelta>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct PackageDelta {
+    pub base_version: String,
+    /// Fetched like a package, through `/smith/package`.
+    pub file: String,
+    pub size: i64,
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct SafeCommandResponse {
     pub id: i32,
     pub command: SafeCommandRx,
     pub status: i32,
+    /// How the command ended, left out by agents from before it and for
+    /// responses the device sends on its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<CommandResult>,
+}
+
+/// Common to the responses of every command, next to the variant specific
+/// one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandResult {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    pub duration_ms: u64,
+    pub outcome: CommandOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CommandResult {
+    /// For handlers that know better than the status how a command ended,
+    /// the timings are filled in once it returns.
+    pub fn failed(outcome: CommandOutcome, error: impl Into<String>) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+            outcome,
+            error: Some(error.into()),
+        }
+    }
+}
+
+#[derive(
+    Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[sqlx(type_name = "command_outcome", rename_all = "snake_case")]
+pub enum CommandOutcome {
+    Success,
+    /// The process ran and exited with anything but 0.
+    NonZeroExit,
+    Timeout,
+    /// Cancelled in the API before the device fetched it.
+    Cancelled,
+    /// This smithd can't run the command.
+    Unsupported,
+    PolicyDenied,
+    InternalError,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
@@ -70,6 +186,11 @@ pub enum SafeCommandRx {
     UpdateSystemInfo {
         system_info: Value,
     },
+    /// Only what changed since the last acknowledged `SystemInfo`, see
+    /// [`crate::utils::system::changes`].
+    SystemInfoChanged {
+        changes: Value,
+    },
     UpdatePackage {
         name: String,
         version: String,
@@ -79,10 +200,72 @@ pub enum SafeCommandRx {
         stdout: String,
         stderr: String,
     },
+    NetworkUpdated {
+        /// The new profile couldn't reach the API and the previous one was restored.
+        rolled_back: bool,
+        message: String,
+    },
     DownloadOTA,
     CheckOTAStatus {
         status: String,
     },
+    ShellOpened,
+    GetConfigFiles,
+    UpdateConfigFiles {
+        changed: Vec<String>,
+        errors: Vec<String>,
+    },
+    /// Sent by smithd whenever an OTA attempt changes phase, also across reboots.
+    OtaStatus {
+        attempt: OtaAttempt,
+    },
+    /// Sent by smithd once the updater settled a self-update.
+    SelfUpdateStatus {
+        attempt: SelfUpdateAttempt,
+    },
+    Downloads {
+        message: String,
+    },
+    /// The agent configuration smithd runs with, also sent on startup.
+    AgentConfig {
+        /// `None` before the API pushed any.
+        version: Option<String>,
+        /// Sections that changed and were reloaded.
+        #[serde(default)]
+        reloaded: Vec<String>,
+        #[serde(default)]
+        error: Option<String>,
+    },
+    SupportBundleCollected {
+        profile: SupportBundleProfile,
+        /// Object key of the uploaded tar.gz.
+        file: String,
+        size: u64,
+    },
+    /// What the plugin returned.
+    Plugin {
+        name: String,
+        output: Value,
+    },
+    /// The plugin wasn't found, failed or timed out.
+    PluginFailed {
+        name: String,
+        error: String,
+    },
+    /// Plugins smithd can run, compiled in or found in
+    /// `/etc/smith/commands.d`, sent on startup.
+    Plugins {
+        plugins: Vec<String>,
+    },
+    /// The `[policy]` of the device's magic.toml refused the command.
+    PolicyDenied {
+        reason: String,
+    },
+    /// The agent couldn't decode the command, it is most likely older than
+    /// the command.
+    Unsupported {
+        reason: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -90,42 +273,516 @@ pub struct SafeCommandRequest {
     pub id: i32,
     pub command: SafeCommandTx,
     pub continue_on_error: bool,
+    /// Set by an admin, for devices whose policy requires signed commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<CommandSignature>,
+}
+
+/// Ed25519 signature of a command for a single device, made with
+/// `smithd policy sign`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandSignature {
+    /// Base64 public key, one of the device's `policy.admin_keys`.
+    pub key: String,
+    /// The signature isn't accepted after this.
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Random, a device runs a signed command only once.
+    pub nonce: String,
+    /// Base64, over the serial number of the device, `expires_at`, the nonce
+    /// and the command.
+    pub signature: String,
 }
 
+// (de)serialized through the impls below, see `LEGACY_UNIT_VARIANTS`
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(remote = "Self")]
 pub enum SafeCommandTx {
     #[default]
     Ping,
     Upgrade,
-    Restart,
+    /// Replaces any restart already scheduled.
+    Restart {
+        /// In a minute when left out.
+        #[serde(default)]
+        at: Option<RestartAt>,
+        #[serde(default)]
+        reason: Option<String>,
+        /// Don't wait for apps holding a shutdown inhibitor.
+        #[serde(default)]
+        force: bool,
+    },
+    CancelRestart,
     FreeForm {
         cmd: String,
     },
     OpenTunnel {
         port: Option<u16>,
+        /// How long the tunnel stays open, in seconds. Defaults to 30 minutes.
+        ttl: Option<u64>,
+        requested_by: Option<String>,
+    },
+    CloseTunnel {
+        port: Option<u16>,
     },
-    CloseTunnel,
     UpdateNetwork {
         network: Network,
     },
+    /// Writes `/root/.teton_environment` for services that still source it,
+    /// everything else gets its configuration through `UpdateConfigFiles`.
     UpdateVariables {
         variables: HashMap<String, String>,
+        /// Names of the secret variables, kept out of support bundles.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        secrets: Vec<String>,
+    },
+    UpdateConfigFiles {
+        files: Vec<ConfigFile>,
     },
     DownloadOTA {
-        tools: String,
+        /// Name of an `[[ota]]` profile in magic.toml, the Jetson one when left out.
+        #[serde(default)]
+        profile: Option<String>,
+        /// Only for profiles with a tools archive.
+        #[serde(default)]
+        tools: Option<String>,
         payload: String,
         rate: f64,
+        /// OS version the device has to boot into for the OTA to be confirmed.
+        #[serde(default)]
+        expected_version: Option<String>,
+        /// What the artifacts have to match for the download to be verified.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksums: Option<OtaChecksums>,
     },
     CheckOTAStatus,
-    StartOTA,
+    StartOTA {
+        #[serde(default)]
+        profile: Option<String>,
+    },
+    OpenShell {
+        session: String,
+        cols: u16,
+        rows: u16,
+    },
+    CollectSupportBundle {
+        #[serde(default)]
+        profile: SupportBundleProfile,
+    },
+    /// Replaces the limits kept in magic.toml.
+    SetDownloadLimits {
+        limits: DownloadLimits,
+    },
+    /// Holds every download until `ResumeDownloads` or smithd restarts.
+    PauseDownloads,
+    ResumeDownloads,
+    /// Agent configuration merged by the API, applied as a drop-in of
+    /// magic.toml that only holds the sections the API manages.
+    UpdateAgentConfig {
+        /// Hash of `config`, reported back once it is in use.
+        version: String,
+        /// TOML.
+        config: String,
+    },
+    /// Runs a plugin, see `SafeCommandRx::Plugins` for the ones a device
+    /// has.
+    Plugin {
+        name: String,
+        /// Handed to the plugin as is.
+        #[serde(default)]
+        args: Value,
+    },
+}
+
+/// Unit variants that later took fields. Left at their defaults they still
+/// travel as the bare name, so agents and APIs from before can parse them,
+/// and the bare name still parses, like the rows queued before they changed.
+const LEGACY_UNIT_VARIANTS: [&str; 3] = ["CloseTunnel", "StartOTA", "Restart"];
+
+impl Serialize for SafeCommandTx {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::CloseTunnel { port: None }
+            | Self::StartOTA { profile: None }
+            | Self::Restart {
+                at: None,
+                reason: None,
+                force: false,
+            } => serializer.serialize_str(self.name()),
+            _ => Self::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SafeCommandTx {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = match Value::deserialize(deserializer)? {
+            Value::String(name) if LEGACY_UNIT_VARIANTS.contains(&name.as_str()) => {
+                serde_json::json!({ name: {} })
+            }
+            value => value,
+        };
+        Self::deserialize(value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl SafeCommandTx {
+    /// Every variant, reported to the API as the commands the agent can run.
+    pub const NAMES: [&str; 20] = [
+        "Ping",
+        "Upgrade",
+        "Restart",
+        "CancelRestart",
+        "FreeForm",
+        "OpenTunnel",
+        "CloseTunnel",
+        "UpdateNetwork",
+        "UpdateVariables",
+        "UpdateConfigFiles",
+        "DownloadOTA",
+        "CheckOTAStatus",
+        "StartOTA",
+        "OpenShell",
+        "CollectSupportBundle",
+        "SetDownloadLimits",
+        "PauseDownloads",
+        "ResumeDownloads",
+        "UpdateAgentConfig",
+        "Plugin",
+    ];
+
+    /// What agents from before capabilities run, in the shape they knew.
+    pub const BASELINE: [&str; 11] = [
+        "Ping",
+        "Upgrade",
+        "Restart",
+        "FreeForm",
+        "OpenTunnel",
+        "CloseTunnel",
+        "UpdateNetwork",
+        "UpdateVariables",
+        "DownloadOTA",
+        "CheckOTAStatus",
+        "StartOTA",
+    ];
+
+    /// Variants of the baseline that took fields since, an agent that
+    /// doesn't list the new shape would fail to parse them or drop the
+    /// fields.
+    pub const SHAPES: [&str; 5] = [
+        "Restart:2",
+        "OpenTunnel:2",
+        "CloseTunnel:2",
+        "DownloadOTA:2",
+        "StartOTA:2",
+    ];
+
+    /// What an agent has to report to run the command as it is: the name of
+    /// the variant, with the version of its shape when it uses fields the
+    /// baseline didn't have.
+    pub fn capability(&self) -> &'static str {
+        match self {
+            Self::Restart {
+                at: None,
+                reason: None,
+                force: false,
+            } => "Restart",
+            Self::Restart { .. } => "Restart:2",
+            Self::OpenTunnel {
+                ttl: None,
+                requested_by: None,
+                ..
+            } => "OpenTunnel",
+            Self::OpenTunnel { .. } => "OpenTunnel:2",
+            Self::CloseTunnel { port: None } => "CloseTunnel",
+            Self::CloseTunnel { .. } => "CloseTunnel:2",
+            // `tools` was required
+            Self::DownloadOTA {
+                profile: None,
+                tools: Some(_),
+                expected_version: None,
+                checksums: None,
+                ..
+            } => "DownloadOTA",
+            Self::DownloadOTA { .. } => "DownloadOTA:2",
+            Self::StartOTA { profile: None } => "StartOTA",
+            Self::StartOTA { .. } => "StartOTA:2",
+            _ => self.name(),
+        }
+    }
+
+    /// Whether an agent reporting `capabilities` can run the command, the
+    /// [`BASELINE`](Self::BASELINE) when it reports none.
+    pub fn runs_on(&self, capabilities: Option<&[String]>) -> bool {
+        let capability = self.capability();
+        match capabilities {
+            Some(capabilities) => capabilities.iter().any(|name| name == capability),
+            None => Self::BASELINE.contains(&capability),
+        }
+    }
+
+    /// Name of the variant as it is serialized, like `FreeForm`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ping => "Ping",
+            Self::Upgrade => "Upgrade",
+            Self::Restart { .. } => "Restart",
+            Self::CancelRestart => "CancelRestart",
+            Self::FreeForm { .. } => "FreeForm",
+            Self::OpenTunnel { .. } => "OpenTunnel",
+            Self::CloseTunnel { .. } => "CloseTunnel",
+            Self::UpdateNetwork { .. } => "UpdateNetwork",
+            Self::UpdateVariables { .. } => "UpdateVariables",
+            Self::UpdateConfigFiles { .. } => "UpdateConfigFiles",
+            Self::DownloadOTA { .. } => "DownloadOTA",
+            Self::CheckOTAStatus => "CheckOTAStatus",
+            Self::StartOTA { .. } => "StartOTA",
+            Self::OpenShell { .. } => "OpenShell",
+            Self::CollectSupportBundle { .. } => "CollectSupportBundle",
+            Self::SetDownloadLimits { .. } => "SetDownloadLimits",
+            Self::PauseDownloads => "PauseDownloads",
+            Self::ResumeDownloads => "ResumeDownloads",
+            Self::UpdateAgentConfig { .. } => "UpdateAgentConfig",
+            Self::Plugin { .. } => "Plugin",
+        }
+    }
+}
+
+/// Caps shared by every download of smithd, in MB per second. No cap when
+/// left out.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub struct DownloadLimits {
+    #[serde(default)]
+    pub rate: Option<f64>,
+    /// Used instead of `rate` while the default route goes through a
+    /// cellular modem.
+    #[serde(default)]
+    pub metered_rate: Option<f64>,
+}
+
+/// How much a support bundle collects.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub enum SupportBundleProfile {
+    /// The last day of the journal and the current state of the device.
+    #[default]
+    Standard,
+    /// The last week of the journal, the previous boot and the process list.
+    Full,
+}
+
+/// A configuration file rendered by the API for this device.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ConfigFile {
+    pub path: String,
+    pub content: String,
+    pub mode: u32,
+    pub owner: String,
+    pub group: String,
+    /// Units reloaded after the file changed.
+    #[serde(default)]
+    pub reload: Vec<String>,
+    /// Units restarted after the file changed.
+    #[serde(default)]
+    pub restart: Vec<String>,
+}
+
+/// Control messages of a shell session, sent as text frames next to the
+/// binary frames carrying the terminal data.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ShellControl {
+    Resize { cols: u16, rows: u16 },
+    Exit { code: i32 },
+}
+
+/// Modem state posted to `/telemetry/modem`, `null` when the device has none.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct NewModem {
+    pub imei: String,
+    /// Operator the modem is registered with.
+    pub network_provider: String,
+    #[serde(default)]
+    pub iccid: Option<String>,
+    #[serde(default)]
+    pub access_technology: Option<String>,
+    /// Signal quality in percent.
+    #[serde(default)]
+    pub signal_quality: Option<i32>,
+    /// Bytes received and sent through the active data bearers.
+    #[serde(default)]
+    pub rx_bytes: Option<i64>,
+    #[serde(default)]
+    pub tx_bytes: Option<i64>,
+}
+
+/// A journal entry, posted in batches to `/telemetry/logs`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub unit: Option<String>,
+    /// Syslog priority, 0 (emerg) to 7 (debug).
+    pub priority: i16,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RestartAt {
+    /// Seconds after the command is received.
+    In(u64),
+    Time(chrono::DateTime<chrono::Utc>),
+    /// Next time the device clock reads this `HH:MM`, so a single command
+    /// restarts a fleet at night in each device's own time zone.
+    LocalTime(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingRestart {
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub reason: Option<String>,
+    pub force: bool,
+}
+
+/// Where an OTA attempt is, smithd keeps this on disk so it survives the reboot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtaPhase {
+    #[default]
+    Idle,
+    Downloading,
+    /// The artifacts are on disk and match the checksums of the release.
+    Verified,
+    Applying,
+    /// Applied, waiting for the device to boot into the new OS.
+    Rebooting,
+    Confirmed,
+    Failed,
+}
+
+/// Size and SHA-256 of an OTA artifact as released.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactChecksum {
+    pub size: u64,
+    /// Hex encoded.
+    pub sha256: String,
+}
+
+/// Checksums of the artifacts of an OTA release.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OtaChecksums {
+    pub payload: ArtifactChecksum,
+    /// Only for profiles with a tools archive.
+    #[serde(default)]
+    pub tools: Option<ArtifactChecksum>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OtaAttempt {
+    /// Identifies the attempt.
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub profile: String,
+    pub phase: OtaPhase,
+    pub expected_version: Option<String>,
+    /// OS version when the attempt started.
+    pub previous_version: Option<String>,
+    /// OS version booted after the update.
+    pub version: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Where a self-update of smithd is, kept on disk by the updater.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfUpdatePhase {
+    Installing,
+    /// Installed, waiting for the new smithd to reach the API.
+    Verifying,
+    Confirmed,
+    /// The new smithd didn't come up and the previous package is back.
+    RolledBack,
+    Failed,
+}
+
+impl SelfUpdatePhase {
+    pub fn is_settled(self) -> bool {
+        matches!(self, Self::Confirmed | Self::RolledBack | Self::Failed)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SelfUpdateAttempt {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Package version installed when the attempt started.
+    pub previous_version: Option<String>,
+    pub version: String,
+    pub phase: SelfUpdatePhase,
+    pub error: Option<String>,
 }
 
 // RESPONSE THAT IT GETS
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct HomePostResponse {
     pub timestamp: Duration,
+    #[serde(default)]
     pub commands: Vec<SafeCommandRequest>,
     pub target_release_id: Option<i32>,
+    /// 0 for APIs from before [`PROTOCOL_VERSION`].
+    #[serde(default)]
+    pub protocol_version: u32,
+}
+
+/// A command the agent couldn't decode, most likely added after it was
+/// built.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndecodedCommand {
+    pub id: i32,
+    pub error: String,
+}
+
+impl UndecodedCommand {
+    /// What the agent answers instead of running it.
+    pub fn response(self) -> SafeCommandResponse {
+        SafeCommandResponse {
+            id: self.id,
+            command: SafeCommandRx::Unsupported {
+                reason: self.error.clone(),
+            },
+            status: -1,
+            result: Some(CommandResult::failed(
+                CommandOutcome::Unsupported,
+                self.error,
+            )),
+        }
+    }
+}
+
+impl HomePostResponse {
+    /// Decodes the commands one by one, so one the agent doesn't know
+    /// doesn't cost it the rest of the batch. Commands without an id can't
+    /// be answered and are left out.
+    pub fn decode(mut value: Value) -> serde_json::Result<(Self, Vec<UndecodedCommand>)> {
+        let commands = match value
+            .as_object_mut()
+            .and_then(|response| response.remove("commands"))
+        {
+            Some(Value::Array(commands)) => commands,
+            _ => vec![],
+        };
+        let mut response: Self = serde_json::from_value(value)?;
+
+        let mut undecoded = vec![];
+        for command in commands {
+            let id = command.get("id").and_then(Value::as_i64);
+            match serde_json::from_value::<SafeCommandRequest>(command) {
+                Ok(command) => response.commands.push(command),
+                Err(err) => {
+                    if let Some(id) = id.and_then(|id| i32::try_from(id).ok()) {
+                        undecoded.push(UndecodedCommand {
+                            id,
+                            error: err.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok((response, undecoded))
+    }
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -145,6 +802,7 @@ pub struct DeviceRegistrationResponse {
 pub enum NetworkType {
     Wifi,
     Ethernet,
+    /// Cellular modem.
     Dongle,
 }
 
@@ -172,7 +830,32 @@ pub struct Network {
     pub ssid: Option<String>,
     pub name: String,
     pub description: Option<String>,
+    /// Empty for secret networks, except in the command sent to the device.
     pub password: Option<String>,
+    #[serde(default)]
+    pub secret: bool,
+    /// Profiles with a higher priority are preferred when several are in range.
+    #[serde(default = "default_network_priority")]
+    pub priority: i32,
+    /// 802.1X method (`peap` or `ttls`) for enterprise Wi-Fi, WPA-PSK otherwise.
+    #[serde(default)]
+    pub eap_method: Option<String>,
+    #[serde(default)]
+    pub eap_identity: Option<String>,
+    /// Static address in CIDR notation, DHCP otherwise.
+    #[serde(default)]
+    pub ipv4_address: Option<String>,
+    #[serde(default)]
+    pub ipv4_gateway: Option<String>,
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// Access point name of cellular networks.
+    #[serde(default)]
+    pub apn: Option<String>,
+}
+
+fn default_network_priority() -> i32 {
+    500
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -183,4 +866,134 @@ pub struct NewNetwork {
     pub name: String,
     pub description: Option<String>,
     pub password: Option<String>,
+    #[serde(default)]
+    pub secret: bool,
+    /// Profiles with a higher priority are preferred when several are in range.
+    #[serde(default = "default_network_priority")]
+    pub priority: i32,
+    /// 802.1X method (`peap` or `ttls`) for enterprise Wi-Fi, WPA-PSK otherwise.
+    #[serde(default)]
+    pub eap_method: Option<String>,
+    #[serde(default)]
+    pub eap_identity: Option<String>,
+    /// Static address in CIDR notation, DHCP otherwise.
+    #[serde(default)]
+    pub ipv4_address: Option<String>,
+    #[serde(default)]
+    pub ipv4_gateway: Option<String>,
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// Access point name of cellular networks.
+    #[serde(default)]
+    pub apn: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_every_command() {
+        // serde lists the variants it knows when it meets one it doesn't
+        let err = serde_json::from_str::<SafeCommandTx>("\"Unknown\"").unwrap_err();
+        let message = err.to_string();
+        // unknown variant `Unknown`, expected one of `Ping`, `Upgrade`, ...
+        let known = message.split('`').skip(3).step_by(2).collect::<Vec<_>>();
+        assert_eq!(known, SafeCommandTx::NAMES);
+
+        let command = SafeCommandTx::FreeForm {
+            cmd: "uptime".to_string(),
+        };
+        assert_eq!(command.name(), "FreeForm");
+    }
+
+    #[test]
+    fn versions_changed_shapes() {
+        let restart = SafeCommandTx::Restart {
+            at: None,
+            reason: None,
+            force: false,
+        };
+        assert_eq!(restart.capability(), "Restart");
+        let forced = SafeCommandTx::Restart {
+            at: None,
+            reason: None,
+            force: true,
+        };
+        assert_eq!(forced.capability(), "Restart:2");
+        let download = |tools: Option<&str>| SafeCommandTx::DownloadOTA {
+            profile: None,
+            tools: tools.map(str::to_string),
+            payload: "payload.tar.gz".to_string(),
+            rate: 1.0,
+            expected_version: None,
+            checksums: None,
+        };
+        assert_eq!(download(Some("tools.tar.gz")).capability(), "DownloadOTA");
+        assert_eq!(download(None).capability(), "DownloadOTA:2");
+
+        // agents that report nothing get the baseline
+        assert!(restart.runs_on(None));
+        assert!(!forced.runs_on(None));
+        assert!(!SafeCommandTx::PauseDownloads.runs_on(None));
+        let capabilities = HomePost::new(vec![], None, vec![], None).capabilities;
+        assert!(forced.runs_on(capabilities.as_deref()));
+        assert!(download(None).runs_on(capabilities.as_deref()));
+        assert!(!forced.runs_on(Some(&["Restart".to_string()])));
+    }
+
+    #[test]
+    fn keeps_legacy_unit_variants() {
+        // queued by, or sent to, a version from before they took fields
+        for name in LEGACY_UNIT_VARIANTS {
+            let command: SafeCommandTx = serde_json::from_value(Value::from(name)).unwrap();
+            assert_eq!(command.name(), name);
+            assert_eq!(serde_json::to_value(&command).unwrap(), Value::from(name));
+        }
+
+        let command = SafeCommandTx::CloseTunnel { port: Some(8080) };
+        let value = serde_json::to_value(&command).unwrap();
+        assert_eq!(value, serde_json::json!({"CloseTunnel": {"port": 8080}}));
+        let command: SafeCommandTx = serde_json::from_value(value).unwrap();
+        assert!(matches!(
+            command,
+            SafeCommandTx::CloseTunnel { port: Some(8080) }
+        ));
+        let command: SafeCommandTx =
+            serde_json::from_value(serde_json::json!({"CloseTunnel": {}})).unwrap();
+        assert!(matches!(command, SafeCommandTx::CloseTunnel { port: None }));
+        let command: SafeCommandTx =
+            serde_json::from_value(serde_json::json!({"StartOTA": {}})).unwrap();
+        assert!(matches!(command, SafeCommandTx::StartOTA { profile: None }));
+        let command = SafeCommandTx::Restart {
+            at: None,
+            reason: None,
+            force: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&command).unwrap(),
+            serde_json::json!({"Restart": {"at": null, "reason": null, "force": true}})
+        );
+    }
+
+    #[test]
+    fn decodes_commands_one_by_one() {
+        let value = serde_json::json!({
+            "timestamp": {"secs": 1, "nanos": 0},
+            "commands": [
+                {"id": 1, "command": "Ping", "continue_on_error": false},
+                {"id": 2, "command": {"Teleport": {"to": "mars"}}, "continue_on_error": false},
+                {"command": "Shrug"},
+            ],
+            "target_release_id": 7,
+        });
+
+        let (response, undecoded) = HomePostResponse::decode(value).unwrap();
+        assert_eq!(response.commands.len(), 1);
+        assert_eq!(response.commands[0].id, 1);
+        assert_eq!(response.target_release_id, Some(7));
+        assert_eq!(undecoded.len(), 1);
+        assert_eq!(undecoded[0].id, 2);
+        assert!(undecoded[0].error.contains("Teleport"));
+    }
 }
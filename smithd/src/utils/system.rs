@@ -1,3 +1,5 @@
+use crate::utils::host::{Host, HostCommand};
+use crate::utils::resources::Resources;
 use pnet::datalink;
 use pnet::datalink::NetworkInterface;
 use serde::{Deserialize, Serialize};
@@ -71,10 +73,11 @@ pub struct SystemInfo {
     pub network: Network,
     pub device_tree: DeviceTree,
     pub connection_statuses: Vec<ConnectionStatus>,
+    pub resources: Resources,
 }
 
 impl SystemInfo {
-    pub async fn new() -> SystemInfo {
+    pub async fn new(host: &dyn Host) -> SystemInfo {
         let os_release = tokio::fs::read_to_string("/etc/os-release")
             .await
             .unwrap_or_default();
@@ -141,7 +144,8 @@ impl SystemInfo {
                             .collect()
                     }),
             },
-            connection_statuses: get_connection_statuses(),
+            connection_statuses: get_connection_statuses(host).await,
+            resources: Resources::new().await,
         }
     }
     pub fn print(&self) {
@@ -155,6 +159,61 @@ impl SystemInfo {
     }
 }
 
+/// The parts of `current` that differ from `previous`. Objects are compared
+/// key by key, removed keys come back as `null`, anything else is replaced
+/// as a whole.
+pub fn changes(previous: &Value, current: &Value) -> Option<Value> {
+    let (Value::Object(previous), Value::Object(current)) = (previous, current) else {
+        return (previous != current).then(|| current.clone());
+    };
+
+    let mut changes = serde_json::Map::new();
+    for (key, value) in current {
+        let changed = match previous.get(key) {
+            Some(previous) => changes_of(previous, value),
+            None => Some(value.clone()),
+        };
+        if let Some(changed) = changed {
+            changes.insert(key.clone(), changed);
+        }
+    }
+    for key in previous.keys().filter(|key| !current.contains_key(*key)) {
+        changes.insert(key.clone(), Value::Null);
+    }
+
+    (!changes.is_empty()).then_some(Value::Object(changes))
+}
+
+fn changes_of(previous: &Value, current: &Value) -> Option<Value> {
+    match (previous, current) {
+        (Value::Object(_), Value::Object(_)) => changes(previous, current),
+        _ => (previous != current).then(|| current.clone()),
+    }
+}
+
+/// Applies what [`changes`] produced on top of the previous value.
+pub fn merge(target: &mut Value, changes: Value) {
+    match (target, changes) {
+        (Value::Object(target), Value::Object(changes)) => {
+            for (key, value) in changes {
+                if value.is_null() {
+                    target.remove(&key);
+                    continue;
+                }
+                match target.get_mut(&key) {
+                    Some(existing) if existing.is_object() && value.is_object() => {
+                        merge(existing, value)
+                    }
+                    _ => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, changes) => *target = changes,
+    }
+}
+
 async fn get_last_boot_time() -> u64 {
     let content = tokio::fs::read_to_string("/proc/stat")
         .await
@@ -234,23 +293,18 @@ fn get_ips_from_interface(interface: &NetworkInterface) -> Vec<String> {
 }
 
 /// Returns the list of connection statuses as provided by `nmcli`.
-fn get_connection_statuses() -> Vec<ConnectionStatus> {
-    let output = std::process::Command::new("nmcli")
-        .args([
-            "-t",
-            "-f",
-            "CONNECTION,STATE,TYPE,DEVICE",
-            "device",
-            "status",
-        ])
-        .output();
-
-    if let Ok(output) = output {
-        let network_statuses =
-            std::str::from_utf8(&output.stdout).expect("error: failed to read CLI output");
-        parse_connection_statuses(network_statuses)
-    } else {
-        vec![]
+async fn get_connection_statuses(host: &dyn Host) -> Vec<ConnectionStatus> {
+    let command = HostCommand::new("nmcli").args([
+        "-t",
+        "-f",
+        "CONNECTION,STATE,TYPE,DEVICE",
+        "device",
+        "status",
+    ]);
+
+    match host.output(command).await {
+        Ok(output) => parse_connection_statuses(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => vec![],
     }
 }
 
@@ -278,3 +332,93 @@ fn parse_connection_statuses(statuses: &str) -> Vec<ConnectionStatus> {
         })
         .collect()
 }
+
+/// Whether the default route goes through a metered link: a cellular modem,
+/// or a device NetworkManager says or guesses is metered. Modems often route
+/// through a `wwan` or `ppp` interface rather than the device nmcli lists.
+pub async fn is_metered(host: &dyn Host) -> bool {
+    let Some(interface) = tokio::fs::read_to_string("/proc/net/route")
+        .await
+        .ok()
+        .and_then(|routes| parse_default_route(&routes))
+    else {
+        return false;
+    };
+
+    if interface.starts_with("wwan") || interface.starts_with("ppp") {
+        return true;
+    }
+
+    let command = HostCommand::new("nmcli")
+        .args(["-g", "GENERAL.METERED", "device", "show"])
+        .arg(interface);
+    match host.output(command).await {
+        // "yes" or "yes (guessed)"
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).starts_with("yes")
+        }
+        _ => false,
+    }
+}
+
+/// Interface of the default route with the lowest metric in `/proc/net/route`.
+fn parse_default_route(routes: &str) -> Option<String> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+            let default = fields.get(1) == Some(&"00000000") && fields.get(7) == Some(&"00000000");
+            // RTF_UP
+            if !default || flags & 1 == 0 {
+                return None;
+            }
+            let metric: u32 = fields.get(6)?.parse().ok()?;
+            Some((metric, fields[0]))
+        })
+        .min()
+        .map(|(_, interface)| interface.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_round_trip() {
+        let previous = json!({
+            "hostname": "smith",
+            "resources": { "cpu_usage": 10, "disks": [{ "mount": "/", "used": 1 }] },
+            "gone": true,
+        });
+        let current = json!({
+            "hostname": "smith",
+            "resources": { "cpu_usage": 80, "disks": [{ "mount": "/", "used": 1 }] },
+        });
+
+        let delta = changes(&previous, &current).unwrap();
+        assert_eq!(
+            delta,
+            json!({ "resources": { "cpu_usage": 80 }, "gone": null })
+        );
+
+        let mut merged = previous;
+        merge(&mut merged, delta);
+        assert_eq!(merged, current);
+
+        assert_eq!(changes(&current, &current), None);
+    }
+
+    #[test]
+    fn finds_default_route() {
+        let routes =
+            "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\tMTU\tWindow\tIRTT
+eth0\t0000A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+wwan0\t00000000\t0100000A\t0003\t0\t0\t700\t00000000\t0\t0\t0
+eth0\t00000000\t0100A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+";
+        assert_eq!(parse_default_route(routes).as_deref(), Some("eth0"));
+        assert_eq!(parse_default_route("Iface\tDestination\n"), None);
+    }
+}
@@ -1,95 +1,260 @@
+use anyhow::{Context, Result, anyhow};
 use clap::Parser;
 use smith::magic::MagicHandle;
+use smith::magic::state::{WriteOptions, replace};
 use smith::shutdown::ShutdownHandler;
-use tokio::time;
-use tracing::{error, info};
+use smith::updater::self_update::{Heartbeat, KEPT_PACKAGE, SelfUpdateState};
+use smith::utils::schema::SelfUpdatePhase;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio::time::{self, Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Name of the smith package in dpkg.
+const PACKAGE: &str = "smith";
+/// How long the new smithd has to reach the API before it is rolled back.
+const HEALTH_DEADLINE: Duration = Duration::from_secs(5 * 60);
+const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<()> {
     Args::parse();
     tracing_subscriber::fmt::init();
     info!("Smith Updater Starting");
 
-    tokio::time::sleep(time::Duration::from_secs(30)).await;
+    // let smithd finish the upgrade that started us
+    time::sleep(Duration::from_secs(30)).await;
+
+    if let Some(attempt) = SelfUpdateState::load()
+        .attempt
+        .filter(|attempt| !attempt.phase.is_settled())
+    {
+        info!("Resuming self-update to {}", attempt.version);
+        verify().await?;
+        info!("Smith Updater Shutting Down");
+        return Ok(());
+    }
 
     info!("Smith Updater Updating");
     let shutdown = ShutdownHandler::new();
 
     let configuration = MagicHandle::new(shutdown.signals());
 
-    configuration.load(None).await;
-
-    time::sleep(time::Duration::from_secs(5)).await;
+    configuration.load(None).await?;
 
     let packages = configuration.get_packages().await;
-
-    let smith_package = packages
+    let Some(package) = packages
         .iter()
-        .filter(|package| package.name == "smith" || package.name == "smith_amd64")
-        .collect::<Vec<_>>();
+        .find(|package| package.name == "smith" || package.name == "smith_amd64")
+    else {
+        error!("No smith package in the magic file");
+        return Ok(());
+    };
 
-    assert_eq!(smith_package.len(), 1);
+    let installed = installed_version().await;
+    if installed.as_deref() == Some(package.version.as_str()) {
+        info!("Package already installed");
+        return Ok(());
+    }
+    info!(
+        "Package installed -> {} | {} <- Magic Version",
+        installed.as_deref().unwrap_or("none"),
+        package.version
+    );
 
-    // check current version of smith
-    let output = tokio::process::Command::new("dpkg")
-        .arg("-l")
-        .arg("smith")
-        .output()
-        .await
-        .expect("Failed to execute dpkg command");
-
-    let package_file = smith_package[0].file.clone();
-    let package_version = smith_package[0].version.clone();
-    let mut package_installed = false;
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let lines: Vec<&str> = stdout.lines().collect();
-
-        // Assuming the package version is in the second column of the first line
-        if let Some(package_info) = lines.get(5) {
-            let fields: Vec<&str> = package_info.split_whitespace().collect();
-            if let Some(version) = fields.get(2) {
-                info!(
-                    "Package installed -> {version} | {} <- Magic Version",
-                    &package_version
-                );
-                package_installed = version == &package_version
-            }
+    if let Some(installed) = &installed {
+        if let Err(err) = keep(installed).await {
+            warn!("No package of {} to roll back to: {:?}", installed, err);
         }
+    }
+
+    SelfUpdateState::update(|state| state.begin(&package.version, installed)).await?;
+
+    let package_file = std::env::current_dir()?
+        .join("packages")
+        .join(&package.file);
+    info!("Installing package: smith");
+    if let Err(err) = install(&package_file).await {
+        error!("Failed to install smith: {:?}", err);
+        SelfUpdateState::update(|state| {
+            state.set_phase(SelfUpdatePhase::Failed, Some(err.to_string()))
+        })
+        .await?;
+        return Ok(());
+    }
+
+    SelfUpdateState::update(|state| state.set_phase(SelfUpdatePhase::Verifying, None)).await?;
+
+    verify().await?;
+
+    info!("Smith Updater Shutting Down");
+
+    Ok(())
+}
+
+/// Waits for the new smithd to reach the API, rolling back to the kept
+/// package when it doesn't in time.
+async fn verify() -> Result<()> {
+    let Some(attempt) = SelfUpdateState::load().attempt else {
+        return Ok(());
     };
+    let version = attempt.version;
+
+    let deadline = Instant::now() + HEALTH_DEADLINE;
+    loop {
+        // the old smithd keeps writing heartbeats until apt restarts it, only
+        // one of the new version since the install started counts
+        let heartbeat = Heartbeat::read()
+            .filter(|heartbeat| heartbeat.version == version && heartbeat.at > attempt.started_at);
+        if heartbeat.is_some() && installed_version().await.as_deref() == Some(version.as_str()) {
+            info!("smithd {} reached the API", version);
+            if let Err(err) = keep(&version).await {
+                warn!("Failed to keep package of {}: {:?}", version, err);
+            }
+            return SelfUpdateState::update(|state| {
+                state.set_phase(SelfUpdatePhase::Confirmed, None)
+            })
+            .await;
+        }
+
+        if Instant::now() >= deadline {
+            break;
+        }
+        time::sleep(HEALTH_INTERVAL).await;
+    }
+
+    let reason = format!(
+        "smithd {} didn't reach the API within {:?}",
+        version, HEALTH_DEADLINE
+    );
+    error!("{}, rolling back", reason);
 
-    if !package_installed {
-        info!("Package must already be available");
-        let package_location = format!("packages/{}", package_file);
-        info!("Installing package: smith");
-        let install_command = format!(
-            "sudo apt install ./{} -y --allow-downgrades",
-            package_location
-        );
-        let status = tokio::process::Command::new("sh")
-            .arg("-c")
-            .arg(install_command)
-            .output()
+    let kept = match attempt.previous_version {
+        Some(previous) => package_version(Path::new(KEPT_PACKAGE))
             .await
-            .map_err(|e| {
-                error!("Failed to run command to install smith: {}", e);
-                e
-            })?;
-
-        if status.status.success() {
-            info!("Smith installed! Restarting");
-        } else {
-            error!("Failed to install smith");
+            .ok()
+            .filter(|kept| *kept == previous),
+        None => None,
+    };
+
+    let (phase, error) = match kept {
+        Some(kept) => match install(Path::new(KEPT_PACKAGE)).await {
+            Ok(()) => {
+                info!("Rolled back to {}", kept);
+                (SelfUpdatePhase::RolledBack, reason)
+            }
+            Err(err) => (
+                SelfUpdatePhase::Failed,
+                format!("{}, and rolling back to {} failed: {}", reason, kept, err),
+            ),
+        },
+        None => (
+            SelfUpdatePhase::Failed,
+            format!(
+                "{}, and there is no previous package to roll back to",
+                reason
+            ),
+        ),
+    };
+    SelfUpdateState::update(|state| state.set_phase(phase, Some(error))).await
+}
+
+/// Version of the smith package dpkg has installed.
+async fn installed_version() -> Option<String> {
+    let output = Command::new("dpkg-query")
+        .args(["--show", "--showformat=${Version}", PACKAGE])
+        .output()
+        .await
+        .ok()?;
+
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+/// Keeps a package of `version` around to roll back to, from the packages
+/// folder unless it is kept already.
+async fn keep(version: &str) -> Result<()> {
+    if package_version(Path::new(KEPT_PACKAGE))
+        .await
+        .ok()
+        .as_deref()
+        == Some(version)
+    {
+        return Ok(());
+    }
+
+    let package = find_package(version)
+        .await?
+        .ok_or_else(|| anyhow!("no package of {} in the packages folder", version))?;
+
+    tokio::task::spawn_blocking(move || {
+        replace(Path::new(KEPT_PACKAGE), WriteOptions::default(), |file| {
+            std::io::copy(&mut std::fs::File::open(&package)?, file)?;
+            Ok(())
+        })
+    })
+    .await?
+}
+
+/// A smith package of `version` in the packages folder.
+async fn find_package(version: &str) -> Result<Option<PathBuf>> {
+    let mut entries = tokio::fs::read_dir(std::env::current_dir()?.join("packages")).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|extension| extension == "deb")
+            && package_version(&path).await.ok().as_deref() == Some(version)
+        {
+            return Ok(Some(path));
         }
-    } else {
-        info!("Package already installed");
     }
 
-    info!("Smith Updater Shutting Down");
+    Ok(None)
+}
+
+/// Version of a smith .deb, an error for other packages.
+async fn package_version(path: &Path) -> Result<String> {
+    let output = Command::new("dpkg-deb")
+        .arg("--field")
+        .arg(path)
+        .args(["Package", "Version"])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!("{} is not a package", path.display()));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .map(|value| value.trim().to_string())
+    };
+
+    if field("Package").as_deref() != Some(PACKAGE) {
+        return Err(anyhow!("{} is not a smith package", path.display()));
+    }
+    field("Version").ok_or_else(|| anyhow!("{} has no version", path.display()))
+}
+
+async fn install(package: &Path) -> Result<()> {
+    let output = Command::new("apt")
+        .args(["install", "-y", "--allow-downgrades"])
+        .arg(package)
+        .output()
+        .await
+        .context("Failed to run apt")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "apt install failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
 
     Ok(())
 }